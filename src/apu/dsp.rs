@@ -0,0 +1,938 @@
+use std::io::{Error, ErrorKind};
+
+use apu::ARAM;
+use apu::gauss::GAUSS;
+use savestate::{StateReader, StateWriter};
+
+// S-DSP register map
+// ==================
+// voice registers live at (voice << 4) | n
+const V_VOLL: usize   = 0x00;
+const V_VOLR: usize   = 0x01;
+const V_PITCHL: usize = 0x02;
+const V_PITCHH: usize = 0x03;
+const V_SRCN: usize   = 0x04;
+const V_ADSR1: usize  = 0x05;
+const V_ADSR2: usize  = 0x06;
+const V_GAIN: usize   = 0x07;
+const V_ENVX: usize   = 0x08;
+const V_OUTX: usize   = 0x09;
+
+// global registers
+const MVOLL: usize = 0x0C;
+const MVOLR: usize = 0x1C;
+const EVOLL: usize = 0x2C;
+const EVOLR: usize = 0x3C;
+const KON: usize   = 0x4C;
+const KOFF: usize  = 0x5C;
+const FLG: usize   = 0x6C;
+const ENDX: usize  = 0x7C;
+const EFB: usize   = 0x0D;
+const PMON: usize  = 0x2D;
+const NON: usize   = 0x3D;
+const EON: usize   = 0x4D;
+const DIR: usize   = 0x5D;
+const ESA: usize   = 0x6D;
+const EDL: usize   = 0x7D;
+const FIR: usize   = 0x0F; // FIR0-FIR7 at $0F, $1F ... $7F
+
+const VOICE_COUNT: usize = 8;
+
+// number of samples between KON being latched and the voice producing sound
+const KON_DELAY: u8 = 5;
+
+// BRR samples are decoded 4 at a time into a 12 sample ring. the ring is stored twice
+// so the interpolator can always read 4 consecutive samples without wrapping
+const BRR_BUF_SIZE: usize = 12;
+const BRR_BLOCK_SIZE: u16 = 9;
+
+// the echo history is 8 samples per channel, stored twice to avoid wrapping when running the FIR
+const ECHO_HIST_SIZE: usize = 8;
+
+// the global counter used for envelope and noise rates
+// it counts down from 0x77FF and every rate is derived from it
+const COUNTER_RANGE: u32 = 2048 * 5 * 3;
+
+const COUNTER_RATES: [u32; 32] = [
+    COUNTER_RANGE + 1, // never fires
+    2048, 1536, 1280, 1024, 768, 640, 512, 384, 320, 256, 192, 160, 128, 96, 80, 64,
+    48, 40, 32, 24, 20, 16, 12, 10, 8, 6, 5, 4, 3, 2, 1,
+];
+
+const COUNTER_OFFSETS: [u32; 32] = [
+    1, 0, 1040, 536, 0, 1040, 536, 0, 1040, 536, 0, 1040, 536, 0, 1040, 536,
+    0, 1040, 536, 0, 1040, 536, 0, 1040, 536, 0, 1040, 536, 0, 1040, 0, 0,
+];
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum EnvelopeMode {
+    Release,
+    Attack,
+    Decay,
+    Sustain,
+}
+
+#[derive(Clone, Copy)]
+pub struct Voice {
+    buf: [i32; BRR_BUF_SIZE * 2],
+    buf_pos: usize,     // where the next 4 decoded samples will be written
+    interp_pos: i32,    // 4.12 fixed point position within the decoded samples
+    brr_addr: u16,      // address of the current BRR block header
+    brr_offset: u16,    // offset of the next pair of bytes to decode within the block
+    brr_header: u8,
+    kon_delay: u8,
+    env_mode: EnvelopeMode,
+    env: i32,           // 11 bit envelope
+    hidden_env: i32,    // envelope before clamping, used by bent increase
+    output: i32,        // last output, needed for pitch modulation of the next voice
+}
+
+impl Voice {
+    pub fn new() -> Voice {
+        Voice {
+            buf: [0; BRR_BUF_SIZE * 2],
+            buf_pos: 0,
+            interp_pos: 0,
+            brr_addr: 0,
+            brr_offset: 1,
+            brr_header: 0,
+            kon_delay: 0,
+            env_mode: EnvelopeMode::Release,
+            env: 0,
+            hidden_env: 0,
+            output: 0,
+        }
+    }
+
+    pub fn env_mode(&self) -> EnvelopeMode {
+        self.env_mode
+    }
+
+    pub fn env(&self) -> i32 {
+        self.env
+    }
+
+    fn save_state(&self, w: &mut StateWriter) {
+        for sample in self.buf.iter() {
+            w.i32(*sample);
+        }
+        w.u8(self.buf_pos as u8);
+        w.i32(self.interp_pos);
+        w.u16(self.brr_addr);
+        w.u16(self.brr_offset);
+        w.u8(self.brr_header);
+        w.u8(self.kon_delay);
+        w.u8(self.env_mode as u8);
+        w.i32(self.env);
+        w.i32(self.hidden_env);
+        w.i32(self.output);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), Error> {
+        for sample in self.buf.iter_mut() {
+            *sample = r.i32()?;
+        }
+        self.buf_pos = r.u8()? as usize % BRR_BUF_SIZE;
+        self.interp_pos = r.i32()?;
+        self.brr_addr = r.u16()?;
+        self.brr_offset = r.u16()?;
+        self.brr_header = r.u8()?;
+        self.kon_delay = r.u8()?;
+        self.env_mode = match r.u8()? {
+            0 => EnvelopeMode::Release,
+            1 => EnvelopeMode::Attack,
+            2 => EnvelopeMode::Decay,
+            3 => EnvelopeMode::Sustain,
+            _ => return Err(Error::new(ErrorKind::InvalidData, "unknown envelope mode")),
+        };
+        self.env = r.i32()?;
+        self.hidden_env = r.i32()?;
+        self.output = r.i32()?;
+        Ok(())
+    }
+}
+
+pub struct DSP {
+    regs: [u8; 0x80],
+    voices: [Voice; VOICE_COUNT],
+
+    counter: u32,
+    noise: i32,        // 15 bit LFSR
+    every_other_sample: bool,
+    new_kon: u8,       // value written to KON, latched every other sample
+    endx: u8,
+
+    // echo
+    echo_hist: [[i32; 2]; ECHO_HIST_SIZE * 2],
+    echo_hist_pos: usize,
+    echo_offset: u16,  // byte offset of the current frame within the echo buffer
+    echo_length: u16,  // EDL * $800, only latched when the offset wraps back to 0
+    esa: u8,           // ESA is latched a sample late
+    echo_out: [i32; 2],
+
+    // 32 kHz interleaved stereo output (left, right, left, right...)
+    samples: Vec<i16>,
+}
+
+impl DSP {
+    pub fn new() -> DSP {
+        let mut dsp = DSP {
+            regs: [0; 0x80],
+            voices: [Voice::new(); VOICE_COUNT],
+
+            counter: 0,
+            noise: 0x4000,
+            every_other_sample: true,
+            new_kon: 0,
+            endx: 0,
+
+            echo_hist: [[0; 2]; ECHO_HIST_SIZE * 2],
+            echo_hist_pos: 0,
+            echo_offset: 0,
+            echo_length: 0,
+            esa: 0,
+            echo_out: [0; 2],
+
+            samples: Vec::new(),
+        };
+
+        dsp.reset();
+        dsp
+    }
+
+    // power on / reset state: all voices are silenced and FLG has soft reset, mute and echo disable set
+    pub fn reset(&mut self) {
+        for voice in self.voices.iter_mut() {
+            *voice = Voice::new();
+        }
+
+        self.regs[FLG] = 0xE0;
+        self.counter = 0;
+        self.noise = 0x4000;
+        self.every_other_sample = true;
+        self.new_kon = 0;
+        self.endx = 0;
+
+        self.echo_hist = [[0; 2]; ECHO_HIST_SIZE * 2];
+        self.echo_hist_pos = 0;
+        self.echo_offset = 0;
+        self.echo_length = 0;
+        self.esa = 0;
+        self.echo_out = [0; 2];
+    }
+
+    pub fn voice(&self, index: usize) -> &Voice {
+        &self.voices[index]
+    }
+
+    ////////////////////////////////////
+    //
+    //          REGISTER ACCESS
+    //
+    ////////////////////////////////////
+
+    // $80-$FF mirror $00-$7F for reads
+    pub fn read(&self, address: u8) -> u8 {
+        let address = (address & 0x7F) as usize;
+        let voice = address >> 4;
+
+        match address & 0x0F {
+            0x08 if voice < VOICE_COUNT => (self.voices[voice].env >> 4) as u8,
+            0x09 if voice < VOICE_COUNT => (self.voices[voice].output >> 8) as u8,
+            _ => {
+                if address == ENDX {
+                    self.endx
+                } else {
+                    self.regs[address]
+                }
+            }
+        }
+    }
+
+    // writes to $80-$FF are ignored
+    pub fn write(&mut self, address: u8, data: u8) {
+        if address >= 0x80 {
+            return;
+        }
+
+        let address = address as usize;
+        self.regs[address] = data;
+
+        match address {
+            KON => self.new_kon = data,
+            // any write to ENDX acknowledges every voice
+            ENDX => self.endx = 0,
+            _ => {}
+        }
+    }
+
+    pub fn regs(&self) -> &[u8; 0x80] {
+        &self.regs
+    }
+
+    // restores every register at once, eg from an .spc snapshot. the voices start
+    // silent and anything set in KON is keyed on the next time KON is polled. the echo
+    // buffer is latched straight away, otherwise echo writes would land at $0000 until
+    // the buffer first wrapped
+    pub fn load_registers(&mut self, regs: &[u8; 0x80]) {
+        self.reset();
+        self.regs = *regs;
+        self.endx = regs[ENDX];
+        self.new_kon = regs[KON];
+        self.esa = regs[ESA];
+        self.echo_offset = 0;
+        self.echo_length = ((regs[EDL] & 0x0F) as u16) << 11;
+    }
+
+    // the registers as the SPC700 would read them, including ENVX/OUTX/ENDX
+    pub fn read_registers(&self) -> [u8; 0x80] {
+        let mut regs = [0; 0x80];
+        for (address, reg) in regs.iter_mut().enumerate() {
+            *reg = self.read(address as u8);
+        }
+        regs
+    }
+
+    ////////////////////////////////////
+    //
+    //             OUTPUT
+    //
+    ////////////////////////////////////
+
+    pub fn samples(&self) -> &[i16] {
+        &self.samples
+    }
+
+    ////////////////////////////////////
+    //
+    //           SAVE STATES
+    //
+    ////////////////////////////////////
+
+    // the output buffer isn't part of the state
+    pub fn save_state(&self, w: &mut StateWriter) {
+        w.bytes(&self.regs);
+        for voice in self.voices.iter() {
+            voice.save_state(w);
+        }
+
+        w.u32(self.counter);
+        w.i32(self.noise);
+        w.bool(self.every_other_sample);
+        w.u8(self.new_kon);
+        w.u8(self.endx);
+
+        for frame in self.echo_hist.iter() {
+            w.i32(frame[0]);
+            w.i32(frame[1]);
+        }
+        w.u8(self.echo_hist_pos as u8);
+        w.u16(self.echo_offset);
+        w.u16(self.echo_length);
+        w.u8(self.esa);
+        w.i32(self.echo_out[0]);
+        w.i32(self.echo_out[1]);
+    }
+
+    pub fn load_state(&mut self, r: &mut StateReader) -> Result<(), Error> {
+        r.bytes(&mut self.regs)?;
+        for voice in self.voices.iter_mut() {
+            voice.load_state(r)?;
+        }
+
+        self.counter = r.u32()?;
+        self.noise = r.i32()?;
+        self.every_other_sample = r.bool()?;
+        self.new_kon = r.u8()?;
+        self.endx = r.u8()?;
+
+        for frame in self.echo_hist.iter_mut() {
+            frame[0] = r.i32()?;
+            frame[1] = r.i32()?;
+        }
+        self.echo_hist_pos = r.u8()? as usize % ECHO_HIST_SIZE;
+        self.echo_offset = r.u16()?;
+        self.echo_length = r.u16()?;
+        self.esa = r.u8()?;
+        self.echo_out[0] = r.i32()?;
+        self.echo_out[1] = r.i32()?;
+        Ok(())
+    }
+
+    pub fn take_samples(&mut self) -> Vec<i16> {
+        let mut samples = Vec::new();
+        ::std::mem::swap(&mut samples, &mut self.samples);
+        samples
+    }
+
+    // runs the DSP for one 32 kHz sample (32 SPC700 clocks)
+    pub fn run_sample(&mut self, aram: &mut ARAM) {
+        self.tick_counter();
+        self.latch_kon_koff();
+
+        let flg = self.regs[FLG];
+
+        if self.read_counter((flg & 0x1F) as usize) {
+            let feedback = (self.noise << 13) ^ (self.noise << 14);
+            self.noise = (feedback & 0x4000) ^ (self.noise >> 1);
+        }
+
+        let mut main_l = 0;
+        let mut main_r = 0;
+        let mut prev_output = 0;
+
+        for index in 0..VOICE_COUNT {
+            let output = self.run_voice(index, prev_output, aram);
+            prev_output = output;
+
+            let base = index << 4;
+            let amp_l = (output * (self.regs[base | V_VOLL] as i8 as i32)) >> 7;
+            let amp_r = (output * (self.regs[base | V_VOLR] as i8 as i32)) >> 7;
+
+            main_l = clamp_16(main_l + amp_l);
+            main_r = clamp_16(main_r + amp_r);
+
+            if self.regs[EON] & (1 << index) != 0 {
+                self.echo_out[0] = clamp_16(self.echo_out[0] + amp_l);
+                self.echo_out[1] = clamp_16(self.echo_out[1] + amp_r);
+            }
+        }
+
+        let echo_in = self.run_echo(aram);
+
+        let (l, r) = if flg & 0x40 != 0 {
+            // FLG bit 6 mutes the output but everything keeps running
+            (0, 0)
+        } else {
+            (self.mix_output(main_l, echo_in[0], MVOLL, EVOLL), self.mix_output(main_r, echo_in[1], MVOLR, EVOLR))
+        };
+
+        self.samples.push(l as i16);
+        self.samples.push(r as i16);
+    }
+
+    fn mix_output(&self, main: i32, echo_in: i32, mvol: usize, evol: usize) -> i32 {
+        let main = ((main * (self.regs[mvol] as i8 as i32)) >> 7) as i16 as i32;
+        let echo = ((echo_in * (self.regs[evol] as i8 as i32)) >> 7) as i16 as i32;
+        clamp_16(main + echo)
+    }
+
+    ////////////////////////////////////
+    //
+    //              ECHO
+    //
+    ////////////////////////////////////
+
+    // the echo buffer lives in ARAM at ESA * $100 and is EDL * 2KB long (4 bytes when EDL is 0).
+    // each frame is 4 bytes: left then right as little endian signed 16 bit samples.
+    // nothing stops the buffer from overlapping code or samples, and some games rely on
+    // (or suffer from) the echo writes trampling the rest of ARAM.
+    fn run_echo(&mut self, aram: &mut ARAM) -> [i32; 2] {
+        self.echo_hist_pos = (self.echo_hist_pos + 1) % ECHO_HIST_SIZE;
+
+        let echo_ptr = ((self.esa as u16) << 8).wrapping_add(self.echo_offset);
+
+        for ch in 0..2 {
+            let addr = echo_ptr.wrapping_add((ch as u16) * 2);
+            let lo = aram[addr as usize] as u16;
+            let hi = aram[addr.wrapping_add(1) as usize] as u16;
+            let s = ((hi << 8) | lo) as i16 as i32;
+
+            self.echo_hist[self.echo_hist_pos][ch] = s >> 1;
+            self.echo_hist[self.echo_hist_pos + ECHO_HIST_SIZE][ch] = s >> 1;
+        }
+
+        let mut echo_in = [0; 2];
+        for ch in 0..2 {
+            // taps 0-6 are summed without clamping and the sum is truncated to 16 bits
+            // before the last tap is added. only the final result is clamped
+            let mut sum = 0;
+            for tap in 0..7 {
+                sum += self.calc_fir(tap, ch);
+            }
+            sum = sum as i16 as i32;
+            sum += self.calc_fir(7, ch) as i16 as i32;
+
+            echo_in[ch] = clamp_16(sum) & !1;
+        }
+
+        let efb = self.regs[EFB] as i8 as i32;
+        let writes_enabled = self.regs[FLG] & 0x20 == 0;
+
+        for ch in 0..2 {
+            let feedback = ((echo_in[ch] * efb) >> 7) as i16 as i32;
+            let out = clamp_16(self.echo_out[ch] + feedback) & !1;
+
+            if writes_enabled {
+                let addr = echo_ptr.wrapping_add((ch as u16) * 2);
+                aram[addr as usize] = out as u8;
+                aram[addr.wrapping_add(1) as usize] = (out >> 8) as u8;
+            }
+
+            self.echo_out[ch] = 0;
+        }
+
+        self.esa = self.regs[ESA];
+
+        if self.echo_offset == 0 {
+            self.echo_length = ((self.regs[EDL] & 0x0F) as u16) << 11;
+        }
+
+        self.echo_offset += 4;
+        if self.echo_offset >= self.echo_length {
+            self.echo_offset = 0;
+        }
+
+        echo_in
+    }
+
+    // FIR0 is applied to the oldest sample in the history and FIR7 to the newest
+    fn calc_fir(&self, tap: usize, ch: usize) -> i32 {
+        let coefficient = self.regs[FIR | (tap << 4)] as i8 as i32;
+        (self.echo_hist[self.echo_hist_pos + tap + 1][ch] * coefficient) >> 6
+    }
+
+    ////////////////////////////////////
+    //
+    //        COUNTER / KON / KOFF
+    //
+    ////////////////////////////////////
+
+    fn tick_counter(&mut self) {
+        if self.counter == 0 {
+            self.counter = COUNTER_RANGE - 1;
+        } else {
+            self.counter -= 1;
+        }
+    }
+
+    // true when an event running at the given rate should happen this sample
+    fn read_counter(&self, rate: usize) -> bool {
+        (self.counter + COUNTER_OFFSETS[rate]) % COUNTER_RATES[rate] == 0
+    }
+
+    // KON and KOFF are only polled every other sample
+    fn latch_kon_koff(&mut self) {
+        self.every_other_sample = !self.every_other_sample;
+        if !self.every_other_sample {
+            return;
+        }
+
+        let kon = self.new_kon;
+        let koff = self.regs[KOFF];
+        let soft_reset = self.regs[FLG] & 0x80 != 0;
+
+        for index in 0..VOICE_COUNT {
+            let bit = 1 << index;
+            let voice = &mut self.voices[index];
+
+            if kon & bit != 0 {
+                voice.kon_delay = KON_DELAY;
+                voice.env_mode = EnvelopeMode::Attack;
+                self.endx &= !bit;
+            } else if koff & bit != 0 && voice.kon_delay == 0 {
+                voice.env_mode = EnvelopeMode::Release;
+            }
+
+            if soft_reset {
+                voice.env_mode = EnvelopeMode::Release;
+                voice.env = 0;
+            }
+        }
+
+        // a KON value is consumed once it has been latched
+        self.new_kon = 0;
+    }
+
+    ////////////////////////////////////
+    //
+    //              VOICE
+    //
+    ////////////////////////////////////
+
+    fn run_voice(&mut self, index: usize, prev_output: i32, aram: &mut ARAM) -> i32 {
+        let base = index << 4;
+        let bit = 1 << index;
+
+        let mut pitch = (((self.regs[base | V_PITCHH] as i32) & 0x3F) << 8) | self.regs[base | V_PITCHL] as i32;
+
+        // pitch modulation uses the previous voice's output. voice 0 cannot be modulated
+        if index > 0 && self.regs[PMON] & bit != 0 {
+            pitch += ((prev_output >> 5) * pitch) >> 10;
+        }
+
+        if self.voices[index].kon_delay > 0 {
+            if self.voices[index].kon_delay == KON_DELAY {
+                let start = self.source_address(index, 0, aram);
+
+                let voice = &mut self.voices[index];
+                voice.brr_addr = start;
+                voice.brr_offset = 1;
+                voice.buf_pos = 0;
+                voice.brr_header = 0;
+                // nothing of the last note is left for the interpolator to read
+                voice.buf = [0; BRR_BUF_SIZE * 2];
+            }
+
+            let voice = &mut self.voices[index];
+            voice.env = 0;
+            voice.hidden_env = 0;
+
+            // the first 3 samples of the delay each decode 4 samples so the ring is full when the voice starts
+            voice.kon_delay -= 1;
+            voice.interp_pos = if voice.kon_delay & 3 != 0 { 0x4000 } else { 0 };
+            pitch = 0;
+        }
+
+        let mut output = self.interpolate(index);
+
+        if self.regs[NON] & bit != 0 {
+            output = (self.noise << 1) as i16 as i32;
+        }
+
+        {
+            let voice = &mut self.voices[index];
+
+            // a block with the end flag and no loop flag silences the voice
+            if voice.kon_delay == 0 && voice.brr_header & 0x03 == 0x01 {
+                voice.env_mode = EnvelopeMode::Release;
+                voice.env = 0;
+            }
+
+            output = ((output * voice.env) >> 11) & !1;
+            voice.output = output;
+        }
+
+        self.run_envelope(index);
+
+        // decoding comes before the pitch is added, so the 0x4000 the KON delay sets is seen
+        if self.voices[index].interp_pos >= 0x4000 {
+            self.decode_brr(index, aram);
+        }
+
+        self.voices[index].interp_pos = (self.voices[index].interp_pos & 0x3FFF) + pitch;
+        // keep from getting too far ahead when pitch modulation is used
+        if self.voices[index].interp_pos > 0x7FFF {
+            self.voices[index].interp_pos = 0x7FFF;
+        }
+
+        self.regs[base | V_ENVX] = (self.voices[index].env >> 4) as u8;
+        self.regs[base | V_OUTX] = (output >> 8) as u8;
+
+        output
+    }
+
+    // reads a sample's start (entry 0) or loop (entry 1) address from the directory at DIR * $100
+    fn source_address(&self, index: usize, entry: u16, aram: &ARAM) -> u16 {
+        let srcn = self.regs[(index << 4) | V_SRCN] as u16;
+        let dir = (self.regs[DIR] as u16) << 8;
+        let entry_addr = dir.wrapping_add(srcn * 4).wrapping_add(entry * 2);
+
+        let lo = aram[entry_addr as usize] as u16;
+        let hi = aram[entry_addr.wrapping_add(1) as usize] as u16;
+        (hi << 8) | lo
+    }
+
+    fn interpolate(&self, index: usize) -> i32 {
+        let voice = &self.voices[index];
+
+        let offset = ((voice.interp_pos >> 4) & 0xFF) as usize;
+        let base = ((voice.interp_pos >> 12) as usize) + voice.buf_pos;
+        let s = &voice.buf[base..base + 4];
+
+        let mut out = (GAUSS[255 - offset] * s[0]) >> 11;
+        out += (GAUSS[511 - offset] * s[1]) >> 11;
+        out += (GAUSS[256 + offset] * s[2]) >> 11;
+        // the hardware truncates to 16 bits before adding the last tap
+        out = out as i16 as i32;
+        out += (GAUSS[offset] * s[3]) >> 11;
+
+        clamp_16(out) & !1
+    }
+
+    ////////////////////////////////////
+    //
+    //               BRR
+    //
+    ////////////////////////////////////
+
+    // decodes the next 4 samples of the current BRR block
+    // block layout: 1 header byte (ssss ffle) then 8 bytes of signed 4 bit samples, high nybble first
+    fn decode_brr(&mut self, index: usize, aram: &mut ARAM) {
+        let loop_addr = self.source_address(index, 1, aram);
+        let voice = &mut self.voices[index];
+
+        if voice.brr_offset == 1 {
+            voice.brr_header = aram[voice.brr_addr as usize];
+        }
+
+        let header = voice.brr_header;
+        let shift = (header >> 4) as i32;
+        let filter = (header >> 2) & 0x03;
+
+        let data_addr = voice.brr_addr.wrapping_add(voice.brr_offset);
+        let nybbles = ((aram[data_addr as usize] as i32) << 8) | aram[data_addr.wrapping_add(1) as usize] as i32;
+
+        for n in 0..4 {
+            // sign extend the nybble
+            let mut s = (((nybbles << (n * 4)) & 0xF000) as i16 as i32) >> 12;
+            s = (s << shift) >> 1;
+
+            // shift values 13-15 give either 0 or -2048 depending on the sign
+            if shift >= 0x0D {
+                s = (s >> 25) << 11;
+            }
+
+            let pos = voice.buf_pos + n;
+            let p1 = voice.buf[pos + BRR_BUF_SIZE - 1];
+            let p2 = voice.buf[pos + BRR_BUF_SIZE - 2] >> 1;
+
+            match filter {
+                1 => {
+                    // s + p1 * 15/16
+                    s += p1 >> 1;
+                    s += (-p1) >> 5;
+                },
+                2 => {
+                    // s + p1 * 61/32 - p2 * 15/16
+                    s += p1;
+                    s -= p2;
+                    s += p2 >> 4;
+                    s += (p1 * -3) >> 6;
+                },
+                3 => {
+                    // s + p1 * 115/64 - p2 * 13/16
+                    s += p1;
+                    s -= p2;
+                    s += (p1 * -13) >> 7;
+                    s += (p2 * 3) >> 4;
+                },
+                _ => {}
+            }
+
+            s = (clamp_16(s) << 1) as i16 as i32;
+
+            voice.buf[pos] = s;
+            voice.buf[pos + BRR_BUF_SIZE] = s;
+        }
+
+        voice.buf_pos = (voice.buf_pos + 4) % BRR_BUF_SIZE;
+        voice.brr_offset += 2;
+
+        if voice.brr_offset >= BRR_BLOCK_SIZE {
+            voice.brr_offset = 1;
+
+            if header & 0x01 != 0 {
+                voice.brr_addr = loop_addr;
+                self.endx |= 1 << index;
+            } else {
+                voice.brr_addr = voice.brr_addr.wrapping_add(BRR_BLOCK_SIZE);
+            }
+        }
+    }
+
+    ////////////////////////////////////
+    //
+    //            ENVELOPE
+    //
+    ////////////////////////////////////
+
+    fn run_envelope(&mut self, index: usize) {
+        let base = index << 4;
+        let adsr1 = self.regs[base | V_ADSR1];
+        let adsr2 = self.regs[base | V_ADSR2];
+        let gain = self.regs[base | V_GAIN];
+
+        let mut env = self.voices[index].env;
+        let env_mode = self.voices[index].env_mode;
+
+        if env_mode == EnvelopeMode::Release {
+            // release always runs at -8 per sample regardless of ADSR/GAIN
+            env -= 0x08;
+            if env < 0 {
+                env = 0;
+            }
+            self.voices[index].env = env;
+            return;
+        }
+
+        let rate;
+        // the sustain level comparison uses ADSR2 or GAIN, whichever is active
+        let env_data;
+
+        if adsr1 & 0x80 != 0 {
+            env_data = adsr2;
+
+            if env_mode == EnvelopeMode::Attack {
+                rate = (((adsr1 & 0x0F) as usize) << 1) + 1;
+                env += if rate < 31 { 0x20 } else { 0x400 };
+            } else {
+                // decay and sustain are both exponential
+                env -= 1;
+                env -= env >> 8;
+
+                rate = if env_mode == EnvelopeMode::Decay {
+                    (((adsr1 >> 3) & 0x0E) as usize) + 0x10
+                } else {
+                    (adsr2 & 0x1F) as usize
+                };
+            }
+        } else {
+            env_data = gain;
+            let mode = gain >> 5;
+
+            if mode < 4 {
+                // direct gain
+                env = (gain as i32) << 4;
+                rate = 31;
+            } else {
+                rate = (gain & 0x1F) as usize;
+
+                match mode {
+                    // linear decrease
+                    4 => env -= 0x20,
+                    // exponential decrease
+                    5 => {
+                        env -= 1;
+                        env -= env >> 8;
+                    },
+                    // linear increase
+                    6 => env += 0x20,
+                    // bent increase: fast until 3/4 then slow
+                    _ => {
+                        env += 0x20;
+                        if (self.voices[index].hidden_env as u32) >= 0x600 {
+                            env += 0x08 - 0x20;
+                        }
+                    },
+                }
+            }
+        }
+
+        let voice = &mut self.voices[index];
+
+        if (env >> 8) == ((env_data >> 5) as i32) && voice.env_mode == EnvelopeMode::Decay {
+            voice.env_mode = EnvelopeMode::Sustain;
+        }
+
+        voice.hidden_env = env;
+
+        if !(0..=0x7FF).contains(&env) {
+            env = if env < 0 { 0 } else { 0x7FF };
+            if voice.env_mode == EnvelopeMode::Attack {
+                voice.env_mode = EnvelopeMode::Decay;
+            }
+        }
+
+        if self.read_counter(rate) {
+            self.voices[index].env = env;
+        }
+    }
+}
+
+fn clamp_16(n: i32) -> i32 {
+    n.clamp(-0x8000, 0x7FFF)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use util::crc32::crc32;
+
+    // one looping block, shift 8 and no filter, so the samples are the nybbles * 256
+    const BRR_NYBBLES: [u8; 8] = [0x12, 0x34, 0x56, 0x7F, 0xED, 0xCB, 0xA9, 0x80];
+
+    fn aram_with_sample() -> Box<ARAM> {
+        let mut aram = Box::new([0; 0x10000]);
+        // directory at $0200, sample 0 starts and loops at $0300
+        aram[0x0200..0x0204].copy_from_slice(&[0x00, 0x03, 0x00, 0x03]);
+        aram[0x0300] = 0x83;
+        aram[0x0301..0x0309].copy_from_slice(&BRR_NYBBLES);
+        aram
+    }
+
+    fn key_on(dsp: &mut DSP) {
+        dsp.write(FLG as u8, 0x20);
+        dsp.write(DIR as u8, 0x02);
+        dsp.write(V_SRCN as u8, 0x00);
+        dsp.write(V_PITCHH as u8, 0x10); // one sample per sample
+        dsp.write(V_ADSR1 as u8, 0x00);
+        dsp.write(V_GAIN as u8, 0x7F); // direct gain, env 0x7F0
+        dsp.write(KON as u8, 0x01);
+    }
+
+    #[test]
+    fn key_on_decodes_before_the_voice_starts() {
+        // KON is latched on the second sample, then the voice is silent for 5 while the
+        // first 12 samples are decoded. the envelope starts on the sample after that
+        let expected = [0, 0, 0, 0, 0, 0, 508, 762, 1014, 1270, 1524, 1358, 62, -512, -764, -1018];
+
+        let mut aram = aram_with_sample();
+        let mut dsp = DSP::new();
+        // whatever an earlier note left in the ring mustn't be heard
+        dsp.voices[0].buf = [0x1234; BRR_BUF_SIZE * 2];
+        key_on(&mut dsp);
+
+        let mut outputs = Vec::new();
+        for _ in 0..expected.len() {
+            dsp.run_sample(&mut aram);
+            outputs.push(dsp.voices[0].output);
+        }
+        assert_eq!(outputs, expected);
+    }
+
+    #[test]
+    fn echo_runs_the_fir_and_feedback_over_the_buffer() {
+        // reference values from a model of blargg's echo stages. 1100 samples goes round
+        // the 2KB buffer twice, so the second time the FIR is reading back its own feedback
+        let fir = [0x40, 0x20, 0x10, 0xF0, 0x08, 0xE0, 0x30, 0x7F];
+        let first = [14342, -8725, 20478, -4133, 7977, 9892, -5689, 16384, -16980, -4643, 13860, -9946, 20478, 6504, 6536, 11849];
+        let last = [9036, 2730, -3214, 5341, -2563, 3484, 9701, -30];
+
+        let mut aram: Box<ARAM> = Box::new([0; 0x10000]);
+        for i in 0..0x400 {
+            let sample = ((i * 7919) ^ 0x5A5A) as u16;
+            aram[i * 2] = sample as u8;
+            aram[i * 2 + 1] = (sample >> 8) as u8;
+        }
+
+        let mut dsp = DSP::new();
+        dsp.write(FLG as u8, 0x00); // echo writes on
+        for (tap, &coefficient) in fir.iter().enumerate() {
+            dsp.write((FIR | (tap << 4)) as u8, coefficient);
+        }
+        dsp.write(EFB as u8, 0xB0);
+        dsp.write(EVOLL as u8, 0x50);
+        dsp.write(EVOLR as u8, 0xC0);
+        dsp.write(ESA as u8, 0x00);
+        dsp.write(EDL as u8, 0x01);
+
+        for _ in 0..1100 {
+            dsp.run_sample(&mut aram);
+        }
+        let samples = dsp.take_samples();
+        assert_eq!(&samples[..16], &first[..]);
+        assert_eq!(&samples[samples.len() - 8..], &last[..]);
+
+        let bytes: Vec<u8> = samples.iter().flat_map(|&sample| vec![sample as u8, (sample >> 8) as u8]).collect();
+        assert_eq!(crc32(&bytes), 0x004DF596);
+        assert_eq!(crc32(&aram[..0x800]), 0x90FEB0F9);
+        // nothing past the end of the buffer is touched
+        assert!(aram[0x800..].iter().all(|&byte| byte == 0));
+    }
+
+    #[test]
+    fn loaded_registers_latch_the_echo_buffer() {
+        let mut aram: Box<ARAM> = Box::new([0xAA; 0x10000]);
+        let mut regs = [0; 0x80];
+        regs[ESA] = 0x40;
+        regs[EDL] = 0x01;
+
+        let mut dsp = DSP::new();
+        dsp.load_registers(&regs);
+        dsp.run_sample(&mut aram);
+
+        assert_eq!(&aram[0x4000..0x4004], &[0, 0, 0, 0]);
+        assert!(aram[..0x4000].iter().all(|&byte| byte == 0xAA));
+    }
+}
@@ -0,0 +1,155 @@
+pub mod dsp;
+pub mod spc700;
+pub mod spc_bus;
+pub mod spc_file;
+pub mod wav;
+
+mod gauss;
+
+use std::io::Error;
+
+use apu::spc700::SPC700;
+use apu::spc_bus::SpcBus;
+use apu::spc_file::{SpcFile, ID666};
+use savestate::{SaveState, StateWriter};
+
+// 64KB of audio ram shared by the SPC700 and the S-DSP
+pub type ARAM = [u8; 0x10000];
+
+// the APU runs off its own 24.576 MHz crystal divided down to 1.024 MHz for the SPC700.
+// NTSC master clock is 21.477272 MHz
+pub const MASTER_CLOCK_HZ: u64 = 21_477_272;
+pub const APU_CLOCK_HZ: u64 = 1_024_000;
+
+// the APU is run in lockstep with the rest of the system rather than on its own thread.
+// the main CPU tells it how far the master clock has advanced and it runs until it has
+// caught up. the ports always sync before they are accessed so handshake loops on either
+// side see each other's writes at the right time and never drift apart
+pub struct APU {
+    spc: SPC700,
+    bus: SpcBus,
+}
+
+impl APU {
+    pub fn new() -> APU {
+        let mut apu = APU {
+            spc: SPC700::new(),
+            bus: SpcBus::new(),
+        };
+
+        apu.reset();
+        apu
+    }
+
+    pub fn reset(&mut self) {
+        self.bus.reset();
+        self.spc.reset(&mut self.bus);
+    }
+
+    pub fn spc(&self) -> &SPC700 {
+        &self.spc
+    }
+
+    pub fn spc_mut(&mut self) -> &mut SPC700 {
+        &mut self.spc
+    }
+
+    pub fn bus(&self) -> &SpcBus {
+        &self.bus
+    }
+
+    pub fn bus_mut(&mut self) -> &mut SpcBus {
+        &mut self.bus
+    }
+
+    // the number of SPC700 cycles that should have elapsed after the given number of master cycles
+    fn apu_cycles_at(master_cycles: u64) -> u64 {
+        ((master_cycles as u128 * APU_CLOCK_HZ as u128) / MASTER_CLOCK_HZ as u128) as u64
+    }
+
+    // runs the SPC700 (and with it the timers and DSP) until it has caught up to the master clock
+    pub fn run_to(&mut self, master_cycles: u64) {
+        let target = Self::apu_cycles_at(master_cycles);
+
+        while self.bus.cycles() < target {
+            self.spc.step(&mut self.bus);
+        }
+    }
+
+    // runs the APU on its own, for playing back music without the rest of the system
+    pub fn run_for(&mut self, apu_cycles: u64) {
+        let target = self.bus.cycles() + apu_cycles;
+
+        while self.bus.cycles() < target {
+            self.spc.step(&mut self.bus);
+        }
+    }
+
+    // $2140-$2143 as seen by the main CPU
+    pub fn read_port(&mut self, master_cycles: u64, port: usize) -> u8 {
+        self.run_to(master_cycles);
+        self.bus.cpu_read_port(port)
+    }
+
+    pub fn write_port(&mut self, master_cycles: u64, port: usize, data: u8) {
+        self.run_to(master_cycles);
+        self.bus.cpu_write_port(port, data);
+    }
+
+    pub fn take_samples(&mut self) -> Vec<i16> {
+        self.bus.dsp_mut().take_samples()
+    }
+
+    ////////////////////////////////////
+    //
+    //          SPC SNAPSHOTS
+    //
+    ////////////////////////////////////
+
+    pub fn load_spc(&mut self, spc: &SpcFile) {
+        self.bus.load_snapshot(&spc.aram, &spc.extra_ram);
+        self.bus.dsp_mut().load_registers(&spc.dsp_regs);
+        self.spc.set_registers(spc.pc, spc.a, spc.x, spc.y, spc.psw, spc.sp);
+    }
+
+    // dumps the live state of the APU, eg from a running game
+    pub fn save_spc(&self, id666: Option<ID666>) -> SpcFile {
+        let (aram, extra_ram) = self.bus.snapshot();
+
+        SpcFile {
+            pc: self.spc.pc(),
+            a: self.spc.a(),
+            x: self.spc.x(),
+            y: self.spc.y(),
+            psw: self.spc.psw(),
+            sp: self.spc.sp(),
+
+            aram,
+            dsp_regs: self.bus.dsp().read_registers(),
+            extra_ram,
+
+            id666,
+        }
+    }
+
+    ////////////////////////////////////
+    //
+    //           SAVE STATES
+    //
+    ////////////////////////////////////
+
+    pub fn save_state(&self, w: &mut StateWriter) {
+        w.section(b"SMP ", |w| {
+            self.spc.save_state(w);
+            self.bus.save_state(w);
+        });
+        w.section(b"DSP ", |w| self.bus.dsp().save_state(w));
+    }
+
+    pub fn load_state(&mut self, state: &SaveState) -> Result<(), Error> {
+        let mut smp = state.section(b"SMP ")?;
+        self.spc.load_state(&mut smp)?;
+        self.bus.load_state(&mut smp)?;
+        self.bus.dsp_mut().load_state(&mut state.section(b"DSP ")?)
+    }
+}
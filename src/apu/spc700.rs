@@ -0,0 +1,802 @@
+use std::io::Error;
+
+use apu::spc_bus::SpcBus;
+use savestate::{StateReader, StateWriter};
+
+// status flags
+const FLAG_C: u8 = 0x01; // carry
+const FLAG_Z: u8 = 0x02; // zero
+const FLAG_I: u8 = 0x04; // interrupt enable (unused on the SNES)
+const FLAG_H: u8 = 0x08; // half carry
+const FLAG_B: u8 = 0x10; // break
+const FLAG_P: u8 = 0x20; // direct page ($00xx or $01xx)
+const FLAG_V: u8 = 0x40; // overflow
+const FLAG_N: u8 = 0x80; // negative
+
+// base cycle counts. branches that are taken add 2 cycles on top of these
+const CYCLES: [u8; 256] = [
+//  0  1  2  3  4  5  6  7  8  9  A  B  C  D  E  F
+    2, 8, 4, 5, 3, 4, 3, 6, 2, 6, 5, 4, 5, 4, 6, 8, // 0
+    2, 8, 4, 5, 4, 5, 5, 6, 5, 5, 6, 5, 2, 2, 4, 6, // 1
+    2, 8, 4, 5, 3, 4, 3, 6, 2, 6, 5, 4, 5, 4, 5, 4, // 2
+    2, 8, 4, 5, 4, 5, 5, 6, 5, 5, 6, 5, 2, 2, 3, 8, // 3
+    2, 8, 4, 5, 3, 4, 3, 6, 2, 6, 4, 4, 5, 4, 6, 6, // 4
+    2, 8, 4, 5, 4, 5, 5, 6, 5, 5, 4, 5, 2, 2, 4, 3, // 5
+    2, 8, 4, 5, 3, 4, 3, 6, 2, 6, 4, 4, 5, 4, 5, 5, // 6
+    2, 8, 4, 5, 4, 5, 5, 6, 5, 5, 5, 5, 2, 2, 3, 6, // 7
+    2, 8, 4, 5, 3, 4, 3, 6, 2, 6, 5, 4, 5, 2, 4, 5, // 8
+    2, 8, 4, 5, 4, 5, 5, 6, 5, 5, 5, 5, 2, 2, 12, 5, // 9
+    3, 8, 4, 5, 3, 4, 3, 6, 2, 6, 4, 4, 5, 2, 4, 4, // A
+    2, 8, 4, 5, 4, 5, 5, 6, 5, 5, 5, 5, 2, 2, 3, 4, // B
+    3, 8, 4, 5, 4, 5, 4, 7, 2, 5, 6, 4, 5, 2, 4, 9, // C
+    2, 8, 4, 5, 5, 6, 6, 7, 4, 5, 5, 5, 2, 2, 6, 3, // D
+    2, 8, 4, 5, 3, 4, 3, 6, 2, 4, 5, 3, 4, 3, 4, 3, // E
+    2, 8, 4, 5, 4, 5, 5, 6, 3, 4, 5, 4, 2, 2, 4, 3, // F
+];
+
+pub struct SPC700 {
+    a: u8,
+    x: u8,
+    y: u8,
+    sp: u8,
+    pc: u16,
+    psw: u8,
+
+    // SLEEP and STOP halt the core until reset
+    stopped: bool,
+}
+
+impl SPC700 {
+    pub fn new() -> SPC700 {
+        SPC700 {
+            a: 0,
+            x: 0,
+            y: 0,
+            sp: 0xEF,
+            pc: 0xFFC0,
+            psw: 0x02,
+
+            stopped: false,
+        }
+    }
+
+    // the reset vector at $FFFE points into the IPL rom
+    pub fn reset(&mut self, bus: &mut SpcBus) {
+        self.stopped = false;
+        self.psw = 0x02;
+        self.pc = bus.read_16(0xFFFE);
+    }
+
+    pub fn a(&self) -> u8 { self.a }
+    pub fn x(&self) -> u8 { self.x }
+    pub fn y(&self) -> u8 { self.y }
+    pub fn sp(&self) -> u8 { self.sp }
+    pub fn pc(&self) -> u16 { self.pc }
+    pub fn psw(&self) -> u8 { self.psw }
+
+    pub fn set_registers(&mut self, pc: u16, a: u8, x: u8, y: u8, psw: u8, sp: u8) {
+        self.pc = pc;
+        self.a = a;
+        self.x = x;
+        self.y = y;
+        self.psw = psw;
+        self.sp = sp;
+        self.stopped = false;
+    }
+
+    pub fn is_stopped(&self) -> bool {
+        self.stopped
+    }
+
+    pub fn save_state(&self, w: &mut StateWriter) {
+        w.u16(self.pc);
+        w.u8(self.a);
+        w.u8(self.x);
+        w.u8(self.y);
+        w.u8(self.psw);
+        w.u8(self.sp);
+        w.bool(self.stopped);
+    }
+
+    pub fn load_state(&mut self, r: &mut StateReader) -> Result<(), Error> {
+        self.pc = r.u16()?;
+        self.a = r.u8()?;
+        self.x = r.u8()?;
+        self.y = r.u8()?;
+        self.psw = r.u8()?;
+        self.sp = r.u8()?;
+        self.stopped = r.bool()?;
+        Ok(())
+    }
+
+    ////////////////////////////////////
+    //
+    //          FLAG HELPERS
+    //
+    ////////////////////////////////////
+
+    fn flag(&self, flag: u8) -> bool {
+        self.psw & flag != 0
+    }
+
+    fn set_flag(&mut self, flag: u8, enabled: bool) {
+        if enabled {
+            self.psw |= flag;
+        } else {
+            self.psw &= !flag;
+        }
+    }
+
+    fn set_nz(&mut self, data: u8) {
+        self.set_flag(FLAG_N, data & 0x80 != 0);
+        self.set_flag(FLAG_Z, data == 0);
+    }
+
+    fn set_nz_16(&mut self, data: u16) {
+        self.set_flag(FLAG_N, data & 0x8000 != 0);
+        self.set_flag(FLAG_Z, data == 0);
+    }
+
+    fn ya(&self) -> u16 {
+        ((self.y as u16) << 8) | self.a as u16
+    }
+
+    fn set_ya(&mut self, ya: u16) {
+        self.y = (ya >> 8) as u8;
+        self.a = ya as u8;
+    }
+
+    ////////////////////////////////////
+    //
+    //            ADDRESSING
+    //
+    ////////////////////////////////////
+
+    fn next_b(&mut self, bus: &mut SpcBus) -> u8 {
+        let data = bus.read(self.pc);
+        self.pc = self.pc.wrapping_add(1);
+        data
+    }
+
+    fn next_w(&mut self, bus: &mut SpcBus) -> u16 {
+        let lo = self.next_b(bus) as u16;
+        let hi = self.next_b(bus) as u16;
+        (hi << 8) | lo
+    }
+
+    fn dp(&self, offset: u8) -> u16 {
+        let page = if self.flag(FLAG_P) { 0x0100 } else { 0x0000 };
+        page | offset as u16
+    }
+
+    // reads a 16 bit word from the direct page. the high byte wraps within the page
+    fn read_dp_16(&self, bus: &mut SpcBus, offset: u8) -> u16 {
+        let lo = bus.read(self.dp(offset)) as u16;
+        let hi = bus.read(self.dp(offset.wrapping_add(1))) as u16;
+        (hi << 8) | lo
+    }
+
+    fn write_dp_16(&self, bus: &mut SpcBus, offset: u8, data: u16) {
+        bus.write(self.dp(offset), data as u8);
+        bus.write(self.dp(offset.wrapping_add(1)), (data >> 8) as u8);
+    }
+
+    // the effective address for the A register alu/mov opcodes. the column of the
+    // opcode picks the addressing mode and the odd rows are the indexed variants
+    fn alu_address(&mut self, bus: &mut SpcBus, opcode: u8) -> u16 {
+        match opcode & 0x1F {
+            0x04 => { let d = self.next_b(bus); self.dp(d) },                                // dp
+            0x14 => { let d = self.next_b(bus); self.dp(d.wrapping_add(self.x)) },           // dp+X
+            0x05 => self.next_w(bus),                                                        // !abs
+            0x15 => self.next_w(bus).wrapping_add(self.x as u16),                            // !abs+X
+            0x06 => self.dp(self.x),                                                         // (X)
+            0x16 => self.next_w(bus).wrapping_add(self.y as u16),                            // !abs+Y
+            0x07 => { let d = self.next_b(bus).wrapping_add(self.x); self.read_dp_16(bus, d) }, // [dp+X]
+            0x17 => { let d = self.next_b(bus); self.read_dp_16(bus, d).wrapping_add(self.y as u16) }, // [dp]+Y
+            _ => panic!("SPC700 opcode {:02X} has no alu addressing mode", opcode),
+        }
+    }
+
+    // mem.bit operands pack a 13 bit address and a 3 bit bit number
+    fn mem_bit(&mut self, bus: &mut SpcBus) -> (u16, u8) {
+        let operand = self.next_w(bus);
+        (operand & 0x1FFF, (operand >> 13) as u8)
+    }
+
+    fn push(&mut self, bus: &mut SpcBus, data: u8) {
+        bus.write(0x0100 | self.sp as u16, data);
+        self.sp = self.sp.wrapping_sub(1);
+    }
+
+    fn pop(&mut self, bus: &mut SpcBus) -> u8 {
+        self.sp = self.sp.wrapping_add(1);
+        bus.read(0x0100 | self.sp as u16)
+    }
+
+    fn push_16(&mut self, bus: &mut SpcBus, data: u16) {
+        self.push(bus, (data >> 8) as u8);
+        self.push(bus, data as u8);
+    }
+
+    fn pop_16(&mut self, bus: &mut SpcBus) -> u16 {
+        let lo = self.pop(bus) as u16;
+        let hi = self.pop(bus) as u16;
+        (hi << 8) | lo
+    }
+
+    fn branch(&mut self, bus: &mut SpcBus, condition: bool) -> u8 {
+        let offset = self.next_b(bus) as i8;
+
+        if condition {
+            self.pc = self.pc.wrapping_add(offset as i16 as u16);
+            2
+        } else {
+            0
+        }
+    }
+
+    ////////////////////////////////////
+    //
+    //               ALU
+    //
+    ////////////////////////////////////
+
+    fn or(&mut self, a: u8, b: u8) -> u8 {
+        let result = a | b;
+        self.set_nz(result);
+        result
+    }
+
+    fn and(&mut self, a: u8, b: u8) -> u8 {
+        let result = a & b;
+        self.set_nz(result);
+        result
+    }
+
+    fn eor(&mut self, a: u8, b: u8) -> u8 {
+        let result = a ^ b;
+        self.set_nz(result);
+        result
+    }
+
+    fn cmp(&mut self, a: u8, b: u8) -> u8 {
+        let result = (a as i16) - (b as i16);
+        self.set_flag(FLAG_C, result >= 0);
+        self.set_nz(result as u8);
+        a
+    }
+
+    fn adc(&mut self, a: u8, b: u8) -> u8 {
+        let carry = if self.flag(FLAG_C) { 1 } else { 0 };
+        let result = (a as u16) + (b as u16) + carry;
+        let r = result as u8;
+
+        self.set_flag(FLAG_V, (!(a ^ b) & (a ^ r) & 0x80) != 0);
+        self.set_flag(FLAG_H, ((a ^ b ^ r) & 0x10) != 0);
+        self.set_flag(FLAG_C, result > 0xFF);
+        self.set_nz(r);
+        r
+    }
+
+    fn sbc(&mut self, a: u8, b: u8) -> u8 {
+        self.adc(a, !b)
+    }
+
+    // the alu operation is picked by the high 3 bits of the opcode
+    fn alu(&mut self, opcode: u8, a: u8, b: u8) -> u8 {
+        match opcode >> 5 {
+            0 => self.or(a, b),
+            1 => self.and(a, b),
+            2 => self.eor(a, b),
+            3 => self.cmp(a, b),
+            4 => self.adc(a, b),
+            5 => self.sbc(a, b),
+            _ => panic!("SPC700 opcode {:02X} is not an alu opcode", opcode),
+        }
+    }
+
+    fn asl(&mut self, data: u8) -> u8 {
+        self.set_flag(FLAG_C, data & 0x80 != 0);
+        let result = data << 1;
+        self.set_nz(result);
+        result
+    }
+
+    fn rol(&mut self, data: u8) -> u8 {
+        let carry = if self.flag(FLAG_C) { 1 } else { 0 };
+        self.set_flag(FLAG_C, data & 0x80 != 0);
+        let result = (data << 1) | carry;
+        self.set_nz(result);
+        result
+    }
+
+    fn lsr(&mut self, data: u8) -> u8 {
+        self.set_flag(FLAG_C, data & 0x01 != 0);
+        let result = data >> 1;
+        self.set_nz(result);
+        result
+    }
+
+    fn ror(&mut self, data: u8) -> u8 {
+        let carry = if self.flag(FLAG_C) { 0x80 } else { 0 };
+        self.set_flag(FLAG_C, data & 0x01 != 0);
+        let result = (data >> 1) | carry;
+        self.set_nz(result);
+        result
+    }
+
+    fn dec(&mut self, data: u8) -> u8 {
+        let result = data.wrapping_sub(1);
+        self.set_nz(result);
+        result
+    }
+
+    fn inc(&mut self, data: u8) -> u8 {
+        let result = data.wrapping_add(1);
+        self.set_nz(result);
+        result
+    }
+
+    // the read-modify-write opcode is picked by the high 3 bits of the opcode
+    fn shift(&mut self, opcode: u8, data: u8) -> u8 {
+        match opcode >> 5 {
+            0 => self.asl(data),
+            1 => self.rol(data),
+            2 => self.lsr(data),
+            3 => self.ror(data),
+            4 => self.dec(data),
+            5 => self.inc(data),
+            _ => panic!("SPC700 opcode {:02X} is not a shift opcode", opcode),
+        }
+    }
+
+    ////////////////////////////////////
+    //
+    //             EXECUTE
+    //
+    ////////////////////////////////////
+
+    // runs one instruction and returns the number of cycles it took
+    pub fn step(&mut self, bus: &mut SpcBus) -> u8 {
+        if self.stopped {
+            bus.add_cycles(2);
+            return 2;
+        }
+
+        bus.begin_instruction(self.pc);
+        let opcode = self.next_b(bus);
+        let extra = self.execute(bus, opcode);
+
+        let cycles = CYCLES[opcode as usize] + extra;
+        bus.add_cycles(cycles as u32);
+        cycles
+    }
+
+    fn execute(&mut self, bus: &mut SpcBus, opcode: u8) -> u8 {
+        match opcode {
+            // A op memory, for OR AND EOR CMP ADC SBC
+            0x04 | 0x05 | 0x06 | 0x07 | 0x14 | 0x15 | 0x16 | 0x17 |
+            0x24 | 0x25 | 0x26 | 0x27 | 0x34 | 0x35 | 0x36 | 0x37 |
+            0x44 | 0x45 | 0x46 | 0x47 | 0x54 | 0x55 | 0x56 | 0x57 |
+            0x64 | 0x65 | 0x66 | 0x67 | 0x74 | 0x75 | 0x76 | 0x77 |
+            0x84 | 0x85 | 0x86 | 0x87 | 0x94 | 0x95 | 0x96 | 0x97 |
+            0xA4 | 0xA5 | 0xA6 | 0xA7 | 0xB4 | 0xB5 | 0xB6 | 0xB7 => {
+                let addr = self.alu_address(bus, opcode);
+                let data = bus.read(addr);
+                let a = self.a;
+                self.a = self.alu(opcode, a, data);
+            },
+
+            // A op #imm
+            0x08 | 0x28 | 0x48 | 0x68 | 0x88 | 0xA8 => {
+                let data = self.next_b(bus);
+                let a = self.a;
+                self.a = self.alu(opcode, a, data);
+            },
+
+            // dp op dp
+            0x09 | 0x29 | 0x49 | 0x69 | 0x89 | 0xA9 => {
+                let src = self.next_b(bus);
+                let dst = self.next_b(bus);
+                let b = bus.read(self.dp(src));
+                let a = bus.read(self.dp(dst));
+                let result = self.alu(opcode, a, b);
+                if opcode != 0x69 {
+                    bus.write(self.dp(dst), result);
+                }
+            },
+
+            // dp op #imm
+            0x18 | 0x38 | 0x58 | 0x78 | 0x98 | 0xB8 => {
+                let imm = self.next_b(bus);
+                let dst = self.next_b(bus);
+                let a = bus.read(self.dp(dst));
+                let result = self.alu(opcode, a, imm);
+                if opcode != 0x78 {
+                    bus.write(self.dp(dst), result);
+                }
+            },
+
+            // (X) op (Y)
+            0x19 | 0x39 | 0x59 | 0x79 | 0x99 | 0xB9 => {
+                let b = bus.read(self.dp(self.y));
+                let a = bus.read(self.dp(self.x));
+                let result = self.alu(opcode, a, b);
+                if opcode != 0x79 {
+                    bus.write(self.dp(self.x), result);
+                }
+            },
+
+            // compare X / Y
+            0xC8 => { let data = self.next_b(bus); let x = self.x; self.cmp(x, data); },
+            0x3E => { let d = self.next_b(bus); let data = bus.read(self.dp(d)); let x = self.x; self.cmp(x, data); },
+            0x1E => { let addr = self.next_w(bus); let data = bus.read(addr); let x = self.x; self.cmp(x, data); },
+            0xAD => { let data = self.next_b(bus); let y = self.y; self.cmp(y, data); },
+            0x7E => { let d = self.next_b(bus); let data = bus.read(self.dp(d)); let y = self.y; self.cmp(y, data); },
+            0x5E => { let addr = self.next_w(bus); let data = bus.read(addr); let y = self.y; self.cmp(y, data); },
+
+            // shift / rotate / inc / dec on memory
+            0x0B | 0x2B | 0x4B | 0x6B | 0x8B | 0xAB => {
+                let d = self.next_b(bus);
+                let addr = self.dp(d);
+                let data = bus.read(addr);
+                let result = self.shift(opcode, data);
+                bus.write(addr, result);
+            },
+            0x1B | 0x3B | 0x5B | 0x7B | 0x9B | 0xBB => {
+                let d = self.next_b(bus);
+                let addr = self.dp(d.wrapping_add(self.x));
+                let data = bus.read(addr);
+                let result = self.shift(opcode, data);
+                bus.write(addr, result);
+            },
+            0x0C | 0x2C | 0x4C | 0x6C | 0x8C | 0xAC => {
+                let addr = self.next_w(bus);
+                let data = bus.read(addr);
+                let result = self.shift(opcode, data);
+                bus.write(addr, result);
+            },
+
+            // shift / rotate / inc / dec on A
+            0x1C | 0x3C | 0x5C | 0x7C | 0x9C | 0xBC => {
+                let a = self.a;
+                self.a = self.shift(opcode, a);
+            },
+
+            0x1D => { let x = self.x; self.x = self.dec(x); },
+            0x3D => { let x = self.x; self.x = self.inc(x); },
+            0xDC => { let y = self.y; self.y = self.dec(y); },
+            0xFC => { let y = self.y; self.y = self.inc(y); },
+
+            // mov memory <- register
+            0xC4 | 0xD4 | 0xC5 | 0xD5 | 0xC6 | 0xD6 | 0xC7 | 0xD7 => {
+                let addr = self.alu_address(bus, opcode);
+                // the hardware performs a dummy read before the write
+                bus.read(addr);
+                bus.write(addr, self.a);
+            },
+            0xD8 => { let d = self.next_b(bus); bus.write(self.dp(d), self.x); },
+            0xD9 => { let d = self.next_b(bus); bus.write(self.dp(d.wrapping_add(self.y)), self.x); },
+            0xC9 => { let addr = self.next_w(bus); bus.write(addr, self.x); },
+            0xCB => { let d = self.next_b(bus); bus.write(self.dp(d), self.y); },
+            0xDB => { let d = self.next_b(bus); bus.write(self.dp(d.wrapping_add(self.x)), self.y); },
+            0xCC => { let addr = self.next_w(bus); bus.write(addr, self.y); },
+            0xAF => {
+                let addr = self.dp(self.x);
+                bus.write(addr, self.a);
+                self.x = self.x.wrapping_add(1);
+            },
+
+            // mov register <- memory
+            0xE4 | 0xF4 | 0xE5 | 0xF5 | 0xE6 | 0xF6 | 0xE7 | 0xF7 => {
+                let addr = self.alu_address(bus, opcode);
+                self.a = bus.read(addr);
+                let a = self.a;
+                self.set_nz(a);
+            },
+            0xE8 => { self.a = self.next_b(bus); let a = self.a; self.set_nz(a); },
+            0xBF => {
+                let addr = self.dp(self.x);
+                self.a = bus.read(addr);
+                self.x = self.x.wrapping_add(1);
+                let a = self.a;
+                self.set_nz(a);
+            },
+            0xCD => { self.x = self.next_b(bus); let x = self.x; self.set_nz(x); },
+            0xF8 => { let d = self.next_b(bus); self.x = bus.read(self.dp(d)); let x = self.x; self.set_nz(x); },
+            0xF9 => { let d = self.next_b(bus); self.x = bus.read(self.dp(d.wrapping_add(self.y))); let x = self.x; self.set_nz(x); },
+            0xE9 => { let addr = self.next_w(bus); self.x = bus.read(addr); let x = self.x; self.set_nz(x); },
+            0x8D => { self.y = self.next_b(bus); let y = self.y; self.set_nz(y); },
+            0xEB => { let d = self.next_b(bus); self.y = bus.read(self.dp(d)); let y = self.y; self.set_nz(y); },
+            0xFB => { let d = self.next_b(bus); self.y = bus.read(self.dp(d.wrapping_add(self.x))); let y = self.y; self.set_nz(y); },
+            0xEC => { let addr = self.next_w(bus); self.y = bus.read(addr); let y = self.y; self.set_nz(y); },
+
+            // mov memory <- memory / immediate
+            0xFA => {
+                let src = self.next_b(bus);
+                let dst = self.next_b(bus);
+                let data = bus.read(self.dp(src));
+                bus.write(self.dp(dst), data);
+            },
+            0x8F => {
+                let imm = self.next_b(bus);
+                let dst = self.next_b(bus);
+                bus.write(self.dp(dst), imm);
+            },
+
+            // register transfers
+            0x5D => { self.x = self.a; let x = self.x; self.set_nz(x); },
+            0x7D => { self.a = self.x; let a = self.a; self.set_nz(a); },
+            0xDD => { self.a = self.y; let a = self.a; self.set_nz(a); },
+            0xFD => { self.y = self.a; let y = self.y; self.set_nz(y); },
+            0x9D => { self.x = self.sp; let x = self.x; self.set_nz(x); },
+            0xBD => { self.sp = self.x; },
+
+            // 16 bit
+            0xBA => {
+                let d = self.next_b(bus);
+                let data = self.read_dp_16(bus, d);
+                self.set_ya(data);
+                self.set_nz_16(data);
+            },
+            0xDA => {
+                let d = self.next_b(bus);
+                let ya = self.ya();
+                self.write_dp_16(bus, d, ya);
+            },
+            0x1A | 0x3A => {
+                let d = self.next_b(bus);
+                let data = self.read_dp_16(bus, d);
+                let result = if opcode == 0x1A { data.wrapping_sub(1) } else { data.wrapping_add(1) };
+                self.write_dp_16(bus, d, result);
+                self.set_nz_16(result);
+            },
+            0x5A => {
+                let d = self.next_b(bus);
+                let data = self.read_dp_16(bus, d);
+                let ya = self.ya();
+                self.set_flag(FLAG_C, ya >= data);
+                self.set_nz_16(ya.wrapping_sub(data));
+            },
+            0x7A | 0x9A => {
+                let d = self.next_b(bus);
+                let mut data = self.read_dp_16(bus, d);
+
+                // ADDW/SUBW behave like two chained 8 bit ADC/SBC
+                if opcode == 0x9A {
+                    data = !data;
+                    self.set_flag(FLAG_C, true);
+                } else {
+                    self.set_flag(FLAG_C, false);
+                }
+
+                let (a, y) = (self.a, self.y);
+                let lo = self.adc(a, data as u8);
+                let hi = self.adc(y, (data >> 8) as u8);
+                let result = ((hi as u16) << 8) | lo as u16;
+                self.set_ya(result);
+                self.set_flag(FLAG_Z, result == 0);
+            },
+            0xCF => {
+                let result = (self.y as u16) * (self.a as u16);
+                self.set_ya(result);
+                let y = self.y;
+                self.set_nz(y);
+            },
+            0x9E => self.div(),
+
+            // decimal adjust
+            0xDF => {
+                if self.flag(FLAG_C) || self.a > 0x99 {
+                    self.a = self.a.wrapping_add(0x60);
+                    self.set_flag(FLAG_C, true);
+                }
+                if self.flag(FLAG_H) || (self.a & 0x0F) > 0x09 {
+                    self.a = self.a.wrapping_add(0x06);
+                }
+                let a = self.a;
+                self.set_nz(a);
+            },
+            0xBE => {
+                if !self.flag(FLAG_C) || self.a > 0x99 {
+                    self.a = self.a.wrapping_sub(0x60);
+                    self.set_flag(FLAG_C, false);
+                }
+                if !self.flag(FLAG_H) || (self.a & 0x0F) > 0x09 {
+                    self.a = self.a.wrapping_sub(0x06);
+                }
+                let a = self.a;
+                self.set_nz(a);
+            },
+            0x9F => {
+                self.a = self.a.rotate_left(4);
+                let a = self.a;
+                self.set_nz(a);
+            },
+
+            // single bit operations
+            0x02 | 0x22 | 0x42 | 0x62 | 0x82 | 0xA2 | 0xC2 | 0xE2 |
+            0x12 | 0x32 | 0x52 | 0x72 | 0x92 | 0xB2 | 0xD2 | 0xF2 => {
+                let d = self.next_b(bus);
+                let addr = self.dp(d);
+                let bit = 1 << (opcode >> 5);
+                let data = bus.read(addr);
+                let result = if opcode & 0x10 == 0 { data | bit } else { data & !bit };
+                bus.write(addr, result);
+            },
+            0x0E | 0x4E => {
+                let addr = self.next_w(bus);
+                let data = bus.read(addr);
+                let a = self.a;
+                self.set_nz(a.wrapping_sub(data));
+                let result = if opcode == 0x0E { data | a } else { data & !a };
+                bus.write(addr, result);
+            },
+            0x0A | 0x2A | 0x4A | 0x6A | 0x8A | 0xAA => {
+                let (addr, bit) = self.mem_bit(bus);
+                let mut set = (bus.read(addr) >> bit) & 1 != 0;
+                // the odd columns use the complement of the bit
+                if opcode == 0x2A || opcode == 0x6A {
+                    set = !set;
+                }
+
+                let carry = self.flag(FLAG_C);
+                let result = match opcode {
+                    0x0A | 0x2A => carry || set,
+                    0x4A | 0x6A => carry && set,
+                    0x8A => carry != set,
+                    _ => set,
+                };
+                self.set_flag(FLAG_C, result);
+            },
+            0xCA => {
+                let (addr, bit) = self.mem_bit(bus);
+                let data = bus.read(addr) & !(1 << bit);
+                let carry = if self.flag(FLAG_C) { 1 << bit } else { 0 };
+                bus.write(addr, data | carry);
+            },
+            0xEA => {
+                let (addr, bit) = self.mem_bit(bus);
+                let data = bus.read(addr) ^ (1 << bit);
+                bus.write(addr, data);
+            },
+
+            // flags
+            0x20 => self.set_flag(FLAG_P, false),
+            0x40 => self.set_flag(FLAG_P, true),
+            0x60 => self.set_flag(FLAG_C, false),
+            0x80 => self.set_flag(FLAG_C, true),
+            0xA0 => self.set_flag(FLAG_I, true),
+            0xC0 => self.set_flag(FLAG_I, false),
+            0xE0 => {
+                self.set_flag(FLAG_V, false);
+                self.set_flag(FLAG_H, false);
+            },
+            0xED => {
+                let carry = self.flag(FLAG_C);
+                self.set_flag(FLAG_C, !carry);
+            },
+
+            // branches
+            0x10 => { let n = !self.flag(FLAG_N); return self.branch(bus, n); },
+            0x30 => { let n = self.flag(FLAG_N); return self.branch(bus, n); },
+            0x50 => { let v = !self.flag(FLAG_V); return self.branch(bus, v); },
+            0x70 => { let v = self.flag(FLAG_V); return self.branch(bus, v); },
+            0x90 => { let c = !self.flag(FLAG_C); return self.branch(bus, c); },
+            0xB0 => { let c = self.flag(FLAG_C); return self.branch(bus, c); },
+            0xD0 => { let z = !self.flag(FLAG_Z); return self.branch(bus, z); },
+            0xF0 => { let z = self.flag(FLAG_Z); return self.branch(bus, z); },
+            // BRA's base cycle count already includes the taken branch
+            0x2F => { self.branch(bus, true); },
+
+            0x03 | 0x23 | 0x43 | 0x63 | 0x83 | 0xA3 | 0xC3 | 0xE3 |
+            0x13 | 0x33 | 0x53 | 0x73 | 0x93 | 0xB3 | 0xD3 | 0xF3 => {
+                let d = self.next_b(bus);
+                let data = bus.read(self.dp(d));
+                let set = data & (1 << (opcode >> 5)) != 0;
+                let condition = if opcode & 0x10 == 0 { set } else { !set };
+                return self.branch(bus, condition);
+            },
+            0x2E => {
+                let d = self.next_b(bus);
+                let data = bus.read(self.dp(d));
+                let condition = data != self.a;
+                return self.branch(bus, condition);
+            },
+            0xDE => {
+                let d = self.next_b(bus);
+                let data = bus.read(self.dp(d.wrapping_add(self.x)));
+                let condition = data != self.a;
+                return self.branch(bus, condition);
+            },
+            0x6E => {
+                let d = self.next_b(bus);
+                let addr = self.dp(d);
+                let data = bus.read(addr).wrapping_sub(1);
+                bus.write(addr, data);
+                return self.branch(bus, data != 0);
+            },
+            0xFE => {
+                self.y = self.y.wrapping_sub(1);
+                let condition = self.y != 0;
+                return self.branch(bus, condition);
+            },
+
+            // jumps / calls
+            0x5F => self.pc = self.next_w(bus),
+            0x1F => {
+                let addr = self.next_w(bus).wrapping_add(self.x as u16);
+                self.pc = bus.read_16(addr);
+            },
+            0x3F => {
+                let addr = self.next_w(bus);
+                let pc = self.pc;
+                self.push_16(bus, pc);
+                self.pc = addr;
+            },
+            0x4F => {
+                let offset = self.next_b(bus);
+                let pc = self.pc;
+                self.push_16(bus, pc);
+                self.pc = 0xFF00 | offset as u16;
+            },
+            0x01 | 0x11 | 0x21 | 0x31 | 0x41 | 0x51 | 0x61 | 0x71 |
+            0x81 | 0x91 | 0xA1 | 0xB1 | 0xC1 | 0xD1 | 0xE1 | 0xF1 => {
+                // TCALL n jumps through the table at $FFDE - 2n
+                let vector = 0xFFDE - ((opcode >> 4) as u16) * 2;
+                let pc = self.pc;
+                self.push_16(bus, pc);
+                self.pc = bus.read_16(vector);
+            },
+            0x0F => {
+                let pc = self.pc;
+                self.push_16(bus, pc);
+                let psw = self.psw;
+                self.push(bus, psw);
+                self.set_flag(FLAG_B, true);
+                self.set_flag(FLAG_I, false);
+                self.pc = bus.read_16(0xFFDE);
+            },
+            0x6F => self.pc = self.pop_16(bus),
+            0x7F => {
+                self.psw = self.pop(bus);
+                self.pc = self.pop_16(bus);
+            },
+
+            // stack
+            0x0D => { let psw = self.psw; self.push(bus, psw); },
+            0x2D => { let a = self.a; self.push(bus, a); },
+            0x4D => { let x = self.x; self.push(bus, x); },
+            0x6D => { let y = self.y; self.push(bus, y); },
+            0x8E => self.psw = self.pop(bus),
+            0xAE => self.a = self.pop(bus),
+            0xCE => self.x = self.pop(bus),
+            0xEE => self.y = self.pop(bus),
+
+            0x00 => {},
+            0xEF | 0xFF => self.stopped = true,
+        }
+
+        0
+    }
+
+    // DIV YA,X. results that don't fit in 8 bits follow the hardware's odd overflow behaviour
+    fn div(&mut self) {
+        let ya = self.ya() as u32;
+        let x = self.x as u32;
+
+        self.set_flag(FLAG_H, (self.y & 0x0F) >= (self.x & 0x0F));
+        self.set_flag(FLAG_V, self.y as u32 >= x);
+
+        if (self.y as u32) < (x << 1) {
+            self.a = (ya / x) as u8;
+            self.y = (ya % x) as u8;
+        } else {
+            self.a = (255 - (ya - (x << 9)) / (256 - x)) as u8;
+            self.y = (x + (ya - (x << 9)) % (256 - x)) as u8;
+        }
+
+        let a = self.a;
+        self.set_nz(a);
+    }
+}
@@ -0,0 +1,371 @@
+use std::io::Error;
+
+use apu::ARAM;
+use apu::dsp::DSP;
+use bus::watch::{Access, Hit, Space, Watcher, Watchpoint};
+use savestate::{StateReader, StateWriter};
+
+// the 64 byte boot rom mapped at $FFC0 while CONTROL bit 7 is set.
+// it clears the zero page, signals $AA/$BB on ports 0/1 and then waits for the
+// main CPU to upload data through the ports before jumping to it
+pub const IPL_ROM: [u8; 64] = [
+    0xCD, 0xEF, 0xBD, 0xE8, 0x00, 0xC6, 0x1D, 0xD0, 0xFC, 0x8F, 0xAA, 0xF4, 0x8F, 0xBB, 0xF5, 0x78,
+    0xCC, 0xF4, 0xD0, 0xFB, 0x2F, 0x19, 0xEB, 0xF4, 0xD0, 0xFC, 0x7E, 0xF4, 0xD0, 0x0B, 0xE4, 0xF5,
+    0xCB, 0xF4, 0xD7, 0x00, 0xFC, 0xD0, 0xF3, 0xAB, 0x01, 0x10, 0xEF, 0x7E, 0xF4, 0x10, 0xEB, 0xBA,
+    0xF6, 0xDA, 0x00, 0xBA, 0xF4, 0xC4, 0xF4, 0xDD, 0x5D, 0xD0, 0xDB, 0x1F, 0x00, 0x00, 0xC0, 0xFF,
+];
+
+const IPL_ROM_START: u16 = 0xFFC0;
+
+// SPC700 cycles per DSP sample (1.024 MHz / 32 kHz)
+const CYCLES_PER_SAMPLE: u32 = 32;
+
+// timers 0 and 1 tick at 8 kHz, timer 2 at 64 kHz
+const TIMER_PERIODS: [u32; 3] = [128, 128, 16];
+
+// io registers at $F0-$FF
+const TEST: u16     = 0xF0;
+const CONTROL: u16  = 0xF1;
+const DSPADDR: u16  = 0xF2;
+const DSPDATA: u16  = 0xF3;
+const CPUIO0: u16   = 0xF4;
+const CPUIO3: u16   = 0xF7;
+const T0TARGET: u16 = 0xFA;
+const T2TARGET: u16 = 0xFC;
+const T0OUT: u16    = 0xFD;
+const T2OUT: u16    = 0xFF;
+
+#[derive(Clone, Copy)]
+pub struct Timer {
+    enabled: bool,
+    target: u8,  // 0 means 256
+    stage: u32,  // cycles until the next tick of the internal counter
+    counter: u8,
+    out: u8,     // 4 bit, cleared when read
+}
+
+impl Timer {
+    fn new() -> Timer {
+        Timer {
+            enabled: false,
+            target: 0,
+            stage: 0,
+            counter: 0,
+            out: 0,
+        }
+    }
+
+    fn tick(&mut self) {
+        self.counter = self.counter.wrapping_add(1);
+        if self.counter == self.target {
+            self.counter = 0;
+            self.out = (self.out + 1) & 0x0F;
+        }
+    }
+}
+
+// everything the SPC700 can see: ARAM, the io registers, the timers, the DSP and the IPL rom
+pub struct SpcBus {
+    aram: Box<ARAM>,
+    dsp: DSP,
+
+    control: u8,
+    dsp_addr: u8,
+
+    // $F4-$F7 on the APU side. ports_in is written by the main CPU, ports_out is read by it
+    ports_in: [u8; 4],
+    ports_out: [u8; 4],
+
+    timers: [Timer; 3],
+
+    cycles: u64,       // total SPC700 cycles elapsed
+    sample_stage: u32, // cycles until the next DSP sample
+
+    watcher: Watcher,
+}
+
+impl SpcBus {
+    pub fn new() -> SpcBus {
+        SpcBus {
+            aram: Box::new([0; 0x10000]),
+            dsp: DSP::new(),
+
+            control: 0x80,
+            dsp_addr: 0,
+
+            ports_in: [0; 4],
+            ports_out: [0; 4],
+
+            timers: [Timer::new(); 3],
+
+            cycles: 0,
+            sample_stage: CYCLES_PER_SAMPLE,
+
+            watcher: Watcher::new(),
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.control = 0x80;
+        self.dsp_addr = 0;
+        self.ports_in = [0; 4];
+        self.ports_out = [0; 4];
+        self.timers = [Timer::new(); 3];
+        self.sample_stage = CYCLES_PER_SAMPLE;
+        self.dsp.reset();
+    }
+
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    pub fn aram(&self) -> &ARAM {
+        &self.aram
+    }
+
+    pub fn aram_mut(&mut self) -> &mut ARAM {
+        &mut self.aram
+    }
+
+    pub fn dsp(&self) -> &DSP {
+        &self.dsp
+    }
+
+    pub fn dsp_mut(&mut self) -> &mut DSP {
+        &mut self.dsp
+    }
+
+    // restores the io registers from the values stored at $F0-$FF in a snapshot of ARAM
+    pub fn load_snapshot(&mut self, aram: &ARAM, extra_ram: &[u8; 0x40]) {
+        self.aram.copy_from_slice(&aram[..]);
+        self.aram[IPL_ROM_START as usize..].copy_from_slice(extra_ram);
+
+        let control = aram[CONTROL as usize];
+        for (index, timer) in self.timers.iter_mut().enumerate() {
+            *timer = Timer::new();
+            timer.enabled = control & (1 << index) != 0;
+            timer.target = aram[(T0TARGET as usize) + index];
+            timer.out = aram[(T0OUT as usize) + index] & 0x0F;
+        }
+
+        self.control = control & 0x87;
+        self.dsp_addr = aram[DSPADDR as usize];
+
+        for port in 0..4 {
+            self.ports_in[port] = aram[(CPUIO0 as usize) + port];
+            self.ports_out[port] = aram[(CPUIO0 as usize) + port];
+        }
+    }
+
+    // ARAM with the io registers filled in, plus the RAM under the IPL rom, as stored in a snapshot
+    pub fn snapshot(&self) -> (Box<ARAM>, [u8; 0x40]) {
+        let mut aram = self.aram.clone();
+
+        aram[CONTROL as usize] = self.control;
+        aram[DSPADDR as usize] = self.dsp_addr;
+        aram[DSPDATA as usize] = self.dsp.read(self.dsp_addr);
+        for port in 0..4 {
+            aram[(CPUIO0 as usize) + port] = self.ports_in[port];
+        }
+        for (index, timer) in self.timers.iter().enumerate() {
+            aram[(T0TARGET as usize) + index] = timer.target;
+            aram[(T0OUT as usize) + index] = timer.out;
+        }
+
+        let mut extra_ram = [0; 0x40];
+        extra_ram.copy_from_slice(&self.aram[IPL_ROM_START as usize..]);
+
+        (aram, extra_ram)
+    }
+
+    // the DSP is saved separately
+    pub fn save_state(&self, w: &mut StateWriter) {
+        w.compressed(&self.aram[..]);
+        w.u8(self.control);
+        w.u8(self.dsp_addr);
+        w.bytes(&self.ports_in);
+        w.bytes(&self.ports_out);
+
+        for timer in self.timers.iter() {
+            w.bool(timer.enabled);
+            w.u8(timer.target);
+            w.u32(timer.stage);
+            w.u8(timer.counter);
+            w.u8(timer.out);
+        }
+
+        w.u64(self.cycles);
+        w.u32(self.sample_stage);
+    }
+
+    pub fn load_state(&mut self, r: &mut StateReader) -> Result<(), Error> {
+        r.compressed(&mut self.aram[..])?;
+        self.control = r.u8()?;
+        self.dsp_addr = r.u8()?;
+        r.bytes(&mut self.ports_in)?;
+        r.bytes(&mut self.ports_out)?;
+
+        for timer in self.timers.iter_mut() {
+            timer.enabled = r.bool()?;
+            timer.target = r.u8()?;
+            timer.stage = r.u32()?;
+            timer.counter = r.u8()?;
+            timer.out = r.u8()? & 0x0F;
+        }
+
+        self.cycles = r.u64()?;
+        self.sample_stage = r.u32()?;
+        Ok(())
+    }
+
+    ////////////////////////////////////
+    //
+    //            WATCHPOINTS
+    //
+    ////////////////////////////////////
+
+    // keeps the ones on ARAM. they see the SPC700's accesses, not the DSP's
+    pub fn set_watchpoints(&mut self, watchpoints: &[Watchpoint]) {
+        self.watcher.set_watchpoints(watchpoints, &[Space::ARAM]);
+    }
+
+    pub fn begin_instruction(&self, pc: u16) {
+        if !self.watcher.is_empty() {
+            let opcode = if pc >= IPL_ROM_START && self.control & 0x80 != 0 {
+                IPL_ROM[(pc - IPL_ROM_START) as usize]
+            } else {
+                self.aram[pc as usize]
+            };
+            self.watcher.begin_instruction(Space::ARAM, 0, pc, opcode);
+        }
+    }
+
+    pub fn take_watch_hits(&mut self) -> Vec<Hit> {
+        self.watcher.take_hits()
+    }
+
+    ////////////////////////////////////
+    //
+    //          MAIN CPU PORTS
+    //
+    ////////////////////////////////////
+
+    // the main CPU side of the ports: writes land in ports_in and reads come from ports_out
+    pub fn cpu_read_port(&self, port: usize) -> u8 {
+        self.ports_out[port & 3]
+    }
+
+    pub fn cpu_write_port(&mut self, port: usize, data: u8) {
+        self.ports_in[port & 3] = data;
+    }
+
+    ////////////////////////////////////
+    //
+    //              ACCESS
+    //
+    ////////////////////////////////////
+
+    pub fn read(&mut self, address: u16) -> u8 {
+        let data = match address {
+            TEST | CONTROL => 0x00, // write only
+            DSPADDR => self.dsp_addr,
+            DSPDATA => self.dsp.read(self.dsp_addr),
+            CPUIO0..=CPUIO3 => self.ports_in[(address - CPUIO0) as usize],
+            T0TARGET..=T2TARGET => 0x00, // write only
+            T0OUT..=T2OUT => {
+                let timer = &mut self.timers[(address - T0OUT) as usize];
+                let out = timer.out;
+                timer.out = 0;
+                out
+            },
+            _ if address >= IPL_ROM_START && self.control & 0x80 != 0 => {
+                IPL_ROM[(address - IPL_ROM_START) as usize]
+            },
+            _ => self.aram[address as usize],
+        };
+
+        if !self.watcher.is_empty() {
+            self.watcher.check(Space::ARAM, address as u32, Access::Read, data);
+        }
+        data
+    }
+
+    pub fn read_16(&mut self, address: u16) -> u16 {
+        let lo = self.read(address) as u16;
+        let hi = self.read(address.wrapping_add(1)) as u16;
+        (hi << 8) | lo
+    }
+
+    // writes to the io registers and the IPL area also go through to the ARAM underneath
+    pub fn write(&mut self, address: u16, data: u8) {
+        if !self.watcher.is_empty() {
+            self.watcher.check(Space::ARAM, address as u32, Access::Write, data);
+        }
+
+        match address {
+            CONTROL => self.write_control(data),
+            DSPADDR => self.dsp_addr = data,
+            DSPDATA => self.dsp.write(self.dsp_addr, data),
+            CPUIO0..=CPUIO3 => self.ports_out[(address - CPUIO0) as usize] = data,
+            T0TARGET..=T2TARGET => self.timers[(address - T0TARGET) as usize].target = data,
+            _ => {}
+        }
+
+        self.aram[address as usize] = data;
+    }
+
+    fn write_control(&mut self, data: u8) {
+        for (index, timer) in self.timers.iter_mut().enumerate() {
+            let enabled = data & (1 << index) != 0;
+
+            // a 0 -> 1 transition resets the timer
+            if enabled && !timer.enabled {
+                timer.counter = 0;
+                timer.out = 0;
+            }
+            timer.enabled = enabled;
+        }
+
+        // bits 4 and 5 clear the input latches for ports 0/1 and 2/3
+        if data & 0x10 != 0 {
+            self.ports_in[0] = 0;
+            self.ports_in[1] = 0;
+        }
+        if data & 0x20 != 0 {
+            self.ports_in[2] = 0;
+            self.ports_in[3] = 0;
+        }
+
+        self.control = data;
+    }
+
+    ////////////////////////////////////
+    //
+    //              CLOCK
+    //
+    ////////////////////////////////////
+
+    // advances the timers and DSP by the given number of SPC700 cycles
+    pub fn add_cycles(&mut self, cycles: u32) {
+        for _ in 0..cycles {
+            for (index, timer) in self.timers.iter_mut().enumerate() {
+                if timer.stage == 0 {
+                    timer.stage = TIMER_PERIODS[index];
+                }
+                timer.stage -= 1;
+
+                if timer.stage == 0 && timer.enabled {
+                    timer.tick();
+                }
+            }
+
+            self.sample_stage -= 1;
+            if self.sample_stage == 0 {
+                self.sample_stage = CYCLES_PER_SAMPLE;
+                self.dsp.run_sample(&mut self.aram);
+            }
+        }
+
+        self.cycles += cycles as u64;
+    }
+}
@@ -0,0 +1,402 @@
+use std::fs;
+use std::io::{Error, ErrorKind, Write};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use apu::{APU, ARAM, APU_CLOCK_HZ};
+use apu::wav;
+use cartridge::CartridgeHeader;
+
+// .spc snapshot layout
+// ====================
+// $00000  33 byte signature "SNES-SPC700 Sound File Data v0.30"
+// $00021  26, 26
+// $00023  26 if the ID666 tag is present, 27 if not
+// $00024  minor version (30)
+// $00025  PC, A, X, Y, PSW, SP
+// $0002E  ID666 tag
+// $00100  64KB of ARAM
+// $10100  128 DSP registers
+// $101C0  64 bytes of RAM hidden under the IPL rom
+const SIGNATURE: &'static [u8] = b"SNES-SPC700 Sound File Data v0.30";
+const HAS_ID666: u8 = 26;
+const NO_ID666: u8 = 27;
+
+const REGISTERS: usize = 0x25;
+const ID666_OFFSET: usize = 0x2E;
+const ARAM_OFFSET: usize = 0x100;
+const DSP_OFFSET: usize = 0x10100;
+const EXTRA_RAM_OFFSET: usize = 0x101C0;
+const FILE_SIZE: usize = 0x10200;
+
+pub const SAMPLE_RATE: u32 = 32000;
+
+// text fields are space/null padded, numbers are stored as ascii digits
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct ID666 {
+    pub song_title: String,   // 32
+    pub game_title: String,   // 32
+    pub dumper: String,       // 16
+    pub comments: String,     // 32
+    pub date: String,         // 11, MM/DD/YYYY
+    pub seconds: u32,         // 3 digits, length before fading out
+    pub fade_ms: u32,         // 5 digits
+    pub artist: String,       // 32
+}
+
+impl ID666 {
+    // a tag for a dump taken from a running game
+    pub fn from_header(header: &CartridgeHeader) -> ID666 {
+        ID666 {
+            song_title: String::new(),
+            game_title: header.title.clone(),
+            dumper: String::new(),
+            comments: String::new(),
+            date: today(),
+            seconds: 180,
+            fade_ms: 10000,
+            artist: String::new(),
+        }
+    }
+
+    fn parse(tag: &[u8]) -> ID666 {
+        // the binary variant stores the song length as a little endian number where the text variant has digits
+        let text_format = tag[0xA9 - ID666_OFFSET..0xB1 - ID666_OFFSET].iter()
+            .all(|&c| c == 0 || c.is_ascii_digit() || c == b' ');
+
+        let field = |start: usize, len: usize| read_string(&tag[start - ID666_OFFSET..start - ID666_OFFSET + len]);
+
+        if text_format {
+            ID666 {
+                song_title: field(0x2E, 32),
+                game_title: field(0x4E, 32),
+                dumper: field(0x6E, 16),
+                comments: field(0x7E, 32),
+                date: field(0x9E, 11),
+                seconds: field(0xA9, 3).parse().unwrap_or(0),
+                fade_ms: field(0xAC, 5).parse().unwrap_or(0),
+                artist: field(0xB1, 32),
+            }
+        } else {
+            let b = |offset: usize| tag[offset - ID666_OFFSET] as u32;
+
+            ID666 {
+                song_title: field(0x2E, 32),
+                game_title: field(0x4E, 32),
+                dumper: field(0x6E, 16),
+                comments: field(0x7E, 32),
+                date: String::new(),
+                seconds: b(0xA9) | (b(0xAA) << 8) | (b(0xAB) << 16),
+                fade_ms: b(0xAC) | (b(0xAD) << 8) | (b(0xAE) << 16) | (b(0xAF) << 24),
+                artist: field(0xB0, 32),
+            }
+        }
+    }
+
+    fn write(&self, file: &mut [u8]) {
+        write_string(&mut file[0x2E..0x4E], &self.song_title);
+        write_string(&mut file[0x4E..0x6E], &self.game_title);
+        write_string(&mut file[0x6E..0x7E], &self.dumper);
+        write_string(&mut file[0x7E..0x9E], &self.comments);
+        write_string(&mut file[0x9E..0xA9], &self.date);
+        write_string(&mut file[0xA9..0xAC], &format!("{}", self.seconds.min(999)));
+        write_string(&mut file[0xAC..0xB1], &format!("{}", self.fade_ms.min(99999)));
+        write_string(&mut file[0xB1..0xD1], &self.artist);
+    }
+}
+
+pub struct SpcFile {
+    pub pc: u16,
+    pub a: u8,
+    pub x: u8,
+    pub y: u8,
+    pub psw: u8,
+    pub sp: u8,
+
+    pub aram: Box<ARAM>,
+    pub dsp_regs: [u8; 0x80],
+    pub extra_ram: [u8; 0x40],
+
+    pub id666: Option<ID666>,
+}
+
+impl SpcFile {
+    pub fn open(path: &str) -> Result<SpcFile, Error> {
+        Self::parse(&fs::read(path)?)
+    }
+
+    pub fn parse(bytes: &[u8]) -> Result<SpcFile, Error> {
+        if bytes.len() < EXTRA_RAM_OFFSET || &bytes[0..SIGNATURE.len()] != SIGNATURE {
+            return Err(Error::new(ErrorKind::InvalidData, "not an SPC700 sound file"));
+        }
+
+        let r = &bytes[REGISTERS..];
+
+        let mut aram = Box::new([0; 0x10000]);
+        aram.copy_from_slice(&bytes[ARAM_OFFSET..ARAM_OFFSET + 0x10000]);
+
+        let mut dsp_regs = [0; 0x80];
+        dsp_regs.copy_from_slice(&bytes[DSP_OFFSET..DSP_OFFSET + 0x80]);
+
+        // some dumps are truncated before the extra ram, in which case the aram copy is used
+        let mut extra_ram = [0; 0x40];
+        if bytes.len() >= FILE_SIZE {
+            extra_ram.copy_from_slice(&bytes[EXTRA_RAM_OFFSET..FILE_SIZE]);
+        } else {
+            extra_ram.copy_from_slice(&aram[0xFFC0..]);
+        }
+
+        let id666 = if bytes[0x23] == HAS_ID666 {
+            Some(ID666::parse(&bytes[ID666_OFFSET..ARAM_OFFSET]))
+        } else {
+            None
+        };
+
+        Ok(SpcFile {
+            pc: (r[0] as u16) | ((r[1] as u16) << 8),
+            a: r[2],
+            x: r[3],
+            y: r[4],
+            psw: r[5],
+            sp: r[6],
+
+            aram,
+            dsp_regs,
+            extra_ram,
+
+            id666,
+        })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![0; FILE_SIZE];
+
+        bytes[0..SIGNATURE.len()].copy_from_slice(SIGNATURE);
+        bytes[0x21] = 26;
+        bytes[0x22] = 26;
+        bytes[0x23] = if self.id666.is_some() { HAS_ID666 } else { NO_ID666 };
+        bytes[0x24] = 30;
+
+        bytes[REGISTERS] = self.pc as u8;
+        bytes[REGISTERS + 1] = (self.pc >> 8) as u8;
+        bytes[REGISTERS + 2] = self.a;
+        bytes[REGISTERS + 3] = self.x;
+        bytes[REGISTERS + 4] = self.y;
+        bytes[REGISTERS + 5] = self.psw;
+        bytes[REGISTERS + 6] = self.sp;
+
+        if let Some(ref id666) = self.id666 {
+            id666.write(&mut bytes);
+        }
+
+        bytes[ARAM_OFFSET..ARAM_OFFSET + 0x10000].copy_from_slice(&self.aram[..]);
+        bytes[DSP_OFFSET..DSP_OFFSET + 0x80].copy_from_slice(&self.dsp_regs);
+        bytes[EXTRA_RAM_OFFSET..FILE_SIZE].copy_from_slice(&self.extra_ram);
+
+        bytes
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), Error> {
+        let mut f = fs::File::create(path)?;
+        f.write_all(&self.to_bytes())
+    }
+}
+
+////////////////////////////////////
+//
+//            PLAYBACK
+//
+////////////////////////////////////
+
+// renders the snapshot for the given number of seconds followed by a linear fade out.
+// returns 32 kHz interleaved stereo samples
+pub fn render(spc: &SpcFile, seconds: u32, fade_ms: u32) -> Vec<i16> {
+    let mut apu = APU::new();
+    apu.load_spc(spc);
+
+    let total_samples = (seconds as u64 * SAMPLE_RATE as u64) + (fade_ms as u64 * SAMPLE_RATE as u64 / 1000);
+    let fade_start = seconds as u64 * SAMPLE_RATE as u64;
+
+    let mut samples = Vec::with_capacity((total_samples * 2) as usize);
+
+    // one sample worth of cycles at a time so the output length is exact
+    let cycles_per_sample = APU_CLOCK_HZ / SAMPLE_RATE as u64;
+    while ((samples.len() / 2) as u64) < total_samples {
+        apu.run_for(cycles_per_sample);
+        samples.extend(apu.take_samples());
+    }
+    samples.truncate((total_samples * 2) as usize);
+
+    let fade_length = total_samples - fade_start;
+    if fade_length > 0 {
+        for (i, s) in samples.iter_mut().enumerate().skip((fade_start * 2) as usize) {
+            let remaining = total_samples - (i as u64 / 2);
+            *s = ((*s as i64 * remaining as i64) / fade_length as i64) as i16;
+        }
+    }
+
+    samples
+}
+
+// renders an .spc to a .wav. the length comes from the ID666 tag when one isn't given
+pub fn render_to_wav(spc_path: &str, wav_path: &str, seconds: Option<u32>) -> Result<(), Error> {
+    let spc = SpcFile::open(spc_path)?;
+
+    let (tag_seconds, fade_ms) = match spc.id666 {
+        Some(ref id666) if id666.seconds > 0 => (id666.seconds, id666.fade_ms),
+        _ => (180, 10000),
+    };
+
+    let samples = render(&spc, seconds.unwrap_or(tag_seconds), fade_ms);
+    wav::write_wav(wav_path, &samples, SAMPLE_RATE)
+}
+
+////////////////////////////////////
+//
+//             HELPERS
+//
+////////////////////////////////////
+
+fn read_string(bytes: &[u8]) -> String {
+    let end = bytes.iter().position(|&c| c == 0).unwrap_or(bytes.len());
+    String::from_utf8_lossy(&bytes[..end]).trim().to_string()
+}
+
+fn write_string(field: &mut [u8], s: &str) {
+    for (dst, src) in field.iter_mut().zip(s.bytes()) {
+        *dst = src;
+    }
+}
+
+// MM/DD/YYYY for the dump date
+fn today() -> String {
+    let secs = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+    let days = (secs / 86400) as i64;
+
+    // days since 1970-01-01 to a civil date
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!("{:02}/{:02}/{:04}", month, day, year)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cartridge::Cartridge;
+
+    fn snapshot(id666: Option<ID666>) -> SpcFile {
+        let mut aram = Box::new([0; 0x10000]);
+        for (n, byte) in aram.iter_mut().enumerate() {
+            *byte = (n * 13 + n / 256) as u8;
+        }
+        let mut dsp_regs = [0; 0x80];
+        for (n, reg) in dsp_regs.iter_mut().enumerate() {
+            *reg = 0x80 | n as u8;
+        }
+
+        SpcFile {
+            pc: 0x1234,
+            a: 0x56,
+            x: 0x78,
+            y: 0x9A,
+            psw: 0x02,
+            sp: 0xEF,
+
+            aram,
+            dsp_regs,
+            extra_ram: [0x5A; 0x40],
+
+            id666,
+        }
+    }
+
+    fn tag() -> ID666 {
+        ID666 {
+            song_title: "Overworld".to_string(),
+            game_title: "TEST GAME".to_string(),
+            dumper: "someone".to_string(),
+            comments: "looped twice".to_string(),
+            date: "06/21/1994".to_string(),
+            seconds: 150,
+            fade_ms: 8000,
+            artist: "composer".to_string(),
+        }
+    }
+
+    #[test]
+    fn snapshots_survive_to_bytes_and_parse() {
+        let spc = snapshot(Some(tag()));
+        let bytes = spc.to_bytes();
+        assert_eq!(bytes.len(), FILE_SIZE);
+        assert_eq!(&bytes[..SIGNATURE.len()], SIGNATURE);
+
+        let parsed = SpcFile::parse(&bytes).unwrap();
+        assert_eq!((parsed.pc, parsed.a, parsed.x, parsed.y, parsed.psw, parsed.sp), (0x1234, 0x56, 0x78, 0x9A, 0x02, 0xEF));
+        assert!(parsed.aram[..] == spc.aram[..]);
+        assert_eq!(parsed.dsp_regs[..], spc.dsp_regs[..]);
+        assert_eq!(parsed.extra_ram[..], spc.extra_ram[..]);
+        assert_eq!(parsed.id666, Some(tag()));
+        assert!(parsed.to_bytes() == bytes);
+    }
+
+    #[test]
+    fn the_tag_is_optional() {
+        let bytes = snapshot(None).to_bytes();
+        assert_eq!(bytes[0x23], NO_ID666);
+        assert_eq!(SpcFile::parse(&bytes).unwrap().id666, None);
+    }
+
+    #[test]
+    fn text_tags_store_numbers_as_digits() {
+        let bytes = snapshot(Some(tag())).to_bytes();
+        assert_eq!(&bytes[0xA9..0xB1], b"1508000\0");
+        assert_eq!(&bytes[0xB1..0xB9], b"composer");
+    }
+
+    #[test]
+    fn binary_tags_are_read_too() {
+        let mut bytes = snapshot(None).to_bytes();
+        bytes[0x23] = HAS_ID666;
+        bytes[0x2E..0x33].copy_from_slice(b"Title");
+        bytes[0x4E..0x52].copy_from_slice(b"Game");
+        // 300 seconds and a 10000 ms fade as little endian numbers, then the artist a byte earlier
+        bytes[0xA9..0xB0].copy_from_slice(&[0x2C, 0x01, 0x00, 0x10, 0x27, 0x00, 0x00]);
+        bytes[0xB0..0xB6].copy_from_slice(b"Artist");
+
+        let id666 = SpcFile::parse(&bytes).unwrap().id666.unwrap();
+        assert_eq!((id666.song_title.as_str(), id666.game_title.as_str()), ("Title", "Game"));
+        assert_eq!((id666.seconds, id666.fade_ms), (300, 10000));
+        assert_eq!(id666.artist, "Artist");
+        assert_eq!(id666.date, "");
+    }
+
+    #[test]
+    fn truncated_dumps_take_the_extra_ram_from_aram() {
+        let spc = snapshot(None);
+        let parsed = SpcFile::parse(&spc.to_bytes()[..EXTRA_RAM_OFFSET]).unwrap();
+        assert_eq!(parsed.extra_ram[..], spc.aram[0xFFC0..]);
+
+        assert!(SpcFile::parse(&spc.to_bytes()[..EXTRA_RAM_OFFSET - 1]).is_err());
+        assert!(SpcFile::parse(&[0; FILE_SIZE]).is_err());
+    }
+
+    #[test]
+    fn dumps_from_a_game_are_tagged_with_its_title() {
+        let mut rom = vec![0; 0x8000];
+        rom[0x7FC0..0x7FD5].copy_from_slice(b"SOUND TEST           ");
+        let cartridge = Cartridge::new(rom).unwrap();
+
+        let id666 = ID666::from_header(cartridge.header());
+        assert_eq!(id666.game_title, "SOUND TEST");
+        assert_eq!((id666.seconds, id666.fade_ms), (180, 10000));
+        assert_eq!(id666.date.len(), 10);
+        assert_eq!(SpcFile::parse(&snapshot(Some(id666.clone())).to_bytes()).unwrap().id666, Some(id666));
+    }
+}
@@ -0,0 +1,49 @@
+use std::fs;
+use std::io::{Error, Write};
+
+// writes interleaved 16 bit stereo samples as a PCM .wav file
+pub fn write_wav(path: &str, samples: &[i16], sample_rate: u32) -> Result<(), Error> {
+    let mut f = fs::File::create(path)?;
+    f.write_all(&wav_bytes(samples, sample_rate))
+}
+
+pub fn wav_bytes(samples: &[i16], sample_rate: u32) -> Vec<u8> {
+    let channels: u16 = 2;
+    let bits_per_sample: u16 = 16;
+    let block_align = channels * bits_per_sample / 8;
+    let byte_rate = sample_rate * block_align as u32;
+    let data_size = (samples.len() * 2) as u32;
+
+    let mut bytes = Vec::with_capacity(44 + data_size as usize);
+
+    bytes.extend_from_slice(b"RIFF");
+    push_u32(&mut bytes, 36 + data_size);
+    bytes.extend_from_slice(b"WAVE");
+
+    bytes.extend_from_slice(b"fmt ");
+    push_u32(&mut bytes, 16);
+    push_u16(&mut bytes, 1); // PCM
+    push_u16(&mut bytes, channels);
+    push_u32(&mut bytes, sample_rate);
+    push_u32(&mut bytes, byte_rate);
+    push_u16(&mut bytes, block_align);
+    push_u16(&mut bytes, bits_per_sample);
+
+    bytes.extend_from_slice(b"data");
+    push_u32(&mut bytes, data_size);
+    for s in samples {
+        push_u16(&mut bytes, *s as u16);
+    }
+
+    bytes
+}
+
+fn push_u16(bytes: &mut Vec<u8>, n: u16) {
+    bytes.push(n as u8);
+    bytes.push((n >> 8) as u8);
+}
+
+fn push_u32(bytes: &mut Vec<u8>, n: u32) {
+    push_u16(bytes, n as u16);
+    push_u16(bytes, (n >> 16) as u16);
+}
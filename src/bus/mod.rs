@@ -0,0 +1,861 @@
+pub mod watch;
+
+use std::any::Any;
+use std::cell::{Cell, Ref, RefCell};
+use std::io::{Error, ErrorKind};
+
+use apu::APU;
+use cartridge::Cartridge;
+use cdl::CodeDataLog;
+use cheat::Cheats;
+use cpu::disasm::Instruction;
+use cpu::memory;
+use cpu::memory::{Mem, SimpleMemory};
+use dma;
+use dma::Dma;
+use input::Input;
+use ppu::counters::Counters;
+use ppu::ppu::PPU;
+use savestate::{SaveState, StateWriter};
+use scheduler::{Event, Scheduler};
+use symbols::{Symbol, Symbols};
+
+use self::watch::{Access, Hit, Space, Watcher, Watchpoint};
+
+// NTSC frame timing
+const MASTER_CYCLES_PER_LINE: u64 = 1364;
+const LINES_PER_FRAME: u64 = 262;
+const MASTER_CYCLES_PER_FRAME: u64 = MASTER_CYCLES_PER_LINE * LINES_PER_FRAME;
+const VBLANK_START_LINE: u64 = 225;
+
+// where in each line, in master cycles, the cpu loses 40 cycles to the WRAM refresh
+const DRAM_REFRESH: u64 = 536;
+const DRAM_REFRESH_CYCLES: u64 = 40;
+
+const HBLANK_START: u64 = 274 * 4;
+
+// the first visible dot of a line, where a light gun aimed at x = 0 sees the beam
+const DISPLAY_START_DOT: u64 = 22;
+
+// 128KB of work ram in banks $7E-$7F
+const WRAM_START: usize = 0x7E0000;
+const WRAM_SIZE: usize = 0x20000;
+
+// the main CPU's view of the system. everything that isn't memory mapped hardware
+// falls through to the flat memory underneath
+pub struct Bus {
+    mem: SimpleMemory,
+    cartridge: Option<Cartridge>,
+    cheats: Cheats,
+    ppu: PPU,
+
+    // Mem::load takes &self but reading the ports has to catch the APU up first
+    apu: RefCell<APU>,
+    input: RefCell<Input>,
+    counters: RefCell<Counters>,
+    dma: Dma,
+
+    wrio: u8, // $4201, the IOBit pins of both ports
+    memsel: u8, // $420D, bit 0 runs banks $80-$FF at the fast rom speed
+
+    // $4200 NMITIMEN and the h/v irq timers at $4207-$420A
+    nmitimen: u8,
+    htime: u16,
+    vtime: u16,
+
+    // $4210 RDNMI and $4211 TIMEUP, both cleared when read
+    nmi_flag: Cell<bool>,
+    irq_flag: Cell<bool>,
+    nmi_pending: bool,
+
+    // everything runs off the master clock. the cpu drives it forward through Mem::tick and
+    // the scheduler fires line, refresh and irq events at their exact timestamps on the way
+    scheduler: Scheduler,
+    master_cycles: u64,
+    frame: u64,
+
+    // the ARAM ones are checked by the APU, the rest here
+    watchpoints: Vec<Watchpoint>,
+    watcher: Watcher,
+
+    // rom reads are logged from Mem::load, which takes &self
+    code_data_log: Option<RefCell<CodeDataLog>>,
+
+    symbols: Symbols,
+}
+
+impl Bus {
+    pub fn new(mem: SimpleMemory, apu: APU) -> Bus {
+        let mut bus = Bus {
+            mem,
+            cartridge: None,
+            cheats: Cheats::new(),
+            ppu: PPU::new(),
+            apu: RefCell::new(apu),
+            input: RefCell::new(Input::new()),
+            counters: RefCell::new(Counters::new()),
+            dma: Dma::new(),
+            wrio: 0xFF,
+            memsel: 0,
+            nmitimen: 0,
+            htime: 0x1FF,
+            vtime: 0x1FF,
+            nmi_flag: Cell::new(false),
+            irq_flag: Cell::new(false),
+            nmi_pending: false,
+            scheduler: Scheduler::new(),
+            master_cycles: 0,
+            frame: 0,
+            watchpoints: Vec::new(),
+            watcher: Watcher::new(),
+            code_data_log: None,
+            symbols: Symbols::new(),
+        };
+
+        bus.scheduler.schedule(0, Event::NewLine);
+        bus
+    }
+
+    // the reset button. WRAM and the cartridge survive, the chips go back to their reset state
+    pub fn reset(&mut self) {
+        self.apu.get_mut().reset();
+        self.write_nmitimen(0);
+        self.write_wrio(0xFF);
+        self.memsel = 0;
+        self.counters = RefCell::new(Counters::new());
+        self.nmi_flag.set(false);
+        self.irq_flag.set(false);
+        self.nmi_pending = false;
+    }
+
+    // turning the console off and on again. only the cartridge and what's plugged into the ports are kept
+    pub fn power_cycle(&mut self) {
+        self.mem = SimpleMemory::new();
+        self.ppu = PPU::new();
+        self.apu = RefCell::new(APU::new());
+        self.apu.get_mut().bus_mut().set_watchpoints(&self.watchpoints);
+        self.dma = Dma::new();
+        self.htime = 0x1FF;
+        self.vtime = 0x1FF;
+        self.master_cycles = 0;
+        self.frame = 0;
+        self.scheduler.clear();
+        self.scheduler.schedule(0, Event::NewLine);
+        self.reset();
+    }
+
+    // a code/data log and symbols only fit the rom they were made for, so they're dropped
+    pub fn load_cartridge(&mut self, cartridge: Cartridge) {
+        self.cartridge = Some(cartridge);
+        self.code_data_log = None;
+        self.symbols.clear();
+    }
+
+    pub fn cartridge(&self) -> Option<&Cartridge> {
+        self.cartridge.as_ref()
+    }
+
+    pub fn cartridge_mut(&mut self) -> Option<&mut Cartridge> {
+        self.cartridge.as_mut()
+    }
+
+    pub fn cheats(&self) -> &Cheats {
+        &self.cheats
+    }
+
+    pub fn cheats_mut(&mut self) -> &mut Cheats {
+        &mut self.cheats
+    }
+
+    // what the cpu would read, without the side effects of reading io registers. those
+    // show whatever is in the flat memory underneath instead
+    pub fn peek(&self, bank: u8, address: u16) -> u8 {
+        if let Some(data) = self.cartridge.as_ref().and_then(|cartridge| cartridge.read(bank, address)) {
+            return self.cheats.patch_read(bank, address, data);
+        }
+
+        let (bank, address) = Self::wram_mirror(bank, address);
+        self.mem.load(bank, address)
+    }
+
+    ////////////////////////////////////
+    //
+    //            WATCHPOINTS
+    //
+    ////////////////////////////////////
+
+    // replaces every watchpoint. a list with any in a space that can't be watched yet, which
+    // is vram, cgram and oam for now, is turned down and the old watchpoints are kept
+    pub fn set_watchpoints(&mut self, watchpoints: &[Watchpoint]) -> Result<(), Error> {
+        if let Some(watchpoint) = watchpoints.iter().find(|watchpoint| !watchpoint.space.is_watchable()) {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("{} watchpoints aren't supported yet", watchpoint.space.name()),
+            ));
+        }
+
+        self.watchpoints = watchpoints.to_vec();
+        self.watcher.set_watchpoints(watchpoints, &[Space::Bus]);
+        self.apu.get_mut().bus_mut().set_watchpoints(watchpoints);
+        Ok(())
+    }
+
+    pub fn watchpoints(&self) -> &[Watchpoint] {
+        &self.watchpoints
+    }
+
+    // the cpu doesn't tell the bus which of its reads are opcode fetches, so whoever is
+    // stepping it says where each instruction starts
+    pub fn begin_instruction(&self, bank: u8, pc: u16) {
+        if !self.watcher.is_empty() {
+            self.watcher.begin_instruction(Space::Bus, bank, pc, self.peek(bank, pc));
+        }
+    }
+
+    // every access that triggered a watchpoint since the last call, main cpu and dma first
+    pub fn take_watch_hits(&mut self) -> Vec<Hit> {
+        let mut hits = self.watcher.take_hits();
+        hits.extend(self.apu.get_mut().bus_mut().take_watch_hits());
+        hits
+    }
+
+    ////////////////////////////////////
+    //
+    //          CODE/DATA LOG
+    //
+    ////////////////////////////////////
+
+    pub fn set_code_data_log(&mut self, log: Option<CodeDataLog>) {
+        self.code_data_log = log.map(RefCell::new);
+    }
+
+    pub fn code_data_log(&self) -> Option<Ref<'_, CodeDataLog>> {
+        self.code_data_log.as_ref().map(|log| log.borrow())
+    }
+
+    pub fn take_code_data_log(&mut self) -> Option<CodeDataLog> {
+        self.code_data_log.take().map(RefCell::into_inner)
+    }
+
+    pub fn is_logging_code_data(&self) -> bool {
+        self.code_data_log.is_some()
+    }
+
+    // like begin_instruction, whoever steps the cpu says what each instruction is
+    pub fn log_instruction(&mut self, instruction: &Instruction, m8: bool, x8: bool, pointer: Option<(u8, u16, usize)>) {
+        let cartridge = self.cartridge.as_ref();
+        if let Some(log) = self.code_data_log.as_mut() {
+            let code_offset = |bank, address| cartridge.and_then(|cartridge| cartridge.rom_offset(bank, address));
+            log.get_mut().begin_instruction(instruction, m8, x8, pointer, code_offset);
+        }
+    }
+
+    fn log_rom_read(&self, bank: u8, address: u16) {
+        if let Some(log) = self.code_data_log.as_ref() {
+            if let Some(offset) = self.cartridge.as_ref().and_then(|cartridge| cartridge.rom_offset(bank, address)) {
+                log.borrow_mut().log_read(bank, address, offset);
+            }
+        }
+    }
+
+    ////////////////////////////////////
+    //
+    //             SYMBOLS
+    //
+    ////////////////////////////////////
+
+    // for the cartridge that's in now
+    pub fn add_symbols(&mut self, symbols: Vec<Symbol>) {
+        self.symbols.add(symbols, self.cartridge.as_ref());
+    }
+
+    pub fn symbols(&self) -> &Symbols {
+        &self.symbols
+    }
+
+    pub fn clear_symbols(&mut self) {
+        self.symbols.clear();
+    }
+
+    pub fn comment(&self, bank: u8, address: u16) -> Option<&str> {
+        self.symbols.comment(bank, address, self.cartridge.as_ref())
+    }
+
+    // "name" or "BB:name"
+    pub fn symbol_address(&self, name: &str) -> Option<(u8, u16)> {
+        self.symbols.address_of(name, self.cartridge.as_ref())
+    }
+
+    pub fn wram(&self) -> &[u8] {
+        &self.mem.bytes()[WRAM_START..WRAM_START + WRAM_SIZE]
+    }
+
+    pub fn ppu(&self) -> &PPU {
+        &self.ppu
+    }
+
+    pub fn master_cycles(&self) -> u64 {
+        self.master_cycles
+    }
+
+    pub fn apu(&self) -> Ref<'_, APU> {
+        self.apu.borrow()
+    }
+
+    pub fn apu_mut(&mut self) -> &mut APU {
+        self.apu.get_mut()
+    }
+
+    pub fn input(&self) -> Ref<'_, Input> {
+        self.input.borrow()
+    }
+
+    pub fn input_mut(&mut self) -> &mut Input {
+        self.input.get_mut()
+    }
+
+    pub fn frame(&self) -> u64 {
+        self.frame
+    }
+
+    fn in_vblank(&self) -> bool {
+        self.ppu.line() as u64 >= VBLANK_START_LINE
+    }
+
+    // $4212 HVBJOY. bit 7 vblank, bit 6 hblank, bit 0 auto joypad read in progress
+    fn read_hvbjoy(&self) -> u8 {
+        let mut hvbjoy = 0;
+
+        if self.in_vblank() {
+            hvbjoy |= 0x80;
+        }
+
+        let position = self.master_cycles % MASTER_CYCLES_PER_LINE;
+        if !(4..HBLANK_START).contains(&position) {
+            hvbjoy |= 0x40;
+        }
+
+        if self.input.borrow().auto_read_busy(self.master_cycles) {
+            hvbjoy |= 0x01;
+        }
+
+        hvbjoy
+    }
+
+    // the dot and line the beam is on
+    pub fn beam_position(&self) -> (u16, u16) {
+        (self.ppu.dot(self.master_cycles), self.ppu.line())
+    }
+
+    // $2137 SLHV and the light gun only latch the counters while $4201 bit 7 is set
+    fn latch_counters(&self) {
+        if self.wrio & 0x80 != 0 {
+            let (h, v) = self.beam_position();
+            self.counters.borrow_mut().latch(h, v);
+        }
+    }
+
+    fn write_wrio(&mut self, data: u8) {
+        if self.wrio & 0x80 != 0 && data & 0x80 == 0 {
+            let (h, v) = self.beam_position();
+            self.counters.get_mut().latch(h, v);
+        }
+
+        self.wrio = data;
+        self.input.get_mut().write_wrio(data);
+    }
+
+    // a light gun sees the beam when it passes the point it's aimed at, which latches the counters
+    fn check_light_gun(&mut self, before: u64) {
+        if self.wrio & 0x80 == 0 {
+            return;
+        }
+
+        let (x, y) = match self.input.get_mut().light_position() {
+            Some(position) => position,
+            None => return,
+        };
+
+        let dot = x as u64 + DISPLAY_START_DOT;
+        let line = y as u64 + 1;
+        let offset = line * MASTER_CYCLES_PER_LINE + dot * 4;
+
+        let frame_start = before - before % MASTER_CYCLES_PER_FRAME;
+        for start in [frame_start, frame_start + MASTER_CYCLES_PER_FRAME].iter() {
+            let target = start + offset;
+            if before < target && target <= self.master_cycles {
+                self.counters.get_mut().latch(dot as u16, line as u16);
+            }
+        }
+    }
+
+    ////////////////////////////////////
+    //
+    //             TIMING
+    //
+    ////////////////////////////////////
+
+    // moves the clock to the given time, catching up everything that runs alongside the cpu
+    fn advance(&mut self, to: u64) {
+        let before = self.master_cycles;
+        self.master_cycles = to;
+        self.apu.get_mut().run_to(to);
+        self.check_light_gun(before);
+    }
+
+    // returns how long the cpu is halted for because of the event
+    fn handle_event(&mut self, at: u64, event: Event) -> u64 {
+        match event {
+            Event::NewLine => {
+                self.scheduler.schedule(at + DRAM_REFRESH, Event::DramRefresh);
+                self.scheduler.schedule(at + HBLANK_START, Event::HBlankStart);
+                self.scheduler.schedule(at + MASTER_CYCLES_PER_LINE, Event::NewLine);
+                self.schedule_irq(at);
+
+                let line = (at % MASTER_CYCLES_PER_FRAME) / MASTER_CYCLES_PER_LINE;
+                self.ppu.start_line(line as u16, at);
+                if line == 0 {
+                    self.nmi_flag.set(false);
+                } else if line == VBLANK_START_LINE {
+                    self.start_vblank(at);
+                }
+                0
+            },
+            Event::DramRefresh => DRAM_REFRESH_CYCLES,
+            // todo -> HDMA runs here
+            Event::HBlankStart => 0,
+            Event::HvIrq => {
+                self.irq_flag.set(true);
+                0
+            },
+        }
+    }
+
+    fn start_vblank(&mut self, at: u64) {
+        self.nmi_flag.set(true);
+        if self.nmitimen & 0x80 != 0 {
+            self.nmi_pending = true;
+        }
+
+        self.input.get_mut().vblank(self.frame, at);
+        self.apply_cheats();
+        self.frame += 1;
+    }
+
+    // pro action replay codes poke ram directly, so they skip the io registers. writes to
+    // rom addresses are dropped like any other
+    fn apply_cheats(&mut self) {
+        for code in self.cheats.writes() {
+            let (bank, address) = Self::wram_mirror(code.bank(), code.offset());
+
+            if let Some(cartridge) = self.cartridge.as_mut() {
+                if let Some(current) = cartridge.read(bank, address) {
+                    if code.compare.is_none_or(|compare| compare == current) {
+                        cartridge.write(bank, address, code.value);
+                    }
+                    continue;
+                }
+            }
+
+            if code.compare.is_none_or(|compare| compare == self.mem.load(bank, address)) {
+                self.mem.store(bank, address, code.value);
+            }
+        }
+    }
+
+    // schedules the h/v irq for the line starting at the given time, if it fires on that line.
+    // bits 4 and 5 of NMITIMEN enable matching on HTIME and VTIME
+    fn schedule_irq(&mut self, line_start: u64) {
+        let line = ((line_start % MASTER_CYCLES_PER_FRAME) / MASTER_CYCLES_PER_LINE) as u16;
+        let h_enabled = self.nmitimen & 0x10 != 0;
+        let v_enabled = self.nmitimen & 0x20 != 0;
+
+        if !h_enabled && !v_enabled {
+            return;
+        }
+        if v_enabled && line != self.vtime {
+            return;
+        }
+
+        let dot = if h_enabled { self.htime as u64 } else { 0 };
+        if dot >= MASTER_CYCLES_PER_LINE / 4 {
+            return;
+        }
+
+        let at = line_start + dot * 4;
+        if at >= self.master_cycles {
+            self.scheduler.schedule(at, Event::HvIrq);
+        }
+    }
+
+    // the irq timers were reprogrammed partway through a line
+    fn reschedule_irq(&mut self) {
+        self.scheduler.cancel(Event::HvIrq);
+        let line_start = self.master_cycles - self.master_cycles % MASTER_CYCLES_PER_LINE;
+        self.schedule_irq(line_start);
+    }
+
+    ////////////////////////////////////
+    //
+    //        CPU REGISTERS
+    //
+    ////////////////////////////////////
+
+    // $4200 NMITIMEN. bit 7 nmi enable, bits 4-5 h/v irq enable, bit 0 auto joypad read
+    fn write_nmitimen(&mut self, data: u8) {
+        // enabling nmi partway through vblank fires it straight away
+        if self.nmitimen & 0x80 == 0 && data & 0x80 != 0 && self.nmi_flag.get() {
+            self.nmi_pending = true;
+        }
+
+        if data & 0x30 == 0 {
+            self.irq_flag.set(false);
+        }
+
+        self.nmitimen = data;
+        self.input.get_mut().write_nmitimen(data);
+        self.reschedule_irq();
+    }
+
+    // $4207-$420A
+    fn write_irq_timer(&mut self, address: u16, data: u8) {
+        match address {
+            0x4207 => self.htime = (self.htime & 0x100) | data as u16,
+            0x4208 => self.htime = (self.htime & 0x0FF) | ((data as u16 & 1) << 8),
+            0x4209 => self.vtime = (self.vtime & 0x100) | data as u16,
+            _ => self.vtime = (self.vtime & 0x0FF) | ((data as u16 & 1) << 8),
+        }
+        self.reschedule_irq();
+    }
+
+    // $4210 RDNMI. bit 7 is set at the start of vblank, the low bits are the cpu version
+    fn read_rdnmi(&self) -> u8 {
+        let flag = if self.nmi_flag.replace(false) { 0x80 } else { 0x00 };
+        flag | 0x02
+    }
+
+    // $4211 TIMEUP
+    fn read_timeup(&self) -> u8 {
+        if self.irq_flag.replace(false) { 0x80 } else { 0x00 }
+    }
+
+    ////////////////////////////////////
+    //
+    //               DMA
+    //
+    ////////////////////////////////////
+
+    // $420B MDMAEN. each enabled channel runs to completion in order, with the cpu halted
+    // and the clock moving on a byte at a time
+    fn run_dma(&mut self, channels: u8) {
+        if channels == 0 {
+            return;
+        }
+
+        self.tick(dma::START_CYCLES);
+        if let Some(log) = self.code_data_log.as_mut() {
+            log.get_mut().set_dma(true);
+        }
+
+        for index in 0..dma::CHANNEL_COUNT {
+            if channels & (1 << index) == 0 {
+                continue;
+            }
+            self.tick(dma::CHANNEL_CYCLES);
+            self.watcher.set_dma_channel(Some(index as u8));
+
+            let mut unit = 0;
+            loop {
+                let channel = *self.dma.channel(index);
+                let pattern = channel.pattern();
+                let b_address = 0x2100 | channel.b_address.wrapping_add(pattern[unit % pattern.len()]) as u16;
+
+                if channel.b_to_a() {
+                    let data = self.load(0x00, b_address);
+                    self.store(channel.a_bank, channel.a_address, data);
+                } else {
+                    let data = self.load(channel.a_bank, channel.a_address);
+                    self.store(0x00, b_address, data);
+                }
+
+                let channel = self.dma.channel_mut(index);
+                channel.step_a_address();
+                channel.count = channel.count.wrapping_sub(1);
+                let done = channel.count == 0;
+
+                self.tick(dma::BYTE_CYCLES);
+                unit += 1;
+
+                if done {
+                    break;
+                }
+            }
+        }
+
+        self.watcher.set_dma_channel(None);
+        if let Some(log) = self.code_data_log.as_mut() {
+            log.get_mut().set_dma(false);
+        }
+    }
+
+    ////////////////////////////////////
+    //
+    //           SAVE STATES
+    //
+    ////////////////////////////////////
+
+    // everything but the rom and the input provider, which belong to the frontend
+    pub fn save_state(&self, w: &mut StateWriter) {
+        w.section(b"BUS ", |w| {
+            w.u8(self.wrio);
+            w.u8(self.memsel);
+            w.u8(self.nmitimen);
+            w.u16(self.htime);
+            w.u16(self.vtime);
+            w.bool(self.nmi_flag.get());
+            w.bool(self.irq_flag.get());
+            w.bool(self.nmi_pending);
+            w.u64(self.master_cycles);
+            w.u64(self.frame);
+        });
+        w.section(b"MEM ", |w| w.compressed(self.mem.bytes()));
+        w.section(b"SCHD", |w| self.scheduler.save_state(w));
+        w.section(b"DMA ", |w| self.dma.save_state(w));
+        w.section(b"PPU ", |w| {
+            self.counters.borrow().save_state(w);
+            self.ppu.save_state(w);
+        });
+        w.section(b"INPT", |w| self.input.borrow().save_state(w));
+        self.apu.borrow().save_state(w);
+
+        if let Some(cartridge) = self.cartridge.as_ref().filter(|cartridge| !cartridge.sram().is_empty()) {
+            w.section(b"SRAM", |w| w.compressed(cartridge.sram()));
+        }
+    }
+
+    pub fn load_state(&mut self, state: &SaveState) -> Result<(), Error> {
+        let mut r = state.section(b"BUS ")?;
+        self.wrio = r.u8()?;
+        self.memsel = r.u8()?;
+        self.nmitimen = r.u8()?;
+        self.htime = r.u16()?;
+        self.vtime = r.u16()?;
+        self.nmi_flag.set(r.bool()?);
+        self.irq_flag.set(r.bool()?);
+        self.nmi_pending = r.bool()?;
+        self.master_cycles = r.u64()?;
+        self.frame = r.u64()?;
+
+        state.section(b"MEM ")?.compressed(self.mem.bytes_mut())?;
+        self.scheduler.load_state(&mut state.section(b"SCHD")?)?;
+        self.dma.load_state(&mut state.section(b"DMA ")?)?;
+
+        let mut r = state.section(b"PPU ")?;
+        self.counters.get_mut().load_state(&mut r)?;
+        self.ppu.load_state(&mut r)?;
+
+        self.input.get_mut().load_state(&mut state.section(b"INPT")?)?;
+        self.apu.get_mut().load_state(state)?;
+
+        // states from before sram was saved don't have the section, and leave it as it is
+        if let Some(cartridge) = self.cartridge.as_mut().filter(|cartridge| !cartridge.sram().is_empty()) {
+            if state.has_section(b"SRAM") {
+                state.section(b"SRAM")?.compressed(cartridge.sram_mut())?;
+            }
+        }
+        Ok(())
+    }
+
+    // the b-bus and cpu registers only live in banks $00-$3F and $80-$BF
+    fn is_system_bank(bank: u8) -> bool {
+        (bank & 0x40) == 0
+    }
+
+    // the first 8KB of WRAM is mirrored at the bottom of every system bank
+    fn wram_mirror(bank: u8, address: u16) -> (u8, u16) {
+        if Self::is_system_bank(bank) && address < 0x2000 {
+            (0x7E, address)
+        } else {
+            (bank, address)
+        }
+    }
+
+    // $2140-$217F in banks $00-$3F and $80-$BF, the 4 ports are mirrored through the whole range
+    fn apu_port(bank: u8, address: u16) -> Option<usize> {
+        if Self::is_system_bank(bank) && (0x2140..=0x217F).contains(&address) {
+            Some((address & 0x03) as usize)
+        } else {
+            None
+        }
+    }
+
+    // the accesses themselves. Mem::load and Mem::store wrap these to check watchpoints
+    fn read(&self, bank: u8, address: u16) -> u8 {
+        if let Some(port) = Self::apu_port(bank, address) {
+            return self.apu.borrow_mut().read_port(self.master_cycles, port);
+        }
+
+        if Self::is_system_bank(bank) {
+            match address {
+                0x2137 => self.latch_counters(),
+                0x213C => return self.counters.borrow_mut().read_ophct(),
+                0x213D => return self.counters.borrow_mut().read_opvct(),
+                0x213F => return self.counters.borrow_mut().read_stat78(),
+                0x4016 => return self.input.borrow_mut().read_serial(0),
+                0x4017 => return self.input.borrow_mut().read_serial(1),
+                0x4210 => return self.read_rdnmi(),
+                0x4211 => return self.read_timeup(),
+                0x4212 => return self.read_hvbjoy(),
+                0x4213 => return self.wrio, // RDIO, nothing else pulls the pins low
+                0x4218..=0x421F => return self.input.borrow().read_joy(address),
+                0x4300..=0x437F => return self.dma.read(address),
+                _ => {}
+            }
+        }
+
+        if let Some(data) = self.cartridge.as_ref().and_then(|cartridge| cartridge.read(bank, address)) {
+            self.log_rom_read(bank, address);
+            return self.cheats.patch_read(bank, address, data);
+        }
+
+        let (bank, address) = Self::wram_mirror(bank, address);
+        self.mem.load(bank, address)
+    }
+
+    fn write(&mut self, bank: u8, address: u16, to_store: u8) {
+        if let Some(port) = Self::apu_port(bank, address) {
+            return self.apu.get_mut().write_port(self.master_cycles, port, to_store);
+        }
+
+        if Self::is_system_bank(bank) {
+            match address {
+                0x4016 => self.input.get_mut().write_latch(to_store),
+                0x4200 => self.write_nmitimen(to_store),
+                0x4201 => self.write_wrio(to_store),
+                0x4207..=0x420A => self.write_irq_timer(address, to_store),
+                0x420B => self.run_dma(to_store),
+                0x420D => self.memsel = to_store & 0x01,
+                0x4300..=0x437F => return self.dma.write(address, to_store),
+                _ => {}
+            }
+        }
+
+        if self.cartridge.as_mut().is_some_and(|cartridge| cartridge.write(bank, address, to_store)) {
+            return;
+        }
+
+        let (bank, address) = Self::wram_mirror(bank, address);
+        self.mem.store(bank, address, to_store);
+    }
+}
+
+impl Mem for Bus {
+    fn load(&self, bank: u8, address: u16) -> u8 {
+        let data = self.read(bank, address);
+        if !self.watcher.is_empty() {
+            self.watcher.check(Space::Bus, ((bank as u32) << 16) | address as u32, Access::Read, data);
+        }
+        data
+    }
+
+    fn store(&mut self, bank: u8, address: u16, to_store: u8) {
+        if !self.watcher.is_empty() {
+            self.watcher.check(Space::Bus, ((bank as u32) << 16) | address as u32, Access::Write, to_store);
+        }
+        self.write(bank, address, to_store);
+    }
+
+    fn access_cycles(&self, bank: u8, address: u16) -> u32 {
+        memory::access_cycles(bank, address, self.memsel & 0x01 != 0)
+    }
+
+    // runs every event that falls inside the cpu's time slice, in order. a refresh partway
+    // through stretches the slice, since the cpu can't do anything while it happens
+    fn tick(&mut self, master_cycles: u32) {
+        let mut target = self.master_cycles + master_cycles as u64;
+
+        while let Some((at, event)) = self.scheduler.pop_due(target) {
+            self.advance(at);
+            target += self.handle_event(at, event);
+        }
+
+        self.advance(target);
+    }
+
+    fn take_nmi(&mut self) -> bool {
+        let nmi = self.nmi_pending;
+        self.nmi_pending = false;
+        nmi
+    }
+
+    fn irq(&self) -> bool {
+        self.irq_flag.get()
+    }
+
+    fn label(&self, bank: u8, address: u16) -> Option<&str> {
+        self.symbols.label(bank, address, self.cartridge.as_ref())
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cpu::memory::{FAST_CYCLES, SLOW_CYCLES};
+
+    fn bus() -> Bus {
+        Bus::new(SimpleMemory::new(), APU::new())
+    }
+
+    #[test]
+    fn memsel_speeds_up_the_upper_rom_banks() {
+        let mut bus = bus();
+        assert_eq!(bus.access_cycles(0x80, 0x8000), SLOW_CYCLES);
+
+        bus.store(0x00, 0x420D, 0x01);
+        assert_eq!(bus.access_cycles(0x80, 0x8000), FAST_CYCLES);
+        assert_eq!(bus.access_cycles(0x00, 0x8000), SLOW_CYCLES);
+
+        bus.reset();
+        assert_eq!(bus.access_cycles(0x80, 0x8000), SLOW_CYCLES);
+    }
+
+    #[test]
+    fn the_scheduler_moves_the_beam_down_the_screen() {
+        let mut bus = bus();
+        bus.tick(10 * 4);
+        assert_eq!(bus.beam_position(), (10, 0));
+
+        // the refresh on each line stretches the cpu's slice past it
+        bus.tick(MASTER_CYCLES_PER_LINE as u32 * 2);
+        assert_eq!(bus.beam_position(), (10 + 2 * DRAM_REFRESH_CYCLES as u16 / 4, 2));
+        assert!(!bus.in_vblank());
+
+        while bus.ppu().line() < VBLANK_START_LINE as u16 {
+            bus.tick(4);
+        }
+        assert!(bus.in_vblank());
+        assert_eq!(bus.frame(), 1);
+    }
+
+    #[test]
+    fn ppu_memory_watchpoints_are_turned_down() {
+        let watchpoint = |space| Watchpoint { space, start: 0, end: 0x1F, accesses: watch::WRITE, value: None };
+
+        let mut bus = bus();
+        bus.set_watchpoints(&[watchpoint(Space::Bus), watchpoint(Space::ARAM)]).unwrap();
+
+        for &space in [Space::VRAM, Space::CGRAM, Space::OAM].iter() {
+            let error = bus.set_watchpoints(&[watchpoint(Space::Bus), watchpoint(space)]).unwrap_err();
+            assert_eq!(error.to_string(), format!("{} watchpoints aren't supported yet", space.name()));
+        }
+        assert_eq!(bus.watchpoints(), &[watchpoint(Space::Bus), watchpoint(Space::ARAM)][..]);
+    }
+}
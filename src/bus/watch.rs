@@ -0,0 +1,164 @@
+use std::cell::{Cell, RefCell};
+
+// the address spaces a watchpoint can cover
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Space {
+    Bus,   // what the main cpu and dma see, 24 bit bank:address
+    VRAM,
+    CGRAM,
+    OAM,
+    ARAM,  // the SPC700's 64KB
+}
+
+impl Space {
+    pub const ALL: [Space; 5] = [Space::Bus, Space::VRAM, Space::CGRAM, Space::OAM, Space::ARAM];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Space::Bus => "bus",
+            Space::VRAM => "vram",
+            Space::CGRAM => "cgram",
+            Space::OAM => "oam",
+            Space::ARAM => "aram",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Space> {
+        Space::ALL.iter().find(|space| space.name() == name).cloned()
+    }
+
+    // whether anything reports accesses to the space. the ppu doesn't keep vram, cgram or
+    // oam yet, so a watchpoint on them could never trigger
+    // todo -> vram, cgram and oam watchpoints, once the ppu has the memory to report on
+    pub fn is_watchable(self) -> bool {
+        match self {
+            Space::VRAM | Space::CGRAM | Space::OAM => false,
+            Space::Bus | Space::ARAM => true,
+        }
+    }
+
+    // in bytes
+    pub fn size(self) -> u32 {
+        match self {
+            Space::Bus => 0x1000000,
+            Space::VRAM => 0x10000,
+            Space::CGRAM => 0x200,
+            Space::OAM => 0x220,
+            Space::ARAM => 0x10000,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Access {
+    Read,
+    Write,
+    Execute, // an opcode fetch
+}
+
+impl Access {
+    fn bit(self) -> u8 {
+        match self {
+            Access::Read => READ,
+            Access::Write => WRITE,
+            Access::Execute => EXECUTE,
+        }
+    }
+}
+
+pub const READ: u8 = 0x01;
+pub const WRITE: u8 = 0x02;
+pub const EXECUTE: u8 = 0x04;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Watchpoint {
+    pub space: Space,
+    pub start: u32,
+    pub end: u32,       // inclusive
+    pub accesses: u8,   // READ | WRITE | EXECUTE
+    pub value: Option<u8>, // only trigger when this byte is read, written or executed
+}
+
+impl Watchpoint {
+    pub fn matches(&self, space: Space, address: u32, access: Access, value: u8) -> bool {
+        self.space == space
+            && self.accesses & access.bit() != 0
+            && address >= self.start && address <= self.end
+            && self.value.is_none_or(|expected| expected == value)
+    }
+}
+
+// one triggered watchpoint
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Hit {
+    pub index: usize, // of the watchpoint, in the list it was set from
+    pub space: Space,
+    pub address: u32,
+    pub access: Access,
+    pub value: u8,
+    pub pc: (u8, u16),           // the instruction running at the time, on the cpu that owns the space
+    pub dma_channel: Option<u8>, // set when dma made the access on the instruction's behalf
+}
+
+// checks accesses against the watchpoints for some of the address spaces. it sits under
+// the memory accessors, which take &self, so the hits and the current instruction are
+// kept in cells
+pub struct Watcher {
+    watchpoints: Vec<(usize, Watchpoint)>,
+    hits: RefCell<Vec<Hit>>,
+    pc: Cell<(u8, u16)>,
+    dma_channel: Cell<Option<u8>>,
+}
+
+impl Watcher {
+    pub fn new() -> Watcher {
+        Watcher {
+            watchpoints: Vec::new(),
+            hits: RefCell::new(Vec::new()),
+            pc: Cell::new((0, 0)),
+            dma_channel: Cell::new(None),
+        }
+    }
+
+    // keeps the watchpoints in the given spaces, remembering where each was in the list
+    pub fn set_watchpoints(&mut self, watchpoints: &[Watchpoint], spaces: &[Space]) {
+        self.watchpoints = watchpoints.iter().cloned().enumerate()
+            .filter(|&(_, watchpoint)| spaces.contains(&watchpoint.space))
+            .collect();
+    }
+
+    // checked before anything else so an idle watcher costs one branch per access
+    pub fn is_empty(&self) -> bool {
+        self.watchpoints.is_empty()
+    }
+
+    // called as each instruction starts, with its opcode
+    pub fn begin_instruction(&self, space: Space, bank: u8, pc: u16, opcode: u8) {
+        self.pc.set((bank, pc));
+        self.check(space, ((bank as u32) << 16) | pc as u32, Access::Execute, opcode);
+    }
+
+    pub fn set_dma_channel(&self, channel: Option<u8>) {
+        self.dma_channel.set(channel);
+    }
+
+    pub fn check(&self, space: Space, address: u32, access: Access, value: u8) {
+        for &(index, ref watchpoint) in self.watchpoints.iter() {
+            if watchpoint.matches(space, address, access, value) {
+                self.hits.borrow_mut().push(Hit {
+                    index,
+                    space,
+                    address,
+                    access,
+                    value,
+                    pc: self.pc.get(),
+                    dma_channel: self.dma_channel.get(),
+                });
+            }
+        }
+    }
+
+    pub fn take_hits(&self) -> Vec<Hit> {
+        self.hits.replace(Vec::new())
+    }
+}
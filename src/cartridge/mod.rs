@@ -0,0 +1,359 @@
+use std::fs;
+use std::io::{Error, ErrorKind};
+use std::path::Path;
+
+use util::crc32::crc32;
+
+// copiers prepend a 512 byte header that isn't part of the rom
+const COPIER_HEADER_SIZE: usize = 512;
+
+// offsets of the internal header for each mapping
+const LOROM_HEADER: usize = 0x7FC0;
+const HIROM_HEADER: usize = 0xFFC0;
+
+// the biggest sram a cartridge header can ask for that any board actually had
+const MAX_SRAM_SIZE: usize = 0x80000;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum MapMode {
+    LoROM,
+    HiROM,
+}
+
+// the internal header found at $FFC0-$FFDF in the cpu's address space
+#[derive(Clone, Debug)]
+pub struct CartridgeHeader {
+    pub title: String,        // 21 bytes, padded with spaces
+    pub map_mode: MapMode,
+    pub map_byte: u8,         // $FFD5: speed and mapping
+    pub cartridge_type: u8,   // $FFD6: rom / ram / battery / coprocessor
+    pub rom_size: u8,         // $FFD7: 1KB << n
+    pub sram_size: u8,        // $FFD8: 1KB << n, 0 for none
+    pub region: u8,           // $FFD9
+    pub developer: u8,        // $FFDA
+    pub version: u8,          // $FFDB
+    pub checksum_complement: u16,
+    pub checksum: u16,
+}
+
+impl CartridgeHeader {
+    fn parse(rom: &[u8], offset: usize, map_mode: MapMode) -> CartridgeHeader {
+        let h = &rom[offset..offset + 0x20];
+
+        let title = h[0x00..0x15].iter()
+            .map(|&c| if (0x20..0x7F).contains(&c) { c as char } else { ' ' })
+            .collect::<String>()
+            .trim_end()
+            .to_string();
+
+        CartridgeHeader {
+            title,
+            map_mode,
+            map_byte: h[0x15],
+            cartridge_type: h[0x16],
+            rom_size: h[0x17],
+            sram_size: h[0x18],
+            region: h[0x19],
+            developer: h[0x1A],
+            version: h[0x1B],
+            checksum_complement: (h[0x1C] as u16) | ((h[0x1D] as u16) << 8),
+            checksum: (h[0x1E] as u16) | ((h[0x1F] as u16) << 8),
+        }
+    }
+
+    // $FFD8 is 1KB << n. anything bigger than any real board is treated as none
+    pub fn sram_bytes(&self) -> usize {
+        match self.sram_size {
+            0 => 0,
+            n if n < 16 && (0x400 << n) <= MAX_SRAM_SIZE => 0x400 << n,
+            _ => 0,
+        }
+    }
+
+    // the low nibble of $FFD6: 2 is rom + ram + battery, 5 and 6 are the same with a coprocessor
+    pub fn has_battery(&self) -> bool {
+        matches!(self.cartridge_type & 0x0F, 0x02 | 0x05 | 0x06)
+    }
+
+    // rough guess at how likely it is that a real header lives at this offset
+    fn score(rom: &[u8], offset: usize, map_mode: MapMode) -> i32 {
+        if rom.len() < offset + 0x40 {
+            return -1;
+        }
+
+        let h = &rom[offset..offset + 0x20];
+        let mut score = 0;
+
+        let complement = (h[0x1C] as u16) | ((h[0x1D] as u16) << 8);
+        let checksum = (h[0x1E] as u16) | ((h[0x1F] as u16) << 8);
+        if checksum.wrapping_add(complement) == 0xFFFF {
+            score += 4;
+        }
+
+        let mode = h[0x15] & 0x0F;
+        match map_mode {
+            MapMode::LoROM if mode == 0x00 || mode == 0x02 => score += 2,
+            MapMode::HiROM if mode == 0x01 || mode == 0x05 => score += 2,
+            _ => {}
+        }
+
+        if h[0x00..0x15].iter().all(|&c| (0x20..0x7F).contains(&c)) {
+            score += 1;
+        }
+
+        // the reset vector should point into rom
+        let reset = (rom[offset + 0x3C] as u16) | ((rom[offset + 0x3D] as u16) << 8);
+        if reset >= 0x8000 {
+            score += 1;
+        }
+
+        score
+    }
+}
+
+pub struct Cartridge {
+    rom: Vec<u8>,
+    header: CartridgeHeader,
+    crc32: u32, // of the rom without a copier header, identifies the game in save states
+
+    sram: Vec<u8>,
+    sram_dirty: bool, // written since it was last saved
+}
+
+impl Cartridge {
+    // takes a raw .smc/.sfc file, with or without a copier header
+    pub fn new(mut rom: Vec<u8>) -> Result<Cartridge, Error> {
+        let header_len = copier_header_len(&rom);
+        rom.drain(0..header_len);
+
+        let lo = CartridgeHeader::score(&rom, LOROM_HEADER, MapMode::LoROM);
+        let hi = CartridgeHeader::score(&rom, HIROM_HEADER, MapMode::HiROM);
+
+        if lo < 0 && hi < 0 {
+            return Err(Error::new(ErrorKind::InvalidData, "rom is too small to contain a header"));
+        }
+
+        let header = if hi > lo {
+            CartridgeHeader::parse(&rom, HIROM_HEADER, MapMode::HiROM)
+        } else {
+            CartridgeHeader::parse(&rom, LOROM_HEADER, MapMode::LoROM)
+        };
+
+        let crc32 = crc32(&rom);
+        let sram = vec![0; header.sram_bytes()];
+
+        Ok(Cartridge {
+            rom,
+            header,
+            crc32,
+
+            sram,
+            sram_dirty: false,
+        })
+    }
+
+    pub fn rom(&self) -> &[u8] {
+        &self.rom
+    }
+
+    pub fn header(&self) -> &CartridgeHeader {
+        &self.header
+    }
+
+    // whether the header's checksum is the rom's. dumps and most hacks keep it right
+    pub fn checksum_matches(&self) -> bool {
+        rom_checksum(&self.rom) == self.header.checksum
+    }
+
+    pub fn crc32(&self) -> u32 {
+        self.crc32
+    }
+
+    // where a cpu address lands in the rom, if it's rom at all for this mapping.
+    // roms that don't fill their address space are mirrored
+    pub fn rom_offset(&self, bank: u8, address: u16) -> Option<usize> {
+        if self.rom.is_empty() || bank & 0xFE == 0x7E {
+            return None;
+        }
+
+        let offset = match self.header.map_mode {
+            // 32KB per bank in the upper half. banks $40-$6F mirror it in the lower half too
+            MapMode::LoROM => {
+                let b = (bank & 0x7F) as usize;
+                if address >= 0x8000 {
+                    b * 0x8000 + (address - 0x8000) as usize
+                } else if (0x40..0x70).contains(&b) {
+                    b * 0x8000 + address as usize
+                } else {
+                    return None;
+                }
+            },
+            // 64KB banks at $40-$7D / $C0-$FF, with their upper halves also in the system banks
+            MapMode::HiROM => {
+                if bank & 0x40 != 0 || address >= 0x8000 {
+                    ((bank & 0x3F) as usize) << 16 | address as usize
+                } else {
+                    return None;
+                }
+            },
+        };
+
+        Some(offset % self.rom.len())
+    }
+
+    // the usual cpu address of a rom offset: the first banks for LoROM, $C0 and up for HiROM
+    pub fn rom_address(&self, offset: usize) -> Option<(u8, u16)> {
+        if offset >= self.rom.len() {
+            return None;
+        }
+
+        match self.header.map_mode {
+            MapMode::LoROM => Some(((offset >> 15) as u8, 0x8000 | (offset & 0x7FFF) as u16)),
+            MapMode::HiROM => Some((0xC0 | (offset >> 16) as u8, offset as u16)),
+        }
+    }
+
+    // where a cpu address lands in sram, if the board has any. sram smaller than its
+    // window is mirrored through it
+    pub fn sram_offset(&self, bank: u8, address: u16) -> Option<usize> {
+        if self.sram.is_empty() {
+            return None;
+        }
+
+        let b = (bank & 0x7F) as usize;
+        let offset = match self.header.map_mode {
+            // the lower half of banks $70-$7D and $F0-$FF. $7E/$7F are wram, but their mirrors
+            // $FE/$FF aren't
+            MapMode::LoROM if ((0x70..=0x7D).contains(&bank) || bank >= 0xF0) && address < 0x8000 => {
+                (b - 0x70) * 0x8000 + address as usize
+            },
+            // 8KB at $6000-$7FFF in banks $20-$3F and $A0-$BF
+            MapMode::HiROM if (0x20..0x40).contains(&b) && (0x6000..0x8000).contains(&address) => {
+                (b - 0x20) * 0x2000 + (address - 0x6000) as usize
+            },
+            _ => return None,
+        };
+
+        Some(offset % self.sram.len())
+    }
+
+    // the first cpu address an sram offset shows up at
+    pub fn sram_address(&self, offset: usize) -> Option<(u8, u16)> {
+        if offset >= self.sram.len() {
+            return None;
+        }
+
+        match self.header.map_mode {
+            MapMode::LoROM => Some((0x70 + (offset >> 15) as u8, (offset & 0x7FFF) as u16)),
+            MapMode::HiROM => Some((0x20 + (offset >> 13) as u8, 0x6000 | (offset & 0x1FFF) as u16)),
+        }
+    }
+
+    pub fn read(&self, bank: u8, address: u16) -> Option<u8> {
+        if let Some(offset) = self.sram_offset(bank, address) {
+            return Some(self.sram[offset]);
+        }
+        self.rom_offset(bank, address).map(|offset| self.rom[offset])
+    }
+
+    // returns false if the address isn't on the cartridge. writes to rom are dropped
+    pub fn write(&mut self, bank: u8, address: u16, data: u8) -> bool {
+        if let Some(offset) = self.sram_offset(bank, address) {
+            if self.sram[offset] != data {
+                self.sram[offset] = data;
+                self.sram_dirty = true;
+            }
+            return true;
+        }
+        self.rom_offset(bank, address).is_some()
+    }
+
+    // changes a byte of rom in place, the way a debugger patches code. crc32 stays the
+    // original rom's, so save states and movies made before still load
+    pub fn patch_rom(&mut self, bank: u8, address: u16, data: u8) -> bool {
+        match self.rom_offset(bank, address) {
+            Some(offset) => {
+                self.rom[offset] = data;
+                true
+            },
+            None => false,
+        }
+    }
+
+    ////////////////////////////////////
+    //
+    //              SRAM
+    //
+    ////////////////////////////////////
+
+    pub fn sram(&self) -> &[u8] {
+        &self.sram
+    }
+
+    // counts as a write, so the sram gets saved again
+    pub fn sram_mut(&mut self) -> &mut [u8] {
+        self.sram_dirty = true;
+        &mut self.sram
+    }
+
+    // only battery backed sram outlives the console being switched off
+    pub fn has_battery_sram(&self) -> bool {
+        !self.sram.is_empty() && self.header.has_battery()
+    }
+
+    pub fn is_sram_dirty(&self) -> bool {
+        self.sram_dirty
+    }
+
+    // a .srm file is the raw contents of sram. files of the wrong size, eg from another
+    // emulator that rounds sizes differently, are truncated or padded
+    pub fn load_sram(&mut self, path: &str) -> Result<(), Error> {
+        let bytes = fs::read(path)?;
+        let len = bytes.len().min(self.sram.len());
+
+        self.sram[..len].copy_from_slice(&bytes[..len]);
+        for byte in self.sram[len..].iter_mut() {
+            *byte = 0;
+        }
+        self.sram_dirty = false;
+        Ok(())
+    }
+
+    pub fn save_sram(&mut self, path: &str) -> Result<(), Error> {
+        fs::write(path, &self.sram)?;
+        self.sram_dirty = false;
+        Ok(())
+    }
+}
+
+// roms are a multiple of 1KB, anything left over is a copier header
+pub fn copier_header_len(file: &[u8]) -> usize {
+    if file.len() % 1024 == COPIER_HEADER_SIZE {
+        COPIER_HEADER_SIZE
+    } else {
+        0
+    }
+}
+
+// the 16 bit sum of the rom's bytes, the one its header holds. a rom that isn't a power
+// of two in size is summed as if its last part were mirrored up to one, as on the cartridge
+pub fn rom_checksum(rom: &[u8]) -> u16 {
+    if rom.is_empty() {
+        return 0;
+    }
+    let sum = |bytes: &[u8]| bytes.iter().fold(0u16, |sum, &byte| sum.wrapping_add(byte as u16));
+
+    let first = if rom.len().is_power_of_two() { rom.len() } else { rom.len().next_power_of_two() / 2 };
+    let rest = &rom[first..];
+    if rest.is_empty() {
+        return sum(rom);
+    }
+
+    let mirrors = first / rest.len().next_power_of_two();
+    sum(&rom[..first]).wrapping_add(rom_checksum(rest).wrapping_mul(mirrors as u16))
+}
+
+// game.sfc -> game.srm
+pub fn sram_path(rom_path: &str) -> String {
+    Path::new(rom_path).with_extension("srm").to_string_lossy().into_owned()
+}
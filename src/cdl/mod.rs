@@ -0,0 +1,262 @@
+use std::fs;
+use std::io::{Error, ErrorKind};
+use std::path::Path;
+
+use cpu::disasm::{self, Instruction, Mode};
+
+// code/data log layout
+// ====================
+// one flag byte per rom byte in Mesen's snes layout, saved as is with nothing around it,
+// the same length as the rom without its copier header, so Mesen and the debuggers that
+// read its files can use our logs and we can use theirs. the low byte of the flags below
+// is that file. Mesen keeps 0x40 and 0x80 for the GSU and CX4, which we never set.
+// what Mesen has no bit for is the high byte, saved the same way to a second file next
+// to the log. a log without one, eg from Mesen, just doesn't know those.
+// flags only ever get set, so a log can be built up over many runs
+const EXTENSION: &'static str = "cdl";
+const EXTRA_EXTENSION: &'static str = "extra";
+
+pub const CODE: u16 = 0x01;            // executed, as an opcode or an operand
+pub const DATA: u16 = 0x02;            // read by an instruction
+pub const JUMP_TARGET: u16 = 0x04;     // where a taken branch or jump went
+pub const SUB_ENTRY_POINT: u16 = 0x08; // where a JSR or JSL went
+pub const X8: u16 = 0x10;              // executed with 8 bit index registers
+pub const M8: u16 = 0x20;              // executed with an 8 bit accumulator
+
+pub const INDIRECT: u16 = 0x0100; // read as the pointer of an indirect address or jump
+pub const DMA: u16 = 0x0200;      // read by dma
+pub const OPCODE: u16 = 0x0400;   // the first byte of an instruction
+
+// what the running instruction is made of, so its own fetches aren't logged as data
+#[derive(Clone, Copy)]
+struct Running {
+    instruction: (u8, u16, usize),      // bank, address, size
+    pointer: Option<(u8, u16, usize)>, // bank, address, size
+    jump: Option<u16>,                  // what to mark the next instruction if it doesn't follow on
+}
+
+pub struct CodeDataLog {
+    flags: Vec<u16>,
+    running: Option<Running>,
+    dma: bool,
+}
+
+impl CodeDataLog {
+    // an empty log for a rom of this many bytes
+    pub fn new(rom_len: usize) -> CodeDataLog {
+        CodeDataLog {
+            flags: vec![0; rom_len],
+            running: None,
+            dma: false,
+        }
+    }
+
+    pub fn flags(&self) -> &[u16] {
+        &self.flags
+    }
+
+    // the flags of one rom byte, 0 for offsets past the end
+    pub fn flags_at(&self, offset: usize) -> u16 {
+        self.flags.get(offset).cloned().unwrap_or(0)
+    }
+
+    // fraction of the rom that's been logged as anything
+    pub fn coverage(&self) -> f64 {
+        if self.flags.is_empty() {
+            return 0.0;
+        }
+        self.flags.iter().filter(|&&flags| flags != 0).count() as f64 / self.flags.len() as f64
+    }
+
+    pub fn clear(&mut self) {
+        for flags in self.flags.iter_mut() {
+            *flags = 0;
+        }
+    }
+
+    // the extra flags are read too if they were saved next to the log
+    pub fn load(path: &str, rom_len: usize) -> Result<CodeDataLog, Error> {
+        let low = read_flags(path, rom_len)?;
+        let extra = extra_path(path);
+        let high = if Path::new(&extra).is_file() { read_flags(&extra, rom_len)? } else { vec![0; rom_len] };
+
+        Ok(CodeDataLog {
+            flags: low.iter().zip(high.iter()).map(|(&low, &high)| (high as u16) << 8 | low as u16).collect(),
+            running: None,
+            dma: false,
+        })
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), Error> {
+        fs::write(path, self.flags.iter().map(|&flags| flags as u8).collect::<Vec<u8>>())?;
+        fs::write(extra_path(path), self.flags.iter().map(|&flags| (flags >> 8) as u8).collect::<Vec<u8>>())
+    }
+
+    ////////////////////////////////////
+    //
+    //             LOGGING
+    //
+    ////////////////////////////////////
+
+    // called as each instruction starts. code_offset maps the instruction's bus addresses
+    // to rom offsets. pointer is where an indirect mode reads its address from, see pointer()
+    pub fn begin_instruction<F>(&mut self, instruction: &Instruction, m8: bool, x8: bool, pointer: Option<(u8, u16, usize)>, code_offset: F)
+        where F: Fn(u8, u16) -> Option<usize>
+    {
+        let widths = if m8 { M8 } else { 0 } | if x8 { X8 } else { 0 };
+
+        // the last instruction was a branch, jump or call and this isn't the one after it
+        if let Some(Running { instruction: (bank, address, size), jump: Some(flag), .. }) = self.running {
+            if (instruction.bank, instruction.address) != (bank, address.wrapping_add(size as u16)) {
+                if let Some(offset) = code_offset(instruction.bank, instruction.address) {
+                    self.mark(offset, flag);
+                }
+            }
+        }
+
+        for n in 0..instruction.size() {
+            let address = instruction.address.wrapping_add(n as u16);
+            if let Some(offset) = code_offset(instruction.bank, address) {
+                let opcode = if n == 0 { OPCODE } else { 0 };
+                self.mark(offset, CODE | widths | opcode);
+            }
+        }
+
+        self.running = Some(Running {
+            instruction: (instruction.bank, instruction.address, instruction.size()),
+            pointer,
+            jump: jump_flag(instruction),
+        });
+    }
+
+    // dma runs in the middle of the instruction that started it, everything it reads is
+    // logged as DMA until it's done
+    pub fn set_dma(&mut self, dma: bool) {
+        self.dma = dma;
+    }
+
+    // a rom read through the bus
+    pub fn log_read(&mut self, bank: u8, address: u16, offset: usize) {
+        if self.dma {
+            return self.mark(offset, DMA);
+        }
+
+        let running = match self.running {
+            Some(running) => running,
+            None => return self.mark(offset, DATA),
+        };
+
+        if within(running.instruction, bank, address) {
+            return;
+        }
+        if running.pointer.is_some_and(|pointer| within(pointer, bank, address)) {
+            return self.mark(offset, INDIRECT);
+        }
+        self.mark(offset, DATA);
+    }
+
+    fn mark(&mut self, offset: usize, flags: u16) {
+        if let Some(existing) = self.flags.get_mut(offset) {
+            *existing |= flags;
+        }
+    }
+}
+
+fn read_flags(path: &str, rom_len: usize) -> Result<Vec<u8>, Error> {
+    let flags = fs::read(path)?;
+    if flags.len() != rom_len {
+        return Err(Error::new(ErrorKind::InvalidData, format!("{} is for a rom of {} bytes, this one is {}", path, flags.len(), rom_len)));
+    }
+    Ok(flags)
+}
+
+// what an instruction that can go somewhere other than the next one marks where it went
+fn jump_flag(instruction: &Instruction) -> Option<u16> {
+    if instruction.is_call() {
+        return Some(SUB_ENTRY_POINT);
+    }
+    match (instruction.mnemonic(), instruction.mode()) {
+        ("PER", _) => None,
+        (_, Mode::Relative8) | (_, Mode::Relative16) => Some(JUMP_TARGET),
+        ("JMP", _) | ("JML", _) => Some(JUMP_TARGET),
+        _ => None,
+    }
+}
+
+fn within((bank, start, len): (u8, u16, usize), at_bank: u8, at: u16) -> bool {
+    bank == at_bank && (at.wrapping_sub(start) as usize) < len
+}
+
+// where an indirect addressing mode or jump reads its pointer from, as bank, address and
+// size, given the registers it runs with
+pub fn pointer(instruction: &Instruction, d: u16, s: u16, x: u16, pbr: u8) -> Option<(u8, u16, usize)> {
+    let operand = instruction.value() as u16;
+
+    match instruction.mode() {
+        Mode::DirectIndirect | Mode::DirectIndirectIndexed => Some((0, d.wrapping_add(operand), 2)),
+        Mode::DirectIndexedIndirect => Some((0, d.wrapping_add(operand).wrapping_add(x), 2)),
+        Mode::DirectIndirectLong | Mode::DirectIndirectLongIndexed => Some((0, d.wrapping_add(operand), 3)),
+        Mode::StackRelativeIndirectIndexed => Some((0, s.wrapping_add(operand), 2)),
+        Mode::AbsoluteIndirect => Some((0, operand, 2)),
+        Mode::AbsoluteIndexedIndirect => Some((pbr, operand.wrapping_add(x), 2)),
+        Mode::AbsoluteIndirectLong => Some((0, operand, 3)),
+        _ => None,
+    }
+}
+
+////////////////////////////////////
+//
+//           DISASSEMBLY
+//
+////////////////////////////////////
+
+// the most bytes a data line groups together
+const DATA_LINE_LEN: usize = 8;
+
+// what the disassembler makes of the bytes at an address
+#[derive(Clone, PartialEq, Debug)]
+pub enum Line {
+    Code(Instruction),
+    Data { bank: u8, address: u16, bytes: Vec<u8> },
+}
+
+impl Line {
+    // where the next line starts. addresses wrap within the bank
+    pub fn next_address(&self) -> u16 {
+        match *self {
+            Line::Code(ref instruction) => instruction.next_address(),
+            Line::Data { address, ref bytes, .. } => address.wrapping_add(bytes.len() as u16),
+        }
+    }
+}
+
+// decodes the bytes at bank:address using what the log knows about them. opcodes are
+// decoded with the widths they ran with and bytes only ever read make a data line.
+// bytes the log hasn't seen, or flags_at can't place, decode as code with m8 and x8
+pub fn disassemble<R, F>(read: R, flags_at: F, bank: u8, address: u16, m8: bool, x8: bool) -> Line
+    where R: Fn(u8, u16) -> u8, F: Fn(u8, u16) -> u16
+{
+    let flags = flags_at(bank, address);
+
+    if flags & CODE == 0 && flags != 0 {
+        let bytes = (0..DATA_LINE_LEN as u16)
+            .map(|n| address.wrapping_add(n))
+            .take_while(|&at| at == address || flags_at(bank, at) & CODE == 0 && flags_at(bank, at) != 0)
+            .map(|at| read(bank, at))
+            .collect();
+        return Line::Data { bank, address, bytes };
+    }
+
+    let (m8, x8) = if flags & OPCODE != 0 { (flags & M8 != 0, flags & X8 != 0) } else { (m8, x8) };
+    Line::Code(disasm::disassemble(read, bank, address, m8, x8))
+}
+
+// game.sfc -> game.cdl
+pub fn cdl_path(rom_path: &str) -> String {
+    Path::new(rom_path).with_extension(EXTENSION).to_string_lossy().into_owned()
+}
+
+// game.cdl -> game.cdl.extra, where the flags Mesen has no bit for go
+pub fn extra_path(cdl_path: &str) -> String {
+    format!("{}.{}", cdl_path, EXTRA_EXTENSION)
+}
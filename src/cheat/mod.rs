@@ -0,0 +1,480 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io::{Error, ErrorKind};
+use std::path::Path;
+
+// cheat list layout
+// =================
+// a text file next to the rom, one cheat per line:
+//   on  7E:0DBE:63            infinite lives
+//   off C2D3-ABCD+7E:0019:02  several codes joined with '+' make up one cheat
+// a cheat is "on" or "off", its codes and then an optional description.
+// blank lines and lines starting with '#' are skipped
+//
+// codes
+// =====
+//   7E:0DBE:63 or 7E0DBE63   pro action replay, bank:address:value written to ram every frame
+//   C2D3-ABCD                game genie, replaces what the cpu reads from the rom
+// either kind can end in "?XX" to make it a compare code, which only takes effect while
+// the byte underneath is XX. it keeps a code for one bank switched chunk of a rom from
+// breaking another one mapped at the same address
+const EXTENSION: &'static str = "cht";
+
+// game genie letters in the order of the hex digit they stand for
+const GAME_GENIE_LETTERS: &'static [u8; 16] = b"DF4709156BC8A23E";
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum CodeKind {
+    ProActionReplay,
+    GameGenie,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Code {
+    pub kind: CodeKind,
+    pub address: u32, // 24 bit, bank in the top 8 bits
+    pub value: u8,
+    pub compare: Option<u8>,
+}
+
+impl Code {
+    pub fn parse(text: &str) -> Result<Code, Error> {
+        let bad = || Error::new(ErrorKind::InvalidData, format!("bad cheat code: {}", text));
+
+        let (code, compare) = match text.find('?') {
+            Some(at) => (&text[..at], Some(parse_hex(&text[at + 1..], 2).ok_or_else(bad)? as u8)),
+            None => (text, None),
+        };
+
+        let (kind, address, value) = if code.len() == 9 && code.as_bytes()[4] == b'-' {
+            let (address, value) = decode_game_genie(&code.replace('-', "")).ok_or_else(bad)?;
+            (CodeKind::GameGenie, address, value)
+        } else {
+            let digits = match code.len() {
+                8 => code.to_string(),
+                10 if code.as_bytes()[2] == b':' && code.as_bytes()[7] == b':' => code.replace(':', ""),
+                _ => return Err(bad()),
+            };
+            let raw = parse_hex(&digits, 8).ok_or_else(bad)?;
+            (CodeKind::ProActionReplay, raw >> 8, raw as u8)
+        };
+
+        Ok(Code {
+            kind,
+            address,
+            value,
+            compare,
+        })
+    }
+
+    pub fn bank(&self) -> u8 {
+        (self.address >> 16) as u8
+    }
+
+    pub fn offset(&self) -> u16 {
+        self.address as u16
+    }
+}
+
+impl fmt::Display for Code {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.kind {
+            CodeKind::ProActionReplay => write!(f, "{:02X}:{:04X}:{:02X}", self.bank(), self.offset(), self.value)?,
+            CodeKind::GameGenie => {
+                let letters = encode_game_genie(self.address, self.value);
+                write!(f, "{}-{}", &letters[..4], &letters[4..])?;
+            },
+        }
+
+        match self.compare {
+            Some(compare) => write!(f, "?{:02X}", compare),
+            None => Ok(()),
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct Cheat {
+    pub codes: Vec<Code>,
+    pub enabled: bool,
+    pub description: String,
+}
+
+impl Cheat {
+    // codes joined with '+'
+    pub fn parse(codes: &str, description: &str) -> Result<Cheat, Error> {
+        Ok(Cheat {
+            codes: codes.split('+').map(Code::parse).collect::<Result<_, _>>()?,
+            enabled: true,
+            description: description.to_string(),
+        })
+    }
+}
+
+// the cheats for the loaded game. the bus asks this for every rom read and applies the
+// ram writes at the start of each vblank, when the game is about to run its nmi handler
+pub struct Cheats {
+    cheats: Vec<Cheat>,
+
+    // built from the enabled cheats whenever the list changes
+    reads: HashMap<u32, (u8, Option<u8>)>,
+    writes: Vec<Code>,
+}
+
+impl Cheats {
+    pub fn new() -> Cheats {
+        Cheats {
+            cheats: Vec::new(),
+            reads: HashMap::new(),
+            writes: Vec::new(),
+        }
+    }
+
+    pub fn cheats(&self) -> &[Cheat] {
+        &self.cheats
+    }
+
+    pub fn add(&mut self, cheat: Cheat) {
+        self.cheats.push(cheat);
+        self.rebuild();
+    }
+
+    pub fn remove(&mut self, index: usize) -> Option<Cheat> {
+        if index >= self.cheats.len() {
+            return None;
+        }
+        let cheat = self.cheats.remove(index);
+        self.rebuild();
+        Some(cheat)
+    }
+
+    pub fn set_enabled(&mut self, index: usize, enabled: bool) {
+        if let Some(cheat) = self.cheats.get_mut(index) {
+            cheat.enabled = enabled;
+        }
+        self.rebuild();
+    }
+
+    pub fn clear(&mut self) {
+        self.cheats.clear();
+        self.rebuild();
+    }
+
+    fn rebuild(&mut self) {
+        self.reads.clear();
+        self.writes.clear();
+
+        for code in self.cheats.iter().filter(|cheat| cheat.enabled).flat_map(|cheat| cheat.codes.iter()) {
+            match code.kind {
+                CodeKind::ProActionReplay => self.writes.push(*code),
+                CodeKind::GameGenie => {
+                    self.reads.insert(code.address, (code.value, code.compare));
+                },
+            }
+        }
+    }
+
+    // what the cpu sees when it reads data from the rom at the given address
+    pub fn patch_read(&self, bank: u8, address: u16, data: u8) -> u8 {
+        if self.reads.is_empty() {
+            return data;
+        }
+
+        match self.reads.get(&(((bank as u32) << 16) | address as u32)) {
+            Some(&(value, compare)) if compare.is_none_or(|compare| compare == data) => value,
+            _ => data,
+        }
+    }
+
+    // the ram writes to make this frame
+    pub fn writes(&self) -> &[Code] {
+        &self.writes
+    }
+
+    ////////////////////////////////////
+    //
+    //            CHEAT FILES
+    //
+    ////////////////////////////////////
+
+    pub fn load(&mut self, path: &str) -> Result<(), Error> {
+        let text = fs::read_to_string(path)?;
+        self.cheats = Self::parse(&text)?;
+        self.rebuild();
+        Ok(())
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), Error> {
+        fs::write(path, self.to_text())
+    }
+
+    pub fn parse(text: &str) -> Result<Vec<Cheat>, Error> {
+        let mut cheats = Vec::new();
+
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let (state, rest) = split_word(line);
+            let enabled = match state {
+                "on" => true,
+                "off" => false,
+                _ => return Err(line_error(number, "expected on or off")),
+            };
+            let (codes, description) = split_word(rest);
+            if codes.is_empty() {
+                return Err(line_error(number, "missing codes"));
+            }
+
+            let mut cheat = Cheat::parse(codes, description).map_err(|e| line_error(number, &e.to_string()))?;
+            cheat.enabled = enabled;
+            cheats.push(cheat);
+        }
+
+        Ok(cheats)
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut text = String::new();
+
+        for cheat in self.cheats.iter() {
+            let codes: Vec<String> = cheat.codes.iter().map(|code| code.to_string()).collect();
+            let line = format!("{:<3} {} {}", if cheat.enabled { "on" } else { "off" }, codes.join("+"), cheat.description);
+            text += line.trim_end();
+            text += "\n";
+        }
+
+        text
+    }
+}
+
+// game.sfc -> game.cht
+pub fn cheat_path(rom_path: &str) -> String {
+    Path::new(rom_path).with_extension(EXTENSION).to_string_lossy().into_owned()
+}
+
+// the first word and whatever follows it
+fn split_word(text: &str) -> (&str, &str) {
+    match text.find(char::is_whitespace) {
+        Some(at) => (&text[..at], text[at..].trim()),
+        None => (text, ""),
+    }
+}
+
+fn line_error(number: usize, reason: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, format!("cheat list line {}: {}", number + 1, reason))
+}
+
+fn parse_hex(digits: &str, len: usize) -> Option<u32> {
+    if digits.len() != len || !digits.bytes().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    u32::from_str_radix(digits, 16).ok()
+}
+
+////////////////////////////////////
+//
+//            GAME GENIE
+//
+////////////////////////////////////
+
+// 8 letters, each standing for a hex digit. the first 2 digits are the value and the other
+// 6 the address with its bits shuffled:
+//   ijklqrst opabcduv wxefghmn -> abcdefgh ijklmnop qrstuvwx
+fn decode_game_genie(letters: &str) -> Option<(u32, u8)> {
+    if letters.len() != 8 {
+        return None;
+    }
+
+    let mut raw: u32 = 0;
+    for c in letters.bytes() {
+        let digit = GAME_GENIE_LETTERS.iter().position(|&letter| letter == c.to_ascii_uppercase())?;
+        raw = (raw << 4) | digit as u32;
+    }
+
+    let value = (raw >> 24) as u8;
+    let n = raw & 0xFFFFFF;
+    let address = ((n & 0x003C00) << 10)
+                | ((n & 0x00003C) << 14)
+                | ((n & 0xF00000) >> 8)
+                | ((n & 0x000003) << 10)
+                | ((n & 0x00C000) >> 6)
+                | ((n & 0x0F0000) >> 12)
+                | ((n & 0x0003C0) >> 6);
+
+    Some((address, value))
+}
+
+fn encode_game_genie(address: u32, value: u8) -> String {
+    let n = ((address & 0xF00000) >> 10)
+          | ((address & 0x0F0000) >> 14)
+          | ((address & 0x00F000) << 8)
+          | ((address & 0x000C00) >> 10)
+          | ((address & 0x000300) << 6)
+          | ((address & 0x0000F0) << 12)
+          | ((address & 0x00000F) << 6);
+    let raw = ((value as u32) << 24) | n;
+
+    (0..8).rev()
+        .map(|digit| GAME_GENIE_LETTERS[((raw >> (digit * 4)) & 0xF) as usize] as char)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // one code per address bit, worked out by hand from the bit map above
+    const SINGLE_BITS: &'static [(u32, &'static str)] = &[
+        (0x800000, "DDDD-4DDD"), (0x400000, "DDDD-FDDD"), (0x200000, "DDDD-D6DD"), (0x100000, "DDDD-D0DD"),
+        (0x080000, "DDDD-DD4D"), (0x040000, "DDDD-DDFD"), (0x020000, "DDDD-DDD6"), (0x010000, "DDDD-DDD0"),
+        (0x008000, "DD6D-DDDD"), (0x004000, "DD0D-DDDD"), (0x002000, "DD4D-DDDD"), (0x001000, "DDFD-DDDD"),
+        (0x000800, "DDDD-DDD4"), (0x000400, "DDDD-DDDF"), (0x000200, "DDDD-6DDD"), (0x000100, "DDDD-0DDD"),
+        (0x000080, "DDD6-DDDD"), (0x000040, "DDD0-DDDD"), (0x000020, "DDD4-DDDD"), (0x000010, "DDDF-DDDD"),
+        (0x000008, "DDDD-D4DD"), (0x000004, "DDDD-DFDD"), (0x000002, "DDDD-DD6D"), (0x000001, "DDDD-DD0D"),
+    ];
+
+    // whole codes, decoded separately through the same bit map
+    const CODES: &'static [(&'static str, u32, u8)] = &[
+        ("DD32-6DAD", 0x00E2D3, 0x00),
+        ("C2B3-4F07", 0x809CE5, 0xAD),
+        ("F38C-4404", 0x80B8A9, 0x1E),
+        ("6D6B-DF0D", 0x008095, 0x80),
+        ("DF4D-0F6D", 0x002106, 0x01),
+        ("3C6D-7DDD", 0xC08000, 0xEA),
+    ];
+
+    fn game_genie(address: u32, value: u8) -> Code {
+        Code { kind: CodeKind::GameGenie, address, value, compare: None }
+    }
+
+    #[test]
+    fn game_genie_address_bits() {
+        for &(address, text) in SINGLE_BITS.iter() {
+            assert_eq!(Code::parse(text).unwrap(), game_genie(address, 0), "{}", text);
+            assert_eq!(game_genie(address, 0).to_string(), text, "{:06X}", address);
+        }
+    }
+
+    #[test]
+    fn game_genie_codes() {
+        for &(text, address, value) in CODES.iter() {
+            assert_eq!(Code::parse(text).unwrap(), game_genie(address, value), "{}", text);
+            assert_eq!(game_genie(address, value).to_string(), text);
+        }
+
+        // the value letters, and lower case
+        assert_eq!(Code::parse("E3DD-DDDD").unwrap().value, 0xFE);
+        assert_eq!(Code::parse("c2b3-4f07").unwrap(), game_genie(0x809CE5, 0xAD));
+    }
+
+    #[test]
+    fn pro_action_replay() {
+        let code = Code {
+            kind: CodeKind::ProActionReplay,
+            address: 0x7E0DBE,
+            value: 0x63,
+            compare: None,
+        };
+        assert_eq!(Code::parse("7E:0DBE:63").unwrap(), code);
+        assert_eq!(Code::parse("7E0DBE63").unwrap(), code);
+        assert_eq!(Code::parse("7e0dbe63").unwrap(), code);
+        assert_eq!((code.bank(), code.offset()), (0x7E, 0x0DBE));
+        assert_eq!(code.to_string(), "7E:0DBE:63");
+    }
+
+    #[test]
+    fn bad_codes() {
+        for text in ["", "7E0DBE6", "7E0DBE633", "7E-0DBE-63", "7E:0DBE63", "XE0DBE63", "C2B3-4F0X", "C2B34-F07",
+                     "C2B3-4F07?", "C2B3-4F07?1", "C2B3-4F07?XY", "7E0DBE63?123"].iter() {
+            assert!(Code::parse(text).is_err(), "{}", text);
+        }
+    }
+
+    #[test]
+    fn compare_codes() {
+        let code = Code::parse("DD32-6DAD?A9").unwrap();
+        assert_eq!(code.compare, Some(0xA9));
+        assert_eq!(code.to_string(), "DD32-6DAD?A9");
+        assert_eq!(Code::parse("7E0DBE63?05").unwrap().to_string(), "7E:0DBE:63?05");
+
+        let mut cheats = Cheats::new();
+        cheats.add(Cheat::parse("DD32-6DAD?A9+DF4D-0F6D", "").unwrap());
+
+        // only while the rom holds the compare byte
+        assert_eq!(cheats.patch_read(0x00, 0xE2D3, 0xA9), 0x00);
+        assert_eq!(cheats.patch_read(0x00, 0xE2D3, 0xAD), 0xAD);
+
+        // without a compare byte it always applies, and only at its own address
+        assert_eq!(cheats.patch_read(0x00, 0x2106, 0x55), 0x01);
+        assert_eq!(cheats.patch_read(0x80, 0x2106, 0x55), 0x55);
+
+        cheats.set_enabled(0, false);
+        assert_eq!(cheats.patch_read(0x00, 0x2106, 0x55), 0x55);
+    }
+
+    #[test]
+    fn writes_come_from_enabled_replay_codes() {
+        let mut cheats = Cheats::new();
+        cheats.add(Cheat::parse("7E0DBE63+C2B3-4F07", "").unwrap());
+        cheats.add(Cheat::parse("7E:0019:02", "").unwrap());
+        assert_eq!(cheats.writes().iter().map(|code| code.address).collect::<Vec<_>>(), vec![0x7E0DBE, 0x7E0019]);
+
+        cheats.set_enabled(0, false);
+        assert_eq!(cheats.writes().len(), 1);
+        assert!(cheats.remove(1).is_some());
+        assert!(cheats.writes().is_empty());
+    }
+
+    #[test]
+    fn cheat_list_round_trip() {
+        let text = "# smw\n\
+                    on  7E:0DBE:63 infinite lives\n\
+                    \n\
+                    off   c2b3-4f07+7E0019 02\n\
+                    off C2B3-4F07+7E001902   big mario  \n\
+                    on DD32-6DAD?A9\n";
+        assert!(Cheats::parse(text).is_err());
+
+        let text = text.replace("+7E0019 02", "+7E001902");
+        let mut cheats = Cheats::new();
+        for cheat in Cheats::parse(&text).unwrap() {
+            cheats.add(cheat);
+        }
+
+        let list = cheats.cheats();
+        assert_eq!(list.len(), 4);
+        assert_eq!((list[0].enabled, list[0].description.as_str()), (true, "infinite lives"));
+        assert_eq!((list[1].enabled, list[1].codes.len()), (false, 2));
+        assert_eq!(list[2].description, "big mario");
+        assert_eq!(list[3].description, "");
+
+        let out = cheats.to_text();
+        assert_eq!(out, "on  7E:0DBE:63 infinite lives\n\
+                         off C2B3-4F07+7E:0019:02\n\
+                         off C2B3-4F07+7E:0019:02 big mario\n\
+                         on  DD32-6DAD?A9\n");
+        assert_eq!(Cheats::parse(&out).unwrap(), list);
+    }
+
+    #[test]
+    fn cheat_list_errors() {
+        let error = Cheats::parse("on 7E0DBE63\nmaybe 7E0DBE63\n").unwrap_err();
+        assert_eq!(error.to_string(), "cheat list line 2: expected on or off");
+
+        let error = Cheats::parse("# codes\non\n").unwrap_err();
+        assert_eq!(error.to_string(), "cheat list line 2: missing codes");
+
+        let error = Cheats::parse("off 7E0DBE63+nope\n").unwrap_err();
+        assert_eq!(error.to_string(), "cheat list line 1: bad cheat code: nope");
+    }
+
+    #[test]
+    fn cheat_file_next_to_rom() {
+        assert_eq!(cheat_path("roms/smw.sfc"), "roms/smw.cht");
+        assert_eq!(cheat_path("smw"), "smw.cht");
+    }
+}
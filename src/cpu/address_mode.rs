@@ -1,5 +1,4 @@
-use cpu::cpu::{CPU};
-use cpu::memory::Mem;
+use cpu::cpu::CPU;
 
 const JMP_JSR_OPCODES: [u8; 7] = [0x4C, 0x5C, 0x6C, 0x7C, 0xDC, 0x20, 0xFC];
 const PEI_OPCODE: u8 = 0xD4;
@@ -56,161 +55,147 @@ pub enum AddressMode {
 }
 
 impl AddressMode {
-    fn absolute(cpu: &mut CPU, opcode: &u8) -> MemoryAddress {
+    fn next_w(cpu: &mut CPU) -> u16 {
         let lo = cpu.next_b() as u16;
         let hi = cpu.next_b() as u16;
+        hi << 8 | lo
+    }
+
+    fn read_w(cpu: &mut CPU, lo: MemoryAddress, hi: MemoryAddress) -> u16 {
+        let lo = cpu.read(lo.0, lo.1) as u16;
+        let hi = cpu.read(hi.0, hi.1) as u16;
+        hi << 8 | lo
+    }
+
+    // emulation mode keeps the old 6502 behaviour of wrapping direct page accesses within the
+    // page, but only while the direct register sits on a page boundary
+    fn wraps_in_page(cpu: &CPU) -> bool {
+        cpu.is_emulation() && cpu.dl() == 0x00
+    }
+
+    fn absolute(cpu: &mut CPU, opcode: u8) -> MemoryAddress {
+        let address = Self::next_w(cpu);
 
-        let bank = if JMP_JSR_OPCODES.contains(opcode) {
+        // jumps stay in the program bank, everything else reads data
+        let bank = if JMP_JSR_OPCODES.contains(&opcode) {
             cpu.pbr()
         } else {
             cpu.dbr()
         };
 
-        let address = (hi << 8) | lo;
         (bank, address)
     }
 
     fn absolute_indexed(cpu: &mut CPU, index: u16) -> MemoryAddress {
-        let lo = cpu.next_b() as u16;
-        let hi = cpu.next_b() as u16;
-
-        let addr = ((hi << 8) | lo).wrapping_add(index);
-        (cpu.dbr(), addr)
+        let address = Self::next_w(cpu);
+        Self::add_index_to_address((cpu.dbr(), address), index)
     }
 
-    fn absolute_indirect(cpu: &mut CPU, mem: &Box<dyn Mem>) -> MemoryAddress {
-        let p_lo = cpu.next_b() as u16;
-        let p_hi = cpu.next_b() as u16;
-
-        let p_base_addr = (p_hi << 8) | p_lo;
-        let lo = mem.load(0, p_base_addr) as u16;
-        let hi = mem.load(0, p_base_addr.wrapping_add(1)) as u16;
-
-        (cpu.pbr(), (hi << 8 | lo))
+    // JMP (abs). the pointer is always in bank 0
+    fn absolute_indirect(cpu: &mut CPU) -> MemoryAddress {
+        let pointer = Self::next_w(cpu);
+        let address = Self::read_w(cpu, (0, pointer), (0, pointer.wrapping_add(1)));
+        (cpu.pbr(), address)
     }
 
-    fn absolute_indirect_long(cpu: &mut CPU, mem: &Box<dyn Mem>) -> MemoryAddress {
-        let p_lo = cpu.next_b() as u16;
-        let p_hi = cpu.next_b() as u16;
+    // JML [abs]
+    fn absolute_indirect_long(cpu: &mut CPU) -> MemoryAddress {
+        let pointer = Self::next_w(cpu);
+        let address = Self::read_w(cpu, (0, pointer), (0, pointer.wrapping_add(1)));
+        let bank = cpu.read(0, pointer.wrapping_add(2));
+        (bank, address)
+    }
 
-        let p_base_addr = (p_hi << 8) | p_lo;
-        let lo = mem.load(0, p_base_addr) as u16;
-        let md = mem.load(0, p_base_addr.wrapping_add(1)) as u16;
-        let hi = mem.load(0, p_base_addr.wrapping_add(2));
+    // JMP (abs,X) and JSR (abs,X). the pointer is in the program bank
+    fn absolute_indexed_indirect(cpu: &mut CPU) -> MemoryAddress {
+        let pointer = Self::next_w(cpu).wrapping_add(cpu.x());
+        cpu.idle();
 
-        (hi, (md << 8 | lo))
+        let pbr = cpu.pbr();
+        let address = Self::read_w(cpu, (pbr, pointer), (pbr, pointer.wrapping_add(1)));
+        (pbr, address)
     }
 
-    fn absolute_indexed_indirect(cpu: &mut CPU, mem: &Box<dyn Mem>) -> MemoryAddress {
-        let p_lo = cpu.next_b() as u32;
-        let p_hi = cpu.next_b() as u32;
+    fn direct_offset(cpu: &mut CPU) -> u8 {
+        let offset = cpu.next_b();
 
-        let addr_pointer = (hi << 8 | lo).wrapping_add(cpu.x());
-        let lo = mem.load(cpu.pbr(), addr_pointer) as u16;
-        let hi = mem.load(cpu.pbr(), addr_pointer.wrapping_add(1)) as u16;
+        // an extra cycle whenever the direct register isn't page aligned
+        if cpu.dl() != 0x00 {
+            cpu.idle();
+        }
 
-        (cpu.pbr(), hi << 8 | lo)
+        offset
     }
 
-    fn direct(cpu: &mut CPU, opcode: &u8) -> MemoryAddress {
-        let lo = cpu.next_b();
-
-        if opcode != &PEI_OPCODE && cpu.is_emulation() && cpu.dl() == 0x00 {
-            let addr = (cpu.dh() as u16) << 8 | lo as u16;
-            (0, addr)
-        } else {
-            (0, (cpu.d().wrapping_add(lo as u16)))
-        }
+    fn direct(cpu: &mut CPU) -> MemoryAddress {
+        let offset = Self::direct_offset(cpu);
+        (0, cpu.d().wrapping_add(offset as u16))
     }
 
     fn direct_indexed(cpu: &mut CPU, index: u16) -> MemoryAddress {
-        let lo = cpu.next_b();
+        let offset = Self::direct_offset(cpu);
+        cpu.idle();
 
-        if cpu.is_emulation() && cpu.dl() == 0x00 {
-            let addr = (cpu.dh() as u16) << 8 | lo.wrapping_add(index);
-            (0, addr)
+        if Self::wraps_in_page(cpu) {
+            (0, cpu.d() | offset.wrapping_add(index as u8) as u16)
         } else {
-            (0, cpu.d().wrapping_add(lo).wrapping_add(index))
+            (0, cpu.d().wrapping_add(offset as u16).wrapping_add(index))
         }
     }
 
-    fn direct_indirect(cpu: &mut CPU, mem: &Box<dyn Mem>) -> MemoryAddress {
-        let lo = cpu.next_b() as u16;
-
-        let (pointer_lo, pointer_hi) = if cpu.is_emulation() && cpu.dl() == 0 {
-            let lp = ((cpu.dh() as u16) << 8) | lo;
-            let hp = ((cpu.dh() as u16) << 8) | (lo.wrapping_add(1));
-            (lp, hp)
+    // the two bytes of a pointer in the direct page
+    fn direct_pointer(cpu: &mut CPU, pointer: u16) -> u16 {
+        let hi = if Self::wraps_in_page(cpu) {
+            (pointer & 0xFF00) | (pointer.wrapping_add(1) & 0x00FF)
         } else {
-            let lp = cpu.d().wrapping_add(lo);
-            (lp, lp.wrapping_add(1))
+            pointer.wrapping_add(1)
         };
 
-        let data_lo = mem.load(0, pointer_lo);
-        let data_hi = mem.load(0, pointer_hi);
-
-        let data_addr = (data_hi as u16) << 8 | data_lo as u16;
-        (cpu.dbr(), data_addr)
+        Self::read_w(cpu, (0, pointer), (0, hi))
     }
 
-    fn direct_indirect_long(cpu: &mut CPU, mem: &Box<dyn Mem>) -> MemoryAddress {
-        let lo = cpu.next_b();
-
-        let pointer_lo = cpu.dbr().wrapping_add(lo);
-        let pointer_md = pointer_lo.wrapping_add(1);
-        let pointer_hi = pointer_md.wrapping_add(1);
-
-        let data_lo = mem.load(0, pointer_lo);
-        let data_md = mem.load(0, pointer_md);
-        let data_hi = mem.load(0, pointer_hi);
-
-        (data_hi, (data_md as u16) << 8 | data_lo as u16)
+    fn direct_indirect(cpu: &mut CPU) -> MemoryAddress {
+        let (_, pointer) = Self::direct(cpu);
+        let address = Self::direct_pointer(cpu, pointer);
+        (cpu.dbr(), address)
     }
 
-    fn direct_indexed_indirect(cpu: &mut CPU, mem: &Box<dyn Mem>) -> MemoryAddress {
-        let lo = cpu.next_b();
-
-        let (pointer_lo, pointer_hi) = if cpu.is_emulation() && cpu.dl() == 0x00 {
-            let mid = (cpu.dh() as u16) << 8;
-            let lp = mid | lo.wrapping_add(cpu.x());
-            let hp = mid | lo.wrapping_add(cpu.x()).wrapping_add(1);
-            (lp, hp)
-        } else {
-            let lp = cpu.d().wrapping_add(lo).wrapping_add(cpu.x());
-            let hp = lp.wrapping_add(1);
-            (lp, hp)
-        };
-
-        let data_lo = mem.load(0, pointer_lo);
-        let data_hi = mem.load(0, pointer_hi);
+    // long pointers never wrap within the page, they're 65816 only
+    fn direct_indirect_long(cpu: &mut CPU) -> MemoryAddress {
+        let (_, pointer) = Self::direct(cpu);
+        let address = Self::read_w(cpu, (0, pointer), (0, pointer.wrapping_add(1)));
+        let bank = cpu.read(0, pointer.wrapping_add(2));
+        (bank, address)
+    }
 
-        let final_addr = (data_hi as u16) << 8 | data_lo as u16;
-        (cpu.dbr(), final_addr)
+    fn direct_indexed_indirect(cpu: &mut CPU) -> MemoryAddress {
+        let x = cpu.x();
+        let (_, pointer) = Self::direct_indexed(cpu, x);
+        let address = Self::direct_pointer(cpu, pointer);
+        (cpu.dbr(), address)
     }
 
-    fn direct_indirect_indexed(cpu: &mut CPU, mem: &Box<dyn Mem>) -> MemoryAddress {
-        let addr = Self::direct_indirect(cpu, mem);
-        Self::add_index_to_address(addr, cpu.y())
+    fn direct_indirect_indexed(cpu: &mut CPU) -> MemoryAddress {
+        let address = Self::direct_indirect(cpu);
+        Self::add_index_to_address(address, cpu.y())
     }
 
-    fn direct_indirect_indexed_long(cpu: &mut CPU, mem: &Box<dyn Mem>) -> MemoryAddress {
-        let addr = Self::direct_indirect_long(cpu, mem);
-        Self::add_index_to_address(addr, cpu.y());
+    fn direct_indirect_indexed_long(cpu: &mut CPU) -> MemoryAddress {
+        let address = Self::direct_indirect_long(cpu);
+        Self::add_index_to_address(address, cpu.y())
     }
 
+    // the operand itself, right after the opcode
     fn immediate(cpu: &mut CPU) -> MemoryAddress {
-        // TODO -> maybe handle the 4 cases listed online but I think the instructions handle those
-
+        let address = (cpu.pbr(), cpu.pc());
         cpu.increment_pc();
-        (cpu.pbr(), cpu.pc())
+        address
     }
 
     fn absolute_long(cpu: &mut CPU) -> MemoryAddress {
-        let lo = cpu.next_b();
-        let md = cpu.next_b();
-        let hi = cpu.next_b();
-
-        (hi, (md as u16) << 8 | (lo as u16))
+        let address = Self::next_w(cpu);
+        let bank = cpu.next_b();
+        (bank, address)
     }
 
     fn absolute_long_indexed(cpu: &mut CPU) -> MemoryAddress {
@@ -218,228 +203,199 @@ impl AddressMode {
         Self::add_index_to_address(address, cpu.x())
     }
 
+    // branch targets are relative to the instruction after the branch
     fn relative_8(cpu: &mut CPU) -> MemoryAddress {
-        let lo = cpu.next_b();
-
-        if lo <= 0x7F {
-            (cpu.pbr(), cpu.pc().wrapping_add(2).wrapping_add(lo))
-        } else {
-            (cpu.pbr(), cpu.pc().wrapping_sub(254).wrapping_add(lo))
-        }
+        let offset = cpu.next_b() as i8;
+        (cpu.pbr(), cpu.pc().wrapping_add(offset as u16))
     }
 
     fn relative_16(cpu: &mut CPU) -> MemoryAddress {
-        let lo = cpu.next_b();
-        let hi = cpu.next_b();
-
-        let displacement = (hi as u16) << 8 | (lo as u16);
-        (cpu.pbr(), cpu.pc().wrapping_add(3).wrapping_add(displacement))
+        let offset = Self::next_w(cpu);
+        (cpu.pbr(), cpu.pc().wrapping_add(offset))
     }
 
     fn stack_relative(cpu: &mut CPU) -> MemoryAddress {
-        let lo = cpu.next_b();
-        (0, lo.wrapping_add(cpu.sp()))
+        let offset = cpu.next_b();
+        cpu.idle();
+        (0, cpu.sp().wrapping_add(offset as u16))
     }
 
-    fn stack_relative_indirect_indexed(cpu: &mut CPU, mem: &Box<dyn Mem>) -> MemoryAddress {
-        let lo = cpu.next_b();
-
-        let pointer_lo = lo.wrapping_add(cpu.sp());
-        let pointer_hi = pointer_lo.wrapping_add(1);
-
-        let addr_lo = mem.load(0, pointer_lo) as u16;
-        let addr_hi = mem.load(0, pointer_hi) as u16;
-
-        let address = (cpu.dbr(), addr_hi << 8 | addr_lo);
-        Self::add_index_to_address(address, cpu.y())
+    fn stack_relative_indirect_indexed(cpu: &mut CPU) -> MemoryAddress {
+        let (_, pointer) = Self::stack_relative(cpu);
+        let address = Self::read_w(cpu, (0, pointer), (0, pointer.wrapping_add(1)));
+        cpu.idle();
+        Self::add_index_to_address((cpu.dbr(), address), cpu.y())
     }
 
+    // indexing carries into the bank, the address space is 24 bits wide
     fn add_index_to_address(address: MemoryAddress, index: u16) -> MemoryAddress {
-        let (base_bank, base_addr) = address;
-
-        if (base_addr as u32) + (index as u32) > 0xFFFF {
-            (base_bank.wrapping_add(1), index - 1)
-        } else {
-            (base_bank, base_addr + index)
-        }
+        let (bank, address) = address;
+        let full = ((bank as u32) << 16 | address as u32).wrapping_add(index as u32);
+        ((full >> 16) as u8, full as u16)
     }
 
-    pub fn get_address_8(&self, cpu: &mut CPU, mem: &Box<dyn Mem>) -> MemoryAddress {
-        return match self {
-            Self::Accumulator => {
+    pub fn get_address_8(&self, cpu: &mut CPU) -> MemoryAddress {
+        match *self {
+            AddressMode::Accumulator => {
                 panic!("trying to load with accumulator addressing mode");
             },
-            Self::Immediate => {
+            AddressMode::Immediate => {
                 Self::immediate(cpu)
             },
-            Self::Implied => {
+            AddressMode::Implied => {
                 panic!("trying to generate address with implied addressing");
             },
-            Self::Relative8 => {
+            AddressMode::Relative8 => {
                 Self::relative_8(cpu)
             },
-            Self::Relative16 => {
+            AddressMode::Relative16 => {
                 Self::relative_16(cpu)
             },
-            Self::Absolute(opcode) => {
+            AddressMode::Absolute(opcode) => {
                 Self::absolute(cpu, opcode)
             },
-            Self::Direct(opcode) => {
-                Self::direct(cpu, opcode)
+            AddressMode::Direct(_) => {
+                Self::direct(cpu)
             },
-            Self::DirectIndexedX => {
-                Self::direct_indexed(cpu, cpu.x())
+            AddressMode::DirectIndexedX => {
+                let x = cpu.x();
+                Self::direct_indexed(cpu, x)
             },
-            Self::DirectIndexedY => {
-                Self::direct_indexed(cpu, cpu.y())
+            AddressMode::DirectIndexedY => {
+                let y = cpu.y();
+                Self::direct_indexed(cpu, y)
             },
-            Self::DirectIndexedIndirect => {
-                Self::direct_indexed_indirect(cpu, mem)
+            AddressMode::DirectIndexedIndirect => {
+                Self::direct_indexed_indirect(cpu)
             }
-            Self::DirectIndirect => {
-                Self::direct_indirect(cpu, mem)
+            AddressMode::DirectIndirect => {
+                Self::direct_indirect(cpu)
             },
-            Self::DirectIndirectLong => {
-                Self::direct_indirect_long(cpu, mem)
+            AddressMode::DirectIndirectLong => {
+                Self::direct_indirect_long(cpu)
             },
-            Self::DirectIndirectIndexed => {
-                Self::direct_indirect_indexed(cpu, mem)
+            AddressMode::DirectIndirectIndexed => {
+                Self::direct_indirect_indexed(cpu)
             },
-            Self::DirectIndirectIndexedLong => {
-                Self::direct_indirect_indexed_long(cpu, mem)
+            AddressMode::DirectIndirectIndexedLong => {
+                Self::direct_indirect_indexed_long(cpu)
             },
-            Self::AbsoluteIndexedX => {
-                Self::absolute_indexed(cpu, cpu.x())
+            AddressMode::AbsoluteIndexedX => {
+                let x = cpu.x();
+                Self::absolute_indexed(cpu, x)
             },
-            Self::AbsoluteIndexedY => {
-                Self::absolute_indexed(cpu, cpu.y())
+            AddressMode::AbsoluteIndexedY => {
+                let y = cpu.y();
+                Self::absolute_indexed(cpu, y)
             },
-            Self::StackRelative => {
+            AddressMode::StackRelative => {
                 Self::stack_relative(cpu)
             },
-            Self::StackRelativeIndirectIndexed => {
-                Self::stack_relative_indirect_indexed(cpu, mem)
+            AddressMode::StackRelativeIndirectIndexed => {
+                Self::stack_relative_indirect_indexed(cpu)
             },
-            Self::AbsoluteLong => {
+            AddressMode::AbsoluteLong => {
                 Self::absolute_long(cpu)
             },
-            Self::AbsoluteLongIndexedX => {
+            AddressMode::AbsoluteLongIndexedX => {
                 Self::absolute_long_indexed(cpu)
             },
-            Self::AbsoluteIndirect => {
-                Self::absolute_indirect(cpu, mem)
+            AddressMode::AbsoluteIndirect => {
+                Self::absolute_indirect(cpu)
             },
-            Self::AbsoluteIndirectLong => {
-                Self::absolute_indirect_long(cpu, mem)
+            AddressMode::AbsoluteIndirectLong => {
+                Self::absolute_indirect_long(cpu)
             },
-            Self::AbsoluteIndexedIndirect => {
-                Self::absolute_indexed_indirect(cpu, mem)
+            AddressMode::AbsoluteIndexedIndirect => {
+                Self::absolute_indexed_indirect(cpu)
             },
-            Self::SourceDestination => {
-                panice!("trying to get a single address from SourceDestination addressing");
+            AddressMode::SourceDestination => {
+                panic!("trying to get a single address from SourceDestination addressing");
             }
-        };
+        }
     }
 
+    // the high byte of a 16 bit access. data reads carry on into the next bank
     fn increment_addr_with_bank_wrapping(addr: MemoryAddress) -> MemoryAddress {
-        if addr.1 == 0xFFFF {
-            (addr.0.wrapping_add(1), 0x0000)
-        } else {
-            (addr.0, addr.1 + 1)
-        }
+        Self::add_index_to_address(addr, 1)
     }
 
+    // while anything in bank 0 (direct page, stack) wraps around within it
     fn increment_addr_with_page_wrapping(addr: MemoryAddress) -> MemoryAddress {
         (addr.0, addr.1.wrapping_add(1))
     }
 
-    pub fn get_address_16(&self, cpu: &mut CPU, mem: &Box<dyn Mem>) -> (MemoryAddress, MemoryAddress) {
-        match self {
+    pub fn get_address_16(&self, cpu: &mut CPU) -> (MemoryAddress, MemoryAddress) {
+        match *self {
             AddressMode::Absolute(opcode) => {
-                if JMP_JSR_OPCODES.contains(opcode) {
-                    panic!("trying to get 16 bit address for jmp/jsr opcodes")
-                }
-
                 let lo = Self::absolute(cpu, opcode);
                 let hi = Self::increment_addr_with_bank_wrapping(lo);
                 (lo, hi)
             },
             AddressMode::AbsoluteIndexedX => {
-                let lo = Self::absolute_indexed(cpu, cpu.x());
+                let x = cpu.x();
+                let lo = Self::absolute_indexed(cpu, x);
                 let hi = Self::increment_addr_with_bank_wrapping(lo);
                 (lo, hi)
             },
             AddressMode::AbsoluteIndexedY => {
-                let lo = Self::absolute_indexed(cpu, cpu.y());
+                let y = cpu.y();
+                let lo = Self::absolute_indexed(cpu, y);
                 let hi = Self::increment_addr_with_bank_wrapping(lo);
                 (lo, hi)
             }
-            AddressMode::AbsoluteIndirect => {
-                panic!("trying to get two addresses with AbsoluteIndirect addressing mode");
-
-                // let lo = Self::absolute_indirect(cpu, mem);
-                // let hi = Self::increment_addr_with_page_wrapping(lo);
-                // (lo, hi)
-            },
-            AddressMode::AbsoluteIndirectLong => {
-                panic!("trying to get two addresses with AbsoluteIndirectLong addressing mode");
-
-                // let lo = Self::absolute_indirect(cpu, mem);
-                // let hi = Self::increment_addr_with_page_wrapping(lo);
-                // (lo, hi)
-            },
+            AddressMode::AbsoluteIndirect |
+            AddressMode::AbsoluteIndirectLong |
             AddressMode::AbsoluteIndexedIndirect => {
-                panic!("trying to get two addresses with AbsoluteIndexedIndirect addressing mode");
-
-                // let lo = Self::absolute_indexed_indirect(cpu, mem);
-                // let hi = Self::increment_addr_with_page_wrapping(lo);
-                // (lo, hi)
-            }
+                panic!("trying to get two addresses with an indirect jump addressing mode");
+            },
             AddressMode::Accumulator => {
                 panic!("trying to get address from accumulator addressing mode");
             },
             AddressMode::Direct(opcode) => {
-                // todo -> remove this if check after some testing to make sure we don't panic
-                if opcode != &PEI_OPCODE && cpu.is_emulation() && cpu.dl() == 0x00 {
-                    panic!("trying to get 2 two addresses with Direct addressing but CPU conditions only allow 1")
-                }
-
-                let lo = Self::direct(cpu, opcode);
-                let hi = increment_addr_with_page_wrapping(lo);
+                let lo = Self::direct(cpu);
+                // PEI is new to the 65816 and doesn't keep to the page
+                let hi = if opcode != PEI_OPCODE && Self::wraps_in_page(cpu) {
+                    (0, (lo.1 & 0xFF00) | (lo.1.wrapping_add(1) & 0x00FF))
+                } else {
+                    Self::increment_addr_with_page_wrapping(lo)
+                };
                 (lo, hi)
             },
             AddressMode::DirectIndexedX => {
-                let lo = Self::direct_indexed(cpu, cpu.x());
+                let x = cpu.x();
+                let lo = Self::direct_indexed(cpu, x);
                 let hi = Self::increment_addr_with_page_wrapping(lo);
                 (lo, hi)
             },
             AddressMode::DirectIndexedY => {
-                let lo = Self::direct_indexed(cpu, cpu.y());
+                let y = cpu.y();
+                let lo = Self::direct_indexed(cpu, y);
                 let hi = Self::increment_addr_with_page_wrapping(lo);
                 (lo, hi)
             },
             AddressMode::DirectIndirect => {
-                let lo = Self::direct_indirect(cpu, mem);
+                let lo = Self::direct_indirect(cpu);
                 let hi = Self::increment_addr_with_bank_wrapping(lo);
                 (lo, hi)
             },
             AddressMode::DirectIndirectLong => {
-                let lo = Self::direct_indirect_long(cpu, mem);
+                let lo = Self::direct_indirect_long(cpu);
                 let hi = Self::increment_addr_with_bank_wrapping(lo);
                 (lo, hi)
             },
             AddressMode::DirectIndexedIndirect => {
-                let lo = Self::direct_indexed_indirect(cpu, mem);
+                let lo = Self::direct_indexed_indirect(cpu);
                 let hi = Self::increment_addr_with_bank_wrapping(lo);
                 (lo, hi)
             },
             AddressMode::DirectIndirectIndexed => {
-                let lo = Self::direct_indirect_indexed(cpu, mem);
+                let lo = Self::direct_indirect_indexed(cpu);
                 let hi = Self::increment_addr_with_bank_wrapping(lo);
                 (lo, hi)
             },
             AddressMode::DirectIndirectIndexedLong => {
-                let lo = Self::direct_indirect_indexed_long(cpu, mem);
+                let lo = Self::direct_indirect_indexed_long(cpu);
                 let hi = Self::increment_addr_with_bank_wrapping(lo);
                 (lo, hi)
             },
@@ -467,6 +423,8 @@ impl AddressMode {
             AddressMode::Relative16 => {
                 panic!("attempting to get two addresses from Relative16 addressing");
             },
+            // the operand is the destination bank then the source bank. the addresses
+            // themselves come from x and y
             AddressMode::SourceDestination => {
                 let tt = cpu.next_b();
                 let ss = cpu.next_b();
@@ -482,10 +440,10 @@ impl AddressMode {
                 (lo, hi)
             },
             AddressMode::StackRelativeIndirectIndexed => {
-                let lo = Self::stack_relative_indirect_indexed(cpu, mem);
+                let lo = Self::stack_relative_indirect_indexed(cpu);
                 let hi = Self::increment_addr_with_bank_wrapping(lo);
                 (lo, hi)
             }
         }
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,591 @@
+use std::collections::HashMap;
+use std::io::{Error, ErrorKind};
+
+use cpu::disasm::Mode::{self, *};
+use cpu::disasm::OPCODES;
+
+// assembly syntax
+// ===============
+// what the disassembler writes, so its output assembles back to the same bytes:
+//   loop:   LDA $1234,X     ; a comment
+//           BNE loop
+// an operand's size comes from how it's written: $12 is direct page, $1234 absolute and
+// $123456 long. labels are absolute within their own bank and long outside it. a .b, .w
+// or .l after the mnemonic forces the size, eg LDA.l counter
+//
+// numbers are $hex or decimal, and addresses can be given with their bank as BB:AAAA.
+// a label or number can have numbers added or taken away, eg table+2, and <, > or ^ in
+// front picks out its low, high or bank byte. MVN and MVP take the source bank first,
+// as numbers or labels in the banks
+//
+// directives:
+//   .org BB:AAAA         carry on at another address, in the same bank for .org AAAA
+//   .a8 .a16 .i8 .i16    the accumulator and index widths immediate operands are assembled
+//                        for. REP and SEP don't change them, the cpu's flags aren't known here
+//   .db .dw .dl          bytes, words and longs, separated by commas
+
+// labels can change the size of the instructions before them, so assembly is repeated
+// until they stop moving
+const MAX_PASSES: usize = 4;
+
+// where each label points
+type Labels = HashMap<String, (u8, u16)>;
+
+// a run of assembled bytes at consecutive addresses
+#[derive(Clone, PartialEq, Debug)]
+pub struct Chunk {
+    pub bank: u8,
+    pub address: u16,
+    pub bytes: Vec<u8>,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct Program {
+    pub chunks: Vec<Chunk>, // a new one starts at each .org
+    pub labels: Labels,
+}
+
+impl Program {
+    // every chunk's bytes, one after another
+    pub fn bytes(&self) -> Vec<u8> {
+        self.chunks.iter().flat_map(|chunk| chunk.bytes.iter().cloned()).collect()
+    }
+
+    // hands each byte to write with its address, eg to store it in memory or patch it into
+    // a rom. addresses wrap within the bank like the program counter does
+    pub fn write<F: FnMut(u8, u16, u8)>(&self, mut write: F) {
+        for chunk in self.chunks.iter() {
+            for (offset, &byte) in chunk.bytes.iter().enumerate() {
+                write(chunk.bank, chunk.address.wrapping_add(offset as u16), byte);
+            }
+        }
+    }
+}
+
+// assembles source starting at bank:address, with the accumulator and index widths given
+pub fn assemble(source: &str, bank: u8, address: u16, m8: bool, x8: bool) -> Result<Program, Error> {
+    assemble_with(source, bank, address, m8, x8, |_| None)
+}
+
+// like assemble, with symbols looking up labels the source doesn't define, eg ones loaded
+// from the game's symbol files
+pub fn assemble_with<F>(source: &str, bank: u8, address: u16, m8: bool, x8: bool, symbols: F) -> Result<Program, Error>
+    where F: Fn(&str) -> Option<(u8, u16)>
+{
+    let lines = source.lines().enumerate()
+        .map(|(number, text)| parse_line(text).map_err(|reason| line_error(number, &reason)))
+        .collect::<Result<Vec<Line>, Error>>()?;
+
+    let pass = Pass {
+        lines: &lines,
+        start: (bank, address, m8, x8),
+        symbols: &symbols,
+    };
+
+    let mut labels = HashMap::new();
+    for _ in 0..MAX_PASSES {
+        let (_, found) = pass.run(&labels, false)?;
+        if found == labels {
+            let (chunks, labels) = pass.run(&labels, true)?;
+            return Ok(Program {
+                chunks: chunks.into_iter().filter(|chunk| !chunk.bytes.is_empty()).collect(),
+                labels,
+            });
+        }
+        labels = found;
+    }
+
+    Err(Error::new(ErrorKind::InvalidData, "labels kept moving, check for a label whose size depends on itself"))
+}
+
+////////////////////////////////////
+//
+//             PARSING
+//
+////////////////////////////////////
+
+// the ways an operand can be written, each of which fits a handful of modes
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Shape {
+    None,
+    Accumulator,          // A
+    Immediate,            // #expr
+    Plain,                // expr
+    IndexedX,             // expr,X
+    IndexedY,             // expr,Y
+    Stack,                // expr,S
+    Indirect,             // (expr)
+    IndexedIndirect,      // (expr,X)
+    IndirectIndexed,      // (expr),Y
+    StackIndirectIndexed, // (expr,S),Y
+    IndirectLong,         // [expr]
+    IndirectLongIndexed,  // [expr],Y
+    Move,                 // expr,expr
+}
+
+impl Shape {
+    // smallest operand first
+    fn modes(self) -> &'static [Mode] {
+        match self {
+            Shape::None => &[Implied, Accumulator, Immediate8],
+            Shape::Accumulator => &[Accumulator],
+            Shape::Immediate => &[Immediate8, ImmediateM, ImmediateX],
+            Shape::Plain => &[Relative8, Relative16, Direct, Absolute, AbsoluteLong],
+            Shape::IndexedX => &[DirectX, AbsoluteX, AbsoluteLongX],
+            Shape::IndexedY => &[DirectY, AbsoluteY],
+            Shape::Stack => &[StackRelative],
+            Shape::Indirect => &[DirectIndirect, AbsoluteIndirect],
+            Shape::IndexedIndirect => &[DirectIndexedIndirect, AbsoluteIndexedIndirect],
+            Shape::IndirectIndexed => &[DirectIndirectIndexed],
+            Shape::StackIndirectIndexed => &[StackRelativeIndirectIndexed],
+            Shape::IndirectLong => &[DirectIndirectLong, AbsoluteIndirectLong],
+            Shape::IndirectLongIndexed => &[DirectIndirectLongIndexed],
+            Shape::Move => &[BlockMove],
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+enum Base {
+    Number(u32, usize), // and the bytes it was written with
+    Label(String),
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Part {
+    All,
+    Low,  // <
+    High, // >
+    Bank, // ^
+}
+
+#[derive(Clone, PartialEq, Debug)]
+struct Expr {
+    part: Part,
+    base: Base,
+    offset: i64,
+}
+
+enum Statement {
+    Org(Option<u8>, u16),
+    Widths(Option<bool>, Option<bool>), // m8, x8
+    Data(usize, Vec<Expr>),
+    Instruction(&'static str, Option<usize>, Shape, Vec<Expr>),
+}
+
+struct Line {
+    label: Option<String>,
+    statement: Option<Statement>,
+}
+
+fn parse_line(text: &str) -> Result<Line, String> {
+    let text = match text.find(';') {
+        Some(at) => &text[..at],
+        None => text,
+    }.trim();
+
+    let (label, text) = match text.find(':') {
+        Some(at) if is_label(&text[..at]) => (Some(text[..at].to_string()), text[at + 1..].trim()),
+        _ => (None, text),
+    };
+    if text.is_empty() {
+        return Ok(Line { label, statement: None });
+    }
+
+    let (word, operand) = match text.find(char::is_whitespace) {
+        Some(at) => (&text[..at], &text[at..]),
+        None => (text, ""),
+    };
+    let operand: String = operand.split_whitespace().collect();
+    let word = word.to_ascii_uppercase();
+
+    let statement = match word.as_str() {
+        ".ORG" => match parse_expr(&operand)? {
+            Expr { part: Part::All, base: Base::Number(value, 3), offset: 0 } => Statement::Org(Some((value >> 16) as u8), value as u16),
+            Expr { part: Part::All, base: Base::Number(value, _), offset: 0 } => Statement::Org(None, value as u16),
+            _ => return Err(format!(".org needs an address, not {}", operand)),
+        },
+        ".A8" => Statement::Widths(Some(true), None),
+        ".A16" => Statement::Widths(Some(false), None),
+        ".I8" => Statement::Widths(None, Some(true)),
+        ".I16" => Statement::Widths(None, Some(false)),
+        ".DB" | ".DW" | ".DL" => {
+            let width = match word.as_str() {
+                ".DB" => 1,
+                ".DW" => 2,
+                _ => 3,
+            };
+            let values = operand.split(',').map(parse_expr).collect::<Result<Vec<Expr>, String>>()?;
+            Statement::Data(width, values)
+        },
+        _ if word.starts_with('.') && !word[1..].contains('.') => return Err(format!("unknown directive {}", word)),
+        _ => {
+            let (mnemonic, size) = match word.find('.') {
+                Some(at) => (&word[..at], Some(match &word[at + 1..] {
+                    "B" => 1,
+                    "W" => 2,
+                    "L" => 3,
+                    _ => return Err(format!("unknown size {}", word)),
+                })),
+                None => (word.as_str(), None),
+            };
+            let mnemonic = match OPCODES.iter().find(|&&(name, _)| name == mnemonic) {
+                Some(&(name, _)) => name,
+                None => return Err(format!("unknown instruction {}", mnemonic)),
+            };
+            let (shape, exprs) = parse_operand(&operand)?;
+            Statement::Instruction(mnemonic, size, shape, exprs)
+        },
+    };
+
+    Ok(Line { label, statement: Some(statement) })
+}
+
+fn parse_operand(text: &str) -> Result<(Shape, Vec<Expr>), String> {
+    let upper = text.to_ascii_uppercase();
+    let inner = |start: usize, end: &str| parse_expr(&text[start..text.len() - end.len()]).map(|expr| vec![expr]);
+
+    let (shape, exprs) = if text.is_empty() {
+        (Shape::None, Vec::new())
+    } else if upper == "A" {
+        (Shape::Accumulator, Vec::new())
+    } else if let Some(rest) = text.strip_prefix('#') {
+        (Shape::Immediate, vec![parse_expr(rest)?])
+    } else if upper.starts_with('(') && upper.ends_with(",S),Y") {
+        (Shape::StackIndirectIndexed, inner(1, ",S),Y")?)
+    } else if upper.starts_with('(') && upper.ends_with(",X)") {
+        (Shape::IndexedIndirect, inner(1, ",X)")?)
+    } else if upper.starts_with('(') && upper.ends_with("),Y") {
+        (Shape::IndirectIndexed, inner(1, "),Y")?)
+    } else if upper.starts_with('(') && upper.ends_with(')') {
+        (Shape::Indirect, inner(1, ")")?)
+    } else if upper.starts_with('[') && upper.ends_with("],Y") {
+        (Shape::IndirectLongIndexed, inner(1, "],Y")?)
+    } else if upper.starts_with('[') && upper.ends_with(']') {
+        (Shape::IndirectLong, inner(1, "]")?)
+    } else if upper.ends_with(",X") {
+        (Shape::IndexedX, inner(0, ",X")?)
+    } else if upper.ends_with(",Y") {
+        (Shape::IndexedY, inner(0, ",Y")?)
+    } else if upper.ends_with(",S") {
+        (Shape::Stack, inner(0, ",S")?)
+    } else if let Some(at) = text.find(',') {
+        (Shape::Move, vec![parse_expr(&text[..at])?, parse_expr(&text[at + 1..])?])
+    } else {
+        (Shape::Plain, vec![parse_expr(text)?])
+    };
+
+    Ok((shape, exprs))
+}
+
+// [<>^] base [+-N]...
+fn parse_expr(text: &str) -> Result<Expr, String> {
+    let bad = || format!("bad operand {}", text);
+
+    let (part, rest) = match text.chars().next() {
+        Some('<') => (Part::Low, &text[1..]),
+        Some('>') => (Part::High, &text[1..]),
+        Some('^') => (Part::Bank, &text[1..]),
+        _ => (Part::All, text),
+    };
+
+    let end = rest.find(['+', '-']).unwrap_or(rest.len());
+    let (base, mut offsets) = (&rest[..end], &rest[end..]);
+
+    let base = match parse_number(base) {
+        Some((value, len)) => Base::Number(value, len),
+        None if is_label(base) => Base::Label(base.to_string()),
+        // "BB:name" bank qualified, which only the symbols passed in can answer
+        None if base.contains(':') && is_label(&base[base.find(':').unwrap() + 1..]) => Base::Label(base.to_string()),
+        None => return Err(bad()),
+    };
+
+    let mut offset = 0;
+    while !offsets.is_empty() {
+        let sign = if offsets.starts_with('-') { -1 } else { 1 };
+        let end = offsets[1..].find(['+', '-']).map_or(offsets.len(), |at| at + 1);
+        let (value, _) = parse_number(&offsets[1..end]).ok_or_else(bad)?;
+        offset += sign * value as i64;
+        offsets = &offsets[end..];
+    }
+
+    Ok(Expr { part, base, offset })
+}
+
+// $hex, decimal or BB:AAAA, with the number of bytes it's written in
+fn parse_number(text: &str) -> Option<(u32, usize)> {
+    let hex = |digits: &str| match digits.len() {
+        1..=6 if digits.chars().all(|c| c.is_ascii_hexdigit()) => u32::from_str_radix(digits, 16).ok(),
+        _ => None,
+    };
+
+    if let Some(at) = text.find(':') {
+        let bank = hex(text[..at].trim_start_matches('$')).filter(|&bank| bank <= 0xFF)?;
+        let address = hex(text[at + 1..].trim_start_matches('$')).filter(|&address| address <= 0xFFFF)?;
+        return Some(((bank << 16) | address, 3));
+    }
+    if let Some(digits) = text.strip_prefix('$') {
+        return hex(digits).map(|value| (value, digits.len().div_ceil(2)));
+    }
+    if !text.is_empty() && text.chars().all(|c| c.is_ascii_digit()) {
+        let value: u32 = text.parse().ok().filter(|&value| value <= 0xFFFFFF)?;
+        return Some((value, size_of(value)));
+    }
+    None
+}
+
+fn is_label(text: &str) -> bool {
+    let mut chars = text.chars();
+    chars.next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_' || c == '@')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '@' || c == '.')
+}
+
+fn size_of(value: u32) -> usize {
+    match value {
+        0..=0xFF => 1,
+        0x100..=0xFFFF => 2,
+        _ => 3,
+    }
+}
+
+fn line_error(number: usize, reason: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, format!("line {}: {}", number + 1, reason))
+}
+
+////////////////////////////////////
+//
+//            ASSEMBLING
+//
+////////////////////////////////////
+
+struct Pass<'a, F: 'a> {
+    lines: &'a [Line],
+    start: (u8, u16, bool, bool), // bank, address, m8, x8
+    symbols: &'a F,
+}
+
+impl<'a, F: Fn(&str) -> Option<(u8, u16)>> Pass<'a, F> {
+    // lays out every line with the labels found by the pass before. only the last pass
+    // has to resolve every label and check branches reach, the others are finding out
+    // where the labels go
+    fn run(&self, labels: &Labels, last: bool) -> Result<(Vec<Chunk>, Labels), Error> {
+        let (mut bank, mut address, mut m8, mut x8) = self.start;
+        let mut chunks = vec![Chunk { bank, address, bytes: Vec::new() }];
+        let mut found = HashMap::new();
+
+        for (number, line) in self.lines.iter().enumerate() {
+            let at = |reason: String| line_error(number, &reason);
+
+            if let Some(ref label) = line.label {
+                if found.insert(label.clone(), (bank, address)).is_some() {
+                    return Err(at(format!("{} is defined more than once", label)));
+                }
+            }
+
+            let context = Context { bank, address, labels, symbols: self.symbols, last };
+            let bytes = match line.statement {
+                None => continue,
+                Some(Statement::Org(new_bank, new_address)) => {
+                    bank = new_bank.unwrap_or(bank);
+                    address = new_address;
+                    if !chunks.last().unwrap().bytes.is_empty() {
+                        chunks.push(Chunk { bank, address, bytes: Vec::new() });
+                    }
+                    let chunk = chunks.last_mut().unwrap();
+                    chunk.bank = bank;
+                    chunk.address = address;
+                    continue;
+                },
+                Some(Statement::Widths(m, x)) => {
+                    m8 = m.unwrap_or(m8);
+                    x8 = x.unwrap_or(x8);
+                    continue;
+                },
+                Some(Statement::Data(width, ref values)) => context.data(width, values).map_err(at)?,
+                Some(Statement::Instruction(mnemonic, size, shape, ref exprs)) => {
+                    context.instruction(mnemonic, size, shape, exprs, m8, x8).map_err(at)?
+                },
+            };
+
+            address = address.wrapping_add(bytes.len() as u16);
+            chunks.last_mut().unwrap().bytes.extend(bytes);
+        }
+
+        Ok((chunks, found))
+    }
+}
+
+// where a line is being assembled and what it can refer to
+struct Context<'a, F: 'a> {
+    bank: u8,
+    address: u16,
+    labels: &'a Labels,
+    symbols: &'a F,
+    last: bool,
+}
+
+impl<'a, F: Fn(&str) -> Option<(u8, u16)>> Context<'a, F> {
+    // the value and the number of bytes it needs. labels not known yet stand in for the
+    // current address until the last pass
+    fn evaluate(&self, expr: &Expr) -> Result<(u32, usize), String> {
+        let (value, size) = match expr.base {
+            Base::Number(value, size) => (value, size),
+            Base::Label(ref name) => {
+                let (bank, address) = match self.labels.get(name).cloned().or_else(|| (self.symbols)(name)) {
+                    Some(found) => found,
+                    None if self.last => return Err(format!("unknown label {}", name)),
+                    None => (self.bank, self.address),
+                };
+                ((bank as u32) << 16 | address as u32, if bank == self.bank { 2 } else { 3 })
+            },
+        };
+
+        let value = (value as i64 + expr.offset) as u32 & 0xFFFFFF;
+        Ok(match expr.part {
+            Part::All => (value, size),
+            Part::Low => (value & 0xFF, 1),
+            Part::High => ((value >> 8) & 0xFF, 1),
+            Part::Bank => (value >> 16, 1),
+        })
+    }
+
+    // numbers have to fit, labels are cut down to the low bytes
+    fn fitted(&self, expr: &Expr, len: usize) -> Result<u32, String> {
+        let (value, _) = self.evaluate(expr)?;
+        match expr.base {
+            Base::Number(..) if expr.part == Part::All && value >> (8 * len) != 0 => Err(format!("${:X} doesn't fit in {} byte{}", value, len, if len == 1 { "" } else { "s" })),
+            _ => Ok(value & ((1 << (8 * len)) - 1)),
+        }
+    }
+
+    fn data(&self, width: usize, values: &[Expr]) -> Result<Vec<u8>, String> {
+        let mut bytes = Vec::new();
+        for expr in values {
+            bytes.extend(little_endian(self.fitted(expr, width)?, width));
+        }
+        Ok(bytes)
+    }
+
+    fn instruction(&self, mnemonic: &str, size: Option<usize>, shape: Shape, exprs: &[Expr], m8: bool, x8: bool) -> Result<Vec<u8>, String> {
+        let available: Vec<(u8, Mode)> = shape.modes().iter()
+            .filter_map(|&mode| OPCODES.iter().position(|&(name, other)| name == mnemonic && other == mode).map(|opcode| (opcode as u8, mode)))
+            .collect();
+        let (opcode, mode) = match available.first() {
+            Some(&first) => first,
+            None => return Err(format!("{} can't take that operand", mnemonic)),
+        };
+        let len = mode.operand_len(m8, x8);
+
+        let operand = match mode {
+            Implied | Accumulator => Vec::new(),
+            Immediate8 if exprs.is_empty() => vec![0], // BRK and COP with no signature byte
+            Immediate8 | ImmediateM | ImmediateX => little_endian(self.fitted(&exprs[0], len)?, len),
+            Relative8 | Relative16 => {
+                let (target, written) = self.evaluate(&exprs[0])?;
+                if written == 3 && (target >> 16) as u8 != self.bank {
+                    return Err(format!("{} can't branch out of bank ${:02X}", mnemonic, self.bank));
+                }
+                let next = self.address.wrapping_add(1 + len as u16);
+                let offset = (target as u16).wrapping_sub(next);
+                if mode == Relative8 && self.last && !(-128..=127).contains(&(offset as i16)) {
+                    return Err(format!("{} target is {} bytes away, too far for a short branch", mnemonic, offset as i16));
+                }
+                little_endian(offset as u32, len)
+            },
+            // the destination bank is stored first
+            BlockMove => vec![self.bank_of(&exprs[1])?, self.bank_of(&exprs[0])?],
+            _ => {
+                let (value, written) = self.evaluate(&exprs[0])?;
+                let need = size.unwrap_or(written);
+                let fits = |&&(_, mode): &&(u8, Mode)| match size {
+                    Some(size) => mode.operand_len(m8, x8) == size,
+                    None => mode.operand_len(m8, x8) >= need,
+                };
+                let (opcode, mode) = match available.iter().find(fits) {
+                    Some(&found) => found,
+                    None => return Err(format!("{} has no {} byte form of that operand", mnemonic, need)),
+                };
+                let len = mode.operand_len(m8, x8);
+                return Ok([opcode].iter().cloned().chain(little_endian(value, len)).collect());
+            },
+        };
+
+        Ok([opcode].iter().cloned().chain(operand).collect())
+    }
+
+    // MVN and MVP operands are banks, given as the bank number or something in the bank
+    fn bank_of(&self, expr: &Expr) -> Result<u8, String> {
+        match (&expr.base, expr.part) {
+            (&Base::Label(_), Part::All) => self.evaluate(expr).map(|(value, _)| (value >> 16) as u8),
+            _ => self.fitted(expr, 1).map(|value| value as u8),
+        }
+    }
+}
+
+fn little_endian(value: u32, len: usize) -> Vec<u8> {
+    (0..len).map(|n| (value >> (8 * n)) as u8).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cpu::disasm;
+
+    const BANK: u8 = 0x80;
+    const ADDRESS: u16 = 0x8000;
+
+    // disassembles bytes at BANK:ADDRESS and assembles the text back
+    fn round_trip(bytes: &[u8], m8: bool, x8: bool) -> (String, Vec<u8>, Vec<u8>) {
+        let instruction = disasm::disassemble(|_, address| bytes[address.wrapping_sub(ADDRESS) as usize], BANK, ADDRESS, m8, x8);
+        let text = instruction.to_string();
+        let assembled = assemble(&text, BANK, ADDRESS, m8, x8)
+            .unwrap_or_else(|e| panic!("{} didn't assemble: {}", text, e))
+            .bytes();
+        (text, bytes[..instruction.size()].to_vec(), assembled)
+    }
+
+    #[test]
+    fn every_opcode_assembles_back_to_its_bytes() {
+        // the zero operand checks a small value written out in full keeps its size
+        for operand in [[0x12, 0x34, 0x56], [0x00, 0x00, 0x00]].iter() {
+            for opcode in 0..=0xFF {
+                for &(m8, x8) in [(true, true), (true, false), (false, true), (false, false)].iter() {
+                    let bytes = [opcode, operand[0], operand[1], operand[2]];
+                    let (text, expected, assembled) = round_trip(&bytes, m8, x8);
+                    assert_eq!(assembled, expected, "{} with m8 {} x8 {}", text, m8, x8);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn block_moves_take_the_source_bank_first() {
+        // the destination bank is stored first
+        let (text, expected, assembled) = round_trip(&[0x54, 0x7E, 0x7F], true, true);
+        assert_eq!(text, "MVN $7F,$7E");
+        assert_eq!(assembled, expected);
+        assert_eq!(assemble("MVP $01,$02", BANK, ADDRESS, true, true).unwrap().bytes(), vec![0x44, 0x02, 0x01]);
+    }
+
+    #[test]
+    fn branches_are_written_as_their_targets() {
+        let (text, expected, assembled) = round_trip(&[0x80, 0xFE], true, true);
+        assert_eq!(text, "BRA $8000");
+        assert_eq!(assembled, expected);
+
+        let (text, expected, assembled) = round_trip(&[0xD0, 0x7F], true, true);
+        assert_eq!(text, "BNE $8081");
+        assert_eq!(assembled, expected);
+
+        // a long branch reaches anywhere in the bank, wrapping around its end
+        let (text, expected, assembled) = round_trip(&[0x82, 0x00, 0x80], true, true);
+        assert_eq!(text, "BRL $0003");
+        assert_eq!(assembled, expected);
+
+        let (text, expected, assembled) = round_trip(&[0x62, 0xFD, 0xFF], true, true);
+        assert_eq!(text, "PER $8000");
+        assert_eq!(assembled, expected);
+
+        assert!(assemble("BRA $8100", BANK, ADDRESS, true, true).is_err());
+    }
+}
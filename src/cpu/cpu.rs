@@ -1,153 +1,41 @@
-use Rom;
+use std::io::{Error, Write};
 
 use cpu::memory::*;
 use cpu::address_mode::AddressMode;
-use std::sync::{RwLock, Arc};
+use savestate::{StateReader, StateWriter};
+
+// a cycle with nothing on the bus always takes the fast speed. memory accesses take however
+// long the region they land in does, see memory::access_cycles
+const INTERNAL_CYCLES: u32 = FAST_CYCLES;
+
+// how far the clock moves per step while waiting for an interrupt
+const WAIT_MASTER_CYCLES: u32 = INTERNAL_CYCLES;
+
+// interrupt vectors in bank 0
+const NMI_VECTOR: u16 = 0xFFEA;
+const NMI_VECTOR_EMULATION: u16 = 0xFFFA;
+const IRQ_VECTOR: u16 = 0xFFEE;
+const IRQ_VECTOR_EMULATION: u16 = 0xFFFE;
+
+// status register bits. StatusFlags is a plain u8, so these are masked in and out directly
+const FLAG_CARRY: u8 = 0x01;
+const FLAG_ZERO: u8 = 0x02;
+const FLAG_IRQ_DISABLE: u8 = 0x04;
+const FLAG_DECIMAL: u8 = 0x08;
+const FLAG_INDEX_WIDTH: u8 = 0x10;
+const FLAG_BREAK: u8 = 0x10; // bit 4 in emulation mode, only ever seen in the pushed copy
+const FLAG_ACCUMULATOR_WIDTH: u8 = 0x20;
+const FLAG_OVERFLOW: u8 = 0x40;
+const FLAG_NEGATIVE: u8 = 0x80;
+
+// BRK and COP vectors, native and emulation mode
+const BRK_VECTOR: u16 = 0xFFE6;
+const BRK_VECTOR_EMULATION: u16 = 0xFFFE;
+const COP_VECTOR: u16 = 0xFFE4;
+const COP_VECTOR_EMULATION: u16 = 0xFFF4;
 
 type StatusFlags = u8;
 
-impl StatusFlags {
-    pub fn new() -> StatusFlags {
-        0
-    }
-
-    pub fn carry(&self) -> bool {
-        self & 0x0000_0001 == 0x0000_0001
-    }
-
-    pub fn zero(&self) -> bool {
-        self & 0x0000_0010 == 0x0000_0010
-    }
-
-    pub fn interrupt_disable(&self) -> bool {
-        self & 0x0000_0100 == 0x0000_0100
-    }
-
-    pub fn decimal(&self) -> bool {
-        self & 0x0000_1000 == 0x0000_1000
-    }
-
-    // bit 4 if native
-    pub fn index_width(&self) -> bool {
-        self & 0x0001_0000 == 0x0001_0000
-    }
-
-    // bit 4 if emulation
-    pub fn break_flag(&self) -> bool {
-        self & 0x0001_0000 == 0x0001_0000
-    }
-
-    pub fn accumulator_width(&self) -> bool {
-        self & 0x0010_0000 == 0x0010_0000
-    }
-
-    pub fn overflow(&self) -> bool {
-        self & 0x0100_0000 == 0x0100_0000
-    }
-
-    pub fn negative(&self) -> bool {
-        self & 0x1000_0000 == 0x1000_0000
-    }
-
-    pub fn set_carry(&mut self, enabled: bool) {
-        if enabled {
-            self = self | 0x0000_0001
-        } else {
-            self = self & !(0x000_0001)
-        }
-    }
-
-    pub fn set_zero(&mut self, enabled: bool) {
-        if enabled {
-            self = self | 0x0000_0010
-        } else {
-            self = self & !(0x000_0010)
-        }
-    }
-
-    pub fn set_zero_from_data(&mut self, data: u16) {
-        if data == 0 {
-            self = self | 0x0000_0010
-        } else {
-            self = self & !(0x000_0010)
-        }
-    }
-
-    pub fn set_interrupt_disable(&mut self, enabled: bool) {
-        if enabled {
-            self = self | 0x0000_0100
-        } else {
-            self = self & !(0x000_0100)
-        }
-    }
-
-    pub fn set_decimal(&mut self, enabled: bool) {
-        if enabled {
-            self = self | 0x0000_1000
-        } else {
-            self = self & !(0x000_1000)
-        }
-    }
-
-    // bit 4 if native
-    pub fn set_index_width(&mut self, enabled: bool) {
-        if enabled {
-            self = self | 0x0001_0000
-        } else {
-            self = self & !(0x0001_0000)
-        }
-    }
-
-    // bit 4 if emulation
-    pub fn set_break_flag(&mut self, enabled: bool) {
-        if enabled {
-            self = self | 0x0001_0000
-        } else {
-            self = self & !(0x0001_0000)
-        }
-    }
-
-    pub fn set_accumulator_width(&mut self, enabled: bool) {
-        if enabled {
-            self = self | 0x0010_0000
-        } else {
-            self = self & !(0x0010_0000)
-        }
-    }
-
-    pub fn set_overflow(&mut self, enabled: bool) {
-        if enabled {
-            self = self | 0x0100_0000
-        } else {
-            self = self & !(0x0100_0000)
-        }
-    }
-
-    pub fn set_negative(&mut self, enabled: bool) {
-        if enabled {
-            self = self | 0x1000_0000
-        } else {
-            self = self & !(0x1000_0000)
-        }
-    }
-
-    pub fn set_negative_from_data_8(&mut self, data: u8) {
-        if data >> 7 == 1 {
-            self = self | 0x1000_0000
-        } else {
-            self = self & !(0x1000_0000)
-        }
-    }
-
-    pub fn set_negative_from_data_16(&mut self, data: u16) {
-        if data >> 15 == 1 {
-            self = self | 0x1000_0000
-        } else {
-            self = self & !(0x1000_0000)
-        }
-    }
-}
-
 pub struct CPU {
     a: u16,
     x: u16,
@@ -159,31 +47,35 @@ pub struct CPU {
     pc: u16,  // program counter
     p: StatusFlags,
 
-    cy: u16, // cycle_counter
-
-    // emulation: bool,
-    // wai: bool
-    // trace: bool,
+    emulation: bool, // the E flag, 6502 compatibility mode
+    waiting: bool, // WAI, woken by any interrupt
+    last_interrupt: Option<u16>, // the vector of the interrupt taken by the last step, for debuggers
+    cycles: u32, // master cycles run since the rest of the system last caught up
+    trace: Option<Box<dyn Write>>,
     should_exit: bool,
-    mem: Arc<RwLock<Mem>>,
+    mem: Box<dyn Mem>,
 }
 
 impl CPU {
-    pub fn new(mem: Arc<RwLock<Mem>>) -> CPU {
+    pub fn new(mem: Box<dyn Mem>) -> CPU {
         CPU {
             a:   0,
             x:   0,
             y:   0,
-            sp:  0, // stack pointer
+            sp:  0x01FF, // stack pointer, as reset leaves it
             dbr: 0, // data bank register    -- memory access
             pbr: 0, // program bank register -- op codes
             d:   0, // direct register       -- Address offset for all instruction using "direct addressing" mode.
             pc:  0, // program counter
-            p: StatusFlags::new(),
+            p: 0x34, // emulation mode starts with 8 bit registers
 
-            cy: 10,
+            emulation: true,
+            waiting: false,
+            last_interrupt: None,
+            cycles: 0,
 
             mem,
+            trace: None,
             should_exit: false,
         }
     }
@@ -224,613 +116,897 @@ impl CPU {
         self.pbr
     }
 
+    pub fn sp(&self) -> u16 {
+        self.sp
+    }
+
+    pub fn p(&self) -> u8 {
+        self.p
+    }
+
+    pub fn is_emulation(&self) -> bool {
+        self.emulation
+    }
+
+    // for debuggers. nothing here enforces what the cpu would, like the high byte of the
+    // index registers being 0 while the x flag is set
+    pub fn set_a(&mut self, a: u16) {
+        self.a = a;
+    }
+
+    pub fn set_x(&mut self, x: u16) {
+        self.x = x;
+    }
+
+    pub fn set_y(&mut self, y: u16) {
+        self.y = y;
+    }
+
+    pub fn set_pc(&mut self, pc: u16) {
+        self.pc = pc;
+    }
+
+    pub fn set_dbr(&mut self, dbr: u8) {
+        self.dbr = dbr;
+    }
+
+    pub fn set_d(&mut self, d: u16) {
+        self.d = d;
+    }
+
+    pub fn set_pbr(&mut self, pbr: u8) {
+        self.pbr = pbr;
+    }
+
+    pub fn set_sp(&mut self, sp: u16) {
+        self.sp = sp;
+    }
+
+    pub fn set_p(&mut self, p: u8) {
+        self.p = p;
+    }
+
+    pub fn set_emulation(&mut self, emulation: bool) {
+        self.emulation = emulation;
+    }
+
+    // set by STP. only a reset gets the cpu going again
+    pub fn is_stopped(&self) -> bool {
+        self.should_exit
+    }
+
+    // set by WAI. steps don't run instructions until an interrupt comes
+    pub fn is_waiting(&self) -> bool {
+        self.waiting
+    }
+
+    // the vector the last step's interrupt went through, if it took one
+    pub fn last_interrupt(&self) -> Option<u16> {
+        self.last_interrupt
+    }
+
+    pub fn mem(&self) -> &dyn Mem {
+        &*self.mem
+    }
+
+    pub fn mem_mut(&mut self) -> &mut dyn Mem {
+        &mut *self.mem
+    }
+
     pub fn increment_pc(&mut self) {
         self.pc = self.pc.wrapping_add(1);
     }
 
-    fn get_cycles(op: u8) -> u8 {
-        // todo -> impl;
-        7
+    ////////////////////////////////////
+    //
+    //          MEMORY ACCESS
+    //
+    ////////////////////////////////////
+
+    // every access the cpu makes goes through read and write so it can count its cycles.
+    // the debugger's peeks go straight to mem and cost nothing
+    pub fn read(&mut self, bank: u8, address: u16) -> u8 {
+        self.cycles += self.mem.access_cycles(bank, address);
+        self.mem.load(bank, address)
+    }
+
+    pub fn write(&mut self, bank: u8, address: u16, to_store: u8) {
+        self.cycles += self.mem.access_cycles(bank, address);
+        self.mem.store(bank, address, to_store);
+    }
+
+    // a cycle spent inside the cpu, with nothing on the bus
+    pub fn idle(&mut self) {
+        self.cycles += INTERNAL_CYCLES;
     }
 
     pub fn next_b(&mut self) -> u8 {
-        self.load_8(&AddressMode::Immediate)
+        let (pbr, pc) = (self.pbr, self.pc);
+        self.increment_pc();
+        self.read(pbr, pc)
     }
 
     pub fn load_8(&mut self, addr_mode: &AddressMode) -> u8 {
-        let (bank, addr) = addr_mode.get_address_8(self, &self.mem);
-        self.mem.load(bank, addr)
+        let (bank, addr) = addr_mode.get_address_8(self);
+        self.read(bank, addr)
     }
 
     pub fn load_16(&mut self, addr_mode: &AddressMode) -> u16 {
-        let ((lo_bank, lo_addr), (hi_bank, hi_addr)) = addr_mode.get_address_16(self, &self.mem);
+        let ((lo_bank, lo_addr), (hi_bank, hi_addr)) = addr_mode.get_address_16(self);
 
-        let lo = self.mem.load(lo_bank, lo_addr) as u16;
-        let hi = self.mem.load(hi_bank, hi_addr) as u16;
+        let lo = self.read(lo_bank, lo_addr) as u16;
+        let hi = self.read(hi_bank, hi_addr) as u16;
 
         hi << 8 | lo
     }
 
     pub fn store_8(&mut self, addr_mode: &AddressMode, to_store: u8) {
-        let (bank, address) = addr_mode.get_address_8(self, &self.mem);
-        self.mem.store(bank, address, to_store);
+        let (bank, address) = addr_mode.get_address_8(self);
+        self.write(bank, address, to_store);
     }
 
     pub fn store_16(&mut self, addr_mode: &AddressMode, to_store: u16) {
-        let ((lo_bank, lo_addr), (hi_bank, hi_addr)) = addr_mode.get_address_16(self, &self.mem);
+        let ((lo_bank, lo_addr), (hi_bank, hi_addr)) = addr_mode.get_address_16(self);
 
         let lo_to_store = (to_store & 0x00FF) as u8;
         let hi_to_store = (to_store >> 8) as u8;
 
-        self.mem.store(lo_bank, lo_address, lo_to_store);
-        self.mem.store(hi_bank, hi_address, hi_to_store);
-    }
-
-    pub fn run(&mut self, ops: Rom) {
-        let interrupt_period = 7; // TODO -> this was arbitrary. find out what number i need
-
-        loop {
-            // todo -> let op_code = self.mem.load_op(self.pc);
-
-            // todo -> load the rom into the pbr;
-
-            let opcode = rom.get(self.pc.clone() as usize).unwrap();
-            self.pc += 1;
-
-            self.cy -= Self::get_cycles(*opcode) as u16;
-
-            match opcode {
-                // add w carry
-                0x61 => self.adc(AddressMode::DirectIndexedIndirect),
-                0x63 => self.adc(AddressMode::StackRelative),
-                0x65 => self.adc(AddressMode::Direct(*opcode)),
-                0x67 => self.adc(AddressMode::DirectIndirectLong),
-                0x69 => self.adc(AddressMode::Immediate),
-                0x6D => self.adc(AddressMode::Absolute(*opcode)),
-                0x6F => self.adc(AddressMode::AbsoluteLong),
-                0x71 => self.adc(AddressMode::DirectIndirectIndexed),
-                0x72 => self.adc(AddressMode::DirectIndirect),
-                0x73 => self.adc(AddressMode::StackRelativeIndirectIndexed),
-                0x75 => self.adc(AddressMode::DirectIndexedX),
-                0x77 => self.adc(AddressMode::DirectIndirectIndexedLong),
-                0x79 => self.adc(AddressMode::AbsoluteIndexedY),
-                0x7D => self.adc(AddressMode::AbsoluteIndexedX),
-                0x7F => self.adc(AddressMode::AbsoluteLongIndexedX),
-
-                // sub w carry
-                0xE1 => self.sbc(AddressMode::DirectIndexedIndirect),
-                0xE3 => self.sbc(AddressMode::StackRelative),
-                0xE5 => self.sbc(AddressMode::Direct(*opcode)),
-                0xE7 => self.sbc(AddressMode::DirectIndirectLong),
-                0xE9 => self.sbc(AddressMode::Immediate),
-                0xED => self.sbc(AddressMode::Absolute(*opcode)),
-                0xEF => self.sbc(AddressMode::AbsoluteLong),
-                0xF1 => self.sbc(AddressMode::DirectIndirectIndexed),
-                0xF2 => self.sbc(AddressMode::DirectIndirect),
-                0xF3 => self.sbc(AddressMode::StackRelativeIndirectIndexed),
-                0xF5 => self.sbc(AddressMode::DirectIndexedX),
-                0xF7 => self.sbc(AddressMode::DirectIndirectIndexedLong),
-                0xF9 => self.sbc(AddressMode::AbsoluteIndexedY),
-                0xFD => self.sbc(AddressMode::AbsoluteIndexedX),
-                0xFF => self.sbc(AddressMode::AbsoluteLongIndexedX),
-
-                // compare
-                0xC1 => self.cmp(AddressMode::DirectIndexedIndirect),
-                0xC3 => self.cmp(AddressMode::StackRelative),
-                0xC5 => self.cmp(AddressMode::Direct(*opcode)),
-                0xC7 => self.cmp(AddressMode::DirectIndirectLong),
-                0xC9 => self.cmp(AddressMode::Immediate),
-                0xCD => self.cmp(AddressMode::Absolute(*opcode)),
-                0xCF => self.cmp(AddressMode::AbsoluteLong),
-                0xD1 => self.cmp(AddressMode::DirectIndirectIndexed),
-                0xD2 => self.cmp(AddressMode::DirectIndirect),
-                0xD3 => self.cmp(AddressMode::StackRelativeIndirectIndexed),
-                0xD5 => self.cmp(AddressMode::DirectIndexedX),
-                0xD7 => self.cmp(AddressMode::DirectIndirectIndexedLong),
-                0xD9 => self.cmp(AddressMode::AbsoluteIndexedY),
-                0xDD => self.cmp(AddressMode::AbsoluteIndexedX),
-                0xDF => self.cmp(AddressMode::AbsoluteLongIndexedX),
-                0xE0 => self.cpx(AddressMode::Immediate),
-                0xE4 => self.cpx(AddressMode::Direct(*opcode)),
-                0xEC => self.cpx(AddressMode::Absolute(*opcode)),
-                0xC0 => self.cpy(AddressMode::Immediate),
-                0xC4 => self.cpy(AddressMode::Direct(*opcode)),
-                0xCC => self.cpy(AddressMode::Absolute(*opcode)),
-
-                // decrement
-                0x3A => self.dec(AddressMode::Accumulator),
-                0xC6 => self.dec(AddressMode::Direct(*opcode)),
-                0xCE => self.dec(AddressMode::Absolute(*opcode)),
-                0xD6 => self.dec(AddressMode::DirectIndexedX),
-                0xDE => self.dec(AddressMode::AbsoluteIndexedX),
-                0xCA => self.dex(AddressMode::Implied),
-                0x88 => self.dey(AddressMode::Implied),
-
-                // increment
-                0x3A => self.inc(AddressMode::Accumulator),
-                0xC6 => self.inc(AddressMode::Direct(*opcode)),
-                0xCE => self.inc(AddressMode::Absolute(*opcode)),
-                0xD6 => self.inc(AddressMode::DirectIndexedX),
-                0xDE => self.inc(AddressMode::AbsoluteIndexedX),
-                0xCA => self.inx(AddressMode::Implied),
-                0x88 => self.iny(AddressMode::Implied),
-
-                // and
-                0x21 => self.and(AddressMode::DirectIndexedIndirect),
-                0x23 => self.and(AddressMode::StackRelative),
-                0x25 => self.and(AddressMode::Direct(*opcode)),
-                0x27 => self.and(AddressMode::DirectIndirectLong),
-                0x29 => self.and(AddressMode::Immediate),
-                0x2D => self.and(AddressMode::Absolute(*opcode)),
-                0x2F => self.and(AddressMode::AbsoluteLong),
-                0x31 => self.and(AddressMode::DirectIndirectIndexed),
-                0x32 => self.and(AddressMode::DirectIndirect),
-                0x33 => self.and(AddressMode::StackRelativeIndirectIndexed),
-                0x35 => self.and(AddressMode::DirectIndexedX),
-                0x37 => self.and(AddressMode::DirectIndirectIndexedLong),
-                0x39 => self.and(AddressMode::AbsoluteIndexedY),
-                0x3D => self.and(AddressMode::AbsoluteIndexedX),
-                0x3F => self.and(AddressMode::AbsoluteLongIndexedX),
-
-                // eor
-                0x41 => self.eor(AddressMode::DirectIndexedIndirect),
-                0x43 => self.eor(AddressMode::StackRelative),
-                0x45 => self.eor(AddressMode::Direct(*opcode)),
-                0x47 => self.eor(AddressMode::DirectIndirectLong),
-                0x49 => self.eor(AddressMode::Immediate),
-                0x4D => self.eor(AddressMode::Absolute(*opcode)),
-                0x4F => self.eor(AddressMode::AbsoluteLong),
-                0x51 => self.eor(AddressMode::DirectIndirectIndexed),
-                0x52 => self.eor(AddressMode::DirectIndirect),
-                0x53 => self.eor(AddressMode::StackRelativeIndirectIndexed),
-                0x55 => self.eor(AddressMode::DirectIndexedX),
-                0x57 => self.eor(AddressMode::DirectIndirectIndexedLong),
-                0x59 => self.eor(AddressMode::AbsoluteIndexedY),
-                0x5D => self.eor(AddressMode::AbsoluteIndexedX),
-                0x5F => self.eor(AddressMode::AbsoluteLongIndexedX),
-
-                // ora
-                0x01 => self.ora(AddressMode::DirectIndexedIndirect),
-                0x03 => self.ora(AddressMode::StackRelative),
-                0x05 => self.ora(AddressMode::Direct(*opcode)),
-                0x07 => self.ora(AddressMode::DirectIndirectLong),
-                0x09 => self.ora(AddressMode::Immediate),
-                0x0D => self.ora(AddressMode::Absolute(*opcode)),
-                0x0F => self.ora(AddressMode::AbsoluteLong),
-                0x11 => self.ora(AddressMode::DirectIndirectIndexed),
-                0x12 => self.ora(AddressMode::DirectIndirect),
-                0x13 => self.ora(AddressMode::StackRelativeIndirectIndexed),
-                0x15 => self.ora(AddressMode::DirectIndexedX),
-                0x17 => self.ora(AddressMode::DirectIndirectIndexedLong),
-                0x19 => self.ora(AddressMode::AbsoluteIndexedY),
-                0x1D => self.ora(AddressMode::AbsoluteIndexedX),
-                0x1F => self.ora(AddressMode::AbsoluteLongIndexedX),
-
-                // bit
-                0x24 => self.bit(AddressMode::Direct(*opcode)),
-                0x2C => self.bit(AddressMode::Absolute(*opcode)),
-                0x34 => self.bit(AddressMode::DirectIndexedX),
-                0x3C => self.bit(AddressMode::AbsoluteIndexedX),
-                0x89 => self.bit(AddressMode::Immediate),
-
-                // trb | tsb
-                0x14 => self.trb(AddressMode::Direct(*opcode)),
-                0x1C => self.trb(AddressMode::Absolute(*opcode)),
-                0x04 => self.tsb(AddressMode::Direct(*opcode)),
-                0x0C => self.tsb(AddressMode::Absolute(*opcode)),
-
-                // asl
-                0x06 => self.asl(AddressMode::Direct(*opcode)),
-                0x0A => self.asl(AddressMode::Accumulator),
-                0x0E => self.asl(AddressMode::Absolute(*opcode)),
-                0x16 => self.asl(AddressMode::DirectIndexedX),
-                0x1E => self.asl(AddressMode::AbsoluteIndexedX),
-
-                // lsr
-                0x46 => self.lsr(AddressMode::Direct(*opcode)),
-                0x4A => self.lsr(AddressMode::Accumulator),
-                0x4E => self.lsr(AddressMode::Absolute(*opcode)),
-                0x56 => self.lsr(AddressMode::DirectIndexedX),
-                0x5E => self.lsr(AddressMode::AbsoluteIndexedX),
-
-                // rol
-                0x26 => self.rol(AddressMode::Direct(*opcode)),
-                0x2A => self.rol(AddressMode::Accumulator),
-                0x2E => self.rol(AddressMode::Absolute(*opcode)),
-                0x36 => self.rol(AddressMode::DirectIndexedX),
-                0x3E => self.rol(AddressMode::AbsoluteIndexedX),
-
-                // ror
-                0x66 => self.ror(AddressMode::Direct(*opcode)),
-                0x6A => self.ror(AddressMode::Accumulator),
-                0x6E => self.ror(AddressMode::Absolute(*opcode)),
-                0x76 => self.ror(AddressMode::DirectIndexedX),
-                0x7E => self.ror(AddressMode::AbsoluteIndexedX),
-
-                // branch
-                0x90 => self.bcc(AddressMode::Relative8), 
-                0xB0 => self.bcs(AddressMode::Relative8), 
-                0xF0 => self.beq(AddressMode::Relative8), 
-                0x30 => self.bmi(AddressMode::Relative8), 
-                0xD0 => self.bne(AddressMode::Relative8), 
-                0x10 => self.bpl(AddressMode::Relative8), 
-                0x80 => self.bra(AddressMode::Relative8), 
-                0x50 => self.bvc(AddressMode::Relative8), 
-                0x70 => self.bvs(AddressMode::Relative8), 
-                0x82 => self.brl(AddressMode::Relative16),
-
-                // jump
-                0x4C => self.jmp(AddressMode::Absolute(*opcode)),
-                0x5C => self.jmp(AddressMode::AbsoluteLong),
-                0x6C => self.jmp(AddressMode::AbsoluteIndirect),
-                0x7C => self.jmp(AddressMode::AbsoluteIndexedIndirect),
-                0xDC => self.jmp(AddressMode::AbsoluteIndirectLong),
-                0x22 => self.jsl(AddressMode::AbsoluteLong),
-                0x20 => self.jsr(AddressMode::Absolute(*opcode)),
-                0xFC => self.jsr(AddressMode::AbsoluteIndexedIndirect),
-
-                // return
-                0x6B => self.rtl(AddressMode::Implied),
-                0x60 => self.rts(AddressMode::Implied),
-                0x40 => self.rti(AddressMode::Implied),
-
-                // software interrupts
-                0x00 => self.brk(AddressMode::Implied),
-                0x02 => self.cop(AddressMode::Immediate),
-
-                // clear | set
-                0x18 => self.clc(AddressMode::Implied),
-                0xD8 => self.cld(AddressMode::Implied),
-                0x58 => self.cli(AddressMode::Implied),
-                0xB8 => self.clv(AddressMode::Implied),
-                0x38 => self.sec(AddressMode::Implied),
-                0xF8 => self.sed(AddressMode::Implied),
-                0x78 => self.sei(AddressMode::Implied),
-
-                // reset / set processor status bits
-                0xC2 => self.rep(AddressMode::Immediate),
-                0xE2 => self.sep(AddressMode::Immediate),
-
-                // load
-                0xA1 => self.lda(AddressMode::DirectIndexedIndirect),
-                0xA3 => self.lda(AddressMode::StackRelative),
-                0xA5 => self.lda(AddressMode::Direct(*opcode)),
-                0xA7 => self.lda(AddressMode::DirectIndirectLong),
-                0xA9 => self.lda(AddressMode::Immediate), 
-                0xAD => self.lda(AddressMode::Absolute(*opcode)),
-                0xAF => self.lda(AddressMode::AbsoluteLong),
-                0xB1 => self.lda(AddressMode::DirectIndirectIndexed),
-                0xB2 => self.lda(AddressMode::DirectIndirect),
-                0xB3 => self.lda(AddressMode::StackRelativeIndirectIndexed),
-                0xB5 => self.lda(AddressMode::DirectIndexedX),
-                0xB7 => self.lda(AddressMode::DirectIndirectIndexedLong),
-                0xB9 => self.lda(AddressMode::AbsoluteIndexedY),
-                0xBD => self.lda(AddressMode::AbsoluteIndexedX),
-                0xBF => self.lda(AddressMode::AbsoluteLongIndexedX),
-                0xA2 => self.ldx(AddressMode::Immediate),
-                0xA6 => self.ldx(AddressMode::Direct(*opcode)),
-                0xAE => self.ldx(AddressMode::Absolute(*opcode)),
-                0xB6 => self.ldx(AddressMode::DirectIndexedY),
-                0xBE => self.ldx(AddressMode::AbsoluteIndexedY),
-                0xA0 => self.ldy(AddressMode::Immediate),        
-                0xA4 => self.ldy(AddressMode::Direct(*opcode)),
-                0xAC => self.ldy(AddressMode::Absolute(*opcode)),
-                0xB4 => self.ldy(AddressMode::DirectIndexedX),
-                0xBC => self.ldy(AddressMode::AbsoluteIndexedX),
-
-                // store
-                0x81 => self.sta(AddressMode::DirectIndexedIndirect),
-                0x83 => self.sta(AddressMode::StackRelative),
-                0x85 => self.sta(AddressMode::Direct(*opcode)),
-                0x87 => self.sta(AddressMode::DirectIndirectLong),
-                0x8D => self.sta(AddressMode::Absolute(*opcode)),
-                0x8F => self.sta(AddressMode::AbsoluteLong),
-                0x91 => self.sta(AddressMode::DirectIndirectIndexed),
-                0x92 => self.sta(AddressMode::DirectIndirect),
-                0x93 => self.sta(AddressMode::StackRelativeIndirectIndexed),
-                0x95 => self.sta(AddressMode::DirectIndexedX),
-                0x97 => self.sta(AddressMode::DirectIndirectIndexedLong),
-                0x99 => self.sta(AddressMode::AbsoluteIndexedY),
-                0x9D => self.sta(AddressMode::AbsoluteIndexedX),
-                0x9F => self.sta(AddressMode::AbsoluteLongIndexedX),
-                0x86 => self.stx(AddressMode::Direct(*opcode)),
-                0x8E => self.stx(AddressMode::Absolute(*opcode)),
-                0x96 => self.stx(AddressMode::DirectIndexedY),
-                0x84 => self.sty(AddressMode::Direct(*opcode)),
-                0x8C => self.sty(AddressMode::Absolute(*opcode)),
-                0x94 => self.sty(AddressMode::DirectIndexedX),
-                0x64 => self.stz(AddressMode::Direct(*opcode)),
-                0x74 => self.stz(AddressMode::DirectIndexedX),
-                0x9C => self.stz(AddressMode::Absolute(*opcode)),
-                0x9E => self.stz(AddressMode::AbsoluteIndexedX),
-
-                // move memory negative/positive
-                0x54 => self.mvn(AddressMode::SourceDestination),
-                0x44 => self.mvp(AddressMode::SourceDestination),
-
-                // no op and the WDM JR. BABY
-                0xEA => self.nop(AddressMode::Implied),
-                0x42 => self.wdm(AddressMode::Immediate),
-
-                // push effective
-                0xF4 => self.pea(AddressMode::Immediate),
-                0xD4 => self.pei(AddressMode::Direct(*opcode)),
-                0x63 => self.per(AddressMode::Relative16),
-
-                // push / pull
-                0x48 => self.pha(AddressMode::Implied),
-                0xDA => self.phx(AddressMode::Implied),
-                0x5A => self.phy(AddressMode::Implied),
-                0x68 => self.pla(AddressMode::Implied),
-                0xFA => self.plx(AddressMode::Implied),
-                0x7A => self.ply(AddressMode::Implied),
-
-                0x8B => self.phb(AddressMode::Implied),
-                0x0B => self.phd(AddressMode::Implied),
-                0x4B => self.phk(AddressMode::Implied),
-                0x08 => self.php(AddressMode::Implied),
-                0xAB => self.plb(AddressMode::Implied),
-                0x2B => self.pld(AddressMode::Implied),
-                0x28 => self.plp(AddressMode::Implied),
-
-                // stop / wait
-                0xDB => self.stp(AddressMode::Implied),
-                0xCB => self.wai(AddressMode::Implied),
-
-                // transfer
-                0xAA => self.tax(AddressMode::Implied),
-                0xA8 => self.tay(AddressMode::Implied),
-                0xBA => self.tsx(AddressMode::Implied),
-                0x8A => self.txa(AddressMode::Implied),
-                0x9A => self.txs(AddressMode::Implied),
-                0x9B => self.txy(AddressMode::Implied),
-                0x98 => self.tya(AddressMode::Implied),
-                0xBB => self.tyx(AddressMode::Implied),
-                0x5B => self.tcd(AddressMode::Implied),
-                0x1B => self.tcs(AddressMode::Implied),
-                0x7B => self.tdc(AddressMode::Implied),
-                0x3B => self.tsc(AddressMode::Implied),
-
-                // exchange
-                0xEB => self.xba(AddressMode::Implied),
-                0xFB => self.xce(AddressMode::Implied),
-
-                _ => panic!("Opcode {:X} is not implemented", opcode)
-
-                // address mode mappings
-                // =====================
-                // (dir, X)   => DirectIndexedIndirect
-                // stk, S     => StackRelative
-                // dir        => Direct
-                // [dir]      => DirectIndirectLong
-                // imm        => Immediate
-                // abs        => Absolute
-                // (dir), Y   => DirectIndirectIndexed
-                // (dir)      => DirectIndirect
-                // (stk,S), Y => StackRelativeIndirectIndexed
-                // dir, X     => DirectIndexedX
-                // [dir], Y   => DirectIndirectIndexedLong
-                // abs, Y     => AbsoluteIndexedY
-                // abs, X     => AbsoluteIndexedX
-                // long       => AbsoluteLong
-                // long, X    => AbsoluteLongIndexedX
-                // (abs)      => AbsoluteIndirect
-                // [abs]      => AbsoluteIndirectLong
-                // (abs,X)    => AbsoluteIndexedIndirect
-
-            }
-
-            if self.cy <= 0 {
-                // Check for interrupts
-                // and cyclic tasks here
-                
-                self.cy += interrupt_period;
+        self.write(lo_bank, lo_addr, lo_to_store);
+        self.write(hi_bank, hi_addr, hi_to_store);
+    }
 
-                if self.should_exit {
-                    return
-                }
-            }
+    // loads at the width of the accumulator (m) or the index registers (x)
+    fn load(&mut self, am: &AddressMode, wide: bool) -> u16 {
+        if wide {
+            self.load_16(am)
+        } else {
+            self.load_8(am) as u16
         }
     }
 
-//    The n flag is 0 when the high bit of the result (bit 15 when the m flag is 0, bit 7 when the m flag is 1) is 0, and the n flag is 1 when the high bit of the result is 1.
-//    The v flag is 0 when there is not a signed arithmetic overflow, and the v flag is 1 when there is a signed arithmetic overflow.
-//              For 8-bit signed numbers, $00 to $7F represents 0 to 127, and $80 to $FF represents -128 to -1;
-//              an 8-bit arithmetic overflow occurs when the result is outside the range -128 to 127.
-//              For 16-bit signed numbers, $0000 to $7FFF represents 0 to 32767, and $8000 to $FFFF represents -32768 to -1;
-//              a 16-bit arithmetic overflow occurs when the result is outside the range -32768 to 32767.
-//    The z flag is 0 when the 16-bit (when m flag is 0) or 8-bit (when the m flag is 1) result is nonzero, and the z flag is 1 when the result is zero.
-//    The c flag is 0 when there is not an unsigned carry, and the c flag is 1 when there is an unsigned carry.
-//              For 8-bit unsigned numbers, $00 to $FF represents 0 to 255; for addition, an 8-bit carry occurs when the result is greater than 255.
-//              For 16-bit unsigned numbers, $0000 to $FFFF represents 0 to 65535;
-//              for addition, an 16-bit carry occurs when the result is greater than 65535.
-//              For subtraction (8-bit or 16-bit), there is a carry when the accumulator is greater than or equal to the data.
+    fn store(&mut self, am: &AddressMode, wide: bool, to_store: u16) {
+        if wide {
+            self.store_16(am, to_store);
+        } else {
+            self.store_8(am, to_store as u8);
+        }
+    }
 
     ////////////////////////////////////
     //
-    //              ADC
+    //              FLAGS
     //
     ////////////////////////////////////
 
-    fn adc(&mut self, am: AddressMode) {
-        if self.p.accumulator_width {
-            let to_add = self.load_8(&am);
-            self.adc_8(to_add);
+    fn flag(&self, flag: u8) -> bool {
+        self.p & flag != 0
+    }
+
+    fn set_flag(&mut self, flag: u8, set: bool) {
+        if set {
+            self.p |= flag;
         } else {
-            let to_add = self.load_16(&am);
-            self.adc_16(to_add);
+            self.p &= !flag;
         }
     }
 
-    fn adc_8(&mut self, to_add: u8) {
-        // accumulator += data + carry;
-
-        if self.p.decimal_mode {
-            // bcd mode
-            let mut a1: u8  = self.a as u8 & 0x0F;
-            let mut a2: u16 = self.a & 0xF0;
-
-            let to_add1: u8  = to_add & 0x0F;
-            let to_add2: u8  = to_add & 0xF0;
+    // emulation mode keeps both widths at 8 bits, so the flags are enough to go on
+    fn a_wide(&self) -> bool {
+        !self.flag(FLAG_ACCUMULATOR_WIDTH)
+    }
 
-            a1 += to_add1 + self.p.carry as u8;
+    fn x_wide(&self) -> bool {
+        !self.flag(FLAG_INDEX_WIDTH)
+    }
 
-            if a1 > 0x09 {
-                a1 -= 0x0A; // subtract 10
-                a1 &= 0x0F; // zero out unnecessary bits
-                a2 += 0x10; // add 16 to second digit
-            }
+    fn set_nz(&mut self, value: u16, wide: bool) {
+        let (value, sign) = if wide { (value, 0x8000) } else { (value & 0x00FF, 0x0080) };
+        self.set_flag(FLAG_ZERO, value == 0);
+        self.set_flag(FLAG_NEGATIVE, value & sign != 0);
+    }
 
-            a2 += to_add2 as u16;
+    // the accumulator at its current width. an 8 bit write leaves the hidden high byte, B, alone
+    fn acc(&self) -> u16 {
+        if self.a_wide() { self.a } else { self.a & 0x00FF }
+    }
 
-            if a2 > 0x90 {
-                a2 -= 0x0A; // subtract 10
-                a2 &= 0xF0; // zero out unnecessary bits
-                self.p.carry = true;
-            } else {
-                self.p.carry = false;
-            }
+    fn set_acc(&mut self, value: u16) {
+        self.a = if self.a_wide() { value } else { (self.a & 0xFF00) | (value & 0x00FF) };
+        self.set_nz(value, self.a_wide());
+    }
 
-            let result_8 = a1 | a2 as u8;
+    // the index registers lose their high byte whenever they're 8 bits wide
+    fn index(&self, value: u16) -> u16 {
+        if self.x_wide() { value } else { value & 0x00FF }
+    }
 
-            // todo -> decide if ah() is a u16 with lower bits set or if its an 8 bit number and need to shift
-            self.a = self.ah() | result_8 as u16;
+    fn set_x_reg(&mut self, value: u16) {
+        self.x = self.index(value);
+        self.set_nz(value, self.x_wide());
+    }
 
-            // TODO -> DETERMINE OVERFLOW
+    fn set_y_reg(&mut self, value: u16) {
+        self.y = self.index(value);
+        self.set_nz(value, self.x_wide());
+    }
 
-            self.p.set_negative_from_data_8(result_8);
-            self.p.set_zero_from_data(result_8);
+    // how REP, SEP, PLP and RTI change p. emulation mode holds m and x at 1, and an 8 bit
+    // index register drops its high byte
+    fn set_status(&mut self, p: u8) {
+        self.p = if self.emulation {
+            p | FLAG_ACCUMULATOR_WIDTH | FLAG_INDEX_WIDTH
         } else {
-            // binary mode
-            let result_16 = self.a + to_add as u16 + self.p.carry as u16;
-
-            if result_16 >= 0x100 {
-                self.p.carry = true;
-            } else {
-                self.p.carry = false;
-            }
-
-            let result_8 = result_16 as u8;
+            p
+        };
 
-            // TODO -> DETERMINE OVERFLOW
+        if !self.x_wide() {
+            self.x &= 0x00FF;
+            self.y &= 0x00FF;
+        }
+    }
+
+    // the state after /RESET. the program counter comes from the vector at $00:FFFC
+    pub fn reset(&mut self) {
+        self.sp = 0x01FF;
+        self.d = 0;
+        self.dbr = 0;
+        self.pbr = 0;
+        self.p = 0x34; // 8 bit registers, interrupts disabled
+        self.emulation = true;
+        self.waiting = false;
+        self.x &= 0x00FF;
+        self.y &= 0x00FF;
+
+        let lo = self.mem.load(0, 0xFFFC) as u16;
+        let hi = self.mem.load(0, 0xFFFD) as u16;
+        self.pc = hi << 8 | lo;
+
+        self.should_exit = false;
+    }
+
+    // turning the console on. the registers reset leaves alone start out cleared
+    pub fn power_cycle(&mut self) {
+        self.a = 0;
+        self.x = 0;
+        self.y = 0;
+        self.reset();
+    }
+
+    pub fn run(&mut self) {
+        while !self.should_exit {
+            self.step();
+        }
+    }
+
+    // hands the cycles run so far to the rest of the system and returns them
+    fn catch_up(&mut self) -> u32 {
+        let master_cycles = self.cycles;
+        self.cycles = 0;
+        self.mem.tick(master_cycles);
+        master_cycles
+    }
+
+    // executes a single instruction and lets the rest of the system catch up.
+    // returns the number of master cycles it took
+    pub fn step(&mut self) -> u32 {
+        self.last_interrupt = None;
+
+        if self.should_exit {
+            // a stopped cpu does nothing, but time still passes for everything else
+            self.cycles += WAIT_MASTER_CYCLES;
+            return self.catch_up();
+        }
+
+        if self.waiting {
+            self.cycles += WAIT_MASTER_CYCLES;
+            let mut master_cycles = self.catch_up();
+            self.poll_interrupts();
+            master_cycles += self.catch_up();
+            return master_cycles;
+        }
+
+        let (pbr, pc) = (self.pbr, self.pc);
+        let opcode = self.next_b();
+        self.trace_instruction(pbr, pc, opcode);
+
+        match opcode {
+            // add w carry
+            0x61 => self.adc(AddressMode::DirectIndexedIndirect),
+            0x63 => self.adc(AddressMode::StackRelative),
+            0x65 => self.adc(AddressMode::Direct(opcode)),
+            0x67 => self.adc(AddressMode::DirectIndirectLong),
+            0x69 => self.adc(AddressMode::Immediate),
+            0x6D => self.adc(AddressMode::Absolute(opcode)),
+            0x6F => self.adc(AddressMode::AbsoluteLong),
+            0x71 => self.adc(AddressMode::DirectIndirectIndexed),
+            0x72 => self.adc(AddressMode::DirectIndirect),
+            0x73 => self.adc(AddressMode::StackRelativeIndirectIndexed),
+            0x75 => self.adc(AddressMode::DirectIndexedX),
+            0x77 => self.adc(AddressMode::DirectIndirectIndexedLong),
+            0x79 => self.adc(AddressMode::AbsoluteIndexedY),
+            0x7D => self.adc(AddressMode::AbsoluteIndexedX),
+            0x7F => self.adc(AddressMode::AbsoluteLongIndexedX),
+
+            // sub w carry
+            0xE1 => self.sbc(AddressMode::DirectIndexedIndirect),
+            0xE3 => self.sbc(AddressMode::StackRelative),
+            0xE5 => self.sbc(AddressMode::Direct(opcode)),
+            0xE7 => self.sbc(AddressMode::DirectIndirectLong),
+            0xE9 => self.sbc(AddressMode::Immediate),
+            0xED => self.sbc(AddressMode::Absolute(opcode)),
+            0xEF => self.sbc(AddressMode::AbsoluteLong),
+            0xF1 => self.sbc(AddressMode::DirectIndirectIndexed),
+            0xF2 => self.sbc(AddressMode::DirectIndirect),
+            0xF3 => self.sbc(AddressMode::StackRelativeIndirectIndexed),
+            0xF5 => self.sbc(AddressMode::DirectIndexedX),
+            0xF7 => self.sbc(AddressMode::DirectIndirectIndexedLong),
+            0xF9 => self.sbc(AddressMode::AbsoluteIndexedY),
+            0xFD => self.sbc(AddressMode::AbsoluteIndexedX),
+            0xFF => self.sbc(AddressMode::AbsoluteLongIndexedX),
+
+            // compare
+            0xC1 => self.cmp(AddressMode::DirectIndexedIndirect),
+            0xC3 => self.cmp(AddressMode::StackRelative),
+            0xC5 => self.cmp(AddressMode::Direct(opcode)),
+            0xC7 => self.cmp(AddressMode::DirectIndirectLong),
+            0xC9 => self.cmp(AddressMode::Immediate),
+            0xCD => self.cmp(AddressMode::Absolute(opcode)),
+            0xCF => self.cmp(AddressMode::AbsoluteLong),
+            0xD1 => self.cmp(AddressMode::DirectIndirectIndexed),
+            0xD2 => self.cmp(AddressMode::DirectIndirect),
+            0xD3 => self.cmp(AddressMode::StackRelativeIndirectIndexed),
+            0xD5 => self.cmp(AddressMode::DirectIndexedX),
+            0xD7 => self.cmp(AddressMode::DirectIndirectIndexedLong),
+            0xD9 => self.cmp(AddressMode::AbsoluteIndexedY),
+            0xDD => self.cmp(AddressMode::AbsoluteIndexedX),
+            0xDF => self.cmp(AddressMode::AbsoluteLongIndexedX),
+            0xE0 => self.cpx(AddressMode::Immediate),
+            0xE4 => self.cpx(AddressMode::Direct(opcode)),
+            0xEC => self.cpx(AddressMode::Absolute(opcode)),
+            0xC0 => self.cpy(AddressMode::Immediate),
+            0xC4 => self.cpy(AddressMode::Direct(opcode)),
+            0xCC => self.cpy(AddressMode::Absolute(opcode)),
+
+            // decrement
+            0x3A => self.dec(AddressMode::Accumulator),
+            0xC6 => self.dec(AddressMode::Direct(opcode)),
+            0xCE => self.dec(AddressMode::Absolute(opcode)),
+            0xD6 => self.dec(AddressMode::DirectIndexedX),
+            0xDE => self.dec(AddressMode::AbsoluteIndexedX),
+            0xCA => self.dex(AddressMode::Implied),
+            0x88 => self.dey(AddressMode::Implied),
+
+            // increment
+            0x1A => self.inc(AddressMode::Accumulator),
+            0xE6 => self.inc(AddressMode::Direct(opcode)),
+            0xEE => self.inc(AddressMode::Absolute(opcode)),
+            0xF6 => self.inc(AddressMode::DirectIndexedX),
+            0xFE => self.inc(AddressMode::AbsoluteIndexedX),
+            0xE8 => self.inx(AddressMode::Implied),
+            0xC8 => self.iny(AddressMode::Implied),
+
+            // and
+            0x21 => self.and(AddressMode::DirectIndexedIndirect),
+            0x23 => self.and(AddressMode::StackRelative),
+            0x25 => self.and(AddressMode::Direct(opcode)),
+            0x27 => self.and(AddressMode::DirectIndirectLong),
+            0x29 => self.and(AddressMode::Immediate),
+            0x2D => self.and(AddressMode::Absolute(opcode)),
+            0x2F => self.and(AddressMode::AbsoluteLong),
+            0x31 => self.and(AddressMode::DirectIndirectIndexed),
+            0x32 => self.and(AddressMode::DirectIndirect),
+            0x33 => self.and(AddressMode::StackRelativeIndirectIndexed),
+            0x35 => self.and(AddressMode::DirectIndexedX),
+            0x37 => self.and(AddressMode::DirectIndirectIndexedLong),
+            0x39 => self.and(AddressMode::AbsoluteIndexedY),
+            0x3D => self.and(AddressMode::AbsoluteIndexedX),
+            0x3F => self.and(AddressMode::AbsoluteLongIndexedX),
+
+            // eor
+            0x41 => self.eor(AddressMode::DirectIndexedIndirect),
+            0x43 => self.eor(AddressMode::StackRelative),
+            0x45 => self.eor(AddressMode::Direct(opcode)),
+            0x47 => self.eor(AddressMode::DirectIndirectLong),
+            0x49 => self.eor(AddressMode::Immediate),
+            0x4D => self.eor(AddressMode::Absolute(opcode)),
+            0x4F => self.eor(AddressMode::AbsoluteLong),
+            0x51 => self.eor(AddressMode::DirectIndirectIndexed),
+            0x52 => self.eor(AddressMode::DirectIndirect),
+            0x53 => self.eor(AddressMode::StackRelativeIndirectIndexed),
+            0x55 => self.eor(AddressMode::DirectIndexedX),
+            0x57 => self.eor(AddressMode::DirectIndirectIndexedLong),
+            0x59 => self.eor(AddressMode::AbsoluteIndexedY),
+            0x5D => self.eor(AddressMode::AbsoluteIndexedX),
+            0x5F => self.eor(AddressMode::AbsoluteLongIndexedX),
+
+            // ora
+            0x01 => self.ora(AddressMode::DirectIndexedIndirect),
+            0x03 => self.ora(AddressMode::StackRelative),
+            0x05 => self.ora(AddressMode::Direct(opcode)),
+            0x07 => self.ora(AddressMode::DirectIndirectLong),
+            0x09 => self.ora(AddressMode::Immediate),
+            0x0D => self.ora(AddressMode::Absolute(opcode)),
+            0x0F => self.ora(AddressMode::AbsoluteLong),
+            0x11 => self.ora(AddressMode::DirectIndirectIndexed),
+            0x12 => self.ora(AddressMode::DirectIndirect),
+            0x13 => self.ora(AddressMode::StackRelativeIndirectIndexed),
+            0x15 => self.ora(AddressMode::DirectIndexedX),
+            0x17 => self.ora(AddressMode::DirectIndirectIndexedLong),
+            0x19 => self.ora(AddressMode::AbsoluteIndexedY),
+            0x1D => self.ora(AddressMode::AbsoluteIndexedX),
+            0x1F => self.ora(AddressMode::AbsoluteLongIndexedX),
+
+            // bit
+            0x24 => self.bit(AddressMode::Direct(opcode)),
+            0x2C => self.bit(AddressMode::Absolute(opcode)),
+            0x34 => self.bit(AddressMode::DirectIndexedX),
+            0x3C => self.bit(AddressMode::AbsoluteIndexedX),
+            0x89 => self.bit(AddressMode::Immediate),
+
+            // trb | tsb
+            0x14 => self.trb(AddressMode::Direct(opcode)),
+            0x1C => self.trb(AddressMode::Absolute(opcode)),
+            0x04 => self.tsb(AddressMode::Direct(opcode)),
+            0x0C => self.tsb(AddressMode::Absolute(opcode)),
+
+            // asl
+            0x06 => self.asl(AddressMode::Direct(opcode)),
+            0x0A => self.asl(AddressMode::Accumulator),
+            0x0E => self.asl(AddressMode::Absolute(opcode)),
+            0x16 => self.asl(AddressMode::DirectIndexedX),
+            0x1E => self.asl(AddressMode::AbsoluteIndexedX),
+
+            // lsr
+            0x46 => self.lsr(AddressMode::Direct(opcode)),
+            0x4A => self.lsr(AddressMode::Accumulator),
+            0x4E => self.lsr(AddressMode::Absolute(opcode)),
+            0x56 => self.lsr(AddressMode::DirectIndexedX),
+            0x5E => self.lsr(AddressMode::AbsoluteIndexedX),
+
+            // rol
+            0x26 => self.rol(AddressMode::Direct(opcode)),
+            0x2A => self.rol(AddressMode::Accumulator),
+            0x2E => self.rol(AddressMode::Absolute(opcode)),
+            0x36 => self.rol(AddressMode::DirectIndexedX),
+            0x3E => self.rol(AddressMode::AbsoluteIndexedX),
+
+            // ror
+            0x66 => self.ror(AddressMode::Direct(opcode)),
+            0x6A => self.ror(AddressMode::Accumulator),
+            0x6E => self.ror(AddressMode::Absolute(opcode)),
+            0x76 => self.ror(AddressMode::DirectIndexedX),
+            0x7E => self.ror(AddressMode::AbsoluteIndexedX),
+
+            // branch
+            0x90 => self.bcc(AddressMode::Relative8), 
+            0xB0 => self.bcs(AddressMode::Relative8), 
+            0xF0 => self.beq(AddressMode::Relative8), 
+            0x30 => self.bmi(AddressMode::Relative8), 
+            0xD0 => self.bne(AddressMode::Relative8), 
+            0x10 => self.bpl(AddressMode::Relative8), 
+            0x80 => self.bra(AddressMode::Relative8), 
+            0x50 => self.bvc(AddressMode::Relative8), 
+            0x70 => self.bvs(AddressMode::Relative8), 
+            0x82 => self.brl(AddressMode::Relative16),
+
+            // jump
+            0x4C => self.jmp(AddressMode::Absolute(opcode)),
+            0x5C => self.jmp(AddressMode::AbsoluteLong),
+            0x6C => self.jmp(AddressMode::AbsoluteIndirect),
+            0x7C => self.jmp(AddressMode::AbsoluteIndexedIndirect),
+            0xDC => self.jmp(AddressMode::AbsoluteIndirectLong),
+            0x22 => self.jsl(AddressMode::AbsoluteLong),
+            0x20 => self.jsr(AddressMode::Absolute(opcode)),
+            0xFC => self.jsr(AddressMode::AbsoluteIndexedIndirect),
+
+            // return
+            0x6B => self.rtl(AddressMode::Implied),
+            0x60 => self.rts(AddressMode::Implied),
+            0x40 => self.rti(AddressMode::Implied),
+
+            // software interrupts
+            0x00 => self.brk(AddressMode::Implied),
+            0x02 => self.cop(AddressMode::Immediate),
+
+            // clear | set
+            0x18 => self.clc(AddressMode::Implied),
+            0xD8 => self.cld(AddressMode::Implied),
+            0x58 => self.cli(AddressMode::Implied),
+            0xB8 => self.clv(AddressMode::Implied),
+            0x38 => self.sec(AddressMode::Implied),
+            0xF8 => self.sed(AddressMode::Implied),
+            0x78 => self.sei(AddressMode::Implied),
+
+            // reset / set processor status bits
+            0xC2 => self.rep(AddressMode::Immediate),
+            0xE2 => self.sep(AddressMode::Immediate),
+
+            // load
+            0xA1 => self.lda(AddressMode::DirectIndexedIndirect),
+            0xA3 => self.lda(AddressMode::StackRelative),
+            0xA5 => self.lda(AddressMode::Direct(opcode)),
+            0xA7 => self.lda(AddressMode::DirectIndirectLong),
+            0xA9 => self.lda(AddressMode::Immediate), 
+            0xAD => self.lda(AddressMode::Absolute(opcode)),
+            0xAF => self.lda(AddressMode::AbsoluteLong),
+            0xB1 => self.lda(AddressMode::DirectIndirectIndexed),
+            0xB2 => self.lda(AddressMode::DirectIndirect),
+            0xB3 => self.lda(AddressMode::StackRelativeIndirectIndexed),
+            0xB5 => self.lda(AddressMode::DirectIndexedX),
+            0xB7 => self.lda(AddressMode::DirectIndirectIndexedLong),
+            0xB9 => self.lda(AddressMode::AbsoluteIndexedY),
+            0xBD => self.lda(AddressMode::AbsoluteIndexedX),
+            0xBF => self.lda(AddressMode::AbsoluteLongIndexedX),
+            0xA2 => self.ldx(AddressMode::Immediate),
+            0xA6 => self.ldx(AddressMode::Direct(opcode)),
+            0xAE => self.ldx(AddressMode::Absolute(opcode)),
+            0xB6 => self.ldx(AddressMode::DirectIndexedY),
+            0xBE => self.ldx(AddressMode::AbsoluteIndexedY),
+            0xA0 => self.ldy(AddressMode::Immediate),        
+            0xA4 => self.ldy(AddressMode::Direct(opcode)),
+            0xAC => self.ldy(AddressMode::Absolute(opcode)),
+            0xB4 => self.ldy(AddressMode::DirectIndexedX),
+            0xBC => self.ldy(AddressMode::AbsoluteIndexedX),
+
+            // store
+            0x81 => self.sta(AddressMode::DirectIndexedIndirect),
+            0x83 => self.sta(AddressMode::StackRelative),
+            0x85 => self.sta(AddressMode::Direct(opcode)),
+            0x87 => self.sta(AddressMode::DirectIndirectLong),
+            0x8D => self.sta(AddressMode::Absolute(opcode)),
+            0x8F => self.sta(AddressMode::AbsoluteLong),
+            0x91 => self.sta(AddressMode::DirectIndirectIndexed),
+            0x92 => self.sta(AddressMode::DirectIndirect),
+            0x93 => self.sta(AddressMode::StackRelativeIndirectIndexed),
+            0x95 => self.sta(AddressMode::DirectIndexedX),
+            0x97 => self.sta(AddressMode::DirectIndirectIndexedLong),
+            0x99 => self.sta(AddressMode::AbsoluteIndexedY),
+            0x9D => self.sta(AddressMode::AbsoluteIndexedX),
+            0x9F => self.sta(AddressMode::AbsoluteLongIndexedX),
+            0x86 => self.stx(AddressMode::Direct(opcode)),
+            0x8E => self.stx(AddressMode::Absolute(opcode)),
+            0x96 => self.stx(AddressMode::DirectIndexedY),
+            0x84 => self.sty(AddressMode::Direct(opcode)),
+            0x8C => self.sty(AddressMode::Absolute(opcode)),
+            0x94 => self.sty(AddressMode::DirectIndexedX),
+            0x64 => self.stz(AddressMode::Direct(opcode)),
+            0x74 => self.stz(AddressMode::DirectIndexedX),
+            0x9C => self.stz(AddressMode::Absolute(opcode)),
+            0x9E => self.stz(AddressMode::AbsoluteIndexedX),
+
+            // move memory negative/positive
+            0x54 => self.mvn(AddressMode::SourceDestination),
+            0x44 => self.mvp(AddressMode::SourceDestination),
+
+            // no op and the WDM JR. BABY
+            0xEA => self.nop(AddressMode::Implied),
+            0x42 => self.wdm(AddressMode::Immediate),
+
+            // push effective
+            0xF4 => self.pea(AddressMode::Immediate),
+            0xD4 => self.pei(AddressMode::Direct(opcode)),
+            0x62 => self.per(AddressMode::Relative16),
+
+            // push / pull
+            0x48 => self.pha(AddressMode::Implied),
+            0xDA => self.phx(AddressMode::Implied),
+            0x5A => self.phy(AddressMode::Implied),
+            0x68 => self.pla(AddressMode::Implied),
+            0xFA => self.plx(AddressMode::Implied),
+            0x7A => self.ply(AddressMode::Implied),
+
+            0x8B => self.phb(AddressMode::Implied),
+            0x0B => self.phd(AddressMode::Implied),
+            0x4B => self.phk(AddressMode::Implied),
+            0x08 => self.php(AddressMode::Implied),
+            0xAB => self.plb(AddressMode::Implied),
+            0x2B => self.pld(AddressMode::Implied),
+            0x28 => self.plp(AddressMode::Implied),
+
+            // stop / wait
+            0xDB => self.stp(AddressMode::Implied),
+            0xCB => self.wai(AddressMode::Implied),
+
+            // transfer
+            0xAA => self.tax(AddressMode::Implied),
+            0xA8 => self.tay(AddressMode::Implied),
+            0xBA => self.tsx(AddressMode::Implied),
+            0x8A => self.txa(AddressMode::Implied),
+            0x9A => self.txs(AddressMode::Implied),
+            0x9B => self.txy(AddressMode::Implied),
+            0x98 => self.tya(AddressMode::Implied),
+            0xBB => self.tyx(AddressMode::Implied),
+            0x5B => self.tcd(AddressMode::Implied),
+            0x1B => self.tcs(AddressMode::Implied),
+            0x7B => self.tdc(AddressMode::Implied),
+            0x3B => self.tsc(AddressMode::Implied),
+
+            // exchange
+            0xEB => self.xba(AddressMode::Implied),
+            0xFB => self.xce(AddressMode::Implied),
+
+            // every one of the 256 opcodes has an arm above, so there's no fallback that could
+            // take the emulator down on an unknown byte
+
+            // address mode mappings
+            // =====================
+            // (dir, X)   => DirectIndexedIndirect
+            // stk, S     => StackRelative
+            // dir        => Direct
+            // [dir]      => DirectIndirectLong
+            // imm        => Immediate
+            // abs        => Absolute
+            // (dir), Y   => DirectIndirectIndexed
+            // (dir)      => DirectIndirect
+            // (stk,S), Y => StackRelativeIndirectIndexed
+            // dir, X     => DirectIndexedX
+            // [dir], Y   => DirectIndirectIndexedLong
+            // abs, Y     => AbsoluteIndexedY
+            // abs, X     => AbsoluteIndexedX
+            // long       => AbsoluteLong
+            // long, X    => AbsoluteLongIndexedX
+            // (abs)      => AbsoluteIndirect
+            // [abs]      => AbsoluteIndirectLong
+            // (abs,X)    => AbsoluteIndexedIndirect
+
+        }
+
+        // let the rest of the system catch up to the cpu, then see if it raised an interrupt
+        let mut master_cycles = self.catch_up();
+        self.poll_interrupts();
+        master_cycles += self.catch_up();
+
+        master_cycles
+    }
 
-            self.a = self.ah() | result_8 as u16;
+    ////////////////////////////////////
+    //
+    //            INTERRUPTS
+    //
+    ////////////////////////////////////
 
-            self.p.set_negative_from_data_8(result_8);
-            self.p.set_zero_from_data(result_8);
+    // interrupts are only taken between instructions. WAI wakes up on an irq even when
+    // interrupts are disabled, it just carries on without taking it
+    fn poll_interrupts(&mut self) {
+        if self.mem.take_nmi() {
+            self.waiting = false;
+            self.interrupt(NMI_VECTOR, NMI_VECTOR_EMULATION);
+        } else if self.mem.irq() {
+            self.waiting = false;
+            if !self.flag(FLAG_IRQ_DISABLE) {
+                self.interrupt(IRQ_VECTOR, IRQ_VECTOR_EMULATION);
+            }
         }
     }
 
-    fn adc_16(&mut self, to_add: u16) {
-        // accumulator += data + carry;
-
-        if self.p.decimal_mode {
-            // bcd mode
-            let mut a1: u16 = self.a & 0x000F;
-            let mut a2: u16 = self.a & 0x00F0;
-            let mut a3: u16 = self.a & 0x0F00;
-            let mut a4: u32 = self.a as u32 & 0xF000;
-
-            let to_add1: u16 = to_add & 0x000F;
-            let to_add2: u16 = to_add & 0x00F0;
-            let to_add3: u16 = to_add & 0x0F00;
-            let to_add4: u16 = to_add & 0xF000;
-
-            a1 += to_add1 + self.p.carry as u16;
-
-            if a1 > 0x09 {
-                a1 -= 0x000A; // subtract 10
-                a1 &= 0x000F; // zero out unnecessary bits
-                a2 += 0x0010; // add 16 to second digit
-            }
+    fn interrupt(&mut self, vector: u16, emulation_vector: u16) {
+        // the two cycles the cpu spends noticing it
+        self.idle();
+        self.idle();
+        let vector = self.enter_interrupt(vector, emulation_vector, false);
+        self.last_interrupt = Some(vector);
+    }
 
-            a2 += to_add2;
+    // pushes the return address and p and jumps through the vector, for hardware interrupts
+    // and BRK/COP alike. returns the vector that was taken
+    fn enter_interrupt(&mut self, vector: u16, emulation_vector: u16, brk: bool) -> u16 {
+        let vector = if self.emulation {
+            emulation_vector
+        } else {
+            let pbr = self.pbr;
+            self.push_b(pbr);
+            vector
+        };
 
-            if a2 > 0x90 {
-                a2 -= 0x00A0;
-                a2 &= 0x00F0; // zero out unnecessary bits
-                a3 += 0x0100; // add 16 to third digits
-            }
+        let pc = self.pc;
+        self.push_w(pc);
+        // in emulation mode the pushed break flag is how a handler tells an irq from a BRK
+        let p = match (self.emulation, brk) {
+            (true, true) => self.p | FLAG_BREAK,
+            (true, false) => self.p & !FLAG_BREAK,
+            (false, _) => self.p,
+        };
+        self.push_b(p);
 
-            a3 += to_add3;
+        self.p = (self.p | FLAG_IRQ_DISABLE) & !FLAG_DECIMAL;
 
-            if a3 > 0x0900 {
-                a3 -= 0x0A00; // subtract 10
-                a3 &= 0x0F00; // zero out uneccessary bits
-                a4 += 0x1000; // add 16 to last digit;
-            }
+        let lo = self.read(0, vector) as u16;
+        let hi = self.read(0, vector.wrapping_add(1)) as u16;
+        self.pbr = 0x00;
+        self.pc = hi << 8 | lo;
+        vector
+    }
 
-            a4 += to_add4 as u32;
+    ////////////////////////////////////
+    //
+    //              STACK
+    //
+    ////////////////////////////////////
 
-            if a4 > 0x9000 {
-                a4 -= 0xA000;
-                a4 &= 0xF000;
-                self.p.carry = true;
-            } else {
-                self.p.carry = false;
-            }
+    // the stack grows down through bank 0. in emulation mode it wraps within page 1
+    fn push_b(&mut self, value: u8) {
+        let sp = self.sp;
+        self.write(0, sp, value);
+        self.sp = if self.emulation { 0x0100 | (sp.wrapping_sub(1) & 0x00FF) } else { sp.wrapping_sub(1) };
+    }
 
-            let result_32 = a1 | a2 | a3 | (a4 as u16);
+    fn pull_b(&mut self) -> u8 {
+        let sp = self.sp;
+        self.sp = if self.emulation { 0x0100 | (sp.wrapping_add(1) & 0x00FF) } else { sp.wrapping_add(1) };
+        let sp = self.sp;
+        self.read(0, sp)
+    }
 
-            // TODO -> DETERMINE OVERFLOW
+    // high byte first, so the pair sits little endian in memory
+    fn push_w(&mut self, value: u16) {
+        self.push_b((value >> 8) as u8);
+        self.push_b(value as u8);
+    }
 
-            self.a = result_32 as u16;
+    fn pull_w(&mut self) -> u16 {
+        let lo = self.pull_b() as u16;
+        let hi = self.pull_b() as u16;
+        hi << 8 | lo
+    }
 
-            // TODO -> SET ZN FLAGS
+    fn push(&mut self, value: u16, wide: bool) {
+        if wide {
+            self.push_w(value);
+        } else {
+            self.push_b(value as u8);
+        }
+    }
 
+    fn pull(&mut self, wide: bool) -> u16 {
+        if wide {
+            self.pull_w()
         } else {
-            // binary mode
+            self.pull_b() as u16
+        }
+    }
 
-            let result_32: u32 = self.a as u32 + to_add as u32 + self.p.carry as u32;
+    ////////////////////////////////////
+    //
+    //           SAVE STATES
+    //
+    ////////////////////////////////////
 
-            if result_32 >= 0x10000 {
-                self.p.carry = true;
-            } else {
-                self.p.carry = false;
-            }
+    pub fn save_state(&self, w: &mut StateWriter) {
+        w.u16(self.a);
+        w.u16(self.x);
+        w.u16(self.y);
+        w.u16(self.sp);
+        w.u8(self.dbr);
+        w.u8(self.pbr);
+        w.u16(self.d);
+        w.u16(self.pc);
+        w.u8(self.p);
+        w.bool(self.waiting);
+        w.bool(self.should_exit);
+        w.bool(self.emulation);
+    }
+
+    pub fn load_state(&mut self, r: &mut StateReader) -> Result<(), Error> {
+        self.a = r.u16()?;
+        self.x = r.u16()?;
+        self.y = r.u16()?;
+        self.sp = r.u16()?;
+        self.dbr = r.u8()?;
+        self.pbr = r.u8()?;
+        self.d = r.u16()?;
+        self.pc = r.u16()?;
+        self.p = r.u8()?;
+        self.waiting = r.bool()?;
+        self.should_exit = r.bool()?;
+        self.emulation = r.bool()?;
+        Ok(())
+    }
 
-            // TODO -> DETERMINE OVERFLOW
+    ////////////////////////////////////
+    //
+    //              TRACE
+    //
+    ////////////////////////////////////
 
-            self.a = result_32 as u16;
+    // logs every instruction before it executes, one line each
+    pub fn set_trace(&mut self, trace: Option<Box<dyn Write>>) {
+        self.trace = trace;
+    }
 
-            // TODO -> SET ZN FLAGS
+    fn trace_instruction(&mut self, pbr: u8, pc: u16, opcode: u8) {
+        if let Some(ref mut trace) = self.trace {
+            // a trace that can't be written isn't worth stopping the emulation for
+            if let Some(label) = self.mem.label(pbr, pc) {
+                let _ = writeln!(trace, "{}:", label);
+            }
+            let _ = writeln!(trace, "{:02X}:{:04X} {:02X}  A:{:04X} X:{:04X} Y:{:04X} S:{:04X} D:{:04X} DB:{:02X} P:{:02X}",
+                pbr, pc, opcode, self.a, self.x, self.y, self.sp, self.d, self.dbr, self.p);
         }
     }
 
+//    The n flag is 0 when the high bit of the result (bit 15 when the m flag is 0, bit 7 when the m flag is 1) is 0, and the n flag is 1 when the high bit of the result is 1.
+//    The v flag is 0 when there is not a signed arithmetic overflow, and the v flag is 1 when there is a signed arithmetic overflow.
+//              For 8-bit signed numbers, $00 to $7F represents 0 to 127, and $80 to $FF represents -128 to -1;
+//              an 8-bit arithmetic overflow occurs when the result is outside the range -128 to 127.
+//              For 16-bit signed numbers, $0000 to $7FFF represents 0 to 32767, and $8000 to $FFFF represents -32768 to -1;
+//              a 16-bit arithmetic overflow occurs when the result is outside the range -32768 to 32767.
+//    The z flag is 0 when the 16-bit (when m flag is 0) or 8-bit (when the m flag is 1) result is nonzero, and the z flag is 1 when the result is zero.
+//    The c flag is 0 when there is not an unsigned carry, and the c flag is 1 when there is an unsigned carry.
+//              For 8-bit unsigned numbers, $00 to $FF represents 0 to 255; for addition, an 8-bit carry occurs when the result is greater than 255.
+//              For 16-bit unsigned numbers, $0000 to $FFFF represents 0 to 65535;
+//              for addition, an 16-bit carry occurs when the result is greater than 65535.
+//              For subtraction (8-bit or 16-bit), there is a carry when the accumulator is greater than or equal to the data.
+
     ////////////////////////////////////
     //
-    //              SBC
+    //            ADC / SBC
     //
     ////////////////////////////////////
 
-    fn sbc(&mut self, am: AddressMode) {
-        if self.p.accumulator_width {
-            let to_sub = self.load_8(&am);
-            self.sbc_8(to_sub);
-        } else {
-            let to_sub = self.load_16(&am);
-            self.sbc_16(to_sub);
-        }
+    fn adc(&mut self, am: AddressMode) {
+        let wide = self.a_wide();
+        let to_add = self.load(&am, wide);
+        self.add_with_carry(to_add, false);
     }
 
-    fn sbc_8(&self, to_sub: u8) {
-        // accumulator -= data - 1 + carry;
+    // subtraction is addition of the complement, with the carry standing in for "no borrow"
+    fn sbc(&mut self, am: AddressMode) {
+        let wide = self.a_wide();
+        let to_sub = self.load(&am, wide);
+        self.add_with_carry(to_sub, true);
+    }
+
+    // in decimal mode each nibble is a digit. a digit that goes past 9 is pushed on by 6 so
+    // it carries into the next one, or pulled back by 6 when subtracting borrows from it.
+    // overflow comes from the binary result before the top digit is adjusted, as on hardware
+    fn add_with_carry(&mut self, data: u16, subtract: bool) {
+        let wide = self.a_wide();
+        let (top, sign, digits) = if wide { (0xFFFF, 0x8000, 4) } else { (0x00FF, 0x0080, 2) };
+
+        let a = self.acc() as i32;
+        let data = (if subtract { !data } else { data }) as i32 & top;
+        let decimal = self.flag(FLAG_DECIMAL);
+        let mut carry = self.flag(FLAG_CARRY) as i32;
+
+        let mut result = a + data + carry;
+        if decimal {
+            for digit in 0..digits {
+                let shift = digit * 4;
+                let below = (1 << shift) - 1;
+                let mask = 0xF << shift;
+                result = (a & mask) + (data & mask) + (carry << shift) + (result & below);
+
+                // the top digit is adjusted after overflow is worked out
+                if digit == digits - 1 {
+                    break;
+                }
 
-        if self.p.decimal_mode {
-            // bcd mode
-        } else {
-            // binary mode
+                if subtract {
+                    if result <= mask | below {
+                        result -= 0x6 << shift;
+                    }
+                } else if result > (0x9 << shift | below) {
+                    result += 0x6 << shift;
+                }
+                carry = (result > (mask | below)) as i32;
+            }
         }
-    }
 
-    fn sbc_16(&self, to_sub: u16) {
-        // accumulator -= data - 1 + carry;
+        self.set_flag(FLAG_OVERFLOW, !(a ^ data) & (a ^ result) & sign != 0);
 
-        if self.p.decimal_mode {
-            // bcd mode
-        } else {
-            // binary mode
+        if decimal {
+            let shift = (digits - 1) * 4;
+            if subtract {
+                if result <= top {
+                    result -= 0x6 << shift;
+                }
+            } else if result > (0x9 << shift | ((1 << shift) - 1)) {
+                result += 0x6 << shift;
+            }
         }
+
+        self.set_flag(FLAG_CARRY, result > top);
+        self.set_acc(result as u16);
     }
 
     ////////////////////////////////////
@@ -839,133 +1015,105 @@ impl CPU {
     //
     ////////////////////////////////////
 
-    // todo -> fix this
-    fn set_flags_for_cmp(&mut self, a: u16, b: u16) {
-        self.p.carry = a >= b;
-        self.p.negative = a < b;
-        self.p.zero = a == b;
+    fn compare(&mut self, register: u16, am: AddressMode, wide: bool) {
+        let to_compare = self.load(&am, wide);
+        let register = if wide { register } else { register & 0x00FF };
+
+        self.set_flag(FLAG_CARRY, register >= to_compare);
+        self.set_nz(register.wrapping_sub(to_compare), wide);
     }
 
     fn cmp(&mut self, am: AddressMode) {
-        if self.p.accumulator_width {
-            // 8
-            let to_compare = self.load_8(&am);
-            let a = self.a as u8;
-            self.set_flags_for_cmp(a as u16, to_compare as u16);
-        } else {
-            // 16
-            let to_compare = self.load_16(&am);
-            let a = self.a;
-            self.set_flags_for_cmp(a, to_compare);
-        }
+        let (a, wide) = (self.a, self.a_wide());
+        self.compare(a, am, wide);
     }
 
     fn cpx(&mut self, am: AddressMode) {
-        if self.p.index_width {
-            // 8
-            let to_compare = self.load_8(&am);
-            let a = self.x as u8;
-            self.set_flags_for_cmp(a as u16, to_compare as u16);
-        } else {
-            // 16
-            let to_compare = self.load_16(&am);
-            let a = self.x;
-            self.set_flags_for_cmp(a, to_compare);
-        }
+        let (x, wide) = (self.x, self.x_wide());
+        self.compare(x, am, wide);
     }
 
     fn cpy(&mut self, am: AddressMode) {
-        if self.p.index_width {
-            // 8
-            let to_compare = self.load_8(&am);
-            let a = self.y as u8;
-            self.set_flags_for_cmp(a as u16, to_compare as u16);
+        let (y, wide) = (self.y, self.x_wide());
+        self.compare(y, am, wide);
+    }
+
+    ////////////////////////////////////
+    //
+    //        READ-MODIFY-WRITE
+    //
+    ////////////////////////////////////
+
+    // the shifts, rotates, increments and decrements work on the accumulator or on memory at
+    // the accumulator's width. memory is read, given an internal cycle, then written back
+    fn modify(&mut self, am: AddressMode, op: fn(&mut CPU, u16) -> u16) {
+        if am == AddressMode::Accumulator {
+            self.idle();
+            let a = self.acc();
+            let result = op(self, a);
+            self.set_acc(result);
+        } else if self.a_wide() {
+            let ((lo_bank, lo_addr), (hi_bank, hi_addr)) = am.get_address_16(self);
+            let lo = self.read(lo_bank, lo_addr) as u16;
+            let hi = self.read(hi_bank, hi_addr) as u16;
+            self.idle();
+
+            let result = op(self, hi << 8 | lo);
+            self.set_nz(result, true);
+            self.write(hi_bank, hi_addr, (result >> 8) as u8);
+            self.write(lo_bank, lo_addr, result as u8);
         } else {
-            // 16
-            let to_compare = self.load_16(&am);
-            let a = self.y;
-            self.set_flags_for_cmp(a, to_compare);
+            let (bank, addr) = am.get_address_8(self);
+            let data = self.read(bank, addr) as u16;
+            self.idle();
+
+            let result = op(self, data);
+            self.set_nz(result, false);
+            self.write(bank, addr, result as u8);
         }
     }
 
+    // the bit that falls off the top, for the shifts and rotates
+    fn sign_bit(&self) -> u16 {
+        if self.a_wide() { 0x8000 } else { 0x0080 }
+    }
+
     ////////////////////////////////////
     //
     //      INCREMENT / DECREMENT
     //
     ////////////////////////////////////
 
-    fn dec(&mut self, am: AddressMode){
-        match am {
-            AddressMode::Accumulator => {
-                self.a -= 1;
-
-                self.p.negative = if self.p.accumulator_width {
-                    self.a & (1 << 7) != 0
-                } else {
-                    self.a & (1 << 15) != 0
-                };
-
-                self.p.zero = self.a == 0;
-            },
-            _ => {
-                if self.p.accumulator_width {
-                    let to_dec = self.load_8(&am) - 1;
-                    self.p.negative = to_dec & (1 << 7) != 0;
-                    self.p.zero = to_dec == 0;
-                    self.store_8(&am, to_dec);
-                } else {
-                    let to_dec = self.load_16(&am) - 1;
-                    self.p.negative = to_dec & (1 << 15) != 0;
-                    self.p.zero = to_dec == 0;
-                    self.store_16(&am, to_dec);
-                }
-            }
-        };
+    fn dec(&mut self, am: AddressMode) {
+        self.modify(am, |_, data| data.wrapping_sub(1));
     }
 
     fn dex(&mut self, _: AddressMode) {
-        self.x -= 1;
+        self.idle();
+        let x = self.x.wrapping_sub(1);
+        self.set_x_reg(x);
     }
 
     fn dey(&mut self, _: AddressMode) {
-        self.y -= 1;
+        self.idle();
+        let y = self.y.wrapping_sub(1);
+        self.set_y_reg(y);
     }
 
     fn inc(&mut self, am: AddressMode) {
-        match am {
-            AddressMode::Accumulator => {
-                self.a += 1;
-
-                self.p.negative = if self.p.accumulator_width {
-                    self.a & (1 << 7) != 0
-                } else {
-                    self.a & (1 << 15) != 0
-                };
-
-                self.p.zero = self.a == 0;
-            },
-            _ => {
-                if self.p.accumulator_width {
-                    let to_dec = self.load_8(&am) + 1;
-                    self.p.negative = to_dec & (1 << 7) != 0;
-                    self.p.zero = to_dec == 0;
-                    self.store_8(&am, to_dec);
-                } else {
-                    let to_dec = self.load_16(&am) + 1;
-                    self.p.negative = to_dec & (1 << 15) != 0;
-                    self.p.zero = to_dec == 0;
-                    self.store_16(&am, to_dec);
-                }
-            }
-        };
+        self.modify(am, |_, data| data.wrapping_add(1));
     }
 
     fn inx(&mut self, _: AddressMode) {
-        self.x += 1;
+        self.idle();
+        let x = self.x.wrapping_add(1);
+        self.set_x_reg(x);
     }
 
     fn iny(&mut self, _: AddressMode) {
-        self.y += 1;
+        self.idle();
+        let y = self.y.wrapping_add(1);
+        self.set_y_reg(y);
     }
 
     ////////////////////////////////////
@@ -974,25 +1122,19 @@ impl CPU {
     //
     ////////////////////////////////////
 
+    // the immediate form only sets z, the others copy the top two bits of the data into n and v
     fn bit(&mut self, am: AddressMode) {
-        if self.p.index_width {
-            let to_comp = self.load_8(&am);
+        let wide = self.a_wide();
+        let immediate = am == AddressMode::Immediate;
+        let data = self.load(&am, wide);
 
-            if am == AddressMode::Immediate {
-                self.p.negative = to_comp & (1 << 7) != 0;
-                self.p.overflow = to_comp & (1 << 6) != 0;
-            }
+        let a = self.acc();
+        self.set_flag(FLAG_ZERO, a & data == 0);
 
-            self.p.zero = to_comp == 0;
-        } else {
-            let to_comp = self.load_16(&am);
-
-            if am == AddressMode::Immediate {
-                self.p.negative = to_comp & (1 << 15) != 0;
-                self.p.overflow = to_comp & (1 << 14) != 0;
-            }
-
-            self.p.zero = to_comp == 0;
+        if !immediate {
+            let sign = self.sign_bit();
+            self.set_flag(FLAG_NEGATIVE, data & sign != 0);
+            self.set_flag(FLAG_OVERFLOW, data & (sign >> 1) != 0);
         }
     }
 
@@ -1007,37 +1149,37 @@ impl CPU {
 //
 //    TRB: "Logically AND together the _complement_ of the value in the accumulator with the data
 //          at the effective address specified by the operand. Store the result at the memory location"
+//
+//    both set z from the accumulator ANDed with the data as it was, like BIT does
     fn trb(&mut self, am: AddressMode) {
-        if self.p.accumulator_width {
-            // 8
-            let data = self.load_8(&am);
-            let result = self.al() | data;
-
-            self.p.set_zero(result == 0);
-            self.store_8(&am, result);
-        } else {
-            // 16
-            let data = self.load_16(&am);
-            let result = self.a | data;
-
-            self.p.set_zero(result == 0);
-            self.store_16(&am, result);
-        }
+        self.test_bits(am, |a, data| !a & data);
     }
 
     fn tsb(&mut self, am: AddressMode) {
-        if self.p.accumulator_width {
-            let data = self.load_8(&am);
-            let result = !self.al() & data;
+        self.test_bits(am, |a, data| a | data);
+    }
 
-            self.p.set_zero(result == 0);
-            self.store_8(&am, result);
+    fn test_bits(&mut self, am: AddressMode, op: fn(u16, u16) -> u16) {
+        let a = self.acc();
+
+        if self.a_wide() {
+            let ((lo_bank, lo_addr), (hi_bank, hi_addr)) = am.get_address_16(self);
+            let lo = self.read(lo_bank, lo_addr) as u16;
+            let hi = self.read(hi_bank, hi_addr) as u16;
+            let data = hi << 8 | lo;
+            self.idle();
+
+            self.set_flag(FLAG_ZERO, a & data == 0);
+            let result = op(a, data);
+            self.write(hi_bank, hi_addr, (result >> 8) as u8);
+            self.write(lo_bank, lo_addr, result as u8);
         } else {
-            let data = self.load_16(&am);
-            let result = (!self.a) & data;
+            let (bank, addr) = am.get_address_8(self);
+            let data = self.read(bank, addr) as u16;
+            self.idle();
 
-            self.p.set_zero(result == 0);
-            self.store_16(&am, result);
+            self.set_flag(FLAG_ZERO, a & data == 0);
+            self.write(bank, addr, op(a, data) as u8);
         }
     }
 
@@ -1047,100 +1189,19 @@ impl CPU {
     //
     ////////////////////////////////////
 
-    //The n flag reflects the high bit of the result.
-    //The z flag reflects whether the result is zero.
-
     fn asl(&mut self, am: AddressMode) {
-        //ASL shifts left; a zero is shifted into the low bit (bit 0); the high bit (bit 15 when the m flag is one, bit 7 when the m flag is 0) is shifted into the c flag.
-
-        if self.p.accumulator_width {
-            let to_and = 1 << 7 as u8;
-            if am == AddressMode::Accumulator {
-                let to_carry = (self.al() & to_and) >> 7;
-                self.a = self.ah() | ((self.al() << 1) as u8);
-                self.p.carry = to_carry == 1;
-                self.p.set_negative_from_data_16(self.al());
-                self.p.set_zero_from_data(self.al() as u16);
-            } else {
-                let addr = am.get_address_8(self, &self.mem);
-
-                let mut data = self.load_8_from_addr(addr);
-                let to_carry = (data & to_and) >> 7;
-                data = (data << 1) as u8;
-
-                self.store_8_from_addr(addr, data);
-
-                self.p.set_negative_from_data_8(data);
-                self.p.set_zero_from_data(data as u16);
-            }
-        } else {
-            let to_and = 1 << 15 as u8;
-            if am == AddressMode::Accumulator {
-                let to_carry = (self.a & to_and) >> 15;
-                self.a = (self.a << 1) as u16;
-                self.p.carry = to_carry == 1;
-                self.p.set_negative_from_data_16(self.a);
-                self.p.set_zero_from_data(self.a);
-            } else {
-                let (lo_addr, hi_addr) = am.get_address_16(self, &self.mem);
-
-                let mut data = self.load_16_from_addresses(lo_addr, hi_addr);
-                let to_carry = (data & to_and) >> 15;
-                self.p.carry = to_carry == 1;
-                data = (data << 1) as u16;
-
-                self.store_16_from_addr(lo_addr, hi_addr, data);
-
-                self.p.set_negative_from_data_16(data);
-                self.p.set_zero_from_data(data);
-            }
-        }
+        self.modify(am, |cpu, data| {
+            let sign = cpu.sign_bit();
+            cpu.set_flag(FLAG_CARRY, data & sign != 0);
+            data << 1
+        });
     }
 
     fn lsr(&mut self, am: AddressMode) {
-        //LSR shifts right; a zero is shifted into the high bit; the low bit is shifted into the c flag.
-
-        if self.p.accumulator_width {
-            if am == AddressMode::Accumulator {
-                let to_carry = (self.al() & 0b1);
-                self.a = self.ah() | ((self.al() >> 1) as u8);
-                self.p.carry = to_carry == 1;
-                self.p.set_negative_from_data_16(self.al());
-                self.p.set_zero_from_data(self.al() as u16);
-            } else {
-                let addr = am.get_address_8(self, &self.mem);
-
-                let mut data = self.load_8_from_addr(addr);
-                let to_carry = (data & 0b1);
-                self.p.carry = to_carry == 1;
-                data = (data >> 1) as u8;
-
-                self.store_8_from_addr(addr, data);
-
-                self.p.set_negative_from_data_8(data);
-                self.p.set_zero_from_data(data as u16);
-            }
-        } else {
-            if am == AddressMode::Accumulator {
-                let to_carry = self.a & 0b1;
-                self.a = (self.a >> 1) as u16;
-                self.p.carry = to_carry == 1;
-                self.p.set_negative_from_data_16(self.a);
-                self.p.set_zero_from_data(self.a);
-            } else {
-                let (lo_addr, hi_addr) = am.get_address_16(self, &self.mem);
-
-                let mut data = self.load_16_from_addresses(lo_addr, hi_addr);
-                let to_carry = data & 0b1;
-                self.p.carry = to_carry == 1;
-                data = (data >> 1) as u16;
-
-                self.store_16_from_addr(lo_addr, hi_addr, data);
-
-                self.p.set_negative_from_data_16(data);
-                self.p.set_zero_from_data(data);
-            }
-        }
+        self.modify(am, |cpu, data| {
+            cpu.set_flag(FLAG_CARRY, data & 1 != 0);
+            data >> 1
+        });
     }
 
     ////////////////////////////////////
@@ -1149,126 +1210,24 @@ impl CPU {
     //
     ////////////////////////////////////
 
-    // for these the main thing is you
-    // (a) grab the high/low bit
-    // (b) set the carry flag to the bit you just grabbed
-    // (c) shift the thing left/right
-    // (d) set the low/high bit to the carry flag
-    //      do this by 'or'ing. ie:
-    //          if you have 0b1111_1111 and you << 1 then you have 0b1111_1110 and you | with the
-    //          carry which will effectively shift it to the lower/higher bit
+    // rotates go through the carry, so it's one bit wider than the data
 
     fn rol(&mut self, am: AddressMode) {
-        //ROL shifts left; the (input) c flag is shifted into the low bit; the high bit is shifted into the c flag (result).
-
-        if self.p.accumulator_width {
-            let to_and = 1 << 7 as u8;
-            if am == AddressMode::Accumulator {
-                let prev_carry = if self.p.carry { 0b1 } else { 0b0 };
-                let to_carry = (self.al() & to_and) >> 7;
-                self.a = self.ah() | ((self.al() << 1) as u8);
-                self.p.carry = to_carry == 1;
-                self.a = self.a | prev_carry;
-                self.p.set_negative_from_data_8(self.al());
-                self.p.set_zero_from_data(self.al() as u16);
-            } else {
-                let addr = am.get_address_8(self, &self.mem);
-
-                let mut data = self.load_8_from_addr(addr);
-                let prev_carry = if self.p.carry { 0b1 } else { 0b0 };
-                let to_carry = (data & to_and) >> 7;
-                data = (data << 1) as u8;
-                data = data | prev_carry;
-                self.store_8(&am, data);
-
-                self.store_8_from_addr(addr, data);
-
-                self.p.set_negative_from_data_8(data);
-                self.p.set_zero_from_data(data as u16);
-            }
-        } else {
-            let to_and = 1 << 15 as u8;
-            if am == AddressMode::Accumulator {
-                let prev_carry = if self.p.carry { 0b1 } else { 0b0 };
-                let to_carry = (self.a & to_and) >> 15;
-                self.a = (self.a << 1) as u16;
-                self.a = self.a | prev_carry;
-                self.p.carry = to_carry == 1;
-                self.p.set_negative_from_data_16(self.a);
-                self.p.set_zero_from_data(self.a);
-            } else {
-                let (lo_addr, hi_addr) = am.get_address_16(self, &self.mem);
-
-                let mut data = self.load_16_from_addresses(lo_addr, hi_addr);
-                let prev_carry = if self.p.carry { 0b1 } else { 0b0 };
-                let to_carry = (data & to_and) >> 15;
-                self.p.carry = to_carry == 1;
-                data = (data << 1) as u16;
-                data = data | prev_carry;
-
-                self.store_16_from_addr(lo_addr, hi_addr, data);
-
-                self.p.set_negative_from_data_16(data);
-                self.p.set_zero_from_data(data);
-            }
-        }
+        self.modify(am, |cpu, data| {
+            let sign = cpu.sign_bit();
+            let carry = cpu.flag(FLAG_CARRY) as u16;
+            cpu.set_flag(FLAG_CARRY, data & sign != 0);
+            data << 1 | carry
+        });
     }
 
     fn ror(&mut self, am: AddressMode) {
-        //ROR shifts right; the (input) c flag is shifted into the high bit; the low bit is shifted into the c flag (result).
-
-        if self.p.accumulator_width {
-            if am == AddressMode::Accumulator {
-                let prev_carry = if self.p.carry { 0b1 } else { 0b0 };
-                let to_carry = (self.al() & 0b1);
-                self.a = self.ah() | ((self.al() >> 1) as u8);
-                self.a = self.a | prev_carry;
-                self.p.carry = to_carry == 1;
-
-                self.p.set_negative_from_data_8(self.al());
-                self.p.set_zero_from_data(self.al() as u16);
-            } else {
-                let addr = am.get_address_8(self, &self.mem);
-
-                let mut data = self.load_8_from_addr(addr);
-                let prev_carry = if self.p.carry { 0b1 } else { 0b0 };
-                let to_carry = (data & 0b1);
-                self.p.carry = to_carry == 1;
-                data = (data >> 1) as u8;
-                data = data | prev_carry;
-
-                self.store_8_from_addr(addr, data);
-
-                self.p.set_negative_from_data_8(data);
-                self.p.set_zero_from_data(data as u16);
-            }
-        } else {
-            if am == AddressMode::Accumulator {
-                let prev_carry = if self.p.carry { 0b1 } else { 0b0 };
-                let to_carry = self.a & 0b1;
-                self.a = (self.a >> 1) as u16;
-                self.a = self.a | prev_carry;
-                self.p.set_carry(to_carry == 1);
-                self.p.set_negative_from_data_16(self.a);
-                self.p.set_zero_from_data(self.a);
-            } else {
-                let (lo_addr, hi_addr) = am.get_address_16(self, &self.mem);
-
-                let mut data = self.load_16_from_addresses(lo_addr, hi_addr);
-                let to_carry = data & 0b1;
-                let prev_carry = if self.p.carry { 0b1 } else { 0b0 };
-                self.p.set_carry(to_carry == 1);
-
-                // todo -> don't think we're handling carries properly...
-                data = (data >> 1) as u16;
-                data = data | prev_carry;
-
-                self.store_16_from_addr(lo_addr, hi_addr, data);
-
-                self.p.set_negative_from_data_16(data);
-                self.p.set_zero_from_data(data);
-            }
-        }
+        self.modify(am, |cpu, data| {
+            let sign = cpu.sign_bit();
+            let carry = if cpu.flag(FLAG_CARRY) { sign } else { 0 };
+            cpu.set_flag(FLAG_CARRY, data & 1 != 0);
+            data >> 1 | carry
+        });
     }
 
     ////////////////////////////////////
@@ -1277,69 +1236,68 @@ impl CPU {
     //
     ////////////////////////////////////
 
-    // probably don't want to pass the address itself and instead just the addressmode
-    fn branch(&mut self, am: AddressMode) {
-        let address = self.get_address(am);
+    // the operand is read whether or not the branch is taken. taking it costs a cycle, and
+    // another in emulation mode when it lands on a different page
+    fn branch(&mut self, am: AddressMode, take: bool) {
+        let (_, target) = am.get_address_8(self);
 
-        // todo -> dont think we want to change the pbr? do we? do we? i dont know? i'm so confused.
-        self.pbr = address.0; // first 8 bits
-        self.pc = address.1;  // last 16 bits
+        if take {
+            self.idle();
+            if self.emulation && target & 0xFF00 != self.pc & 0xFF00 {
+                self.idle();
+            }
+            self.pc = target;
+        }
     }
 
     fn bcc(&mut self, am: AddressMode) {
-        if !self.p.carry {
-            branch(am);
-        }
+        let take = !self.flag(FLAG_CARRY);
+        self.branch(am, take);
     }
 
     fn bcs(&mut self, am: AddressMode) {
-        if self.p.carry {
-            branch(am);
-        }
+        let take = self.flag(FLAG_CARRY);
+        self.branch(am, take);
     }
 
     fn beq(&mut self, am: AddressMode) {
-        if self.p.zero {
-            branch(am);
-        }
+        let take = self.flag(FLAG_ZERO);
+        self.branch(am, take);
     }
 
     fn bmi(&mut self, am: AddressMode) {
-        if self.p.negative {
-            branch(am);
-        }
+        let take = self.flag(FLAG_NEGATIVE);
+        self.branch(am, take);
     }
 
     fn bne(&mut self, am: AddressMode) {
-        if !self.p.zero {
-            branch(am);
-        }
+        let take = !self.flag(FLAG_ZERO);
+        self.branch(am, take);
     }
 
     fn bpl(&mut self, am: AddressMode) {
-        if !self.p.negative {
-            branch(am);
-        }
+        let take = !self.flag(FLAG_NEGATIVE);
+        self.branch(am, take);
     }
 
     fn bvc(&mut self, am: AddressMode) {
-        if !self.p.overflow {
-            branch(am);
-        }
+        let take = !self.flag(FLAG_OVERFLOW);
+        self.branch(am, take);
     }
 
     fn bvs(&mut self, am: AddressMode) {
-        if self.p.overflow {
-            branch(am);
-        }
+        let take = self.flag(FLAG_OVERFLOW);
+        self.branch(am, take);
     }
 
     fn bra(&mut self, am: AddressMode) {
-        branch(am);
+        self.branch(am, true);
     }
 
     fn brl(&mut self, am: AddressMode) {
-        branch(am);
+        let (_, target) = am.get_address_8(self);
+        self.idle();
+        self.pc = target;
     }
 
     ////////////////////////////////////
@@ -1348,30 +1306,38 @@ impl CPU {
     //
     ////////////////////////////////////
 
+    // only the long forms leave the program bank
     fn jmp(&mut self, am: AddressMode) {
-        branch(am);
+        let long = am == AddressMode::AbsoluteLong || am == AddressMode::AbsoluteIndirectLong;
+        let (bank, address) = am.get_address_8(self);
+
+        if long {
+            self.pbr = bank;
+        }
+        self.pc = address;
     }
 
-    // jumps within the same page
+    // the return address pushed is the last byte of the call, RTS and RTL add the one back
     fn jsl(&mut self, am: AddressMode) {
-        let pbr = self.pbr;
-        self.pushb(pbr);
+        let (bank, address) = am.get_address_8(self);
+        self.idle();
 
-        let pc = self.pc - 1;
-        self.pushb((pc >> 8) as u8);
-        self.pushb(pc as u8);
+        let pbr = self.pbr;
+        self.push_b(pbr);
+        let ret = self.pc.wrapping_sub(1);
+        self.push_w(ret);
 
-        let (pbr, pc) = am.address(self);
-        self.pbr = pbr;
-        self.pc = pc;
+        self.pbr = bank;
+        self.pc = address;
     }
 
     fn jsr(&mut self, am: AddressMode) {
-        let pc = self.pc - 1;
-        self.pushb((pc >> 8) as u8);
-        self.pushb(pc as u8);
+        let (_, address) = am.get_address_8(self);
+        self.idle();
 
-        self.pc = am.address(self).1;
+        let ret = self.pc.wrapping_sub(1);
+        self.push_w(ret);
+        self.pc = address;
     }
 
     ////////////////////////////////////
@@ -1380,24 +1346,32 @@ impl CPU {
     //
     ////////////////////////////////////
 
-    fn rti(&mut self, am: AddressMode) {
-        self.return_from_interrupt()
+    // p comes back first, so in native mode the program bank is there to pull after pc
+    fn rti(&mut self, _: AddressMode) {
+        self.idle();
+        self.idle();
+
+        let p = self.pull_b();
+        self.set_status(p);
+        self.pc = self.pull_w();
+
+        if !self.emulation {
+            self.pbr = self.pull_b();
+        }
     }
 
-    fn rts(&mut self, am: AddressMode) {
-        let pcl = self.popb() as u16;
-        let pch = self.popb() as u16;
-        let pc = (pch << 8) | pcl;
-        self.pc = pc + 1;   // +1 since the last byte of the JSR was saved
+    fn rts(&mut self, _: AddressMode) {
+        self.idle();
+        self.idle();
+        self.pc = self.pull_w().wrapping_add(1);
+        self.idle();
     }
 
-    fn rtl(&mut self, am: AddressMode) {
-        let pcl = self.popb() as u16;
-        let pch = self.popb() as u16;
-        let pbr = self.popb();
-        let pc = (pch << 8) | pcl;
-        self.pbr = pbr;
-        self.pc = pc + 1; // +1 since the last byte of the JSR was saved
+    fn rtl(&mut self, _: AddressMode) {
+        self.idle();
+        self.idle();
+        self.pc = self.pull_w().wrapping_add(1);
+        self.pbr = self.pull_b();
     }
 
     ////////////////////////////////////
@@ -1406,34 +1380,15 @@ impl CPU {
     //
     ////////////////////////////////////
 
-    fn brk(&mut self, am: AddressMode) {
-        if native_mode {
-            self.pushb(self.pbr);
-            let pc = self.pc;
-            self.pushb((pc >> 8) as u8);
-            self.pushb(pc as u8);
-            self.pushb(self.p);
-
-            self.pbr = 0x00;
-            self.pc = 0xFFE6;
-        } else {
-            // TODO
-        }
+    // both skip the signature byte after the opcode, so the pushed address is past it
+    fn brk(&mut self, _: AddressMode) {
+        self.next_b();
+        self.enter_interrupt(BRK_VECTOR, BRK_VECTOR_EMULATION, true);
     }
 
-    fn cop(&mut self, am: AddressMode) {
-        if native_mode {
-            self.pushb(self.pbr);
-            let pc = self.pc;
-            self.pushb((pc >> 8) as u8);
-            self.pushb(pc as u8);
-            self.pushb(self.p);
-
-            self.pbr = 0x00;
-            self.pc = 0xFFE4;
-        } else {
-            // TODO
-        }
+    fn cop(&mut self, _: AddressMode) {
+        self.next_b();
+        self.enter_interrupt(COP_VECTOR, COP_VECTOR_EMULATION, false);
     }
 
     ////////////////////////////////////
@@ -1442,32 +1397,39 @@ impl CPU {
     //
     ////////////////////////////////////
 
-    fn clc(&mut self, addr: AddressMode) {
-        self.p.set_carry(false);
+    fn clc(&mut self, _: AddressMode) {
+        self.idle();
+        self.set_flag(FLAG_CARRY, false);
     }
 
-    fn cld(&mut self, addr: AddressMode) {
-        self.p.set_decimal_mode(false);
+    fn cld(&mut self, _: AddressMode) {
+        self.idle();
+        self.set_flag(FLAG_DECIMAL, false);
     }
 
-    fn cli(&mut self, addr: AddressMode) {
-        self.p.set_interrupt_disable(false);
+    fn cli(&mut self, _: AddressMode) {
+        self.idle();
+        self.set_flag(FLAG_IRQ_DISABLE, false);
     }
 
-    fn clv(&mut self, addr: AddressMode) {
-        self.p.set_overflow(false);
+    fn clv(&mut self, _: AddressMode) {
+        self.idle();
+        self.set_flag(FLAG_OVERFLOW, false);
     }
 
-    fn sec(&mut self, addr: AddressMode) {
-        self.p.set_carry(true);
+    fn sec(&mut self, _: AddressMode) {
+        self.idle();
+        self.set_flag(FLAG_CARRY, true);
     }
 
-    fn sed(&mut self, addr: AddressMode) {
-        self.p.set_decimal_mode(true);
+    fn sed(&mut self, _: AddressMode) {
+        self.idle();
+        self.set_flag(FLAG_DECIMAL, true);
     }
 
-    fn sei(&mut self, addr: AddressMode) {
-        self.p.set_interrupt_disable(true);
+    fn sei(&mut self, _: AddressMode) {
+        self.idle();
+        self.set_flag(FLAG_IRQ_DISABLE, true);
     }
 
     ////////////////////////////////////
@@ -1477,25 +1439,17 @@ impl CPU {
     ////////////////////////////////////
 
     fn rep(&mut self, am: AddressMode) {
-        // clear the bits in p register that are 1 in the op
-        let to_comp = self.load_b(am);
-        self.p = self.p & (!to_comp);
-
-        if self.p.emulation_mode {
-            // m and x flags are always 1
-            self.p = self.p | 0b0011_0000;
-        }
+        let mask = self.load_8(&am);
+        self.idle();
+        let p = self.p & !mask;
+        self.set_status(p);
     }
 
     fn sep(&mut self, am: AddressMode) {
-        // set the bits in p register that are 1 in the op
-        let to_comp = self.load_b(am);
-        self.p = self.p | to_comp;
-
-        if self.p.emulation_mode {
-            // m and x flags are always 1
-            self.p = self.p | 0b0011_0000;
-        }
+        let mask = self.load_8(&am);
+        self.idle();
+        let p = self.p | mask;
+        self.set_status(p);
     }
 
     ////////////////////////////////////
@@ -1505,65 +1459,41 @@ impl CPU {
     ////////////////////////////////////
 
     fn lda(&mut self, am: AddressMode) {
-        if self.p.accumulator_width {
-            let val = self.load_8(&am);
-            self.a = self.ah() << 8 | (val as u16);
-        } else {
-            let val = self.load_16(&am);
-            self.a = val;
-        }
+        let wide = self.a_wide();
+        let data = self.load(&am, wide);
+        self.set_acc(data);
     }
 
     fn ldx(&mut self, am: AddressMode) {
-        if self.p.index_width {
-            let val = self.load_8(&am);
-            self.x = self.xh() << 8 | (val as u16);
-        } else {
-            let val = self.load_16(&am);
-            self.x = val;
-        }
+        let wide = self.x_wide();
+        let data = self.load(&am, wide);
+        self.set_x_reg(data);
     }
 
     fn ldy(&mut self, am: AddressMode) {
-        if self.p.index_width {
-            let val = self.load_8(&am);
-            self.y = self.yh() << 8 | (val as u16);
-        } else {
-            let val = self.load_16(&am);
-            self.y = val;
-        }
+        let wide = self.x_wide();
+        let data = self.load(&am, wide);
+        self.set_y_reg(data);
     }
 
     fn sta(&mut self, am: AddressMode) {
-        if self.p.accumulator_width {
-            self.store_8(&am, self.al());
-        } else {
-            self.store_16(&am, self.a);
-        }
+        let (a, wide) = (self.a, self.a_wide());
+        self.store(&am, wide, a);
     }
 
     fn stx(&mut self, am: AddressMode) {
-        if self.p.index_width {
-            self.store_8(&am, self.xl());
-        } else {
-            self.store_16(&am, self.x);
-        }
+        let (x, wide) = (self.x, self.x_wide());
+        self.store(&am, wide, x);
     }
 
     fn sty(&mut self, am: AddressMode) {
-        if self.p.index_width {
-            self.store_8(&am, self.yl());
-        } else {
-            self.store_16(&am, self.y);
-        }
+        let (y, wide) = (self.y, self.x_wide());
+        self.store(&am, wide, y);
     }
 
     fn stz(&mut self, am: AddressMode) {
-        if self.p.accumulator_width{
-            self.store_8(&am, 0);
-        } else {
-            self.store_16(&am, 0);
-        }
+        let wide = self.a_wide();
+        self.store(&am, wide, 0);
     }
 
     ////////////////////////////////////
@@ -1572,34 +1502,34 @@ impl CPU {
     //
     ////////////////////////////////////
 
+    // each step moves a single byte and winds the program counter back onto the instruction
+    // until a runs out, so interrupts still get in during a long move. the data bank ends up
+    // as the destination bank
     fn mvn(&mut self, am: AddressMode) {
-        self.a += 1;
-        while self.a > 0 {
-            swap(ss, tt, self.x, self.y);
-
-            self.x += 1;
-            self.y += 1;
-            self.a -= 1;
-
-            // todo -> dec 7 cycles
-            // todo -> check for hardware interrupt
-        }
-        self.dbr = tt;
+        self.move_byte(am, 1);
     }
 
     fn mvp(&mut self, am: AddressMode) {
-        self.a += 1;
-        while self.a > 0 {
-            swap(ss, tt, self.x, self.y);
+        self.move_byte(am, 0xFFFF);
+    }
+
+    fn move_byte(&mut self, am: AddressMode, step: u16) {
+        let ((source_bank, source), (dest_bank, dest)) = am.get_address_16(self);
+
+        let data = self.read(source_bank, source);
+        self.write(dest_bank, dest, data);
+        self.idle();
+        self.idle();
 
-            self.x -= 1;
-            self.y -= 1;
-            self.a -= 1;
+        self.dbr = dest_bank;
+        let (x, y) = (self.x.wrapping_add(step), self.y.wrapping_add(step));
+        self.x = self.index(x);
+        self.y = self.index(y);
 
-            // todo -> dec 7 cycles
-            // todo -> check for hardware interrupt
+        self.a = self.a.wrapping_sub(1);
+        if self.a != 0xFFFF {
+            self.pc = self.pc.wrapping_sub(3);
         }
-        self.dbr = tt;
     }
 
     ////////////////////////////////////
@@ -1608,13 +1538,13 @@ impl CPU {
     //
     ////////////////////////////////////
 
-    fn nop(&mut self, am: AddressMode) {
-        // TODO cycles
+    fn nop(&mut self, _: AddressMode) {
+        self.idle();
     }
 
+    // reserved for a future expansion that never came. it has an operand byte to skip
     fn wdm(&mut self, am: AddressMode) {
-        self.next_b();
-        // TODO cycles
+        self.load_8(&am);
     }
 
     ////////////////////////////////////
@@ -1623,158 +1553,110 @@ impl CPU {
     //
     ////////////////////////////////////
 
-    fn push_effective(&mut self, am: AddressMode) {
-        let to_push = am.address(self);
-        self.push_b((to_push >> 8) as u8);
-        self.push_b(to_push as u8);
-    }
-
     fn pea(&mut self, am: AddressMode) {
-        self.push_effective(am);
+        let data = self.load_16(&am);
+        self.push_w(data);
     }
 
     fn pei(&mut self, am: AddressMode) {
-        self.push_effective(am);
+        let data = self.load_16(&am);
+        self.push_w(data);
     }
 
+    // pushes the target of a relative address, for position independent code
     fn per(&mut self, am: AddressMode) {
-        self.push_effective(am);
+        let (_, address) = am.get_address_8(self);
+        self.idle();
+        self.push_w(address);
     }
 
-    fn pha(&mut self, am: AddressMode) {
-        if self.p.accumulator_width {
-            self.pusb_b(self.al());
-        } else {
-            self.pusb_b(self.ah());
-            self.pusb_b(self.al());
-        }
+    fn pha(&mut self, _: AddressMode) {
+        self.idle();
+        let (a, wide) = (self.a, self.a_wide());
+        self.push(a, wide);
     }
 
-    fn phx(&mut self, am: AddressMode) {
-        if self.p.index_width {
-            self.push_b(self.xl());
-        } else {
-            self.push_b(self.xh());
-            self.push_b(self.xl());
-        }
+    fn phx(&mut self, _: AddressMode) {
+        self.idle();
+        let (x, wide) = (self.x, self.x_wide());
+        self.push(x, wide);
     }
 
-    fn phy(&mut self, am: AddressMode) {
-        if self.p.index_width {
-            self.push_b(self.yl());
-        } else {
-            self.push_b(self.yh());
-            self.push_b(self.yl());
-        }
+    fn phy(&mut self, _: AddressMode) {
+        self.idle();
+        let (y, wide) = (self.y, self.x_wide());
+        self.push(y, wide);
     }
 
-    fn pla(&mut self, am: AddressMode) {
-        if self.p.accumulator_width {
-            let result = self.pull_b();
-
-            self.p.set_negative_from_data_8(result as u16);
-            self.p.set_zero_from_data(result);
-
-            let high_bits = (self.ah() as u16) << 8;
-            self.a = high_bits | result
-        } else {
-            let lo = self.pull_b();
-            let hi = self.pull_b();
-
-            let result = ((hi as u16) << 8) | lo;
-
-            self.p.set_negative_from_data_16(result);
-            self.p.set_zero_from_data(result);
-
-            self.a = result;
-        }
+    fn pla(&mut self, _: AddressMode) {
+        self.idle();
+        self.idle();
+        let wide = self.a_wide();
+        let data = self.pull(wide);
+        self.set_acc(data);
     }
 
-    fn plx(&mut self, am: AddressMode) {
-        if self.p.index_width {
-            let result = self.pull_b();
-
-            self.p.set_negative_from_data_8(result as u16);
-            self.p.set_zero_from_data(result);
-
-            let high_bits = (self.ah() as u16) << 8;
-            self.x = high_bits | result
-        } else {
-            let lo = self.pull_b();
-            let hi = self.pull_b();
-
-            let result = ((hi as u16) << 8) | lo;
-
-            self.p.set_negative_from_data_16(result);
-            self.p.set_zero_from_data(result);
-
-            self.x = result;
-        }
+    fn plx(&mut self, _: AddressMode) {
+        self.idle();
+        self.idle();
+        let wide = self.x_wide();
+        let data = self.pull(wide);
+        self.set_x_reg(data);
     }
 
-    fn ply(&mut self, am: AddressMode) {
-        if self.p.index_width {
-            let result = self.pull_b();
-
-            self.p.set_negative_from_data_8(result as u16);
-            self.p.set_zero_from_data(result);
-
-            let high_bits = (self.ah() as u16) << 8;
-            self.y = high_bits | result
-        } else {
-            let lo = self.pull_b();
-            let hi = self.pull_b();
-
-            let result = ((hi as u16) << 8) | lo;
-
-            self.p.set_negative_from_data_16(result);
-            self.p.set_zero_from_data(result);
-
-            self.y = result;
-        }
+    fn ply(&mut self, _: AddressMode) {
+        self.idle();
+        self.idle();
+        let wide = self.x_wide();
+        let data = self.pull(wide);
+        self.set_y_reg(data);
     }
 
-    fn phb(&mut self, am: AddressMode) {
-        self.push_b(self.dbr);
+    fn phb(&mut self, _: AddressMode) {
+        self.idle();
+        let dbr = self.dbr;
+        self.push_b(dbr);
     }
 
-    fn phd(&mut self, am: AddressMode) {
-        self.push_b(self.dh());
-        self.push_b(self.dl());
+    fn phd(&mut self, _: AddressMode) {
+        self.idle();
+        let d = self.d;
+        self.push_w(d);
     }
 
-    fn phk(&mut self, am: AddressMode) {
-        self.push_b(self.pbr);
+    fn phk(&mut self, _: AddressMode) {
+        self.idle();
+        let pbr = self.pbr;
+        self.push_b(pbr);
     }
 
-    fn php(&mut self, am: AddressMode) {
-        self.push_b(self.p);
+    fn php(&mut self, _: AddressMode) {
+        self.idle();
+        let p = self.p;
+        self.push_b(p);
     }
 
-    fn plb(&mut self, am: AddressMode) {
-        let result = self.pull_b();
-
-        self.p.set_negative_from_data_8(result as u16);
-        self.p.set_zero_from_data(result);
-
-        let high_bits = (self.ah() as u16) << 8;
-        self.dbr = high_bits | result
+    fn plb(&mut self, _: AddressMode) {
+        self.idle();
+        self.idle();
+        self.dbr = self.pull_b();
+        let dbr = self.dbr as u16;
+        self.set_nz(dbr, false);
     }
 
-    fn pld(&mut self, am: AddressMode) {
-        let lo = self.pull_b();
-        let hi = self.pull_b();
-
-        let result = ((hi as u16) << 8) | lo;
-
-        self.p.set_negative_from_data_16(result);
-        self.p.set_zero_from_data(result);
-
-        self.d = result;
+    fn pld(&mut self, _: AddressMode) {
+        self.idle();
+        self.idle();
+        self.d = self.pull_w();
+        let d = self.d;
+        self.set_nz(d, true);
     }
 
-    fn plp(&mut self, am: AddressMode) {
-        self.p = self.pull_b()
+    fn plp(&mut self, _: AddressMode) {
+        self.idle();
+        self.idle();
+        let p = self.pull_b();
+        self.set_status(p);
     }
 
     ////////////////////////////////////
@@ -1783,12 +1665,16 @@ impl CPU {
     //
     ////////////////////////////////////
 
-    fn stp(&mut self, am: AddressMode) {
-        // shut down until interrupt
+    fn stp(&mut self, _: AddressMode) {
+        self.idle();
+        self.idle();
+        self.should_exit = true;
     }
 
-    fn wai(&mut self, am: AddressMode) {
-        // shut down until interrupt
+    fn wai(&mut self, _: AddressMode) {
+        self.idle();
+        self.idle();
+        self.waiting = true;
     }
 
     ////////////////////////////////////
@@ -1797,117 +1683,82 @@ impl CPU {
     //
     ////////////////////////////////////
 
-    fn tax(&mut self, am: AddressMode) {
-        if self.p.index_width {
-            let hi_x = self.x >> 8;
-            let lo_x = self.al();
-            self.x = ((hi_x as u16) << 8) | (lo_x as u16);
-        } else {
-            self.x = self.a;
-        }
-    }
+    // transfers take the width of the register they write to
 
-    fn tay(&mut self, am: AddressMode) {
-        if self.p.index_width {
-            let hi_y = self.y >> 8;
-            let lo_y = self.al();
-            self.y = ((hi_y as u16) << 8) | (lo_y as u16);
-        } else {
-            self.y = self.a;
-        }
+    fn tax(&mut self, _: AddressMode) {
+        self.idle();
+        let a = self.a;
+        self.set_x_reg(a);
     }
 
-    fn tsx(&mut self, am: AddressMode) {
-        if self.p.index_width {
-            let hi_x = self.x >> 8;
-            let lo_x = self.spl();
-            self.x = ((hi_x as u16) << 8) | (lo_x as u16);
-        } else {
-            self.x = self.sp;
-        }
+    fn tay(&mut self, _: AddressMode) {
+        self.idle();
+        let a = self.a;
+        self.set_y_reg(a);
     }
 
-    fn txa(&mut self, am: AddressMode) {
-        if self.p.accumulator_width {
-            let hi_a = self.a >> 8;
-            let lo_a = self.xl();
-            self.a = ((hi_a as u16) << 8) | (lo_a as u16);
-        } else {
-            self.a = self.x;
-        }
+    fn tsx(&mut self, _: AddressMode) {
+        self.idle();
+        let sp = self.sp;
+        self.set_x_reg(sp);
     }
 
-    fn txs(&mut self, am: AddressMode) {
-        // However, when the e flag is 1, SH is forced to $01, so in effect, TXS is an 8-bit transfer in this case since XL is transferred to SL and SH remains $01.
-        // Note that when the e flag is 0 and the x flag is 1 (i.e. 8-bit native mode), that XH is forced to zero,
-        //      so after a TXS, SH will be $00, rather than $01. This is an important difference that must be accounted for if you want
-        //      to run emulation mode code in (8-bit) native mode.
-
-        if self.p.emulation_mode {
-            let hi_s = 0x01;
-            let lo_s = self.xl();
+    fn txa(&mut self, _: AddressMode) {
+        self.idle();
+        let x = self.x;
+        self.set_acc(x);
+    }
 
-            self.sp = ((hi_s as u16) << 8) | (lo_s as u16);
-        } else {
-            if self.p.index_width {
-                self.sp = self.xl() as u16;
-            } else {
-                self.sp = self.x;
-            }
-        }
+    // the stack pointer is always 16 bits, its high byte held at 1 in emulation mode.
+    // TXS and TCS are the only transfers that leave the flags alone
+    fn txs(&mut self, _: AddressMode) {
+        self.idle();
+        self.sp = if self.emulation { 0x0100 | (self.x & 0x00FF) } else { self.x };
     }
 
-    fn txy(&mut self, am: AddressMode) {
-        if self.p.index_width {
-            let hi_y = self.y >> 8;
-            let lo_y = self.xl();
-            self.y = ((hi_y as u16) << 8) | (lo_y as u16);
-        } else {
-            self.y = self.x;
-        }
+    fn txy(&mut self, _: AddressMode) {
+        self.idle();
+        let x = self.x;
+        self.set_y_reg(x);
     }
 
-    fn tya(&mut self, am: AddressMode) {
-        if self.p.accumulator_width {
-            let hi_a = self.a >> 8;
-            let lo_a = self.yl();
-            self.a = ((hi_a as u16) << 8) | (lo_a as u16);
-        } else {
-            self.a = self.y;
-        }
+    fn tya(&mut self, _: AddressMode) {
+        self.idle();
+        let y = self.y;
+        self.set_acc(y);
     }
 
-    fn tyx(&mut self, am: AddressMode) {
-        if self.p.index_width {
-            let hi_x = self.x >> 8;
-            let lo_x = self.yl();
-            self.x = ((hi_x as u16) << 8) | (lo_x as u16);
-        } else {
-            self.x = self.y;
-        }
+    fn tyx(&mut self, _: AddressMode) {
+        self.idle();
+        let y = self.y;
+        self.set_x_reg(y);
     }
 
-    fn tcd(&mut self, am: AddressMode) {
+    // the C forms always move all 16 bits of the accumulator
+    fn tcd(&mut self, _: AddressMode) {
+        self.idle();
         self.d = self.a;
+        let d = self.d;
+        self.set_nz(d, true);
     }
 
-    fn tcs(&mut self, am: AddressMode) {
-        if self.p.emulation_mode {
-            let hi_s = 0x01;
-            let lo_s = self.al();
-
-            self.sp = ((hi_s as u16) << 8) | (lo_s as u16);
-        } else {
-            self.sp = self.a;
-        }
+    fn tcs(&mut self, _: AddressMode) {
+        self.idle();
+        self.sp = if self.emulation { 0x0100 | (self.a & 0x00FF) } else { self.a };
     }
 
-    fn tdc(&mut self, am: AddressMode) {
+    fn tdc(&mut self, _: AddressMode) {
+        self.idle();
         self.a = self.d;
+        let a = self.a;
+        self.set_nz(a, true);
     }
 
-    fn tsc(&mut self, am: AddressMode) {
-        self.a = self.s;
+    fn tsc(&mut self, _: AddressMode) {
+        self.idle();
+        self.a = self.sp;
+        let a = self.a;
+        self.set_nz(a, true);
     }
 
     ////////////////////////////////////
@@ -1916,21 +1767,29 @@ impl CPU {
     //
     ////////////////////////////////////
 
-    fn xba(&mut self, am: AddressMode) {
-        self.a = ((self.al() as u16) << 8) | (self.ah() as u16);
+    // n and z come from the new low byte whatever the accumulator's width
+    fn xba(&mut self, _: AddressMode) {
+        self.idle();
+        self.idle();
+        self.a = self.a.rotate_left(8);
+        let a = self.a;
+        self.set_nz(a, false);
     }
 
-    fn xce(&mut self, am: AddressMode) {
-        let e_temp = self.p.emulation_mode;
-        self.p.carry = e_temp;
-        self.p.emulation_mode = self.p.carry;
+    // swaps the carry with the E flag. going into emulation mode forces 8 bit registers and
+    // puts the stack back in page 1
+    fn xce(&mut self, _: AddressMode) {
+        self.idle();
 
-        if self.p.emulation_mode {
-            self.p.accumulator_width = true;
-            self.p.index_width = true;
-            self.x = 0x0011 & self.x;
-            self.y = 0x0011 & self.y;
-            self.sp = ((0x01 as u16) << 8) | (self.spl() as u16);
+        let carry = self.flag(FLAG_CARRY);
+        let emulation = self.emulation;
+        self.set_flag(FLAG_CARRY, emulation);
+        self.emulation = carry;
+
+        if self.emulation {
+            self.sp = 0x0100 | (self.sp & 0x00FF);
+            let p = self.p;
+            self.set_status(p);
         }
     }
 
@@ -1941,44 +1800,300 @@ impl CPU {
     ////////////////////////////////////
 
     fn and(&mut self, am: AddressMode) {
-        if self.p.index_width {
-            let to_comp = self.load_8(&am);
-            self.set_al(self.al() & to_comp);
-            self.p.set_negative_from_data_8(self.al());
-            self.p.set_zero_from_data(self.al() as u16);
-        } else {
-            let to_comp = self.load_16(&am);
-            self.set_al(self.al() & to_comp);
-            self.p.set_negative_from_data_16(self.a);
-            self.p.set_zero_from_data(self.a);
-        };
+        let wide = self.a_wide();
+        let data = self.load(&am, wide);
+        let a = self.acc();
+        self.set_acc(a & data);
     }
 
     fn eor(&mut self, am: AddressMode) {
-        if self.p.index_width {
-            let to_comp = self.load_8(&am);
-            self.set_al(self.al() ^ to_comp);
-            self.p.set_negative_from_data_8(self.al());
-            self.p.set_zero_from_data(self.al() as u16);
-        } else {
-            let to_comp = self.load_16(&am);
-            self.set_al(self.al() ^ to_comp);
-            self.p.set_negative_from_data_16(self.a);
-            self.p.set_zero_from_data(self.a);
-        };
+        let wide = self.a_wide();
+        let data = self.load(&am, wide);
+        let a = self.acc();
+        self.set_acc(a ^ data);
     }
 
     fn ora(&mut self, am: AddressMode) {
-        let to_comp = if self.p.index_width {
-            let to_comp = self.load_8(&am);
-            self.set_al(self.al() | to_comp);
-            self.p.set_negative_from_data_8(self.al());
-            self.p.set_zero_from_data(self.al() as u16);
-        } else {
-            let to_comp = self.load_16(&am);
-            self.set_al(self.al() | to_comp);
-            self.p.set_negative_from_data_16(self.a);
-            self.p.set_zero_from_data(self.a);
-        };
+        let wide = self.a_wide();
+        let data = self.load(&am, wide);
+        let a = self.acc();
+        self.set_acc(a | data);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const START: u16 = 0x8000;
+
+    // a cpu out of reset with the program at 00:8000
+    fn cpu_with(program: &[u8]) -> CPU {
+        let mut mem = SimpleMemory::new();
+        mem.bytes_mut()[START as usize..START as usize + program.len()].copy_from_slice(program);
+        mem.bytes_mut()[0xFFFC] = START as u8;
+        mem.bytes_mut()[0xFFFD] = (START >> 8) as u8;
+
+        let mut cpu = CPU::new(Box::new(mem));
+        cpu.reset();
+        cpu
+    }
+
+    fn steps(cpu: &mut CPU, count: usize) {
+        for _ in 0..count {
+            cpu.step();
+        }
+    }
+
+    fn peek(cpu: &CPU, bank: u8, address: u16) -> u8 {
+        cpu.mem().load(bank, address)
+    }
+
+    fn poke(cpu: &mut CPU, bank: u8, address: u16, value: u8) {
+        cpu.mem_mut().store(bank, address, value);
+    }
+
+    // CLC XCE REP #$30, so the rest of the program runs native with 16 bit registers
+    const NATIVE_16: [u8; 4] = [0x18, 0xFB, 0xC2, 0x30];
+
+    fn native_16(program: &[u8]) -> CPU {
+        let mut cpu = cpu_with(&[&NATIVE_16[..], program].concat());
+        steps(&mut cpu, 3);
+        cpu
+    }
+
+    #[test]
+    fn binary_adc_sets_overflow_on_a_sign_change() {
+        // CLC LDA #$7F ADC #$01
+        let mut cpu = cpu_with(&[0x18, 0xA9, 0x7F, 0x69, 0x01]);
+        steps(&mut cpu, 3);
+        assert_eq!(cpu.a(), 0x80);
+        assert_eq!(cpu.p() & (FLAG_OVERFLOW | FLAG_NEGATIVE | FLAG_CARRY), FLAG_OVERFLOW | FLAG_NEGATIVE);
+
+        // SEC LDA #$05 SBC #$06 borrows
+        let mut cpu = cpu_with(&[0x38, 0xA9, 0x05, 0xE9, 0x06]);
+        steps(&mut cpu, 3);
+        assert_eq!(cpu.a(), 0xFF);
+        assert_eq!(cpu.p() & (FLAG_NEGATIVE | FLAG_CARRY), FLAG_NEGATIVE);
+    }
+
+    #[test]
+    fn decimal_mode_adds_and_subtracts_digits() {
+        // SED CLC LDA #$19 ADC #$28
+        let mut cpu = cpu_with(&[0xF8, 0x18, 0xA9, 0x19, 0x69, 0x28]);
+        steps(&mut cpu, 4);
+        assert_eq!(cpu.a(), 0x47);
+
+        // SED SEC LDA #$99 ADC #$00 carries out of both digits
+        let mut cpu = cpu_with(&[0xF8, 0x38, 0xA9, 0x99, 0x69, 0x00]);
+        steps(&mut cpu, 4);
+        assert_eq!(cpu.a(), 0x00);
+        assert_eq!(cpu.p() & (FLAG_CARRY | FLAG_ZERO), FLAG_CARRY | FLAG_ZERO);
+
+        // SED SEC LDA #$1000 SBC #$0001
+        let mut cpu = native_16(&[0xF8, 0x38, 0xA9, 0x00, 0x10, 0xE9, 0x01, 0x00]);
+        steps(&mut cpu, 4);
+        assert_eq!(cpu.a(), 0x0999);
+        assert!(cpu.p() & FLAG_CARRY != 0);
+    }
+
+    #[test]
+    fn eight_bit_accumulator_writes_leave_b_alone() {
+        // LDA #$1234, SEP #$20, LDA #$FF, XBA
+        let mut cpu = native_16(&[0xA9, 0x34, 0x12, 0xE2, 0x20, 0xA9, 0xFF, 0xEB]);
+        steps(&mut cpu, 3);
+        assert_eq!(cpu.a(), 0x12FF);
+        steps(&mut cpu, 1);
+        assert_eq!(cpu.a(), 0xFF12);
+        // n and z come from the new low byte
+        assert_eq!(cpu.p() & (FLAG_NEGATIVE | FLAG_ZERO), 0);
+    }
+
+    #[test]
+    fn narrowing_the_index_registers_drops_their_high_bytes() {
+        // LDX #$1234 LDY #$ABCD SEP #$10
+        let mut cpu = native_16(&[0xA2, 0x34, 0x12, 0xA0, 0xCD, 0xAB, 0xE2, 0x10]);
+        steps(&mut cpu, 2);
+        assert_eq!((cpu.x(), cpu.y()), (0x1234, 0xABCD));
+        steps(&mut cpu, 1);
+        assert_eq!((cpu.x(), cpu.y()), (0x0034, 0x00CD));
+    }
+
+    #[test]
+    fn branches_go_backwards_and_forwards() {
+        // LDX #$03, loop: DEX, BNE loop, BRA +1, NOP, STP
+        let mut cpu = cpu_with(&[0xA2, 0x03, 0xCA, 0xD0, 0xFD, 0x80, 0x01, 0xEA, 0xDB]);
+        steps(&mut cpu, 1 + 3 * 2 + 2);
+        assert_eq!(cpu.x(), 0);
+        assert!(cpu.is_stopped());
+        assert_eq!(cpu.pc(), 0x8009);
+    }
+
+    #[test]
+    fn calls_push_the_last_byte_of_the_call() {
+        // JSR $8010 at 8000, then JSL $018000 at 8010
+        let mut program = vec![0xEA; 0x20];
+        program[..3].copy_from_slice(&[0x20, 0x10, 0x80]);
+        program[0x10..0x14].copy_from_slice(&[0x22, 0x00, 0x80, 0x01]);
+        let mut cpu = cpu_with(&program);
+        // RTL in bank 1
+        poke(&mut cpu, 0x01, 0x8000, 0x6B);
+
+        steps(&mut cpu, 2);
+        assert_eq!((cpu.pbr(), cpu.pc()), (0x01, 0x8000));
+        assert_eq!(cpu.sp(), 0x01FA);
+        assert_eq!((peek(&cpu, 0, 0x01FF), peek(&cpu, 0, 0x01FE)), (0x80, 0x02));
+        assert_eq!((peek(&cpu, 0, 0x01FD), peek(&cpu, 0, 0x01FC), peek(&cpu, 0, 0x01FB)), (0x00, 0x80, 0x13));
+
+        steps(&mut cpu, 1);
+        assert_eq!((cpu.pbr(), cpu.pc()), (0x00, 0x8014));
+    }
+
+    #[test]
+    fn indexing_carries_into_the_next_bank() {
+        // LDY #$0010, LDA ($10),Y with the pointer at $0010 -> $FFF8 in bank $7E
+        let mut cpu = native_16(&[0xA0, 0x10, 0x00, 0x8B, 0xF4, 0x7E, 0x7E, 0xAB, 0xAB, 0xB1, 0x10]);
+        poke(&mut cpu, 0, 0x0010, 0xF8);
+        poke(&mut cpu, 0, 0x0011, 0xFF);
+        poke(&mut cpu, 0x7F, 0x0008, 0xCD);
+        poke(&mut cpu, 0x7F, 0x0009, 0xAB);
+        // LDY, PHB, PEA $7E7E, PLB PLB, LDA
+        steps(&mut cpu, 6);
+        assert_eq!(cpu.dbr(), 0x7E);
+        assert_eq!(cpu.a(), 0xABCD);
+    }
+
+    #[test]
+    fn emulation_mode_wraps_the_direct_page() {
+        // LDX #$01, LDA $FF,X reads $0000, not $0100
+        let mut cpu = cpu_with(&[0xA2, 0x01, 0xB5, 0xFF]);
+        poke(&mut cpu, 0, 0x0000, 0x11);
+        poke(&mut cpu, 0, 0x0100, 0x22);
+        steps(&mut cpu, 2);
+        assert_eq!(cpu.a(), 0x11);
+    }
+
+    #[test]
+    fn block_moves_run_one_byte_a_step() {
+        // LDA #$0002 LDX #$1000 LDY #$2000 MVN $7E,$7F
+        let mut cpu = native_16(&[0xA9, 0x02, 0x00, 0xA2, 0x00, 0x10, 0xA0, 0x00, 0x20, 0x54, 0x7E, 0x7F]);
+        for i in 0..3 {
+            poke(&mut cpu, 0x7F, 0x1000 + i, 0xA0 + i as u8);
+        }
+        steps(&mut cpu, 3);
+
+        steps(&mut cpu, 1);
+        assert_eq!(cpu.pc(), 0x800D);
+        steps(&mut cpu, 2);
+        assert_eq!(cpu.pc(), 0x8010);
+        assert_eq!((cpu.a(), cpu.x(), cpu.y(), cpu.dbr()), (0xFFFF, 0x1003, 0x2003, 0x7E));
+        for i in 0..3 {
+            assert_eq!(peek(&cpu, 0x7E, 0x2000 + i), 0xA0 + i as u8);
+        }
+    }
+
+    #[test]
+    fn test_and_set_bits_check_the_old_value() {
+        // LDA #$0F, TSB $10, TRB $11
+        let mut cpu = cpu_with(&[0xA9, 0x0F, 0x04, 0x10, 0x14, 0x11]);
+        poke(&mut cpu, 0, 0x0010, 0xF0);
+        poke(&mut cpu, 0, 0x0011, 0xFF);
+        steps(&mut cpu, 2);
+        assert_eq!(peek(&cpu, 0, 0x0010), 0xFF);
+        assert!(cpu.p() & FLAG_ZERO != 0);
+        steps(&mut cpu, 1);
+        assert_eq!(peek(&cpu, 0, 0x0011), 0xF0);
+        assert!(cpu.p() & FLAG_ZERO == 0);
+    }
+
+    #[test]
+    fn bit_immediate_only_sets_zero() {
+        // LDA #$01, BIT #$C0, BIT $10
+        let mut cpu = cpu_with(&[0xA9, 0x01, 0x89, 0xC0, 0x24, 0x10]);
+        poke(&mut cpu, 0, 0x0010, 0xC0);
+        steps(&mut cpu, 2);
+        assert_eq!(cpu.p() & (FLAG_NEGATIVE | FLAG_OVERFLOW | FLAG_ZERO), FLAG_ZERO);
+        steps(&mut cpu, 1);
+        assert_eq!(cpu.p() & (FLAG_NEGATIVE | FLAG_OVERFLOW | FLAG_ZERO), FLAG_NEGATIVE | FLAG_OVERFLOW | FLAG_ZERO);
+    }
+
+    #[test]
+    fn shifts_and_rotates_go_through_the_carry() {
+        // LDA #$81, ASL A, ROL A, ROR A, LSR A
+        let mut cpu = cpu_with(&[0xA9, 0x81, 0x0A, 0x2A, 0x6A, 0x4A]);
+        steps(&mut cpu, 2);
+        assert_eq!((cpu.a(), cpu.p() & FLAG_CARRY), (0x02, FLAG_CARRY));
+        steps(&mut cpu, 1);
+        assert_eq!((cpu.a(), cpu.p() & FLAG_CARRY), (0x05, 0));
+        steps(&mut cpu, 1);
+        assert_eq!((cpu.a(), cpu.p() & FLAG_CARRY), (0x02, FLAG_CARRY));
+        steps(&mut cpu, 1);
+        assert_eq!((cpu.a(), cpu.p() & FLAG_CARRY), (0x01, 0));
+
+        // INC on memory wraps at the accumulator's width
+        let mut cpu = cpu_with(&[0xEE, 0x00, 0x10]);
+        poke(&mut cpu, 0, 0x1000, 0xFF);
+        steps(&mut cpu, 1);
+        assert_eq!(peek(&cpu, 0, 0x1000), 0x00);
+        assert!(cpu.p() & FLAG_ZERO != 0);
+    }
+
+    #[test]
+    fn returning_to_emulation_mode_narrows_everything() {
+        // LDX #$1234, TXS, SEC, XCE
+        let mut cpu = native_16(&[0xA2, 0x34, 0x12, 0x9A, 0x38, 0xFB]);
+        steps(&mut cpu, 2);
+        assert_eq!(cpu.sp(), 0x1234);
+        steps(&mut cpu, 2);
+        assert!(cpu.is_emulation());
+        assert_eq!(cpu.sp(), 0x0134);
+        assert_eq!(cpu.x(), 0x0034);
+        assert_eq!(cpu.p() & (FLAG_ACCUMULATOR_WIDTH | FLAG_INDEX_WIDTH), FLAG_ACCUMULATOR_WIDTH | FLAG_INDEX_WIDTH);
+        // the carry holds the old E flag
+        assert!(cpu.p() & FLAG_CARRY == 0);
+    }
+
+    #[test]
+    fn brk_and_cop_push_past_their_signature_byte() {
+        // BRK $AA in emulation mode
+        let mut cpu = cpu_with(&[0x00, 0xAA]);
+        poke(&mut cpu, 0, 0xFFFE, 0x00);
+        poke(&mut cpu, 0, 0xFFFF, 0x90);
+        steps(&mut cpu, 1);
+        assert_eq!(cpu.pc(), 0x9000);
+        assert_eq!((peek(&cpu, 0, 0x01FF), peek(&cpu, 0, 0x01FE)), (0x80, 0x02));
+        assert!(peek(&cpu, 0, 0x01FD) & FLAG_BREAK != 0);
+        assert_eq!(cpu.last_interrupt(), None);
+
+        // COP $AA natively pushes the program bank too
+        let mut cpu = native_16(&[0x02, 0xAA]);
+        poke(&mut cpu, 0, 0xFFE4, 0x00);
+        poke(&mut cpu, 0, 0xFFE5, 0x90);
+        steps(&mut cpu, 1);
+        assert_eq!(cpu.pc(), 0x9000);
+        assert_eq!(cpu.sp(), 0x01FB);
+        assert_eq!((peek(&cpu, 0, 0x01FF), peek(&cpu, 0, 0x01FE), peek(&cpu, 0, 0x01FD)), (0x00, 0x80, 0x06));
+        assert!(cpu.p() & FLAG_IRQ_DISABLE != 0);
+    }
+
+    #[test]
+    fn rti_restores_the_status_before_the_address() {
+        // PHK, PEA $9000, REP #$01, PHP, SEP #$30, RTI
+        let mut cpu = native_16(&[0x4B, 0xF4, 0x00, 0x90, 0xC2, 0x01, 0x08, 0xE2, 0x30, 0x40]);
+        steps(&mut cpu, 6);
+        assert_eq!((cpu.pbr(), cpu.pc()), (0x00, 0x9000));
+        assert_eq!(cpu.p() & (FLAG_ACCUMULATOR_WIDTH | FLAG_INDEX_WIDTH), 0);
+        assert_eq!(cpu.sp(), 0x01FF);
+    }
+
+    #[test]
+    fn steps_count_each_access_at_its_regions_speed() {
+        // NOP is a rom fetch and an internal cycle
+        let mut cpu = cpu_with(&[0xEA, 0xAD, 0x34, 0x12, 0xAD, 0x00, 0x21, 0xAD, 0x16, 0x40]);
+        assert_eq!(cpu.step(), SLOW_CYCLES + INTERNAL_CYCLES);
+        // LDA $1234 reads wram, LDA $2100 a ppu register, LDA $4016 an old joypad port
+        assert_eq!(cpu.step(), 3 * SLOW_CYCLES + SLOW_CYCLES);
+        assert_eq!(cpu.step(), 3 * SLOW_CYCLES + FAST_CYCLES);
+        assert_eq!(cpu.step(), 3 * SLOW_CYCLES + XSLOW_CYCLES);
     }
 }
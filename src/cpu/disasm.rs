@@ -0,0 +1,292 @@
+use std::fmt;
+
+// how an instruction's operand is read and written out. the cpu's AddressMode is how it
+// resolves an operand while running: it has one Immediate for every width, carries the
+// opcode to pick the bank for JMP and JSR, and knows nothing about syntax. the tools need
+// the widths and the syntax, so they keep this table, keyed by opcode like the cpu's
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Mode {
+    Implied,
+    Accumulator,
+    Immediate8,       // #$12, always 8 bit (REP, SEP, BRK, COP, WDM)
+    ImmediateM,       // #$12 or #$1234 depending on the m flag
+    ImmediateX,       // #$12 or #$1234 depending on the x flag
+    Direct,           // $12
+    DirectX,          // $12,X
+    DirectY,          // $12,Y
+    DirectIndirect,   // ($12)
+    DirectIndexedIndirect, // ($12,X)
+    DirectIndirectIndexed, // ($12),Y
+    DirectIndirectLong,    // [$12]
+    DirectIndirectLongIndexed, // [$12],Y
+    Absolute,         // $1234
+    AbsoluteX,        // $1234,X
+    AbsoluteY,        // $1234,Y
+    AbsoluteLong,     // $123456
+    AbsoluteLongX,    // $123456,X
+    AbsoluteIndirect, // ($1234)
+    AbsoluteIndexedIndirect, // ($1234,X)
+    AbsoluteIndirectLong,    // [$1234]
+    StackRelative,    // $12,S
+    StackRelativeIndirectIndexed, // ($12,S),Y
+    Relative8,        // branch target
+    Relative16,       // branch target
+    BlockMove,        // $12,$34, source bank then destination bank
+}
+
+impl Mode {
+    // operand bytes after the opcode
+    pub fn operand_len(self, m8: bool, x8: bool) -> usize {
+        match self {
+            Mode::Implied | Mode::Accumulator => 0,
+            Mode::ImmediateM => if m8 { 1 } else { 2 },
+            Mode::ImmediateX => if x8 { 1 } else { 2 },
+            Mode::Immediate8 | Mode::Direct | Mode::DirectX | Mode::DirectY | Mode::DirectIndirect |
+            Mode::DirectIndexedIndirect | Mode::DirectIndirectIndexed | Mode::DirectIndirectLong |
+            Mode::DirectIndirectLongIndexed | Mode::StackRelative | Mode::StackRelativeIndirectIndexed |
+            Mode::Relative8 => 1,
+            Mode::Absolute | Mode::AbsoluteX | Mode::AbsoluteY | Mode::AbsoluteIndirect |
+            Mode::AbsoluteIndexedIndirect | Mode::AbsoluteIndirectLong | Mode::Relative16 |
+            Mode::BlockMove => 2,
+            Mode::AbsoluteLong | Mode::AbsoluteLongX => 3,
+        }
+    }
+}
+
+use self::Mode::*;
+
+// mnemonic and addressing mode of every opcode
+pub const OPCODES: [(&'static str, Mode); 256] = [
+    // $00
+    ("BRK", Immediate8), ("ORA", DirectIndexedIndirect), ("COP", Immediate8), ("ORA", StackRelative),
+    ("TSB", Direct), ("ORA", Direct), ("ASL", Direct), ("ORA", DirectIndirectLong),
+    ("PHP", Implied), ("ORA", ImmediateM), ("ASL", Accumulator), ("PHD", Implied),
+    ("TSB", Absolute), ("ORA", Absolute), ("ASL", Absolute), ("ORA", AbsoluteLong),
+    // $10
+    ("BPL", Relative8), ("ORA", DirectIndirectIndexed), ("ORA", DirectIndirect), ("ORA", StackRelativeIndirectIndexed),
+    ("TRB", Direct), ("ORA", DirectX), ("ASL", DirectX), ("ORA", DirectIndirectLongIndexed),
+    ("CLC", Implied), ("ORA", AbsoluteY), ("INC", Accumulator), ("TCS", Implied),
+    ("TRB", Absolute), ("ORA", AbsoluteX), ("ASL", AbsoluteX), ("ORA", AbsoluteLongX),
+    // $20
+    ("JSR", Absolute), ("AND", DirectIndexedIndirect), ("JSL", AbsoluteLong), ("AND", StackRelative),
+    ("BIT", Direct), ("AND", Direct), ("ROL", Direct), ("AND", DirectIndirectLong),
+    ("PLP", Implied), ("AND", ImmediateM), ("ROL", Accumulator), ("PLD", Implied),
+    ("BIT", Absolute), ("AND", Absolute), ("ROL", Absolute), ("AND", AbsoluteLong),
+    // $30
+    ("BMI", Relative8), ("AND", DirectIndirectIndexed), ("AND", DirectIndirect), ("AND", StackRelativeIndirectIndexed),
+    ("BIT", DirectX), ("AND", DirectX), ("ROL", DirectX), ("AND", DirectIndirectLongIndexed),
+    ("SEC", Implied), ("AND", AbsoluteY), ("DEC", Accumulator), ("TSC", Implied),
+    ("BIT", AbsoluteX), ("AND", AbsoluteX), ("ROL", AbsoluteX), ("AND", AbsoluteLongX),
+    // $40
+    ("RTI", Implied), ("EOR", DirectIndexedIndirect), ("WDM", Immediate8), ("EOR", StackRelative),
+    ("MVP", BlockMove), ("EOR", Direct), ("LSR", Direct), ("EOR", DirectIndirectLong),
+    ("PHA", Implied), ("EOR", ImmediateM), ("LSR", Accumulator), ("PHK", Implied),
+    ("JMP", Absolute), ("EOR", Absolute), ("LSR", Absolute), ("EOR", AbsoluteLong),
+    // $50
+    ("BVC", Relative8), ("EOR", DirectIndirectIndexed), ("EOR", DirectIndirect), ("EOR", StackRelativeIndirectIndexed),
+    ("MVN", BlockMove), ("EOR", DirectX), ("LSR", DirectX), ("EOR", DirectIndirectLongIndexed),
+    ("CLI", Implied), ("EOR", AbsoluteY), ("PHY", Implied), ("TCD", Implied),
+    ("JML", AbsoluteLong), ("EOR", AbsoluteX), ("LSR", AbsoluteX), ("EOR", AbsoluteLongX),
+    // $60
+    ("RTS", Implied), ("ADC", DirectIndexedIndirect), ("PER", Relative16), ("ADC", StackRelative),
+    ("STZ", Direct), ("ADC", Direct), ("ROR", Direct), ("ADC", DirectIndirectLong),
+    ("PLA", Implied), ("ADC", ImmediateM), ("ROR", Accumulator), ("RTL", Implied),
+    ("JMP", AbsoluteIndirect), ("ADC", Absolute), ("ROR", Absolute), ("ADC", AbsoluteLong),
+    // $70
+    ("BVS", Relative8), ("ADC", DirectIndirectIndexed), ("ADC", DirectIndirect), ("ADC", StackRelativeIndirectIndexed),
+    ("STZ", DirectX), ("ADC", DirectX), ("ROR", DirectX), ("ADC", DirectIndirectLongIndexed),
+    ("SEI", Implied), ("ADC", AbsoluteY), ("PLY", Implied), ("TDC", Implied),
+    ("JMP", AbsoluteIndexedIndirect), ("ADC", AbsoluteX), ("ROR", AbsoluteX), ("ADC", AbsoluteLongX),
+    // $80
+    ("BRA", Relative8), ("STA", DirectIndexedIndirect), ("BRL", Relative16), ("STA", StackRelative),
+    ("STY", Direct), ("STA", Direct), ("STX", Direct), ("STA", DirectIndirectLong),
+    ("DEY", Implied), ("BIT", ImmediateM), ("TXA", Implied), ("PHB", Implied),
+    ("STY", Absolute), ("STA", Absolute), ("STX", Absolute), ("STA", AbsoluteLong),
+    // $90
+    ("BCC", Relative8), ("STA", DirectIndirectIndexed), ("STA", DirectIndirect), ("STA", StackRelativeIndirectIndexed),
+    ("STY", DirectX), ("STA", DirectX), ("STX", DirectY), ("STA", DirectIndirectLongIndexed),
+    ("TYA", Implied), ("STA", AbsoluteY), ("TXS", Implied), ("TXY", Implied),
+    ("STZ", Absolute), ("STA", AbsoluteX), ("STZ", AbsoluteX), ("STA", AbsoluteLongX),
+    // $A0
+    ("LDY", ImmediateX), ("LDA", DirectIndexedIndirect), ("LDX", ImmediateX), ("LDA", StackRelative),
+    ("LDY", Direct), ("LDA", Direct), ("LDX", Direct), ("LDA", DirectIndirectLong),
+    ("TAY", Implied), ("LDA", ImmediateM), ("TAX", Implied), ("PLB", Implied),
+    ("LDY", Absolute), ("LDA", Absolute), ("LDX", Absolute), ("LDA", AbsoluteLong),
+    // $B0
+    ("BCS", Relative8), ("LDA", DirectIndirectIndexed), ("LDA", DirectIndirect), ("LDA", StackRelativeIndirectIndexed),
+    ("LDY", DirectX), ("LDA", DirectX), ("LDX", DirectY), ("LDA", DirectIndirectLongIndexed),
+    ("CLV", Implied), ("LDA", AbsoluteY), ("TSX", Implied), ("TYX", Implied),
+    ("LDY", AbsoluteX), ("LDA", AbsoluteX), ("LDX", AbsoluteY), ("LDA", AbsoluteLongX),
+    // $C0
+    ("CPY", ImmediateX), ("CMP", DirectIndexedIndirect), ("REP", Immediate8), ("CMP", StackRelative),
+    ("CPY", Direct), ("CMP", Direct), ("DEC", Direct), ("CMP", DirectIndirectLong),
+    ("INY", Implied), ("CMP", ImmediateM), ("DEX", Implied), ("WAI", Implied),
+    ("CPY", Absolute), ("CMP", Absolute), ("DEC", Absolute), ("CMP", AbsoluteLong),
+    // $D0
+    ("BNE", Relative8), ("CMP", DirectIndirectIndexed), ("CMP", DirectIndirect), ("CMP", StackRelativeIndirectIndexed),
+    ("PEI", DirectIndirect), ("CMP", DirectX), ("DEC", DirectX), ("CMP", DirectIndirectLongIndexed),
+    ("CLD", Implied), ("CMP", AbsoluteY), ("PHX", Implied), ("STP", Implied),
+    ("JML", AbsoluteIndirectLong), ("CMP", AbsoluteX), ("DEC", AbsoluteX), ("CMP", AbsoluteLongX),
+    // $E0
+    ("CPX", ImmediateX), ("SBC", DirectIndexedIndirect), ("SEP", Immediate8), ("SBC", StackRelative),
+    ("CPX", Direct), ("SBC", Direct), ("INC", Direct), ("SBC", DirectIndirectLong),
+    ("INX", Implied), ("SBC", ImmediateM), ("NOP", Implied), ("XBA", Implied),
+    ("CPX", Absolute), ("SBC", Absolute), ("INC", Absolute), ("SBC", AbsoluteLong),
+    // $F0
+    ("BEQ", Relative8), ("SBC", DirectIndirectIndexed), ("SBC", DirectIndirect), ("SBC", StackRelativeIndirectIndexed),
+    ("PEA", Absolute), ("SBC", DirectX), ("INC", DirectX), ("SBC", DirectIndirectLongIndexed),
+    ("SED", Implied), ("SBC", AbsoluteY), ("PLX", Implied), ("XCE", Implied),
+    ("JSR", AbsoluteIndexedIndirect), ("SBC", AbsoluteX), ("INC", AbsoluteX), ("SBC", AbsoluteLongX),
+];
+
+// one decoded instruction
+#[derive(Clone, PartialEq, Debug)]
+pub struct Instruction {
+    pub bank: u8,
+    pub address: u16,
+    pub opcode: u8,
+    pub operand: Vec<u8>, // little endian, as it appears after the opcode
+}
+
+impl Instruction {
+    pub fn mnemonic(&self) -> &'static str {
+        OPCODES[self.opcode as usize].0
+    }
+
+    pub fn mode(&self) -> Mode {
+        OPCODES[self.opcode as usize].1
+    }
+
+    // in bytes, opcode included
+    pub fn size(&self) -> usize {
+        1 + self.operand.len()
+    }
+
+    // the operand as one number
+    pub fn value(&self) -> u32 {
+        self.operand.iter().rev().fold(0, |value, &byte| (value << 8) | byte as u32)
+    }
+
+    // where the instruction after this one starts. the program counter wraps within the bank
+    pub fn next_address(&self) -> u16 {
+        self.address.wrapping_add(self.size() as u16)
+    }
+
+    // where a branch, jump or call goes, when that doesn't depend on registers or memory
+    pub fn target(&self) -> Option<(u8, u16)> {
+        match (self.mnemonic(), self.mode()) {
+            ("PER", Relative16) => None,
+            (_, Relative8) | (_, Relative16) => Some((self.bank, self.relative_target())),
+            ("JMP", Absolute) | ("JSR", Absolute) => Some((self.bank, self.value() as u16)),
+            ("JML", AbsoluteLong) | ("JSL", AbsoluteLong) => Some(((self.value() >> 16) as u8, self.value() as u16)),
+            _ => None,
+        }
+    }
+
+    // JSR and JSL, which return to the next instruction
+    pub fn is_call(&self) -> bool {
+        self.mnemonic() == "JSR" || self.mnemonic() == "JSL"
+    }
+
+    pub fn is_return(&self) -> bool {
+        matches!(self.mnemonic(), "RTS" | "RTL" | "RTI")
+    }
+
+    // the address in the operand, for the modes that name one outright. data_bank is DB,
+    // which absolute data addresses are in. direct page and stack operands are left out,
+    // they depend on D and S
+    pub fn operand_address(&self, data_bank: u8) -> Option<(u8, u16)> {
+        let value = self.value();
+        match self.mode() {
+            Relative8 | Relative16 => Some((self.bank, self.relative_target())),
+            Absolute | AbsoluteIndexedIndirect if matches!(self.mnemonic(), "JMP" | "JSR") => Some((self.bank, value as u16)),
+            Absolute | AbsoluteX | AbsoluteY => Some((data_bank, value as u16)),
+            AbsoluteIndirect | AbsoluteIndirectLong => Some((0, value as u16)),
+            AbsoluteLong | AbsoluteLongX => Some(((value >> 16) as u8, value as u16)),
+            _ => None,
+        }
+    }
+
+    // like Display, with the operand address written as its label when label knows one
+    pub fn format_with_labels<F>(&self, data_bank: u8, label: F) -> String
+        where F: Fn(u8, u16) -> Option<String>
+    {
+        let text = self.to_string();
+        let (bank, address) = match self.operand_address(data_bank) {
+            Some(at) => at,
+            None => return text,
+        };
+
+        let written = match self.mode() {
+            AbsoluteLong | AbsoluteLongX => format!("${:06X}", self.value()),
+            _ => format!("${:04X}", address),
+        };
+        match label(bank, address) {
+            Some(label) => text.replacen(&written, &label, 1),
+            None => text,
+        }
+    }
+
+    fn relative_target(&self) -> u16 {
+        if self.operand.len() == 1 {
+            self.next_address().wrapping_add(self.operand[0] as i8 as u16)
+        } else {
+            self.next_address().wrapping_add(self.value() as u16)
+        }
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let value = self.value();
+        let mnemonic = self.mnemonic();
+
+        match self.mode() {
+            Implied => write!(f, "{}", mnemonic),
+            Accumulator => write!(f, "{} A", mnemonic),
+            Immediate8 | ImmediateM | ImmediateX if self.operand.len() == 1 => write!(f, "{} #${:02X}", mnemonic, value),
+            Immediate8 | ImmediateM | ImmediateX => write!(f, "{} #${:04X}", mnemonic, value),
+            Direct => write!(f, "{} ${:02X}", mnemonic, value),
+            DirectX => write!(f, "{} ${:02X},X", mnemonic, value),
+            DirectY => write!(f, "{} ${:02X},Y", mnemonic, value),
+            DirectIndirect => write!(f, "{} (${:02X})", mnemonic, value),
+            DirectIndexedIndirect => write!(f, "{} (${:02X},X)", mnemonic, value),
+            DirectIndirectIndexed => write!(f, "{} (${:02X}),Y", mnemonic, value),
+            DirectIndirectLong => write!(f, "{} [${:02X}]", mnemonic, value),
+            DirectIndirectLongIndexed => write!(f, "{} [${:02X}],Y", mnemonic, value),
+            Absolute => write!(f, "{} ${:04X}", mnemonic, value),
+            AbsoluteX => write!(f, "{} ${:04X},X", mnemonic, value),
+            AbsoluteY => write!(f, "{} ${:04X},Y", mnemonic, value),
+            AbsoluteLong => write!(f, "{} ${:06X}", mnemonic, value),
+            AbsoluteLongX => write!(f, "{} ${:06X},X", mnemonic, value),
+            AbsoluteIndirect => write!(f, "{} (${:04X})", mnemonic, value),
+            AbsoluteIndexedIndirect => write!(f, "{} (${:04X},X)", mnemonic, value),
+            AbsoluteIndirectLong => write!(f, "{} [${:04X}]", mnemonic, value),
+            StackRelative => write!(f, "{} ${:02X},S", mnemonic, value),
+            StackRelativeIndirectIndexed => write!(f, "{} (${:02X},S),Y", mnemonic, value),
+            Relative8 | Relative16 => write!(f, "{} ${:04X}", mnemonic, self.relative_target()),
+            // the destination bank is stored first
+            BlockMove => write!(f, "{} ${:02X},${:02X}", mnemonic, self.operand[1], self.operand[0]),
+        }
+    }
+}
+
+// decodes the instruction at bank:address. m8 and x8 are the widths of the accumulator
+// and index registers, which decide how long immediate operands are
+pub fn disassemble<F: Fn(u8, u16) -> u8>(read: F, bank: u8, address: u16, m8: bool, x8: bool) -> Instruction {
+    let opcode = read(bank, address);
+    let len = OPCODES[opcode as usize].1.operand_len(m8, x8);
+    let operand = (1..=len).map(|n| read(bank, address.wrapping_add(n as u16))).collect();
+
+    Instruction {
+        bank,
+        address,
+        opcode,
+        operand,
+    }
+}
+
+// the widths from the status register. emulation mode is always 8 bit
+pub fn widths(p: u8, emulation: bool) -> (bool, bool) {
+    (emulation || p & 0x20 != 0, emulation || p & 0x10 != 0)
+}
@@ -1,19 +1,81 @@
+use std::any::Any;
+
+// master cycles for an access, by where it lands. the cartridge decides through $420D whether
+// the upper half of the rom (banks $80-$FF) gets the fast speed
+pub const FAST_CYCLES: u32 = 6;
+pub const SLOW_CYCLES: u32 = 8;
+pub const XSLOW_CYCLES: u32 = 12;
+
+pub fn access_cycles(bank: u8, address: u16, fast_rom: bool) -> u32 {
+    let rom_cycles = if fast_rom { FAST_CYCLES } else { SLOW_CYCLES };
+
+    match bank {
+        0x40..=0x7F => SLOW_CYCLES,
+        0xC0..=0xFF => rom_cycles,
+        _ => match address {
+            0x0000..=0x1FFF => SLOW_CYCLES,
+            0x2000..=0x3FFF => FAST_CYCLES,
+            0x4000..=0x41FF => XSLOW_CYCLES, // the old style joypad ports
+            0x4200..=0x5FFF => FAST_CYCLES,
+            0x6000..=0x7FFF => SLOW_CYCLES,
+            _ if bank >= 0x80 => rom_cycles,
+            _ => SLOW_CYCLES,
+        },
+    }
+}
+
 pub trait Mem {
     fn load(&self, bank: u8, address: u16) -> u8;
     fn store(&mut self, bank: u8, address: u16, to_store: u8);
+
+    // how long the cpu's access to an address takes. only the bus knows about $420D
+    fn access_cycles(&self, bank: u8, address: u16) -> u32 {
+        access_cycles(bank, address, false)
+    }
+
+    // called by the CPU after each instruction with the number of master clock cycles it took
+    fn tick(&mut self, _master_cycles: u32) {}
+
+    // the interrupt lines, checked by the CPU between instructions. nmi is edge triggered
+    // so taking it clears it, irq is a level that stays up until it's acknowledged
+    fn take_nmi(&mut self) -> bool {
+        false
+    }
+
+    fn irq(&self) -> bool {
+        false
+    }
+
+    // the name the program's symbols give an address, for the trace
+    fn label(&self, _bank: u8, _address: u16) -> Option<&str> {
+        None
+    }
+
+    // lets whoever owns the CPU get at the concrete memory behind it, eg the Bus
+    fn as_any(&self) -> &dyn Any;
+    fn as_any_mut(&mut self) -> &mut dyn Any;
 }
 
 pub struct SimpleMemory {
-    mem: [u8; 1 << 24] 
+    mem: Vec<u8> // the whole 16MB address space. too big to live on the stack
 }
 
 impl SimpleMemory {
     pub fn new() -> SimpleMemory {
         SimpleMemory {
-            mem: [0; 1 << 24]
+            mem: vec![0; 1 << 24]
         }
     }
 
+    // the whole address space as one flat array, bank 0 first
+    pub fn bytes(&self) -> &[u8] {
+        &self.mem
+    }
+
+    pub fn bytes_mut(&mut self) -> &mut [u8] {
+        &mut self.mem
+    }
+
     fn get_index(bank: u8, address: u16) -> usize {
         (bank as usize) << 16 | (address as usize)
     }
@@ -35,5 +97,32 @@ impl Mem for SimpleMemory {
     fn store(&mut self, bank: u8, address: u16, to_store: u8) {
         self.store_value(bank, address, to_store);
     }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fast_rom_only_speeds_up_the_upper_banks() {
+        assert_eq!(access_cycles(0x00, 0x8000, true), SLOW_CYCLES);
+        assert_eq!(access_cycles(0x40, 0x8000, true), SLOW_CYCLES);
+        assert_eq!(access_cycles(0x80, 0x8000, false), SLOW_CYCLES);
+        assert_eq!(access_cycles(0x80, 0x8000, true), FAST_CYCLES);
+        assert_eq!(access_cycles(0xC0, 0x0000, true), FAST_CYCLES);
+
+        // the system area of the upper banks isn't rom
+        assert_eq!(access_cycles(0x80, 0x0000, true), SLOW_CYCLES);
+        assert_eq!(access_cycles(0x80, 0x4016, true), XSLOW_CYCLES);
+        assert_eq!(access_cycles(0x80, 0x4200, true), FAST_CYCLES);
+        assert_eq!(access_cycles(0x80, 0x6000, true), SLOW_CYCLES);
+    }
 }
-    
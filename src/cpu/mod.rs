@@ -1,4 +1,6 @@
+pub mod asm;
 pub mod cpu;
+pub mod disasm;
 
 // TODO -> move memory out of cpu
 pub mod memory;
@@ -0,0 +1,135 @@
+use std::fmt;
+
+use cpu::disasm::Instruction;
+
+// native mode interrupt vectors for nmi, the rest come from irq, plus the emulation mode ones
+const NMI_VECTORS: [u16; 2] = [0xFFEA, 0xFFFA];
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum FrameKind {
+    Call,     // JSR
+    LongCall, // JSL
+    NMI,
+    IRQ,
+    BRK,
+    COP,
+}
+
+impl fmt::Display for FrameKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match *self {
+            FrameKind::Call => "jsr",
+            FrameKind::LongCall => "jsl",
+            FrameKind::NMI => "nmi",
+            FrameKind::IRQ => "irq",
+            FrameKind::BRK => "brk",
+            FrameKind::COP => "cop",
+        };
+        f.write_str(name)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Frame {
+    pub kind: FrameKind,
+    pub entry: (u8, u16),     // where the subroutine or handler starts
+    pub from: (u8, u16),      // the call instruction, or the instruction an interrupt came after
+    pub return_to: (u8, u16), // where the matching return goes
+    pub stack: u16,           // S before the return address went on. once S is back up here the frame is gone
+}
+
+// what the cpu looks like after a step, for CallStack::record
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct StepResult {
+    pub pc: (u8, u16),
+    pub stack: u16,
+    pub emulation: bool,
+    pub interrupt: Option<u16>, // the vector, when the step took an interrupt
+}
+
+// a shadow of the subroutine calls and interrupts the program is inside. the real stack
+// is the only source of truth, so rather than matching returns to calls, frames are dropped
+// as soon as S moves back past where their return address was. that copes with routines
+// that pull their own return address, tables of addresses pushed and "returned" to, and
+// main loops that reset S
+pub struct CallStack {
+    frames: Vec<Frame>,
+}
+
+impl CallStack {
+    pub fn new() -> CallStack {
+        CallStack {
+            frames: Vec::new(),
+        }
+    }
+
+    // outermost first
+    pub fn frames(&self) -> &[Frame] {
+        &self.frames
+    }
+
+    pub fn clear(&mut self) {
+        self.frames.clear();
+    }
+
+    // called after each step with the instruction it ran, None while waiting on WAI, and
+    // the S it ran with. read_stack reads bank 0, where interrupts leave the address they
+    // return to. returns true if the frames changed
+    pub fn record<F>(&mut self, instruction: Option<&Instruction>, stack_before: u16, after: StepResult, read_stack: F) -> bool
+        where F: Fn(u16) -> u8
+    {
+        let depth = self.frames.len();
+        let top = self.frames.last().cloned();
+
+        // an interrupt at the end of the step pushed the place the instruction went on to
+        let (went_to, stack_after_instruction) = match after.interrupt {
+            Some(_) => {
+                let pc = read_stack(after.stack.wrapping_add(2)) as u16 | (read_stack(after.stack.wrapping_add(3)) as u16) << 8;
+                let (bank, pushed) = if after.emulation { (0, 3) } else { (read_stack(after.stack.wrapping_add(4)), 4) };
+                ((bank, pc), after.stack.wrapping_add(pushed))
+            },
+            None => (after.pc, after.stack),
+        };
+
+        if let Some(instruction) = instruction {
+            let kind = match instruction.mnemonic() {
+                "JSR" => Some(FrameKind::Call),
+                "JSL" => Some(FrameKind::LongCall),
+                "BRK" => Some(FrameKind::BRK),
+                "COP" => Some(FrameKind::COP),
+                _ => None,
+            };
+            if let Some(kind) = kind {
+                self.frames.push(Frame {
+                    kind,
+                    entry: went_to,
+                    from: (instruction.bank, instruction.address),
+                    return_to: (instruction.bank, instruction.next_address()),
+                    stack: stack_before,
+                });
+            }
+        }
+
+        self.unwind(stack_after_instruction);
+
+        if let Some(vector) = after.interrupt {
+            let kind = if NMI_VECTORS.contains(&vector) { FrameKind::NMI } else { FrameKind::IRQ };
+            self.frames.push(Frame {
+                kind,
+                entry: after.pc,
+                from: instruction.map_or(went_to, |instruction| (instruction.bank, instruction.address)),
+                return_to: went_to,
+                stack: stack_after_instruction,
+            });
+        }
+
+        self.frames.len() != depth || self.frames.last().cloned() != top
+    }
+
+    // drops the frames whose return address has been pulled off the stack
+    fn unwind(&mut self, stack: u16) {
+        while self.frames.last().is_some_and(|frame| stack >= frame.stack) {
+            self.frames.pop();
+        }
+    }
+}
@@ -0,0 +1,625 @@
+use std::collections::VecDeque;
+use std::io::{Error, ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream};
+
+use bus::watch::{self, Access, Space, Watchpoint};
+use cpu::memory::Mem;
+use debugger::{Debugger, Stop};
+use emulator::Emulator;
+
+// how many instructions run between checks for an interrupt from gdb while continuing
+const INTERRUPT_POLL_INTERVAL: u64 = 4096;
+
+// signals for stop replies
+const SIGINT: u8 = 2;
+const SIGTRAP: u8 = 5;
+
+// gdb has no 65816 of its own, so the target description says what the registers are.
+// pc holds PB:PC as one 24 bit address so breakpoints and memory line up with the bus
+const TARGET_XML: &'static str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+  <feature name="org.snes.w65816.core">
+    <flags id="p_flags" size="1">
+      <field name="c" start="0" end="0"/>
+      <field name="z" start="1" end="1"/>
+      <field name="i" start="2" end="2"/>
+      <field name="d" start="3" end="3"/>
+      <field name="x" start="4" end="4"/>
+      <field name="m" start="5" end="5"/>
+      <field name="v" start="6" end="6"/>
+      <field name="n" start="7" end="7"/>
+    </flags>
+    <reg name="a" bitsize="16" type="uint16" regnum="0"/>
+    <reg name="x" bitsize="16" type="uint16"/>
+    <reg name="y" bitsize="16" type="uint16"/>
+    <reg name="sp" bitsize="16" type="uint16"/>
+    <reg name="d" bitsize="16" type="uint16"/>
+    <reg name="pc" bitsize="32" type="code_ptr"/>
+    <reg name="db" bitsize="8" type="uint8"/>
+    <reg name="pb" bitsize="8" type="uint8"/>
+    <reg name="p" bitsize="8" type="p_flags"/>
+    <reg name="e" bitsize="8" type="uint8"/>
+  </feature>
+</target>
+"#;
+
+// register sizes in bytes, in target description order
+const REGISTER_SIZES: [usize; 10] = [2, 2, 2, 2, 2, 4, 1, 1, 1, 1];
+
+// a gdb remote serial protocol server for the main cpu. it's a front end over the same
+// stepping the command line debugger uses, so breakpoints and watchpoints behave the same
+pub struct GdbStub {
+    debugger: Debugger,
+    ack: bool, // until gdb asks for no-ack mode
+    pending: VecDeque<u8>, // bytes read while checking for an interrupt that weren't one
+}
+
+// waits on localhost for one gdb to connect and serves it until it detaches
+pub fn listen(emulator: &mut Emulator, port: u16) -> Result<(), Error> {
+    let listener = TcpListener::bind(("127.0.0.1", port))?;
+    let (stream, _) = listener.accept()?;
+    GdbStub::new().serve(emulator, stream)
+}
+
+impl GdbStub {
+    pub fn new() -> GdbStub {
+        GdbStub {
+            debugger: Debugger::new(),
+            ack: true,
+            pending: VecDeque::new(),
+        }
+    }
+
+    // serves packets until gdb detaches, kills the target or hangs up
+    pub fn serve(&mut self, emulator: &mut Emulator, mut stream: TcpStream) -> Result<(), Error> {
+        stream.set_nodelay(true)?;
+
+        loop {
+            let packet = match self.read_packet(&mut stream)? {
+                Some(packet) => packet,
+                None => return Ok(()),
+            };
+
+            let reply = match packet.as_bytes().first() {
+                Some(b'D') => {
+                    self.send(&mut stream, "OK")?;
+                    return Ok(());
+                },
+                Some(b'k') => return Ok(()),
+                Some(b's') => {
+                    let stop = self.debugger.run_until(emulator, false, |_, _| true);
+                    self.stop_reply(emulator, Some(stop))
+                },
+                Some(b'c') => {
+                    let stop = self.run(emulator, &mut stream)?;
+                    self.stop_reply(emulator, stop)
+                },
+                _ => self.handle(emulator, &packet),
+            };
+            self.send(&mut stream, &reply)?;
+        }
+    }
+
+    // everything that answers straight away. unsupported packets get an empty reply
+    fn handle(&mut self, emulator: &mut Emulator, packet: &str) -> String {
+        // query and v packets have names, the rest are one letter followed by hex
+        let name_len = match packet.chars().next() {
+            Some('q') | Some('Q') | Some('v') => packet.find([':', ',', ';']).unwrap_or(packet.len()),
+            _ => packet.len().min(1),
+        };
+        let (command, args) = packet.split_at(name_len);
+
+        match command {
+            "?" => format!("S{:02x}", SIGTRAP),
+            "qSupported" => "PacketSize=4000;qXfer:features:read+;swbreak+;QStartNoAckMode+".to_string(),
+            "QStartNoAckMode" => {
+                self.ack = false;
+                "OK".to_string()
+            },
+            "qXfer" => self.read_target_xml(args),
+            "qAttached" => "1".to_string(),
+            "qC" => "QC1".to_string(),
+            "qfThreadInfo" => "m1".to_string(),
+            "qsThreadInfo" => "l".to_string(),
+            "H" | "T" => "OK".to_string(),
+            "g" => (0..REGISTER_SIZES.len()).map(|n| encode_register(emulator, n)).collect(),
+            "G" => self.write_registers(emulator, args),
+            "p" => match usize::from_str_radix(args, 16) {
+                Ok(n) if n < REGISTER_SIZES.len() => encode_register(emulator, n),
+                _ => "E01".to_string(),
+            },
+            "P" => {
+                let parsed = args.find('=').and_then(|at| {
+                    let n = usize::from_str_radix(&args[..at], 16).ok()?;
+                    Some((n, decode_hex(&args[at + 1..])?))
+                });
+                match parsed {
+                    Some((n, bytes)) if n < REGISTER_SIZES.len() && bytes.len() == REGISTER_SIZES[n] => {
+                        write_register(emulator, n, &bytes);
+                        "OK".to_string()
+                    },
+                    _ => "E01".to_string(),
+                }
+            },
+            "m" => match parse_range(args) {
+                Some((address, len)) => (0..len)
+                    .map(|offset| {
+                        let address = address.wrapping_add(offset) & 0xFFFFFF;
+                        format!("{:02x}", emulator.bus().peek((address >> 16) as u8, address as u16))
+                    })
+                    .collect(),
+                None => "E01".to_string(),
+            },
+            "M" => {
+                let parsed = args.find(':').and_then(|at| Some((parse_range(&args[..at])?, decode_hex(&args[at + 1..])?)));
+                match parsed {
+                    Some(((address, len), bytes)) if bytes.len() == len as usize => {
+                        for (offset, &byte) in bytes.iter().enumerate() {
+                            let address = address.wrapping_add(offset as u32) & 0xFFFFFF;
+                            emulator.bus_mut().store((address >> 16) as u8, address as u16, byte);
+                        }
+                        "OK".to_string()
+                    },
+                    _ => "E01".to_string(),
+                }
+            },
+            "Z" | "z" => self.breakpoint(emulator, command == "Z", args),
+            _ => String::new(),
+        }
+    }
+
+    // Z0 software breakpoints, Z2 write, Z3 read and Z4 access watchpoints, as
+    // "type,address,length"
+    fn breakpoint(&mut self, emulator: &mut Emulator, insert: bool, args: &str) -> String {
+        let fields: Vec<&str> = args.split(',').collect();
+        let parsed = match fields.as_slice() {
+            [kind, address, len] => u32::from_str_radix(address, 16).ok()
+                .and_then(|address| Some((*kind, address & 0xFFFFFF, u32::from_str_radix(len, 16).ok()?))),
+            _ => None,
+        };
+        let (kind, address, len) = match parsed {
+            Some(parsed) => parsed,
+            None => return "E01".to_string(),
+        };
+
+        let accesses = match kind {
+            "0" | "1" => {
+                let at = ((address >> 16) as u8, address as u16);
+                if insert {
+                    self.debugger.add_breakpoint(at.0, at.1);
+                } else if let Some(index) = self.debugger.breakpoints().iter().position(|&breakpoint| breakpoint == at) {
+                    self.debugger.remove_breakpoint(index);
+                }
+                return "OK".to_string();
+            },
+            "2" => watch::WRITE,
+            "3" => watch::READ,
+            "4" => watch::READ | watch::WRITE,
+            _ => return String::new(),
+        };
+
+        // a range running past the top of the bus is cut off there, one that overflows is garbage
+        let end = match address.checked_add(len.max(1) - 1) {
+            Some(end) => end.min(0xFFFFFF),
+            None => return "E01".to_string(),
+        };
+        let watchpoint = Watchpoint {
+            space: Space::Bus,
+            start: address,
+            end,
+            accesses,
+            value: None,
+        };
+        if insert {
+            if self.debugger.add_watchpoint(emulator, watchpoint).is_err() {
+                return "E01".to_string();
+            }
+        } else if let Some(index) = emulator.bus().watchpoints().iter().position(|&existing| existing == watchpoint) {
+            self.debugger.remove_watchpoint(emulator, index);
+        }
+        "OK".to_string()
+    }
+
+    // continues until something stops it, or gdb sends an interrupt, which gives None
+    fn run(&mut self, emulator: &mut Emulator, stream: &mut TcpStream) -> Result<Option<Stop>, Error> {
+        let mut steps = 0;
+        let mut interrupted = false;
+        let mut error = None;
+        let pending = &mut self.pending;
+
+        let stop = self.debugger.run_until(emulator, true, |_, _| {
+            steps += 1;
+            if steps % INTERRUPT_POLL_INTERVAL != 0 {
+                return false;
+            }
+            match poll_interrupt(stream, pending) {
+                Ok(true) => interrupted = true,
+                Ok(false) => {},
+                Err(e) => error = Some(e),
+            }
+            interrupted || error.is_some()
+        });
+
+        match error {
+            Some(e) => Err(e),
+            None if interrupted => Ok(None),
+            None => Ok(Some(stop)),
+        }
+    }
+
+    fn stop_reply(&self, emulator: &Emulator, stop: Option<Stop>) -> String {
+        match stop {
+            None => format!("S{:02x}", SIGINT),
+            Some(Stop::Done) | Some(Stop::CpuStopped) => format!("S{:02x}", SIGTRAP),
+            Some(Stop::Breakpoint(_)) => format!("T{:02x}swbreak:;", SIGTRAP),
+            Some(Stop::Watchpoint(hit)) => {
+                let accesses = emulator.bus().watchpoints().get(hit.index).map_or(0, |watchpoint| watchpoint.accesses);
+                let kind = match hit.access {
+                    _ if accesses & watch::READ != 0 && accesses & watch::WRITE != 0 => "awatch",
+                    Access::Read => "rwatch",
+                    Access::Write | Access::Execute => "watch",
+                };
+                format!("T{:02x}{}:{:x};", SIGTRAP, kind, hit.address)
+            },
+        }
+    }
+
+    // qXfer:features:read:target.xml:offset,length
+    fn read_target_xml(&self, args: &str) -> String {
+        let parsed = args.strip_prefix(":features:read:target.xml:").and_then(parse_range);
+        match parsed {
+            Some((offset, len)) => {
+                let start = (offset as usize).min(TARGET_XML.len());
+                let end = (start + len as usize).min(TARGET_XML.len());
+                let more = if end < TARGET_XML.len() { 'm' } else { 'l' };
+                format!("{}{}", more, &TARGET_XML[start..end])
+            },
+            None => "E00".to_string(),
+        }
+    }
+
+    fn write_registers(&self, emulator: &mut Emulator, args: &str) -> String {
+        let bytes = match decode_hex(args) {
+            Some(ref bytes) if bytes.len() == REGISTER_SIZES.iter().sum::<usize>() => bytes.clone(),
+            _ => return "E01".to_string(),
+        };
+
+        let mut offset = 0;
+        for (n, &size) in REGISTER_SIZES.iter().enumerate() {
+            write_register(emulator, n, &bytes[offset..offset + size]);
+            offset += size;
+        }
+        "OK".to_string()
+    }
+
+    ////////////////////////////////////
+    //
+    //             PACKETS
+    //
+    ////////////////////////////////////
+
+    // $data#checksum. acks and stray interrupts between packets are skipped. returns None
+    // once gdb hangs up
+    fn read_packet(&mut self, stream: &mut TcpStream) -> Result<Option<String>, Error> {
+        loop {
+            match self.read_byte(stream)? {
+                None => return Ok(None),
+                Some(b'$') => {},
+                Some(_) => continue,
+            }
+
+            let mut data = Vec::new();
+            loop {
+                match self.read_byte(stream)? {
+                    None => return Ok(None),
+                    Some(b'#') => break,
+                    Some(byte) => data.push(byte),
+                }
+            }
+            let checksum = [self.read_byte(stream)?, self.read_byte(stream)?];
+
+            let expected = format!("{:02x}", checksum_of(&data));
+            let valid = checksum.iter().zip(expected.bytes()).all(|(&c, e)| c.map(|c| c.to_ascii_lowercase()) == Some(e));
+
+            if self.ack {
+                stream.write_all(if valid { b"+" } else { b"-" })?;
+            }
+            if valid {
+                return String::from_utf8(data).map(Some).map_err(|_| Error::new(ErrorKind::InvalidData, "packet isn't utf-8"));
+            }
+        }
+    }
+
+    fn send(&mut self, stream: &mut TcpStream, data: &str) -> Result<(), Error> {
+        let packet = format!("${}#{:02x}", escape(data), checksum_of(escape(data).as_bytes()));
+        stream.write_all(packet.as_bytes())?;
+
+        // the ack, if gdb still sends them. a resend request gets one more try
+        if self.ack {
+            if let Some(b'-') = self.read_byte(stream)? {
+                stream.write_all(packet.as_bytes())?;
+                self.read_byte(stream)?;
+            }
+        }
+        Ok(())
+    }
+
+    // bytes put aside while the target ran come first
+    fn read_byte(&mut self, stream: &mut TcpStream) -> Result<Option<u8>, Error> {
+        if let Some(byte) = self.pending.pop_front() {
+            return Ok(Some(byte));
+        }
+
+        let mut byte = [0];
+        match stream.read(&mut byte)? {
+            0 => Ok(None),
+            _ => Ok(Some(byte[0])),
+        }
+    }
+}
+
+////////////////////////////////////
+//
+//             HELPERS
+//
+////////////////////////////////////
+
+// gdb interrupts a running target with a bare 0x03. anything else is kept for read_packet
+fn poll_interrupt(stream: &mut TcpStream, pending: &mut VecDeque<u8>) -> Result<bool, Error> {
+    stream.set_nonblocking(true)?;
+    let mut byte = [0];
+    let result = match stream.read(&mut byte) {
+        Ok(0) => Err(Error::new(ErrorKind::UnexpectedEof, "gdb hung up")),
+        Ok(_) if byte[0] == 0x03 => Ok(true),
+        Ok(_) => {
+            pending.push_back(byte[0]);
+            Ok(false)
+        },
+        Err(ref e) if e.kind() == ErrorKind::WouldBlock => Ok(false),
+        Err(e) => Err(e),
+    };
+    stream.set_nonblocking(false)?;
+    result
+}
+
+fn checksum_of(data: &[u8]) -> u8 {
+    data.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte))
+}
+
+// '$', '#', '}' and '*' can't appear in a reply as themselves
+fn escape(data: &str) -> String {
+    let mut escaped = String::with_capacity(data.len());
+    for c in data.chars() {
+        match c {
+            '$' | '#' | '}' | '*' => {
+                escaped.push('}');
+                escaped.push((c as u8 ^ 0x20) as char);
+            },
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn decode_hex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) || !text.is_ascii() {
+        return None;
+    }
+    (0..text.len()).step_by(2).map(|at| u8::from_str_radix(&text[at..at + 2], 16).ok()).collect()
+}
+
+// "address,length" in hex
+fn parse_range(text: &str) -> Option<(u32, u32)> {
+    let at = text.find(',')?;
+    Some((u32::from_str_radix(&text[..at], 16).ok()?, u32::from_str_radix(&text[at + 1..], 16).ok()?))
+}
+
+// little endian hex, the width of the register
+fn encode_register(emulator: &Emulator, n: usize) -> String {
+    let cpu = emulator.cpu();
+    let value = match n {
+        0 => cpu.a() as u32,
+        1 => cpu.x() as u32,
+        2 => cpu.y() as u32,
+        3 => cpu.sp() as u32,
+        4 => cpu.d() as u32,
+        5 => ((cpu.pbr() as u32) << 16) | cpu.pc() as u32,
+        6 => cpu.dbr() as u32,
+        7 => cpu.pbr() as u32,
+        8 => cpu.p() as u32,
+        _ => cpu.is_emulation() as u32,
+    };
+    (0..REGISTER_SIZES[n]).map(|byte| format!("{:02x}", (value >> (byte * 8)) as u8)).collect()
+}
+
+fn write_register(emulator: &mut Emulator, n: usize, bytes: &[u8]) {
+    let value = bytes.iter().rev().fold(0u32, |value, &byte| (value << 8) | byte as u32);
+    let cpu = emulator.cpu_mut();
+    match n {
+        0 => cpu.set_a(value as u16),
+        1 => cpu.set_x(value as u16),
+        2 => cpu.set_y(value as u16),
+        3 => cpu.set_sp(value as u16),
+        4 => cpu.set_d(value as u16),
+        5 => {
+            cpu.set_pbr((value >> 16) as u8);
+            cpu.set_pc(value as u16);
+        },
+        6 => cpu.set_dbr(value as u8),
+        7 => cpu.set_pbr(value as u8),
+        8 => cpu.set_p(value as u8),
+        _ => cpu.set_emulation(value & 1 != 0),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    // main at 00:1000 in wram calls a subroutine at 00:1010, stores to $0020 and then spins
+    const MAIN: [u8; 10] = [0xA9, 0x42, 0x20, 0x10, 0x10, 0x8D, 0x20, 0x00, 0x80, 0xFE];
+    const SUBROUTINE: [u8; 3] = [0xE8, 0xE8, 0x60];
+
+    // serves one connection on a thread. the emulator can't leave it, so check looks at it
+    // there once gdb detaches
+    fn connect(check: fn(&Emulator)) -> (TcpStream, thread::JoinHandle<()>) {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        let server = thread::Builder::new().stack_size(64 << 20).spawn(move || {
+            let mut emulator = Emulator::new();
+            for (offset, &byte) in MAIN.iter().enumerate() {
+                emulator.bus_mut().store(0, 0x1000 + offset as u16, byte);
+            }
+            for (offset, &byte) in SUBROUTINE.iter().enumerate() {
+                emulator.bus_mut().store(0, 0x1010 + offset as u16, byte);
+            }
+            emulator.cpu_mut().set_pc(0x1000);
+
+            let (stream, _) = listener.accept().unwrap();
+            GdbStub::new().serve(&mut emulator, stream).unwrap();
+            check(&emulator);
+        }).unwrap();
+
+        (TcpStream::connect(("127.0.0.1", port)).unwrap(), server)
+    }
+
+    fn packet(data: &str) -> String {
+        format!("${}#{:02x}", data, checksum_of(data.as_bytes()))
+    }
+
+    fn read_reply(stream: &mut TcpStream) -> String {
+        let mut byte = [0];
+        while stream.read(&mut byte).unwrap() == 1 && byte[0] != b'$' {}
+
+        let mut data = Vec::new();
+        loop {
+            stream.read_exact(&mut byte).unwrap();
+            if byte[0] == b'#' {
+                break;
+            }
+            data.push(byte[0]);
+        }
+        let mut checksum = [0; 2];
+        stream.read_exact(&mut checksum).unwrap();
+        assert_eq!(String::from_utf8_lossy(&checksum), format!("{:02x}", checksum_of(&data)));
+        String::from_utf8(data).unwrap()
+    }
+
+    // sends a packet in no-ack mode and waits for the reply
+    fn request(stream: &mut TcpStream, data: &str) -> String {
+        stream.write_all(packet(data).as_bytes()).unwrap();
+        read_reply(stream)
+    }
+
+    fn start(stream: &mut TcpStream) {
+        stream.write_all(packet("qSupported:swbreak+").as_bytes()).unwrap();
+        let mut ack = [0];
+        stream.read_exact(&mut ack).unwrap();
+        assert_eq!(ack[0], b'+');
+        assert!(read_reply(stream).contains("qXfer:features:read+"));
+        stream.write_all(b"+").unwrap();
+
+        stream.write_all(packet("QStartNoAckMode").as_bytes()).unwrap();
+        stream.read_exact(&mut ack).unwrap();
+        assert_eq!(read_reply(stream), "OK");
+        stream.write_all(b"+").unwrap();
+    }
+
+    fn detach(mut stream: TcpStream, server: thread::JoinHandle<()>) {
+        assert_eq!(request(&mut stream, "D"), "OK");
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn target_xml_is_read_in_pieces() {
+        let (mut stream, server) = connect(|_| {});
+        start(&mut stream);
+
+        let first = request(&mut stream, "qXfer:features:read:target.xml:0,20");
+        assert_eq!(first, format!("m{}", &TARGET_XML[..0x20]));
+        let rest = request(&mut stream, "qXfer:features:read:target.xml:20,fff");
+        assert_eq!(rest, format!("l{}", &TARGET_XML[0x20..]));
+        assert_eq!(request(&mut stream, "qXfer:features:read:other.xml:0,20"), "E00");
+        assert_eq!(request(&mut stream, "vMustReplyEmpty"), "");
+        detach(stream, server);
+    }
+
+    #[test]
+    fn registers_read_and_write_all_at_once() {
+        let (mut stream, server) = connect(|emulator| {
+            let cpu = emulator.cpu();
+            assert_eq!((cpu.a(), cpu.x(), cpu.y(), cpu.sp(), cpu.d()), (0xFFFF, 0xABCD, 0x5678, 0x01FE, 0x2000));
+            assert_eq!((cpu.pbr(), cpu.pc(), cpu.dbr(), cpu.p(), cpu.is_emulation()), (0x00, 0x1005, 0x7E, 0x30, false));
+        });
+        start(&mut stream);
+
+        // a, x, y, sp, d, pb:pc, db, pb, p, e
+        assert_eq!(request(&mut stream, "g"), "000000000000ff0100000010000000003401");
+        assert_eq!(request(&mut stream, "G3412cdab7856fe010020051000007e00300001"), "E01");
+        assert_eq!(request(&mut stream, "G3412cdab7856fe010020051000007e003000"), "OK");
+        assert_eq!(request(&mut stream, "g"), "3412cdab7856fe010020051000007e003000");
+        assert_eq!(request(&mut stream, "P0=ffff"), "OK");
+        assert_eq!(request(&mut stream, "p0"), "ffff");
+        assert_eq!(request(&mut stream, "pa"), "E01");
+        detach(stream, server);
+    }
+
+    #[test]
+    fn memory_reads_and_writes_go_through_the_bus() {
+        let (mut stream, server) = connect(|_| {});
+        start(&mut stream);
+
+        assert_eq!(request(&mut stream, "m1000,3"), "a94220");
+        assert_eq!(request(&mut stream, "M7e0030,2:aabb"), "OK");
+        assert_eq!(request(&mut stream, "m0030,3"), "aabb00");
+        assert_eq!(request(&mut stream, "M7e0030,2:aa"), "E01");
+        assert_eq!(request(&mut stream, "m7e0030"), "E01");
+        detach(stream, server);
+    }
+
+    #[test]
+    fn step_and_continue_stop_where_they_should() {
+        let (mut stream, server) = connect(|emulator| {
+            assert_eq!(emulator.cpu().x(), 2);
+            assert_eq!(emulator.bus().peek(0, 0x0020), 0x42);
+        });
+        start(&mut stream);
+
+        assert_eq!(request(&mut stream, "?"), "S05");
+        assert_eq!(request(&mut stream, "s"), "S05");
+        assert_eq!(request(&mut stream, "p5"), "02100000");
+
+        assert_eq!(request(&mut stream, "Z0,1011,1"), "OK");
+        assert_eq!(request(&mut stream, "c"), "T05swbreak:;");
+        assert_eq!(request(&mut stream, "p5"), "11100000");
+        assert_eq!(request(&mut stream, "z0,1011,1"), "OK");
+
+        assert_eq!(request(&mut stream, "Z2,20,1"), "OK");
+        assert_eq!(request(&mut stream, "c"), "T05watch:20;");
+        assert_eq!(request(&mut stream, "p5"), "08100000");
+        assert_eq!(request(&mut stream, "z2,20,1"), "OK");
+        assert_eq!(request(&mut stream, "Z2,ffffff,ffffffff"), "E01");
+        detach(stream, server);
+    }
+
+    #[test]
+    fn an_interrupt_stops_a_continue_without_losing_what_came_before_it() {
+        let (mut stream, server) = connect(|_| {});
+        start(&mut stream);
+
+        // the cpu ends up spinning on the BRA, so only the 0x03 stops it. the packet ahead of
+        // it is read while checking for the interrupt and answered afterwards
+        let mut bytes = packet("c").into_bytes();
+        bytes.extend_from_slice(packet("m1000,1").as_bytes());
+        bytes.push(0x03);
+        stream.write_all(&bytes).unwrap();
+
+        assert_eq!(read_reply(&mut stream), "S02");
+        assert_eq!(read_reply(&mut stream), "a9");
+        assert_eq!(request(&mut stream, "p5"), "08100000");
+        detach(stream, server);
+    }
+}
@@ -0,0 +1,991 @@
+pub mod callstack;
+pub mod gdb;
+pub mod profiler;
+
+use std::collections::VecDeque;
+use std::io::{BufRead, Error, Write};
+
+use bus::watch::{self, Access, Hit, Space, Watchpoint};
+use cdl::{self, Line};
+use cpu::asm;
+use cpu::disasm::{self, Instruction};
+use cpu::memory::Mem;
+use debugger::profiler::Profiler;
+use emulator::Emulator;
+use search::{self, Encoding, Filter, Region, Search};
+
+// executed instructions kept to show what led up to the current one
+const HISTORY_LEN: usize = 4;
+
+const DEFAULT_DUMP_LEN: usize = 64;
+const DEFAULT_DISASSEMBLY_LEN: usize = 8;
+const DEFAULT_PROFILE_LEN: usize = 20;
+const DEFAULT_SEARCH_LEN: usize = 20;
+
+const HELP: &'static str = "\
+step [n]              s   execute n instructions
+next                  n   step over a call
+finish                f   run until the current subroutine returns
+continue              c   run until a breakpoint or STP
+break BB:AAAA         b   break when execution reaches an address
+delete N              d   remove breakpoint N
+breakpoints           bl  list breakpoints
+watch rwx RANGE [=XX]     break on reads, writes or execution in a range, optionally of one value
+unwatch N                 remove watchpoint N
+watches                   list watchpoints
+regs [REG VALUE]      r   show the registers, or set A X Y S D DB PB PC P or E
+mem BB:AAAA [len]     m   hexdump memory
+write BB:AAAA XX..    w   write bytes to memory
+asm BB:AAAA CODE      a   assemble an instruction or directive into memory, rom included
+dis [BB:AAAA] [n]     u   disassemble, around the current instruction by default
+scanline N                run until the beam reaches line N
+frame [n]                 run n frames
+spc FILE                  write the sound cpu and dsp as an .spc, tagged from the cartridge header
+sym NAME|BB:AAAA          look up a label's address, or an address's label and comment
+backtrace             bt  show the subroutines and interrupt handlers the cpu is in
+prof [on|off|clear|n]     show the n functions that took the most cycles, or start, stop or clear the profile
+prof save FILE            write the profile as folded stacks, for flame graph tools
+search wram|sram [8|16|24] [bcd]   snapshot memory to start looking for a value
+search FILTER             keep the addresses that match: = N, != N, > N, < N, +N, -N, changed or unchanged
+search list [n]           show the addresses left, with their values now and at the last snapshot
+search watch N [rwx]      watch address N from the list, for writes by default
+search cheat N VALUE      add a cheat holding address N from the list at VALUE
+quit                  q
+an empty line repeats the last command. addresses without a bank use PB for code and DB for data.
+anywhere an address goes, a label from the symbol files works too, or BB:label for one in bank BB.
+watch ranges are BB:AAAA[-BB:AAAA] on the cpu bus, or aram: then AAAA[-AAAA]
+asm uses the widths in P, labels from the symbol files and the syntax dis shows, eg asm 8000 LDA.w #$1234
+search values are decimal, or hex with a leading $. a filter without a value compares with the last snapshot
+while a code/data log is running, dis shows logged data as .db and code with the widths it ran with";
+
+// why running stopped
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Stop {
+    Done,
+    Breakpoint(usize),
+    Watchpoint(Hit),
+    CpuStopped, // STP, nothing runs again until a reset
+}
+
+// drives the emulator one instruction at a time for the command line debugger. the cpu
+// doesn't know it's being debugged, every command is made of plain steps
+pub struct Debugger {
+    breakpoints: Vec<(u8, u16)>,
+    history: VecDeque<(u8, u16)>,
+    last_command: String,
+    search: Option<Search>,
+}
+
+impl Debugger {
+    pub fn new() -> Debugger {
+        Debugger {
+            breakpoints: Vec::new(),
+            history: VecDeque::new(),
+            last_command: String::new(),
+            search: None,
+        }
+    }
+
+    pub fn breakpoints(&self) -> &[(u8, u16)] {
+        &self.breakpoints
+    }
+
+    pub fn add_breakpoint(&mut self, bank: u8, address: u16) -> usize {
+        self.breakpoints.push((bank, address));
+        self.breakpoints.len() - 1
+    }
+
+    pub fn remove_breakpoint(&mut self, index: usize) -> bool {
+        if index >= self.breakpoints.len() {
+            return false;
+        }
+        self.breakpoints.remove(index);
+        true
+    }
+
+    // watchpoints live on the bus, which checks them as the accesses happen
+    pub fn add_watchpoint(&mut self, emulator: &mut Emulator, watchpoint: Watchpoint) -> Result<usize, Error> {
+        let mut watchpoints = emulator.bus().watchpoints().to_vec();
+        watchpoints.push(watchpoint);
+        emulator.bus_mut().set_watchpoints(&watchpoints)?;
+        Ok(watchpoints.len() - 1)
+    }
+
+    pub fn remove_watchpoint(&mut self, emulator: &mut Emulator, index: usize) -> bool {
+        let mut watchpoints = emulator.bus().watchpoints().to_vec();
+        if index >= watchpoints.len() {
+            return false;
+        }
+        watchpoints.remove(index);
+        // the bus took every one of these already, so what's left is fine too
+        emulator.bus_mut().set_watchpoints(&watchpoints).is_ok()
+    }
+
+    ////////////////////////////////////
+    //
+    //             RUNNING
+    //
+    ////////////////////////////////////
+
+    // executes one instruction and returns it
+    pub fn step(&mut self, emulator: &mut Emulator) -> Instruction {
+        let instruction = current_instruction(emulator);
+        if !emulator.cpu().is_stopped() {
+            if self.history.len() == HISTORY_LEN {
+                self.history.pop_front();
+            }
+            self.history.push_back((instruction.bank, instruction.address));
+            emulator.bus().begin_instruction(instruction.bank, instruction.address);
+        }
+        emulator.step_instruction();
+        instruction
+    }
+
+    // steps until done says so, execution reaches a breakpoint or a watchpoint triggers. the
+    // current instruction always runs, so running again from a breakpoint moves past it
+    pub fn run_until<F>(&mut self, emulator: &mut Emulator, stop_when_stopped: bool, mut done: F) -> Stop
+        where F: FnMut(&Emulator, &Instruction) -> bool
+    {
+        loop {
+            if stop_when_stopped && emulator.cpu().is_stopped() {
+                return Stop::CpuStopped;
+            }
+
+            let instruction = self.step(emulator);
+            if let Some(&hit) = emulator.bus_mut().take_watch_hits().first() {
+                return Stop::Watchpoint(hit);
+            }
+            if done(emulator, &instruction) {
+                return Stop::Done;
+            }
+
+            let pc = (emulator.cpu().pbr(), emulator.cpu().pc());
+            if let Some(index) = self.breakpoints.iter().position(|&breakpoint| breakpoint == pc) {
+                return Stop::Breakpoint(index);
+            }
+        }
+    }
+
+    // runs a JSR or JSL through to the instruction after it, anything else is a plain step
+    pub fn step_over(&mut self, emulator: &mut Emulator) -> Stop {
+        let instruction = current_instruction(emulator);
+        if !instruction.is_call() {
+            self.step(emulator);
+            return Stop::Done;
+        }
+
+        let return_to = (instruction.bank, instruction.next_address());
+        let sp = emulator.cpu().sp();
+        // the stack check keeps a recursive call to the same place from ending it early
+        self.run_until(emulator, true, |emulator, _| {
+            (emulator.cpu().pbr(), emulator.cpu().pc()) == return_to && emulator.cpu().sp() >= sp
+        })
+    }
+
+    // runs until a return pops the stack above where it is now
+    pub fn step_out(&mut self, emulator: &mut Emulator) -> Stop {
+        let sp = emulator.cpu().sp();
+        self.run_until(emulator, true, |emulator, instruction| {
+            instruction.is_return() && emulator.cpu().sp() > sp
+        })
+    }
+
+    pub fn run(&mut self, emulator: &mut Emulator) -> Stop {
+        self.run_until(emulator, true, |_, _| false)
+    }
+
+    pub fn run_to_scanline(&mut self, emulator: &mut Emulator, line: u16) -> Stop {
+        let mut previous = emulator.bus().beam_position().1;
+        self.run_until(emulator, false, |emulator, _| {
+            let current = emulator.bus().beam_position().1;
+            let reached = current == line && previous != line;
+            previous = current;
+            reached
+        })
+    }
+
+    pub fn run_frames(&mut self, emulator: &mut Emulator, frames: u64) -> Stop {
+        let end = emulator.frame() + frames;
+        self.run_until(emulator, false, |emulator, _| emulator.frame() >= end)
+    }
+
+    ////////////////////////////////////
+    //
+    //              REPL
+    //
+    ////////////////////////////////////
+
+    // reads commands until quit or the end of the input. nothing is prompted for, so a
+    // script piped in gives the same output as typing it
+    pub fn repl<R: BufRead, W: Write>(&mut self, emulator: &mut Emulator, input: R, out: &mut W) -> Result<(), Error> {
+        self.print_location(emulator, out)?;
+        if emulator.call_stack().is_none() {
+            emulator.set_call_stack(true);
+        }
+
+        for line in input.lines() {
+            let line = line?;
+            let line = match line.trim() {
+                "" => self.last_command.clone(),
+                line => line.to_string(),
+            };
+            self.last_command = line.clone();
+
+            if !self.command(emulator, &line, out)? {
+                break;
+            }
+        }
+
+        Ok(())
+    }
+
+    // returns false once the debugger should quit
+    pub fn command<W: Write>(&mut self, emulator: &mut Emulator, line: &str, out: &mut W) -> Result<bool, Error> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let (name, args) = match words.split_first() {
+            Some((&name, args)) => (name, args),
+            None => return Ok(true),
+        };
+
+        match (name, args.len()) {
+            ("step", 0..=1) | ("s", 0..=1) => {
+                let count: u64 = match args.first() {
+                    Some(count) => match count.parse() {
+                        Ok(count) => count,
+                        Err(_) => return self.usage(out, "bad instruction count"),
+                    },
+                    None => 1,
+                };
+                if count > 0 {
+                    let mut left = count;
+                    let stop = self.run_until(emulator, false, |_, _| {
+                        left -= 1;
+                        left == 0
+                    });
+                    self.report(emulator, stop, out)?;
+                }
+            },
+            ("next", 0) | ("n", 0) => {
+                let stop = self.step_over(emulator);
+                self.report(emulator, stop, out)?;
+            },
+            ("finish", 0) | ("f", 0) => {
+                let stop = self.step_out(emulator);
+                self.report(emulator, stop, out)?;
+            },
+            ("continue", 0) | ("c", 0) => {
+                let stop = self.run(emulator);
+                self.report(emulator, stop, out)?;
+            },
+            ("break", 1) | ("b", 1) => match resolve_address(emulator, args[0], emulator.cpu().pbr()) {
+                Some((bank, address)) => {
+                    let index = self.add_breakpoint(bank, address);
+                    writeln!(out, "breakpoint {} at {}", index, format_address(emulator, bank, address))?;
+                },
+                None => return self.usage(out, "bad address"),
+            },
+            ("delete", 1) | ("d", 1) => match args[0].parse() {
+                Ok(index) if self.remove_breakpoint(index) => writeln!(out, "deleted breakpoint {}", index)?,
+                _ => return self.usage(out, "no such breakpoint"),
+            },
+            ("watch", 2..=3) => match parse_watchpoint(emulator, args) {
+                Some(watchpoint) => match self.add_watchpoint(emulator, watchpoint) {
+                    Ok(index) => writeln!(out, "watchpoint {} on {}", index, format_watchpoint(&watchpoint))?,
+                    Err(e) => writeln!(out, "{}", e)?,
+                },
+                None => return self.usage(out, "bad watchpoint"),
+            },
+            ("unwatch", 1) => match args[0].parse() {
+                Ok(index) if self.remove_watchpoint(emulator, index) => writeln!(out, "deleted watchpoint {}", index)?,
+                _ => return self.usage(out, "no such watchpoint"),
+            },
+            ("watches", 0) => {
+                for (index, watchpoint) in emulator.bus().watchpoints().iter().enumerate() {
+                    writeln!(out, "{}: {}", index, format_watchpoint(watchpoint))?;
+                }
+            },
+            ("breakpoints", 0) | ("bl", 0) => {
+                for (index, &(bank, address)) in self.breakpoints.iter().enumerate() {
+                    writeln!(out, "{}: {}", index, format_address(emulator, bank, address))?;
+                }
+            },
+            ("regs", 0) | ("r", 0) => writeln!(out, "{}", registers(emulator))?,
+            ("regs", 2) | ("r", 2) => {
+                if !set_register(emulator, args[0], args[1]) {
+                    return self.usage(out, "bad register or value");
+                }
+                writeln!(out, "{}", registers(emulator))?;
+            },
+            ("mem", 1..=2) | ("m", 1..=2) => {
+                let start = match resolve_address(emulator, args[0], emulator.cpu().dbr()) {
+                    Some(start) => start,
+                    None => return self.usage(out, "bad address"),
+                };
+                let len = match args.get(1).map(|len| parse_number(len)) {
+                    Some(Some(len)) => len as usize,
+                    Some(None) => return self.usage(out, "bad length"),
+                    None => DEFAULT_DUMP_LEN,
+                };
+                hexdump(emulator, start, len, out)?;
+            },
+            ("write", 2..=usize::MAX) | ("w", 2..=usize::MAX) => {
+                let (bank, address) = match resolve_address(emulator, args[0], emulator.cpu().dbr()) {
+                    Some(start) => start,
+                    None => return self.usage(out, "bad address"),
+                };
+                let bytes: Option<Vec<u8>> = args[1..].iter()
+                    .map(|byte| u8::from_str_radix(byte.trim_start_matches('$'), 16).ok())
+                    .collect();
+                match bytes {
+                    Some(bytes) => for (offset, &byte) in bytes.iter().enumerate() {
+                        emulator.bus_mut().store(bank, address.wrapping_add(offset as u16), byte);
+                    },
+                    None => return self.usage(out, "bad byte"),
+                }
+            },
+            ("asm", 2..=usize::MAX) | ("a", 2..=usize::MAX) => {
+                let (bank, address) = match resolve_address(emulator, args[0], emulator.cpu().pbr()) {
+                    Some(start) => start,
+                    None => return self.usage(out, "bad address"),
+                };
+                let (m8, x8) = disasm::widths(emulator.cpu().p(), emulator.cpu().is_emulation());
+                let program = match asm::assemble_with(&args[1..].join(" "), bank, address, m8, x8, |name| emulator.bus().symbol_address(name)) {
+                    Ok(program) => program,
+                    Err(e) => return self.usage(out, &e.to_string()),
+                };
+                program.write(|bank, address, byte| {
+                    let patched = emulator.bus_mut().cartridge_mut().is_some_and(|cartridge| cartridge.patch_rom(bank, address, byte));
+                    if !patched {
+                        emulator.bus_mut().store(bank, address, byte);
+                    }
+                });
+                let bytes: Vec<String> = program.bytes().iter().map(|byte| format!("{:02X}", byte)).collect();
+                writeln!(out, "{}  {}", format_address(emulator, bank, address), bytes.join(" "))?;
+            },
+            ("dis", 0) | ("u", 0) => self.print_disassembly(emulator, None, DEFAULT_DISASSEMBLY_LEN, out)?,
+            ("dis", 1..=2) | ("u", 1..=2) => {
+                let start = match resolve_address(emulator, args[0], emulator.cpu().pbr()) {
+                    Some(start) => start,
+                    None => return self.usage(out, "bad address"),
+                };
+                let count = match args.get(1).map(|count| count.parse()) {
+                    Some(Ok(count)) => count,
+                    Some(Err(_)) => return self.usage(out, "bad instruction count"),
+                    None => DEFAULT_DISASSEMBLY_LEN,
+                };
+                self.print_disassembly(emulator, Some(start), count, out)?;
+            },
+            ("scanline", 1) => match args[0].parse() {
+                Ok(line) => {
+                    let stop = self.run_to_scanline(emulator, line);
+                    self.report(emulator, stop, out)?;
+                },
+                Err(_) => return self.usage(out, "bad scanline"),
+            },
+            ("frame", 0..=1) => {
+                let frames = match args.first().map(|frames| frames.parse()) {
+                    Some(Ok(frames)) => frames,
+                    Some(Err(_)) => return self.usage(out, "bad frame count"),
+                    None => 1,
+                };
+                let stop = self.run_frames(emulator, frames);
+                self.report(emulator, stop, out)?;
+            },
+            ("sym", 1) => match emulator.bus().symbol_address(args[0]) {
+                Some((bank, address)) => writeln!(out, "{} = {:02X}:{:04X}", args[0], bank, address)?,
+                None => match parse_address(args[0], emulator.cpu().pbr()) {
+                    Some((bank, address)) => {
+                        let label = emulator.bus().label(bank, address).unwrap_or("no label");
+                        match emulator.bus().comment(bank, address) {
+                            Some(comment) => writeln!(out, "{:02X}:{:04X} {} ; {}", bank, address, label, comment)?,
+                            None => writeln!(out, "{:02X}:{:04X} {}", bank, address, label)?,
+                        }
+                    },
+                    None => return self.usage(out, "no such symbol"),
+                },
+            },
+            ("backtrace", 0) | ("bt", 0) => match emulator.call_stack() {
+                Some(call_stack) => for (depth, frame) in call_stack.frames().iter().rev().enumerate() {
+                    writeln!(out, "#{} {} {} from {}", depth, format_address(emulator, frame.entry.0, frame.entry.1),
+                        frame.kind, format_address(emulator, frame.from.0, frame.from.1))?;
+                },
+                None => writeln!(out, "the call stack isn't being followed")?,
+            },
+            ("prof", 0..=1) => match args.first().cloned() {
+                Some("on") => {
+                    if emulator.profiler().is_none() {
+                        emulator.set_profiler(Some(Profiler::new()));
+                    }
+                    writeln!(out, "profiling")?;
+                },
+                Some("off") => {
+                    emulator.set_profiler(None);
+                    writeln!(out, "stopped profiling")?;
+                },
+                Some("clear") => if emulator.profiler().is_some() {
+                    emulator.set_profiler(Some(Profiler::new()));
+                },
+                count => {
+                    let count = match count.map(|count| count.parse()) {
+                        Some(Ok(count)) => count,
+                        Some(Err(_)) => return self.usage(out, "bad function count"),
+                        None => DEFAULT_PROFILE_LEN,
+                    };
+                    match emulator.profiler() {
+                        Some(profiler) => print_profile(emulator, profiler, count, out)?,
+                        None => writeln!(out, "not profiling, try prof on")?,
+                    }
+                },
+            },
+            ("prof", 2) if args[0] == "save" => match emulator.profiler() {
+                Some(profiler) => {
+                    profiler.save_folded(args[1], |bank, address| function_name(emulator, bank, address))?;
+                    writeln!(out, "wrote {}", args[1])?;
+                },
+                None => writeln!(out, "not profiling, try prof on")?,
+            },
+            ("spc", 1) => {
+                emulator.save_spc(args[0])?;
+                writeln!(out, "wrote {}", args[0])?;
+            },
+            ("search", 1..=usize::MAX) => return self.search_command(emulator, args, out),
+            ("help", _) | ("h", _) => writeln!(out, "{}", HELP)?,
+            ("quit", 0) | ("q", 0) => return Ok(false),
+            _ => return self.usage(out, &format!("unknown command: {}", line)),
+        }
+
+        Ok(true)
+    }
+
+    fn usage<W: Write>(&self, out: &mut W, reason: &str) -> Result<bool, Error> {
+        writeln!(out, "{}, try help", reason)?;
+        Ok(true)
+    }
+
+    fn search_command<W: Write>(&mut self, emulator: &mut Emulator, args: &[&str], out: &mut W) -> Result<bool, Error> {
+        if let Some(region) = Region::from_name(args[0]) {
+            let mut width = 1;
+            let mut encoding = Encoding::Binary;
+            for &arg in args[1..].iter() {
+                match arg {
+                    "8" => width = 1,
+                    "16" => width = 2,
+                    "24" => width = 3,
+                    "bcd" => encoding = Encoding::BCD,
+                    _ => return self.usage(out, "bad search size"),
+                }
+            }
+            let search = Search::new(region, width, encoding, region.memory(emulator.bus()));
+            writeln!(out, "{} {} bit {} values at {} addresses", region.name(), width * 8, encoding, search.len())?;
+            self.search = Some(search);
+            return Ok(true);
+        }
+
+        let search = match self.search {
+            Some(ref mut search) => search,
+            None => return self.usage(out, "no search started"),
+        };
+        let memory = search.region().memory(emulator.bus());
+        let candidate = |index: &str| search.offsets().get(index.parse::<usize>().ok()?).cloned();
+
+        match (args[0], args.len()) {
+            ("list", 1..=2) => {
+                let count = match args.get(1).map(|count| count.parse()) {
+                    Some(Ok(count)) => count,
+                    Some(Err(_)) => return self.usage(out, "bad count"),
+                    None => DEFAULT_SEARCH_LEN,
+                };
+                print_candidates(emulator, search, count, out)?;
+            },
+            ("watch", 2..=3) => {
+                let accesses = match args.get(2) {
+                    Some(letters) => match parse_accesses(letters) {
+                        Some(accesses) => accesses,
+                        None => return self.usage(out, "bad watchpoint"),
+                    },
+                    None => watch::WRITE,
+                };
+                let watchpoint = match candidate(args[1]).and_then(|offset| search.watchpoint(offset, accesses, emulator.cartridge())) {
+                    Some(watchpoint) => watchpoint,
+                    None => return self.usage(out, "no such address"),
+                };
+                match self.add_watchpoint(emulator, watchpoint) {
+                    Ok(index) => writeln!(out, "watchpoint {} on {}", index, format_watchpoint(&watchpoint))?,
+                    Err(e) => writeln!(out, "{}", e)?,
+                }
+            },
+            ("cheat", 3) => {
+                let offset = match candidate(args[1]) {
+                    Some(offset) => offset,
+                    None => return self.usage(out, "no such address"),
+                };
+                let value = match search::parse_value(args[2]) {
+                    Some(value) => value,
+                    None => return self.usage(out, "bad value"),
+                };
+                let cheat = match search.cheat(offset, value, emulator.cartridge()) {
+                    Some(cheat) => cheat,
+                    None => return self.usage(out, "value doesn't fit"),
+                };
+                let codes: Vec<String> = cheat.codes.iter().map(|code| code.to_string()).collect();
+                writeln!(out, "cheat {}", codes.join("+"))?;
+                emulator.bus_mut().cheats_mut().add(cheat);
+            },
+            _ => match Filter::parse(&args.join(" ")) {
+                Some(filter) => {
+                    let left = search.filter(filter, memory);
+                    writeln!(out, "{} left", left)?;
+                    if left <= DEFAULT_SEARCH_LEN {
+                        print_candidates(emulator, search, left, out)?;
+                    }
+                },
+                None => return self.usage(out, "bad search filter"),
+            },
+        }
+
+        Ok(true)
+    }
+
+    fn report<W: Write>(&self, emulator: &Emulator, stop: Stop, out: &mut W) -> Result<(), Error> {
+        match stop {
+            Stop::Done => {},
+            Stop::Breakpoint(index) => writeln!(out, "breakpoint {}", index)?,
+            Stop::CpuStopped => writeln!(out, "cpu stopped")?,
+            Stop::Watchpoint(hit) => writeln!(out, "{}", describe_hit(emulator, &hit))?,
+        }
+        self.print_location(emulator, out)
+    }
+
+    // the next instruction and the registers, one line
+    fn print_location<W: Write>(&self, emulator: &Emulator, out: &mut W) -> Result<(), Error> {
+        let (dot, line) = emulator.bus().beam_position();
+        let instruction = current_instruction(emulator);
+        print_label(emulator, instruction.bank, instruction.address, out)?;
+        writeln!(out, "{:<34} {}  frame {} line {} dot {}",
+            format_instruction(emulator, &instruction), registers(emulator), emulator.frame(), line, dot)
+    }
+
+    // from an address, or around the current instruction: the last few executed and the
+    // next few, with the current one marked
+    fn print_disassembly<W: Write>(&self, emulator: &Emulator, start: Option<(u8, u16)>, count: usize, out: &mut W) -> Result<(), Error> {
+        let cpu = emulator.cpu();
+        let (mut m8, mut x8) = disasm::widths(cpu.p(), cpu.is_emulation());
+        let current = (cpu.pbr(), cpu.pc());
+
+        let (mut bank, mut address) = match start {
+            Some(start) => start,
+            None => {
+                for &(bank, address) in self.history.iter() {
+                    let instruction = disasm::disassemble(|bank, address| emulator.bus().peek(bank, address), bank, address, m8, x8);
+                    print_label(emulator, bank, address, out)?;
+                    writeln!(out, "  {}", with_comment(emulator, &instruction, format_instruction(emulator, &instruction)))?;
+                }
+                current
+            },
+        };
+
+        // a code/data log, if one is running, says which bytes are data and what widths code ran with
+        let log = emulator.code_data_log();
+        let flags_at = |bank, address| match (log.as_ref(), emulator.cartridge().and_then(|cartridge| cartridge.rom_offset(bank, address))) {
+            (Some(log), Some(offset)) => log.flags_at(offset),
+            _ => 0,
+        };
+
+        for _ in 0..count {
+            let marker = if (bank, address) == current { '>' } else { ' ' };
+            print_label(emulator, bank, address, out)?;
+            let instruction = match cdl::disassemble(|bank, address| emulator.bus().peek(bank, address), flags_at, bank, address, m8, x8) {
+                Line::Code(instruction) => instruction,
+                Line::Data { bytes, .. } => {
+                    let bytes: Vec<String> = bytes.iter().map(|byte| format!("${:02X}", byte)).collect();
+                    writeln!(out, "{} {:02X}:{:04X}  .db {}", marker, bank, address, bytes.join(","))?;
+                    address = address.wrapping_add(bytes.len() as u16);
+                    continue;
+                },
+            };
+            writeln!(out, "{} {}", marker, with_comment(emulator, &instruction, format_instruction(emulator, &instruction)))?;
+
+            // follow width changes so the immediates after them decode right
+            if instruction.mnemonic() == "REP" || instruction.mnemonic() == "SEP" {
+                let set = instruction.mnemonic() == "SEP";
+                let bits = instruction.operand[0];
+                if bits & 0x20 != 0 && !cpu.is_emulation() {
+                    m8 = set;
+                }
+                if bits & 0x10 != 0 && !cpu.is_emulation() {
+                    x8 = set;
+                }
+            }
+
+            bank = instruction.bank;
+            address = instruction.next_address();
+        }
+
+        Ok(())
+    }
+}
+
+////////////////////////////////////
+//
+//             HELPERS
+//
+////////////////////////////////////
+
+pub fn current_instruction(emulator: &Emulator) -> Instruction {
+    let cpu = emulator.cpu();
+    let (m8, x8) = disasm::widths(cpu.p(), cpu.is_emulation());
+    disasm::disassemble(|bank, address| emulator.bus().peek(bank, address), cpu.pbr(), cpu.pc(), m8, x8)
+}
+
+// 00:8000  A9 12        LDA #$12, with the operand written as its label if it has one
+pub fn format_instruction(emulator: &Emulator, instruction: &Instruction) -> String {
+    let bytes: Vec<String> = Some(instruction.opcode).iter().chain(instruction.operand.iter())
+        .map(|byte| format!("{:02X}", byte))
+        .collect();
+    format!("{:02X}:{:04X}  {:<12} {}", instruction.bank, instruction.address, bytes.join(" "), instruction_text(emulator, instruction))
+}
+
+// LDA player_x
+fn instruction_text(emulator: &Emulator, instruction: &Instruction) -> String {
+    let bus = emulator.bus();
+    instruction.format_with_labels(emulator.cpu().dbr(), |bank, address| bus.label(bank, address).map(|label| label.to_string()))
+}
+
+// the symbol files' comment for the instruction's address goes after it
+fn with_comment(emulator: &Emulator, instruction: &Instruction, line: String) -> String {
+    match emulator.bus().comment(instruction.bank, instruction.address) {
+        Some(comment) => format!("{:<40} ; {}", line, comment.replace('\n', " ")),
+        None => line,
+    }
+}
+
+// "main:" on a line of its own before a labelled address
+fn print_label<W: Write>(emulator: &Emulator, bank: u8, address: u16, out: &mut W) -> Result<(), Error> {
+    match emulator.bus().label(bank, address) {
+        Some(label) => writeln!(out, "{}:", label),
+        None => Ok(()),
+    }
+}
+
+// 00:8000, or 00:8000 (main) when it has a label
+pub fn format_address(emulator: &Emulator, bank: u8, address: u16) -> String {
+    match emulator.bus().label(bank, address) {
+        Some(label) => format!("{:02X}:{:04X} ({})", bank, address, label),
+        None => format!("{:02X}:{:04X}", bank, address),
+    }
+}
+
+// a function's label, or its address when it has none
+pub fn function_name(emulator: &Emulator, bank: u8, address: u16) -> String {
+    match emulator.bus().label(bank, address) {
+        Some(label) => label.to_string(),
+        None => format!("{:02X}:{:04X}", bank, address),
+    }
+}
+
+//  self%       self      total    calls  function
+//  41.2%    1234567    2345678       60  00:8123 (draw_sprites)
+fn print_profile<W: Write>(emulator: &Emulator, profiler: &Profiler, count: usize, out: &mut W) -> Result<(), Error> {
+    let total = profiler.total_cycles().max(1);
+    writeln!(out, "{} master cycles", profiler.total_cycles())?;
+    writeln!(out, "{:>6} {:>10} {:>10} {:>8}  function", "self%", "self", "total", "calls")?;
+    for function in profiler.functions().iter().take(count) {
+        writeln!(out, "{:>5.1}% {:>10} {:>10} {:>8}  {}", function.self_cycles as f64 * 100.0 / total as f64,
+            function.self_cycles, function.total_cycles, function.calls,
+            format_address(emulator, function.entry.0, function.entry.1))?;
+    }
+    Ok(())
+}
+
+// 0: 7E:0DBE  100 ($64), was 101 ($65)
+fn print_candidates<W: Write>(emulator: &Emulator, search: &Search, count: usize, out: &mut W) -> Result<(), Error> {
+    let memory = search.region().memory(emulator.bus());
+    for (index, candidate) in search.candidates(memory).iter().take(count).enumerate() {
+        let address = match search.region().address(candidate.offset, emulator.cartridge()) {
+            Some((bank, address)) => format_address(emulator, bank, address),
+            None => format!("{}:{:05X}", search.region().name(), candidate.offset),
+        };
+        writeln!(out, "{}: {}  {}, was {}", index, address, search::format_value(candidate.value, search.encoding()),
+            search::format_value(candidate.previous, search.encoding()))?;
+    }
+    if search.len() > count {
+        writeln!(out, "and {} more", search.len() - count)?;
+    }
+    Ok(())
+}
+
+// A:0000 X:0000 Y:0000 S:01FF D:0000 DB:00 P:34 nvMXdIzc E:1
+pub fn registers(emulator: &Emulator) -> String {
+    let cpu = emulator.cpu();
+    let flags: String = "NVMXDIZC".chars().enumerate()
+        .map(|(bit, flag)| if cpu.p() & (0x80 >> bit) != 0 { flag } else { flag.to_ascii_lowercase() })
+        .collect();
+    format!("A:{:04X} X:{:04X} Y:{:04X} S:{:04X} D:{:04X} DB:{:02X} P:{:02X} {} E:{}",
+        cpu.a(), cpu.x(), cpu.y(), cpu.sp(), cpu.d(), cpu.dbr(), cpu.p(), flags, cpu.is_emulation() as u8)
+}
+
+fn set_register(emulator: &mut Emulator, name: &str, value: &str) -> bool {
+    let value = match parse_number(value) {
+        Some(value) => value,
+        None => return false,
+    };
+    let cpu = emulator.cpu_mut();
+
+    match (name.to_ascii_uppercase().as_str(), value) {
+        ("A", 0..=0xFFFF) => cpu.set_a(value as u16),
+        ("X", 0..=0xFFFF) => cpu.set_x(value as u16),
+        ("Y", 0..=0xFFFF) => cpu.set_y(value as u16),
+        ("S", 0..=0xFFFF) => cpu.set_sp(value as u16),
+        ("D", 0..=0xFFFF) => cpu.set_d(value as u16),
+        ("PC", 0..=0xFFFF) => cpu.set_pc(value as u16),
+        ("DB", 0..=0xFF) => cpu.set_dbr(value as u8),
+        ("PB", 0..=0xFF) => cpu.set_pbr(value as u8),
+        ("P", 0..=0xFF) => cpu.set_p(value as u8),
+        ("E", 0..=1) => cpu.set_emulation(value == 1),
+        _ => return false,
+    }
+    true
+}
+
+fn hexdump<W: Write>(emulator: &Emulator, (bank, address): (u8, u16), len: usize, out: &mut W) -> Result<(), Error> {
+    let bytes: Vec<u8> = (0..len).map(|offset| emulator.bus().peek(bank, address.wrapping_add(offset as u16))).collect();
+
+    for (row, chunk) in bytes.chunks(16).enumerate() {
+        let hex: Vec<String> = chunk.iter().map(|byte| format!("{:02X}", byte)).collect();
+        let text: String = chunk.iter()
+            .map(|&byte| if byte.is_ascii_graphic() || byte == b' ' { byte as char } else { '.' })
+            .collect();
+        writeln!(out, "{:02X}:{:04X}  {:<47}  {}", bank, address.wrapping_add(row as u16 * 16), hex.join(" "), text)?;
+    }
+
+    Ok(())
+}
+
+// watchpoint 0: write $42 to 7E:0010 by 00:8005 STA $0010
+fn describe_hit(emulator: &Emulator, hit: &Hit) -> String {
+    let access = match hit.access {
+        Access::Read => "read",
+        Access::Write => "write",
+        Access::Execute => "execute",
+    };
+    let direction = if hit.access == Access::Write { "to" } else { "from" };
+
+    let (bank, pc) = hit.pc;
+    let by = if hit.space == Space::ARAM {
+        format!("the spc700 at {:04X}", pc)
+    } else {
+        let instruction = {
+            let cpu = emulator.cpu();
+            // the widths may have changed since, but only immediates depend on them
+            let (m8, x8) = disasm::widths(cpu.p(), cpu.is_emulation());
+            disasm::disassemble(|bank, address| emulator.bus().peek(bank, address), bank, pc, m8, x8)
+        };
+        let instruction = instruction_text(emulator, &instruction);
+        match hit.dma_channel {
+            Some(channel) => format!("dma channel {}, started by {:02X}:{:04X} {}", channel, bank, pc, instruction),
+            None => format!("{:02X}:{:04X} {}", bank, pc, instruction),
+        }
+    };
+
+    let address = match hit.space {
+        Space::Bus => format_address(emulator, (hit.address >> 16) as u8, hit.address as u16),
+        _ => format_space_address(hit.space, hit.address),
+    };
+    format!("watchpoint {}: {} ${:02X} {} {} by {}", hit.index, access, hit.value, direction, address, by)
+}
+
+fn format_watchpoint(watchpoint: &Watchpoint) -> String {
+    let accesses: String = [(watch::READ, 'r'), (watch::WRITE, 'w'), (watch::EXECUTE, 'x')].iter()
+        .filter(|&&(bit, _)| watchpoint.accesses & bit != 0)
+        .map(|&(_, letter)| letter)
+        .collect();
+
+    let mut text = format!("{} {}", accesses, format_space_address(watchpoint.space, watchpoint.start));
+    if watchpoint.end != watchpoint.start {
+        text += &format!("-{}", format_space_address(watchpoint.space, watchpoint.end));
+    }
+    if let Some(value) = watchpoint.value {
+        text += &format!(" ={:02X}", value);
+    }
+    text
+}
+
+fn format_space_address(space: Space, address: u32) -> String {
+    match space {
+        Space::Bus => format!("{:02X}:{:04X}", address >> 16, address & 0xFFFF),
+        _ => format!("{}:{:04X}", space.name(), address),
+    }
+}
+
+// any of r, w and x
+fn parse_accesses(letters: &str) -> Option<u8> {
+    let mut accesses = 0;
+    for letter in letters.chars() {
+        accesses |= match letter {
+            'r' => watch::READ,
+            'w' => watch::WRITE,
+            'x' => watch::EXECUTE,
+            _ => return None,
+        };
+    }
+    Some(accesses)
+}
+
+// rw 7E:0000-7E:00FF =42. bus addresses can be labels
+fn parse_watchpoint(emulator: &Emulator, args: &[&str]) -> Option<Watchpoint> {
+    let accesses = parse_accesses(args[0])?;
+
+    let range = args[1];
+    let (space, range) = match range.find(':').and_then(|at| Space::from_name(&range[..at]).map(|space| (space, at))) {
+        Some((space, at)) => (space, &range[at + 1..]),
+        None => (Space::Bus, range),
+    };
+
+    let parse = |text: &str| match space {
+        Space::Bus => resolve_address(emulator, text, emulator.cpu().dbr()).map(|(bank, address)| ((bank as u32) << 16) | address as u32),
+        _ => parse_number(text).filter(|&address| address < space.size()),
+    };
+    let (start, end) = match range.find('-') {
+        Some(at) => (parse(&range[..at])?, parse(&range[at + 1..])?),
+        None => (parse(range)?, parse(range)?),
+    };
+
+    let value = match args.get(2) {
+        Some(value) if value.starts_with('=') => Some(u8::from_str_radix(value[1..].trim_start_matches('$'), 16).ok()?),
+        Some(_) => return None,
+        None => None,
+    };
+
+    if accesses == 0 || end < start {
+        return None;
+    }
+
+    Some(Watchpoint {
+        space,
+        start,
+        end,
+        accesses,
+        value,
+    })
+}
+
+// hex, with an optional '$'
+fn parse_number(text: &str) -> Option<u32> {
+    u32::from_str_radix(text.trim_start_matches('$'), 16).ok()
+}
+
+// a label, "BB:label" for one in a particular bank, or an address for parse_address
+pub fn resolve_address(emulator: &Emulator, text: &str, bank: u8) -> Option<(u8, u16)> {
+    emulator.bus().symbol_address(text).or_else(|| parse_address(text, bank))
+}
+
+// BB:AAAA, BBAAAA or AAAA in the given bank
+pub fn parse_address(text: &str, bank: u8) -> Option<(u8, u16)> {
+    let text = text.trim_start_matches('$');
+    match text.find(':') {
+        Some(at) => Some((u8::from_str_radix(&text[..at], 16).ok()?, u16::from_str_radix(&text[at + 1..], 16).ok()?)),
+        None if text.len() > 4 => {
+            let address = u32::from_str_radix(text, 16).ok().filter(|&address| address <= 0xFFFFFF)?;
+            Some(((address >> 16) as u8, address as u16))
+        },
+        None => Some((bank, u16::from_str_radix(text, 16).ok()?)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs;
+    use std::io::Cursor;
+
+    use apu::spc_file::SpcFile;
+
+    // a main routine at 00:1000 in wram that calls a subroutine at 00:1010, assembled by the
+    // debugger itself. it leaves the pc at the start of main
+    const PROGRAM: &'static str = "\
+a 1000 LDA #$42
+a 1002 JSR $1010
+a 1005 STA $0020
+a 1008 BRA $1008
+a 1010 INX
+a 1011 INX
+a 1012 RTS
+r PC 1000
+";
+
+    fn run(script: &str) -> (Emulator, String) {
+        let mut emulator = Emulator::new();
+        let mut out = Vec::new();
+        let input = Cursor::new(format!("{}{}", PROGRAM, script));
+        Debugger::new().repl(&mut emulator, input, &mut out).unwrap();
+        (emulator, String::from_utf8(out).unwrap())
+    }
+
+    #[test]
+    fn step_runs_one_instruction_and_next_runs_a_whole_call() {
+        let (emulator, out) = run("s\nn\n");
+        assert!(out.contains("00:1002  20 10 10     JSR $1010    A:0042 X:0000"));
+        assert!(out.contains("00:1005  8D 20 00     STA $0020    A:0042 X:0002"));
+        assert_eq!(emulator.cpu().pc(), 0x1005);
+
+        // an empty line repeats the last command
+        let (emulator, _) = run("s\n\n\n");
+        assert_eq!(emulator.cpu().pc(), 0x1011);
+    }
+
+    #[test]
+    fn finish_runs_until_the_subroutine_returns() {
+        let (emulator, out) = run("s 2\nf\n");
+        assert!(out.contains("00:1010  E8           INX"));
+        assert!(out.contains("00:1005  8D 20 00     STA $0020    A:0042 X:0002"));
+        assert_eq!(emulator.cpu().sp(), 0x01FF);
+    }
+
+    #[test]
+    fn continue_stops_at_a_breakpoint() {
+        let (emulator, out) = run("b 1011\nbl\nc\nd 0\nd 0\n");
+        assert!(out.contains("breakpoint 0 at 00:1011\n0: 00:1011\n"));
+        assert!(out.contains("breakpoint 0\n00:1011  E8           INX          A:0042 X:0001"));
+        assert!(out.contains("deleted breakpoint 0\nno such breakpoint"));
+        assert_eq!(emulator.cpu().pc(), 0x1011);
+    }
+
+    #[test]
+    fn regs_sets_registers_by_name() {
+        let (emulator, out) = run("r A 1234\nr pc 1005\nr DB 7E\nr Q 1\nr X 10000\n");
+        assert!(out.contains("A:1234 X:0000 Y:0000 S:01FF D:0000 DB:7E P:34"));
+        assert!(out.contains("bad register or value, try help\nbad register or value, try help"));
+        assert_eq!((emulator.cpu().a(), emulator.cpu().pc(), emulator.cpu().dbr()), (0x1234, 0x1005, 0x7E));
+    }
+
+    #[test]
+    fn mem_shows_what_write_and_the_program_stored() {
+        let (_, out) = run("s\nn\ns\nm 0020 1\nw 0030 48 49 $0A\nm 0030 3\nw 0030 XY\n");
+        assert!(out.contains("00:0020  42"));
+        assert!(out.contains("00:0030  48 49 0A"));
+        assert!(out.contains("HI."));
+        assert!(out.contains("bad byte, try help"));
+    }
+
+    #[test]
+    fn spc_writes_the_sound_state() {
+        let path = env::temp_dir().join(format!("snes-debugger-{}.spc", std::process::id())).to_string_lossy().into_owned();
+        let (_, out) = run(&format!("spc {}\n", path));
+        assert!(out.contains(&format!("wrote {}", path)));
+
+        // without a cartridge there's no header to tag it from
+        let spc = SpcFile::open(&path).unwrap();
+        assert_eq!(spc.id666, None);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn dis_shows_the_assembled_program() {
+        let (_, out) = run("dis 1000 4\n");
+        assert!(out.contains("00:1000  A9 42        LDA #$42\n"));
+        assert!(out.contains("00:1002  20 10 10     JSR $1010\n"));
+        assert!(out.contains("00:1005  8D 20 00     STA $0020\n"));
+        assert!(out.contains("00:1008  80 FE        BRA $1008\n"));
+    }
+}
@@ -0,0 +1,179 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::Error;
+
+use debugger::callstack::CallStack;
+
+// the name of the root of the call tree, the code that isn't inside any call
+const ROOT_NAME: &'static str = "main";
+
+// one place in the call tree: a function, reached through the calls above it
+struct Node {
+    entry: (u8, u16),
+    parent: usize,
+    children: HashMap<(u8, u16), usize>,
+    cycles: u64, // spent in this function itself, from here
+    calls: u64,
+}
+
+// how one function did over the whole profile
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct FunctionProfile {
+    pub entry: (u8, u16),
+    pub calls: u64,
+    pub self_cycles: u64,  // running its own instructions
+    pub total_cycles: u64, // including the functions it called
+}
+
+// every master cycle goes to the function that was running, along the calls that led to
+// it. an emulator can afford to count every instruction, so there's no sampling error
+pub struct Profiler {
+    nodes: Vec<Node>, // the call tree, nodes[0] is the root
+    path: Vec<(u8, u16, u16, usize)>, // entry bank, entry address, S of the frame, node
+    total_cycles: u64,
+}
+
+impl Profiler {
+    pub fn new() -> Profiler {
+        Profiler {
+            nodes: vec![Node {
+                entry: (0, 0),
+                parent: 0,
+                children: HashMap::new(),
+                cycles: 0,
+                calls: 0,
+            }],
+            path: Vec::new(),
+            total_cycles: 0,
+        }
+    }
+
+    pub fn total_cycles(&self) -> u64 {
+        self.total_cycles
+    }
+
+    // forgets everything counted so far. follow the call stack again to pick up where the cpu is
+    pub fn clear(&mut self) {
+        *self = Profiler::new();
+    }
+
+    // counts cycles spent where the call stack was before they ran
+    pub fn add_cycles(&mut self, master_cycles: u32) {
+        let node = self.path.last().map_or(0, |&(_, _, _, node)| node);
+        self.nodes[node].cycles += master_cycles as u64;
+        self.total_cycles += master_cycles as u64;
+    }
+
+    // moves to the call tree node for the stack's frames. frames that are still there
+    // keep their node, new ones count as calls
+    pub fn follow(&mut self, stack: &CallStack) {
+        let frames = stack.frames();
+        let kept = self.path.iter().zip(frames.iter())
+            .take_while(|&(&(bank, address, stack, _), frame)| frame.entry == (bank, address) && frame.stack == stack)
+            .count();
+        self.path.truncate(kept);
+
+        for frame in frames[kept..].iter() {
+            let parent = self.path.last().map_or(0, |&(_, _, _, node)| node);
+            let node = match self.nodes[parent].children.get(&frame.entry) {
+                Some(&node) => node,
+                None => {
+                    let node = self.nodes.len();
+                    self.nodes.push(Node {
+                        entry: frame.entry,
+                        parent,
+                        children: HashMap::new(),
+                        cycles: 0,
+                        calls: 0,
+                    });
+                    self.nodes[parent].children.insert(frame.entry, node);
+                    node
+                },
+            };
+            self.nodes[node].calls += 1;
+            self.path.push((frame.entry.0, frame.entry.1, frame.stack, node));
+        }
+    }
+
+    // the call stack was reset along with the cpu
+    pub fn reset_path(&mut self) {
+        self.path.clear();
+    }
+
+    // heaviest first, by the cycles spent in each function itself
+    pub fn functions(&self) -> Vec<FunctionProfile> {
+        let mut functions: HashMap<(u8, u16), FunctionProfile> = HashMap::new();
+
+        for (index, node) in self.nodes.iter().enumerate().skip(1) {
+            let function = functions.entry(node.entry).or_insert(FunctionProfile {
+                entry: node.entry,
+                calls: 0,
+                self_cycles: 0,
+                total_cycles: 0,
+            });
+            function.calls += node.calls;
+            function.self_cycles += node.cycles;
+
+            // a recursive function is only charged once for the cycles under it
+            let mut seen = HashSet::new();
+            for ancestor in self.ancestors(index) {
+                let entry = self.nodes[ancestor].entry;
+                if seen.insert(entry) {
+                    let function = functions.get_mut(&entry);
+                    if let Some(function) = function {
+                        function.total_cycles += node.cycles;
+                    } else {
+                        functions.insert(entry, FunctionProfile {
+                            entry,
+                            calls: 0,
+                            self_cycles: 0,
+                            total_cycles: node.cycles,
+                        });
+                    }
+                }
+            }
+        }
+
+        let mut functions: Vec<FunctionProfile> = functions.into_values().collect();
+        functions.sort_by(|a, b| b.self_cycles.cmp(&a.self_cycles).then(a.entry.cmp(&b.entry)));
+        functions
+    }
+
+    // folded stacks, one line per call path with the cycles spent at its end:
+    //   main;update_frame;draw_sprites 123456
+    // which is what flamegraph.pl, inferno and speedscope read. name turns a function's
+    // entry address into what's shown, a label or its address
+    pub fn folded<F: Fn(u8, u16) -> String>(&self, name: F) -> String {
+        let mut lines = Vec::new();
+
+        for (index, node) in self.nodes.iter().enumerate() {
+            if node.cycles == 0 {
+                continue;
+            }
+
+            let mut names: Vec<String> = self.ancestors(index).into_iter()
+                .map(|ancestor| name(self.nodes[ancestor].entry.0, self.nodes[ancestor].entry.1).replace(';', ":").replace(' ', "_"))
+                .collect();
+            names.reverse();
+            names.insert(0, ROOT_NAME.to_string());
+            lines.push(format!("{} {}", names.join(";"), node.cycles));
+        }
+
+        lines.sort();
+        lines.iter().map(|line| format!("{}\n", line)).collect()
+    }
+
+    pub fn save_folded<F: Fn(u8, u16) -> String>(&self, path: &str, name: F) -> Result<(), Error> {
+        fs::write(path, self.folded(name))
+    }
+
+    // the node and the ones above it, not counting the root
+    fn ancestors(&self, mut index: usize) -> Vec<usize> {
+        let mut ancestors = Vec::new();
+        while index != 0 {
+            ancestors.push(index);
+            index = self.nodes[index].parent;
+        }
+        ancestors
+    }
+}
@@ -0,0 +1,152 @@
+use std::io::Error;
+
+use savestate::{StateReader, StateWriter};
+
+// the 8 DMA channels and their registers at $43x0-$43xF.
+// a general purpose transfer moves bytes between an A-bus address (anywhere in the cpu's
+// address space) and a B-bus register ($2100-$21FF), halting the cpu until it's done.
+// todo -> HDMA
+pub const CHANNEL_COUNT: usize = 8;
+
+// master cycles per byte, plus the overhead for starting a transfer and for each channel
+pub const BYTE_CYCLES: u32 = 8;
+pub const START_CYCLES: u32 = 12;
+pub const CHANNEL_CYCLES: u32 = 8;
+
+#[derive(Clone, Copy, Default)]
+pub struct Channel {
+    pub control: u8,       // $43x0 DMAPx
+    pub b_address: u8,     // $43x1 BBADx
+    pub a_address: u16,    // $43x2-$43x3 A1Tx
+    pub a_bank: u8,        // $43x4 A1Bx
+    pub count: u16,        // $43x5-$43x6 DASx, 0 means 65536
+    pub indirect_bank: u8, // $43x7 DASBx
+    pub hdma_address: u16, // $43x8-$43x9 A2Ax
+    pub line_counter: u8,  // $43xA NTRLx
+    pub unused: u8,        // $43xB / $43xF, plain read/write memory
+}
+
+impl Channel {
+    pub fn new() -> Channel {
+        Channel {
+            control: 0xFF,
+            b_address: 0xFF,
+            a_address: 0xFFFF,
+            a_bank: 0xFF,
+            count: 0xFFFF,
+            indirect_bank: 0xFF,
+            hdma_address: 0xFFFF,
+            line_counter: 0xFF,
+            unused: 0xFF,
+        }
+    }
+
+    pub fn read(&self, register: u8) -> u8 {
+        match register & 0x0F {
+            0x0 => self.control,
+            0x1 => self.b_address,
+            0x2 => self.a_address as u8,
+            0x3 => (self.a_address >> 8) as u8,
+            0x4 => self.a_bank,
+            0x5 => self.count as u8,
+            0x6 => (self.count >> 8) as u8,
+            0x7 => self.indirect_bank,
+            0x8 => self.hdma_address as u8,
+            0x9 => (self.hdma_address >> 8) as u8,
+            0xA => self.line_counter,
+            _ => self.unused,
+        }
+    }
+
+    pub fn write(&mut self, register: u8, data: u8) {
+        match register & 0x0F {
+            0x0 => self.control = data,
+            0x1 => self.b_address = data,
+            0x2 => self.a_address = (self.a_address & 0xFF00) | data as u16,
+            0x3 => self.a_address = (self.a_address & 0x00FF) | (data as u16) << 8,
+            0x4 => self.a_bank = data,
+            0x5 => self.count = (self.count & 0xFF00) | data as u16,
+            0x6 => self.count = (self.count & 0x00FF) | (data as u16) << 8,
+            0x7 => self.indirect_bank = data,
+            0x8 => self.hdma_address = (self.hdma_address & 0xFF00) | data as u16,
+            0x9 => self.hdma_address = (self.hdma_address & 0x00FF) | (data as u16) << 8,
+            0xA => self.line_counter = data,
+            0xB | 0xF => self.unused = data,
+            _ => {} // $43xC-$43xE don't exist
+        }
+    }
+
+    // bit 7 of DMAPx: 0 reads the A-bus and writes the B-bus, 1 goes the other way
+    pub fn b_to_a(&self) -> bool {
+        self.control & 0x80 != 0
+    }
+
+    // the B-bus register offsets the transfer cycles through, from bits 0-2 of DMAPx
+    pub fn pattern(&self) -> &'static [u8] {
+        match self.control & 0x07 {
+            0 => &[0],
+            1 => &[0, 1],
+            2 | 6 => &[0, 0],
+            3 | 7 => &[0, 0, 1, 1],
+            4 => &[0, 1, 2, 3],
+            _ => &[0, 1, 0, 1],
+        }
+    }
+
+    // bit 3 fixes the A-bus address, otherwise bit 4 picks decrement over increment.
+    // the bank never changes
+    pub fn step_a_address(&mut self) {
+        match self.control & 0x18 {
+            0x00 => self.a_address = self.a_address.wrapping_add(1),
+            0x10 => self.a_address = self.a_address.wrapping_sub(1),
+            _ => {}
+        }
+    }
+}
+
+pub struct Dma {
+    channels: [Channel; CHANNEL_COUNT],
+}
+
+impl Dma {
+    pub fn new() -> Dma {
+        Dma {
+            channels: [Channel::new(); CHANNEL_COUNT],
+        }
+    }
+
+    pub fn channel(&self, index: usize) -> &Channel {
+        &self.channels[index]
+    }
+
+    pub fn channel_mut(&mut self, index: usize) -> &mut Channel {
+        &mut self.channels[index]
+    }
+
+    // $4300-$437F
+    pub fn read(&self, address: u16) -> u8 {
+        self.channels[((address >> 4) & 0x07) as usize].read(address as u8)
+    }
+
+    pub fn write(&mut self, address: u16, data: u8) {
+        self.channels[((address >> 4) & 0x07) as usize].write(address as u8, data);
+    }
+
+    // the registers hold all of a channel's state
+    pub fn save_state(&self, w: &mut StateWriter) {
+        for channel in self.channels.iter() {
+            for register in 0..0x10 {
+                w.u8(channel.read(register));
+            }
+        }
+    }
+
+    pub fn load_state(&mut self, r: &mut StateReader) -> Result<(), Error> {
+        for channel in self.channels.iter_mut() {
+            for register in 0..0x10 {
+                channel.write(register, r.u8()?);
+            }
+        }
+        Ok(())
+    }
+}
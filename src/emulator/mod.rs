@@ -0,0 +1,538 @@
+use std::cell::Ref;
+use std::io::{Error, ErrorKind, Write};
+use std::path::Path;
+
+use apu::spc_file::ID666;
+use apu::APU;
+use bus::Bus;
+use cartridge::Cartridge;
+use cdl::{self, CodeDataLog};
+use cpu::cpu::CPU;
+use cpu::disasm;
+use cpu::memory::SimpleMemory;
+use debugger::callstack::{CallStack, StepResult};
+use debugger::profiler::Profiler;
+use input::{DeviceKind, Input, PortInput, PORT_COUNT};
+use movie::{Movie, MovieMode, MovieSession, MovieStart};
+use ppu::png;
+use rewind::Rewind;
+use savestate::{SaveState, StateWriter, FORMAT_VERSION};
+
+// how far the clock moves per iteration while the cpu is stopped
+const STOPPED_TICK: u32 = 8;
+
+// how often sram that has changed is written back to its file, about every 5 seconds
+const SRAM_FLUSH_INTERVAL: u64 = 300;
+
+// the whole console behind one type, for frontends, tools and tests that embed the emulator.
+// everything runs on the calling thread and nothing depends on the wall clock
+pub struct Emulator {
+    cpu: CPU,
+    rewind: Option<Rewind>,
+    movie: Option<MovieSession>,
+    sram_path: Option<String>,
+    call_stack: Option<CallStack>,
+    profiler: Option<Profiler>,
+}
+
+impl Emulator {
+    // a console with nothing in the cartridge slot
+    pub fn new() -> Emulator {
+        let bus = Bus::new(SimpleMemory::new(), APU::new());
+
+        Emulator {
+            cpu: CPU::new(Box::new(bus)),
+            rewind: None,
+            movie: None,
+            sram_path: None,
+            call_stack: None,
+            profiler: None,
+        }
+    }
+
+    // inserting a cartridge means turning the console off first. the old cartridge's sram
+    // can't be saved any more, so call flush_sram first if it matters
+    pub fn load_cartridge(&mut self, cartridge: Cartridge) {
+        self.sram_path = None;
+        self.bus_mut().load_cartridge(cartridge);
+        self.power_cycle();
+    }
+
+    pub fn cartridge(&self) -> Option<&Cartridge> {
+        self.bus().cartridge()
+    }
+
+    // the rewind history can't go back past a reset
+    pub fn reset(&mut self) {
+        self.bus_mut().reset();
+        self.cpu.reset();
+        self.clear_rewind();
+        self.clear_call_stack();
+    }
+
+    pub fn power_cycle(&mut self) {
+        self.bus_mut().power_cycle();
+        self.cpu.power_cycle();
+        self.clear_rewind();
+        self.clear_call_stack();
+    }
+
+    ////////////////////////////////////
+    //
+    //             RUNNING
+    //
+    ////////////////////////////////////
+
+    // runs until the next vblank starts. a stopped cpu doesn't stop time, so the rest
+    // of the system still gets its frame
+    pub fn run_frame(&mut self) {
+        let frame = self.frame();
+
+        if let Some(inputs) = self.movie.as_ref().and_then(|session| session.inputs(frame)) {
+            for (port, &input) in inputs.iter().enumerate() {
+                self.set_input(port, input);
+            }
+        }
+
+        while self.frame() == frame {
+            self.step_instruction();
+        }
+
+        let frame = self.frame();
+        if let Some(mut session) = self.movie.take() {
+            let inputs = self.input_mut().take_fed();
+            session.end_frame(frame, inputs, self.bus().wram());
+            self.movie = Some(session);
+        }
+
+        if frame % SRAM_FLUSH_INTERVAL == 0 {
+            // a failed write is tried again next time and reported by flush_sram
+            let _ = self.flush_sram();
+        }
+
+        if self.rewind.as_ref().is_some_and(|rewind| rewind.wants_snapshot(frame)) {
+            let state = self.snapshot();
+            self.rewind.as_mut().unwrap().push(frame, state);
+        }
+    }
+
+    // returns the number of master cycles the instruction took. a stopped cpu just lets
+    // the clock run on for a moment
+    pub fn step_instruction(&mut self) -> u32 {
+        if self.cpu.is_stopped() {
+            self.cpu.mem_mut().tick(STOPPED_TICK);
+            return STOPPED_TICK;
+        }
+        if self.bus().is_logging_code_data() {
+            self.log_instruction();
+        }
+        if self.call_stack.is_none() {
+            return self.cpu.step();
+        }
+
+        let instruction = if self.cpu.is_waiting() { None } else { Some(self.current_instruction()) };
+        let stack_before = self.cpu.sp();
+        let cycles = self.cpu.step();
+
+        if let Some(ref mut profiler) = self.profiler {
+            profiler.add_cycles(cycles);
+        }
+
+        let after = StepResult {
+            pc: (self.cpu.pbr(), self.cpu.pc()),
+            stack: self.cpu.sp(),
+            emulation: self.cpu.is_emulation(),
+            interrupt: self.cpu.last_interrupt(),
+        };
+        let mut call_stack = self.call_stack.take().unwrap();
+        if call_stack.record(instruction.as_ref(), stack_before, after, |address| self.bus().peek(0, address)) {
+            if let Some(ref mut profiler) = self.profiler {
+                profiler.follow(&call_stack);
+            }
+        }
+        self.call_stack = Some(call_stack);
+        cycles
+    }
+
+    pub fn frame(&self) -> u64 {
+        self.bus().frame()
+    }
+
+    pub fn master_cycles(&self) -> u64 {
+        self.bus().master_cycles()
+    }
+
+    ////////////////////////////////////
+    //
+    //          CODE/DATA LOG
+    //
+    ////////////////////////////////////
+
+    // starts logging how every rom byte gets used, or stops with None. loading a
+    // cartridge stops it too
+    pub fn set_code_data_log(&mut self, log: Option<CodeDataLog>) {
+        self.bus_mut().set_code_data_log(log);
+    }
+
+    pub fn code_data_log(&self) -> Option<Ref<'_, CodeDataLog>> {
+        self.bus().code_data_log()
+    }
+
+    pub fn take_code_data_log(&mut self) -> Option<CodeDataLog> {
+        self.bus_mut().take_code_data_log()
+    }
+
+    // the bus sees the reads but not what they're for, so the instruction about to run
+    // is decoded here with the registers it'll use
+    fn log_instruction(&mut self) {
+        let (m8, x8) = disasm::widths(self.cpu.p(), self.cpu.is_emulation());
+        let instruction = self.current_instruction();
+        let pointer = cdl::pointer(&instruction, self.cpu.d(), self.cpu.sp(), self.cpu.x(), self.cpu.pbr());
+        self.bus_mut().log_instruction(&instruction, m8, x8, pointer);
+    }
+
+    ////////////////////////////////////
+    //
+    //     CALL STACK AND PROFILER
+    //
+    ////////////////////////////////////
+
+    // starts following the subroutines and interrupts the cpu goes into, or stops with
+    // false. it starts out empty, so calls made before it was turned on aren't in it
+    pub fn set_call_stack(&mut self, on: bool) {
+        if !on {
+            self.profiler = None;
+        }
+        self.call_stack = if on { Some(self.call_stack.take().unwrap_or_else(CallStack::new)) } else { None };
+    }
+
+    pub fn call_stack(&self) -> Option<&CallStack> {
+        self.call_stack.as_ref()
+    }
+
+    // counts the cycles spent in each function, or stops with None. profiling needs the
+    // call stack, so it's turned on too
+    pub fn set_profiler(&mut self, profiler: Option<Profiler>) {
+        self.profiler = profiler;
+        if let Some(ref mut profiler) = self.profiler {
+            let call_stack = self.call_stack.get_or_insert_with(CallStack::new);
+            profiler.follow(call_stack);
+        }
+    }
+
+    pub fn profiler(&self) -> Option<&Profiler> {
+        self.profiler.as_ref()
+    }
+
+    pub fn profiler_mut(&mut self) -> Option<&mut Profiler> {
+        self.profiler.as_mut()
+    }
+
+    pub fn take_profiler(&mut self) -> Option<Profiler> {
+        self.profiler.take()
+    }
+
+    // the cpu went somewhere the calls it was in can't be followed
+    fn clear_call_stack(&mut self) {
+        if let Some(ref mut call_stack) = self.call_stack {
+            call_stack.clear();
+        }
+        if let Some(ref mut profiler) = self.profiler {
+            profiler.reset_path();
+        }
+    }
+
+    // the instruction at PB:PC, decoded with the register widths it'll run with
+    fn current_instruction(&self) -> disasm::Instruction {
+        let (m8, x8) = disasm::widths(self.cpu.p(), self.cpu.is_emulation());
+        disasm::disassemble(|bank, address| self.bus().peek(bank, address), self.cpu.pbr(), self.cpu.pc(), m8, x8)
+    }
+
+    ////////////////////////////////////
+    //
+    //              SRAM
+    //
+    ////////////////////////////////////
+
+    // keeps battery backed sram in a file, usually cartridge::sram_path(rom). the file is
+    // loaded now if it exists and written whenever sram has changed: every few seconds,
+    // on flush_sram and when the emulator is dropped. None stops saving it
+    pub fn set_sram_path(&mut self, path: Option<String>) -> Result<(), Error> {
+        self.sram_path = None;
+
+        let path = match path {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+
+        if let Some(cartridge) = self.bus_mut().cartridge_mut().filter(|cartridge| cartridge.has_battery_sram()) {
+            if Path::new(&path).exists() {
+                cartridge.load_sram(&path)?;
+            }
+        }
+
+        self.sram_path = Some(path);
+        Ok(())
+    }
+
+    // writes sram to its file if it changed since the last write
+    pub fn flush_sram(&mut self) -> Result<(), Error> {
+        let path = match self.sram_path {
+            Some(ref path) => path.clone(),
+            None => return Ok(()),
+        };
+
+        match self.bus_mut().cartridge_mut() {
+            Some(cartridge) if cartridge.has_battery_sram() && cartridge.is_sram_dirty() => cartridge.save_sram(&path),
+            _ => Ok(()),
+        }
+    }
+
+    ////////////////////////////////////
+    //
+    //           SAVE STATES
+    //
+    ////////////////////////////////////
+
+    // a snapshot of the machine, tied to the loaded rom. the ppu only contributes its beam
+    // position, as it has no vram, cgram, oam or register latches yet
+    pub fn save_state(&self) -> Vec<u8> {
+        self.write_state(StateWriter::new(self.rom_crc32()))
+    }
+
+    // a state that's quicker to take but much bigger, for keeping in memory
+    pub fn snapshot(&self) -> Vec<u8> {
+        self.write_state(StateWriter::uncompressed(self.rom_crc32()))
+    }
+
+    fn write_state(&self, mut w: StateWriter) -> Vec<u8> {
+        w.section(b"CPU ", |w| self.cpu.save_state(w));
+        self.bus().save_state(&mut w);
+        w.into_bytes()
+    }
+
+    // a state that fails to load part way through leaves the machine as it was
+    pub fn load_state(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        let state = SaveState::parse(bytes)?;
+
+        if state.version != FORMAT_VERSION {
+            return Err(Error::new(ErrorKind::InvalidData,
+                format!("save state is version {}, this emulator reads version {}", state.version, FORMAT_VERSION)));
+        }
+        if state.rom_crc32 != self.rom_crc32() {
+            return Err(Error::new(ErrorKind::InvalidData, "save state is for a different rom"));
+        }
+
+        let backup = self.save_state();
+        let result = self.restore(&state);
+        if result.is_err() {
+            self.restore(&SaveState::parse(&backup)?)?;
+        }
+        result
+    }
+
+    fn restore(&mut self, state: &SaveState) -> Result<(), Error> {
+        self.clear_call_stack();
+        self.cpu.load_state(&mut state.section(b"CPU ")?)?;
+        self.bus_mut().load_state(state)
+    }
+
+    fn rom_crc32(&self) -> u32 {
+        self.cartridge().map(|cartridge| cartridge.crc32()).unwrap_or(0)
+    }
+
+    ////////////////////////////////////
+    //
+    //              REWIND
+    //
+    ////////////////////////////////////
+
+    // snapshots are taken at the end of run_frame every rewind.interval() frames.
+    // None turns rewinding off and frees the history
+    pub fn set_rewind(&mut self, rewind: Option<Rewind>) {
+        self.rewind = rewind;
+    }
+
+    pub fn rewind(&self) -> Option<&Rewind> {
+        self.rewind.as_ref()
+    }
+
+    // goes back to the newest snapshot from before the current frame, so with an interval
+    // of 1 each call steps back a single frame. returns false once the history runs out
+    pub fn step_back(&mut self) -> Result<bool, Error> {
+        let frame = self.frame();
+        let found = match self.rewind {
+            Some(ref mut rewind) => rewind.rewind_to_before(frame)?,
+            None => None,
+        };
+
+        match found {
+            Some((frame, state)) => {
+                self.load_state(&state)?;
+                if let Some(ref mut session) = self.movie {
+                    session.rewound_to(frame);
+                }
+                Ok(true)
+            },
+            None => Ok(false),
+        }
+    }
+
+    fn clear_rewind(&mut self) {
+        if let Some(ref mut rewind) = self.rewind {
+            rewind.clear();
+        }
+    }
+
+    ////////////////////////////////////
+    //
+    //              MOVIES
+    //
+    ////////////////////////////////////
+
+    // starts recording the input of every frame, either from a power cycle or from right now
+    // with the current state embedded in the movie. resets aren't recorded
+    pub fn record_movie(&mut self, from_power_on: bool) {
+        self.stop_movie();
+
+        let start = if from_power_on {
+            self.power_cycle();
+            MovieStart::PowerOn
+        } else {
+            MovieStart::SaveState(self.save_state())
+        };
+
+        let mut ports = [DeviceKind::None; PORT_COUNT];
+        for (port, kind) in ports.iter_mut().enumerate() {
+            *kind = self.bus().input().device_kind(port);
+        }
+
+        // only what's set from here on goes in the movie
+        self.input_mut().take_fed();
+
+        let movie = Movie::new(self.rom_crc32(), ports, start);
+        self.movie = Some(MovieSession::new(movie, MovieMode::Recording, self.frame(), None));
+    }
+
+    // puts the machine where the movie starts and takes over the controllers until it's stopped.
+    // each frame's input is set as run_frame starts, the same as a frontend would
+    pub fn play_movie(&mut self, movie: Movie) -> Result<(), Error> {
+        if movie.rom_crc32 != self.rom_crc32() {
+            return Err(Error::new(ErrorKind::InvalidData, "movie is for a different rom"));
+        }
+
+        self.stop_movie();
+
+        match movie.start {
+            MovieStart::PowerOn => self.power_cycle(),
+            MovieStart::SaveState(ref state) => self.load_state(state)?,
+        }
+        for (port, &kind) in movie.ports.iter().enumerate() {
+            self.input_mut().connect(port, kind);
+        }
+
+        let frame = self.frame();
+        let saved_provider = self.input_mut().take_provider();
+        self.movie = Some(MovieSession::new(movie, MovieMode::Playing, frame, saved_provider));
+        Ok(())
+    }
+
+    pub fn movie(&self) -> Option<&MovieSession> {
+        self.movie.as_ref()
+    }
+
+    // hands back the movie recorded or played so far, and the controllers back to the frontend
+    pub fn stop_movie(&mut self) -> Option<Movie> {
+        let session = self.movie.take()?;
+        let (movie, saved_provider) = session.finish();
+
+        if let Some(provider) = saved_provider {
+            self.input_mut().set_provider(provider);
+        }
+
+        Some(movie)
+    }
+
+    ////////////////////////////////////
+    //
+    //             OUTPUT
+    //
+    ////////////////////////////////////
+
+    // 0x00RRGGBB, width() * height() pixels
+    pub fn framebuffer(&self) -> &[u32] {
+        self.bus().ppu().framebuffer()
+    }
+
+    pub fn width(&self) -> usize {
+        self.bus().ppu().width()
+    }
+
+    pub fn height(&self) -> usize {
+        self.bus().ppu().height()
+    }
+
+    // the current frame as a .png at the resolution the ppu output it at
+    pub fn save_screenshot(&self, path: &str) -> Result<(), Error> {
+        png::write_png(path, self.width(), self.height(), self.framebuffer())
+    }
+
+    // the sound cpu and dsp as an .spc, tagged with the game's title from the cartridge header
+    pub fn save_spc(&self, path: &str) -> Result<(), Error> {
+        let id666 = self.cartridge().map(|cartridge| ID666::from_header(cartridge.header()));
+        self.bus().apu().save_spc(id666).save(path)
+    }
+
+    // 32 kHz interleaved stereo produced since the last call
+    pub fn audio_samples(&mut self) -> Vec<i16> {
+        self.bus_mut().apu_mut().take_samples()
+    }
+
+    ////////////////////////////////////
+    //
+    //              INPUT
+    //
+    ////////////////////////////////////
+
+    pub fn set_input(&mut self, port: usize, input: PortInput) {
+        self.bus_mut().input_mut().set_input(port, input);
+    }
+
+    // for plugging in other devices or setting an input provider
+    pub fn input_mut(&mut self) -> &mut Input {
+        self.bus_mut().input_mut()
+    }
+
+    ////////////////////////////////////
+    //
+    //           INTERNALS
+    //
+    ////////////////////////////////////
+
+    pub fn set_trace(&mut self, trace: Option<Box<dyn Write>>) {
+        self.cpu.set_trace(trace);
+    }
+
+    pub fn cpu(&self) -> &CPU {
+        &self.cpu
+    }
+
+    pub fn cpu_mut(&mut self) -> &mut CPU {
+        &mut self.cpu
+    }
+
+    pub fn bus(&self) -> &Bus {
+        self.cpu.mem().as_any().downcast_ref().expect("the cpu is always built on a Bus")
+    }
+
+    pub fn bus_mut(&mut self) -> &mut Bus {
+        self.cpu.mem_mut().as_any_mut().downcast_mut().expect("the cpu is always built on a Bus")
+    }
+}
+
+impl Drop for Emulator {
+    // the last chance to save sram, eg when the frontend exits
+    fn drop(&mut self) {
+        let _ = self.flush_sram();
+    }
+}
@@ -0,0 +1,53 @@
+use std::io::Error;
+
+use input::PortInput;
+use savestate::{StateReader, StateWriter};
+
+// anything that can be plugged into a controller port. the console only ever sees
+// the latch line going out and two serial data lines coming back, plus the IOBit pin
+// that $4201 drives and the light gun can pull low
+pub trait PortDevice {
+    fn latch(&mut self, high: bool);
+
+    // one clock of the serial line. returns the bits on data line 1 and data line 2
+    fn clock(&mut self) -> (u8, u8);
+
+    // $4201 bit 6 for port 1, bit 7 for port 2
+    fn set_iobit(&mut self, _high: bool) {}
+
+    fn set_input(&mut self, input: PortInput);
+
+    fn input(&self) -> PortInput;
+
+    // everything including the shift registers, so a read can be interrupted by a save state
+    fn save_state(&self, w: &mut StateWriter);
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), Error>;
+
+    // where a light gun is pointing, in dots and lines, when it can see the screen
+    fn light_position(&self) -> Option<(u16, u16)> {
+        None
+    }
+}
+
+// an empty port. both data lines float low
+pub struct Unplugged;
+
+impl PortDevice for Unplugged {
+    fn latch(&mut self, _high: bool) {}
+
+    fn clock(&mut self) -> (u8, u8) {
+        (0, 0)
+    }
+
+    fn set_input(&mut self, _input: PortInput) {}
+
+    fn input(&self) -> PortInput {
+        PortInput::None
+    }
+
+    fn save_state(&self, _w: &mut StateWriter) {}
+
+    fn load_state(&mut self, _r: &mut StateReader) -> Result<(), Error> {
+        Ok(())
+    }
+}
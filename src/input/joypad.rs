@@ -0,0 +1,144 @@
+use std::io::Error;
+
+use input::PortInput;
+use input::device::PortDevice;
+use savestate::{StateReader, StateWriter};
+
+// button bits in the order they are shifted out of the controller (B first)
+pub const BUTTON_B: u16      = 0x8000;
+pub const BUTTON_Y: u16      = 0x4000;
+pub const BUTTON_SELECT: u16 = 0x2000;
+pub const BUTTON_START: u16  = 0x1000;
+pub const BUTTON_UP: u16     = 0x0800;
+pub const BUTTON_DOWN: u16   = 0x0400;
+pub const BUTTON_LEFT: u16   = 0x0200;
+pub const BUTTON_RIGHT: u16  = 0x0100;
+pub const BUTTON_A: u16      = 0x0080;
+pub const BUTTON_X: u16      = 0x0040;
+pub const BUTTON_L: u16      = 0x0020;
+pub const BUTTON_R: u16      = 0x0010;
+// the low 4 bits are the controller's id, always 0000 for a standard pad
+
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub struct JoypadState {
+    pub buttons: u16,
+}
+
+impl JoypadState {
+    pub fn new(buttons: u16) -> JoypadState {
+        JoypadState {
+            buttons: buttons & 0xFFF0,
+        }
+    }
+
+    pub fn pressed(&self, button: u16) -> bool {
+        self.buttons & button != 0
+    }
+
+    pub fn set(&mut self, button: u16, pressed: bool) {
+        if pressed {
+            self.buttons |= button;
+        } else {
+            self.buttons &= !button;
+        }
+        self.buttons &= 0xFFF0;
+    }
+}
+
+// a standard controller. while the latch is high the shift register keeps reloading
+// from the buttons, once it goes low each clock shifts out the next bit, B first.
+// after all 16 bits have been read the pad returns 1s
+pub struct Joypad {
+    state: JoypadState,
+    shift: u16,
+    reads: u8,
+    latched: bool,
+}
+
+impl Joypad {
+    pub fn new() -> Joypad {
+        Joypad {
+            state: JoypadState::default(),
+            shift: 0,
+            reads: 16,
+            latched: false,
+        }
+    }
+
+    pub fn set_state(&mut self, state: JoypadState) {
+        self.state = state;
+        if self.latched {
+            self.reload();
+        }
+    }
+
+    pub fn state(&self) -> JoypadState {
+        self.state
+    }
+
+    fn reload(&mut self) {
+        self.shift = self.state.buttons;
+        self.reads = 0;
+    }
+
+    pub fn latch(&mut self, high: bool) {
+        self.latched = high;
+        if high {
+            self.reload();
+        }
+    }
+
+    // one clock of the serial line, returns the data bit
+    pub fn read_bit(&mut self) -> u8 {
+        if self.latched {
+            return (self.state.buttons >> 15) as u8;
+        }
+
+        if self.reads >= 16 {
+            return 1;
+        }
+
+        let bit = (self.shift >> 15) as u8;
+        self.shift <<= 1;
+        self.reads += 1;
+        bit
+    }
+}
+
+impl PortDevice for Joypad {
+    fn latch(&mut self, high: bool) {
+        Joypad::latch(self, high);
+    }
+
+    // a standard pad doesn't drive data line 2
+    fn clock(&mut self) -> (u8, u8) {
+        (self.read_bit(), 0)
+    }
+
+    fn set_input(&mut self, input: PortInput) {
+        match input {
+            PortInput::Joypad(state) => self.set_state(state),
+            PortInput::None => self.set_state(JoypadState::default()),
+            _ => {}
+        }
+    }
+
+    fn input(&self) -> PortInput {
+        PortInput::Joypad(self.state)
+    }
+
+    fn save_state(&self, w: &mut StateWriter) {
+        w.u16(self.state.buttons);
+        w.u16(self.shift);
+        w.u8(self.reads);
+        w.bool(self.latched);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), Error> {
+        self.state = JoypadState::new(r.u16()?);
+        self.shift = r.u16()?;
+        self.reads = r.u8()?;
+        self.latched = r.bool()?;
+        Ok(())
+    }
+}
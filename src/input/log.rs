@@ -0,0 +1,93 @@
+use std::fs;
+use std::io::{Error, ErrorKind};
+
+use input::{PortInput, ScriptedInput, PORT_COUNT};
+use input::joypad::*;
+
+// a button's letter in a log field, in the order the pad shifts them out
+const BUTTON_LETTERS: &'static [u8; 12] = b"BYsSUDLRAXlr";
+
+pub const SHIFT_ORDER: [u16; 12] = [
+    BUTTON_B, BUTTON_Y, BUTTON_SELECT, BUTTON_START, BUTTON_UP, BUTTON_DOWN,
+    BUTTON_LEFT, BUTTON_RIGHT, BUTTON_A, BUTTON_X, BUTTON_L, BUTTON_R,
+];
+
+// a plain text input log with one line per frame and one field per port:
+//   |BYsSUDLRAXlr|............|
+// a letter is a held button and '.' a released one. an empty field releases everything on that port.
+// lines starting with '#' are comments
+pub fn parse_log(text: &str) -> Result<Vec<[PortInput; PORT_COUNT]>, Error> {
+    let mut frames = Vec::new();
+
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let fields = split_fields(line);
+        if fields.len() > PORT_COUNT {
+            return Err(invalid_line(number, "too many ports"));
+        }
+
+        let mut frame = [PortInput::None; PORT_COUNT];
+        for (port, field) in fields.iter().enumerate() {
+            if !field.is_empty() {
+                frame[port] = PortInput::Joypad(parse_field(field).ok_or_else(|| invalid_line(number, "bad button field"))?);
+            }
+        }
+        frames.push(frame);
+    }
+
+    Ok(frames)
+}
+
+// the fields between a line's pipes. only the outer pair is stripped, so an empty first or
+// last field stays where it is rather than shifting the others over
+pub fn split_fields(line: &str) -> Vec<&str> {
+    let line = line.strip_prefix('|').unwrap_or(line);
+    let line = line.strip_suffix('|').unwrap_or(line);
+    line.split('|').collect()
+}
+
+pub fn load_log(path: &str) -> Result<ScriptedInput, Error> {
+    let text = fs::read_to_string(path)?;
+    Ok(ScriptedInput::new(parse_log(&text)?))
+}
+
+pub fn parse_field(field: &str) -> Option<JoypadState> {
+    parse_buttons(field, BUTTON_LETTERS, &SHIFT_ORDER)
+}
+
+pub fn format_field(state: JoypadState) -> String {
+    format_buttons(state, BUTTON_LETTERS, &SHIFT_ORDER)
+}
+
+// a pad field in any letter layout, eg the other emulators' input logs. order gives the
+// button for each letter
+pub fn parse_buttons(field: &str, letters: &[u8; 12], order: &[u16; 12]) -> Option<JoypadState> {
+    if field.len() != letters.len() {
+        return None;
+    }
+
+    let mut buttons = 0;
+    for (index, c) in field.bytes().enumerate() {
+        if c == letters[index] {
+            buttons |= order[index];
+        } else if c != b'.' {
+            return None;
+        }
+    }
+
+    Some(JoypadState::new(buttons))
+}
+
+pub fn format_buttons(state: JoypadState, letters: &[u8; 12], order: &[u16; 12]) -> String {
+    letters.iter().zip(order.iter())
+        .map(|(&letter, &button)| if state.pressed(button) { letter as char } else { '.' })
+        .collect()
+}
+
+fn invalid_line(number: usize, reason: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, format!("input log line {}: {}", number + 1, reason))
+}
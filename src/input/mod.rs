@@ -0,0 +1,367 @@
+pub mod device;
+pub mod joypad;
+pub mod log;
+pub mod mouse;
+pub mod multitap;
+pub mod super_scope;
+
+use std::io::{Error, ErrorKind};
+
+use input::device::PortDevice;
+use input::joypad::{Joypad, JoypadState};
+use input::mouse::{Mouse, MouseState};
+use input::multitap::Multitap;
+use input::super_scope::{SuperScope, SuperScopeState};
+use savestate::{StateReader, StateWriter};
+
+pub const PORT_COUNT: usize = 2;
+
+// the auto read takes about 3 scanlines from the start of vblank
+const AUTO_READ_MASTER_CYCLES: u64 = 4224;
+
+// what a port's device is being told to do for one frame. None releases everything
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub enum PortInput {
+    #[default]
+    None,
+    Joypad(JoypadState),
+    Multitap([JoypadState; 4]),
+    Mouse(MouseState),
+    SuperScope(SuperScopeState),
+}
+
+impl PortInput {
+    // one input with the same effect as setting self and then next. mouse motion adds up
+    // until the game reads it, everything else is simply replaced
+    pub fn then(self, next: PortInput) -> PortInput {
+        match (self, next) {
+            (PortInput::Mouse(first), PortInput::Mouse(second)) => PortInput::Mouse(MouseState {
+                dx: first.dx.saturating_add(second.dx),
+                dy: first.dy.saturating_add(second.dy),
+                ..second
+            }),
+            (PortInput::Mouse(first), PortInput::None) => PortInput::Mouse(MouseState {
+                dx: first.dx,
+                dy: first.dy,
+                ..MouseState::default()
+            }),
+            _ => next,
+        }
+    }
+
+    // what setting the input again would need to be to change nothing
+    fn held(self) -> PortInput {
+        match self {
+            PortInput::Mouse(state) => PortInput::Mouse(MouseState {
+                dx: 0,
+                dy: 0,
+                ..state
+            }),
+            _ => self,
+        }
+    }
+}
+
+impl From<JoypadState> for PortInput {
+    fn from(state: JoypadState) -> PortInput {
+        PortInput::Joypad(state)
+    }
+}
+
+// the kinds of device that can be plugged into a port
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum DeviceKind {
+    None,
+    Joypad,
+    Multitap,
+    Mouse,
+    SuperScope,
+}
+
+impl DeviceKind {
+    const ALL: [DeviceKind; 5] = [
+        DeviceKind::None,
+        DeviceKind::Joypad,
+        DeviceKind::Multitap,
+        DeviceKind::Mouse,
+        DeviceKind::SuperScope,
+    ];
+
+    pub fn id(self) -> u8 {
+        DeviceKind::ALL.iter().position(|&kind| kind == self).unwrap() as u8
+    }
+
+    pub fn from_id(id: u8) -> Option<DeviceKind> {
+        DeviceKind::ALL.get(id as usize).cloned()
+    }
+
+    // as written in movie files
+    pub fn name(self) -> &'static str {
+        match self {
+            DeviceKind::None => "none",
+            DeviceKind::Joypad => "joypad",
+            DeviceKind::Multitap => "multitap",
+            DeviceKind::Mouse => "mouse",
+            DeviceKind::SuperScope => "super-scope",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<DeviceKind> {
+        DeviceKind::ALL.iter().find(|kind| kind.name() == name).cloned()
+    }
+
+    pub fn create(self) -> Box<dyn PortDevice> {
+        match self {
+            DeviceKind::None => Box::new(device::Unplugged),
+            DeviceKind::Joypad => Box::new(Joypad::new()),
+            DeviceKind::Multitap => Box::new(Multitap::new()),
+            DeviceKind::Mouse => Box::new(Mouse::new()),
+            DeviceKind::SuperScope => Box::new(SuperScope::new()),
+        }
+    }
+}
+
+// where controller input comes from. it is asked for every port once per frame, right before
+// the auto read at the start of vblank, so frontends, movie playback and test scripts can
+// all drive the controllers the same way
+pub trait InputProvider {
+    fn poll(&mut self, frame: u64, port: usize) -> PortInput;
+}
+
+// a fixed list of per frame inputs, eg for scripted tests. frames past the end are released
+pub struct ScriptedInput {
+    frames: Vec<[PortInput; PORT_COUNT]>,
+}
+
+impl ScriptedInput {
+    pub fn new(frames: Vec<[PortInput; PORT_COUNT]>) -> ScriptedInput {
+        ScriptedInput {
+            frames,
+        }
+    }
+}
+
+impl InputProvider for ScriptedInput {
+    fn poll(&mut self, frame: u64, port: usize) -> PortInput {
+        self.frames.get(frame as usize)
+            .map(|inputs| inputs[port])
+            .unwrap_or_default()
+    }
+}
+
+// the controller ports and the cpu registers that talk to them
+// $4016 w: latch, $4016/$4017 r: serial data for port 1/2
+// $4200 w: bit 0 enables the auto read
+// $4201 w: bits 6/7 drive the IOBit pin of port 1/2
+// $4212 r: bit 0 is set while the auto read is running
+// $4218-$421F r: results of the auto read
+pub struct Input {
+    ports: [Box<dyn PortDevice>; PORT_COUNT],
+    kinds: [DeviceKind; PORT_COUNT],
+    provider: Option<Box<dyn InputProvider>>,
+
+    // everything set on each port since take_fed, for recording movies
+    fed: [Option<PortInput>; PORT_COUNT],
+
+    auto_read_enabled: bool,
+    auto_read_busy_until: u64,
+
+    // JOY1-JOY4. JOY3/JOY4 come from the second data line of port 1/2
+    joy: [u16; 4],
+}
+
+impl Input {
+    pub fn new() -> Input {
+        Input {
+            ports: [Box::new(Joypad::new()), Box::new(Joypad::new())],
+            kinds: [DeviceKind::Joypad, DeviceKind::Joypad],
+            provider: None,
+
+            fed: [None; PORT_COUNT],
+
+            auto_read_enabled: false,
+            auto_read_busy_until: 0,
+
+            joy: [0; 4],
+        }
+    }
+
+    // swaps the device in a port. a standard pad is plugged into both ports at power on
+    pub fn connect(&mut self, port: usize, kind: DeviceKind) {
+        self.ports[port] = kind.create();
+        self.kinds[port] = kind;
+    }
+
+    pub fn device_kind(&self, port: usize) -> DeviceKind {
+        self.kinds[port]
+    }
+
+    pub fn set_provider(&mut self, provider: Box<dyn InputProvider>) {
+        self.provider = Some(provider);
+    }
+
+    pub fn clear_provider(&mut self) {
+        self.provider = None;
+    }
+
+    // removes the provider and hands it back, eg to put it back later
+    pub fn take_provider(&mut self) -> Option<Box<dyn InputProvider>> {
+        self.provider.take()
+    }
+
+    // sets a port's input directly, for frontends that don't want to implement a provider
+    pub fn set_input(&mut self, port: usize, input: PortInput) {
+        self.ports[port].set_input(input);
+        self.fed[port] = Some(match self.fed[port] {
+            Some(fed) => fed.then(input),
+            None => input,
+        });
+    }
+
+    // what each port was given since the last call, as one input per port that would have
+    // the same effect. ports that weren't touched hold what they have
+    pub fn take_fed(&mut self) -> [PortInput; PORT_COUNT] {
+        let mut fed = [PortInput::None; PORT_COUNT];
+        for (port, input) in fed.iter_mut().enumerate() {
+            *input = self.fed[port].take().unwrap_or_else(|| self.ports[port].input().held());
+        }
+        fed
+    }
+
+    pub fn input(&self, port: usize) -> PortInput {
+        self.ports[port].input()
+    }
+
+    // shorthand for a standard pad
+    pub fn set_state(&mut self, port: usize, state: JoypadState) {
+        self.set_input(port, PortInput::Joypad(state));
+    }
+
+    // where the light gun in port 2 sees the screen, if there is one. only port 2's IOBit
+    // ($4201 bit 7) is wired to the ppu's counter latch, so a gun in port 1 can't latch
+    pub fn light_position(&self) -> Option<(u16, u16)> {
+        self.ports[1].light_position()
+    }
+
+    ////////////////////////////////////
+    //
+    //            REGISTERS
+    //
+    ////////////////////////////////////
+
+    pub fn write_latch(&mut self, data: u8) {
+        let high = data & 0x01 != 0;
+        for device in self.ports.iter_mut() {
+            device.latch(high);
+        }
+    }
+
+    // $4016 / $4017. bit 0 is data line 1, bit 1 is data line 2. $4017 has bits 2-4 tied high
+    pub fn read_serial(&mut self, port: usize) -> u8 {
+        let (d1, d2) = self.ports[port].clock();
+        let bits = d1 | (d2 << 1);
+        if port == 1 {
+            0x1C | bits
+        } else {
+            bits
+        }
+    }
+
+    // $4201 WRIO
+    pub fn write_wrio(&mut self, data: u8) {
+        self.ports[0].set_iobit(data & 0x40 != 0);
+        self.ports[1].set_iobit(data & 0x80 != 0);
+    }
+
+    pub fn write_nmitimen(&mut self, data: u8) {
+        self.auto_read_enabled = data & 0x01 != 0;
+    }
+
+    pub fn auto_read_busy(&self, master_cycles: u64) -> bool {
+        master_cycles < self.auto_read_busy_until
+    }
+
+    // $4218-$421F
+    pub fn read_joy(&self, address: u16) -> u8 {
+        let index = ((address - 0x4218) >> 1) as usize;
+        let joy = self.joy[index];
+
+        if address & 1 == 0 {
+            joy as u8
+        } else {
+            (joy >> 8) as u8
+        }
+    }
+
+    ////////////////////////////////////
+    //
+    //           SAVE STATES
+    //
+    ////////////////////////////////////
+
+    // the devices are plugged back in as they were. the provider belongs to the frontend
+    pub fn save_state(&self, w: &mut StateWriter) {
+        for port in 0..PORT_COUNT {
+            w.u8(self.kinds[port].id());
+            self.ports[port].save_state(w);
+        }
+
+        w.bool(self.auto_read_enabled);
+        w.u64(self.auto_read_busy_until);
+        for joy in self.joy.iter() {
+            w.u16(*joy);
+        }
+    }
+
+    pub fn load_state(&mut self, r: &mut StateReader) -> Result<(), Error> {
+        for port in 0..PORT_COUNT {
+            let kind = DeviceKind::from_id(r.u8()?)
+                .ok_or_else(|| Error::new(ErrorKind::InvalidData, "unknown controller port device"))?;
+            self.connect(port, kind);
+            self.ports[port].load_state(r)?;
+        }
+
+        self.auto_read_enabled = r.bool()?;
+        self.auto_read_busy_until = r.u64()?;
+        for joy in self.joy.iter_mut() {
+            *joy = r.u16()?;
+        }
+        Ok(())
+    }
+
+    ////////////////////////////////////
+    //
+    //             VBLANK
+    //
+    ////////////////////////////////////
+
+    // called at the start of vblank. polls the provider for this frame and runs the auto read
+    pub fn vblank(&mut self, frame: u64, master_cycles: u64) {
+        if let Some(ref mut provider) = self.provider {
+            let inputs: Vec<PortInput> = (0..PORT_COUNT).map(|port| provider.poll(frame, port)).collect();
+            for (port, input) in inputs.into_iter().enumerate() {
+                self.set_input(port, input);
+            }
+        }
+
+        if self.auto_read_enabled {
+            self.auto_read();
+            self.auto_read_busy_until = master_cycles + AUTO_READ_MASTER_CYCLES;
+        }
+    }
+
+    // the hardware strobes the latch and clocks 16 bits out of each port, the same as a manual read
+    fn auto_read(&mut self) {
+        self.write_latch(1);
+        self.write_latch(0);
+
+        self.joy = [0; 4];
+        for _ in 0..16 {
+            for port in 0..PORT_COUNT {
+                let (d1, d2) = self.ports[port].clock();
+                self.joy[port] = (self.joy[port] << 1) | d1 as u16;
+                self.joy[port + 2] = (self.joy[port + 2] << 1) | d2 as u16;
+            }
+        }
+    }
+}
@@ -0,0 +1,142 @@
+use std::io::Error;
+
+use input::PortInput;
+use input::device::PortDevice;
+use savestate::{StateReader, StateWriter};
+
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub struct MouseState {
+    pub dx: i16, // movement since the last frame, positive is right
+    pub dy: i16, // positive is down
+    pub left: bool,
+    pub right: bool,
+}
+
+// the SNES Mouse. it sends a 32 bit report:
+//   8 bits  00000000
+//   8 bits  R L ss 0001   buttons, sensitivity and the mouse's id
+//   8 bits  d yyyyyyy     vertical direction (1 = up) and magnitude
+//   8 bits  d xxxxxxx     horizontal direction (1 = left) and magnitude
+// clocking it while the latch is high cycles through the 3 sensitivity settings
+pub struct Mouse {
+    state: MouseState,
+    sensitivity: u8,
+    latched: bool,
+    report: u32,
+    reads: u8,
+}
+
+impl Mouse {
+    pub fn new() -> Mouse {
+        Mouse {
+            state: MouseState::default(),
+            sensitivity: 0,
+            latched: false,
+            report: 0,
+            reads: 32,
+        }
+    }
+
+    pub fn sensitivity(&self) -> u8 {
+        self.sensitivity
+    }
+
+    // higher sensitivities scale up the movement
+    fn scale(&self, delta: i16) -> u8 {
+        let magnitude = (delta as i32).abs();
+        let scaled = match self.sensitivity {
+            0 => magnitude,
+            1 => magnitude * 3 / 2,
+            _ => magnitude * 2,
+        };
+
+        let direction = if delta < 0 { 0x80 } else { 0x00 };
+        direction | (scaled.min(0x7F) as u8)
+    }
+
+    fn build_report(&mut self) {
+        let mut status = 0x01 | (self.sensitivity << 4);
+        if self.state.left {
+            status |= 0x40;
+        }
+        if self.state.right {
+            status |= 0x80;
+        }
+
+        let y = self.scale(self.state.dy) as u32;
+        let x = self.scale(self.state.dx) as u32;
+
+        self.report = ((status as u32) << 16) | (y << 8) | x;
+        self.reads = 0;
+
+        // movement is relative, so it is only reported once
+        self.state.dx = 0;
+        self.state.dy = 0;
+    }
+}
+
+impl PortDevice for Mouse {
+    // the report is taken when the latch is released, after any sensitivity changes
+    fn latch(&mut self, high: bool) {
+        if !high && self.latched {
+            self.build_report();
+        }
+        self.latched = high;
+    }
+
+    fn clock(&mut self) -> (u8, u8) {
+        if self.latched {
+            self.sensitivity = (self.sensitivity + 1) % 3;
+            return (0, 0);
+        }
+
+        if self.reads >= 32 {
+            return (1, 0);
+        }
+
+        let bit = ((self.report >> (31 - self.reads)) & 1) as u8;
+        self.reads += 1;
+        (bit, 0)
+    }
+
+    fn set_input(&mut self, input: PortInput) {
+        let state = match input {
+            PortInput::Mouse(state) => state,
+            PortInput::None => MouseState::default(),
+            _ => return,
+        };
+
+        // movement accumulates until the game reads it
+        self.state.dx = self.state.dx.saturating_add(state.dx);
+        self.state.dy = self.state.dy.saturating_add(state.dy);
+        self.state.left = state.left;
+        self.state.right = state.right;
+    }
+
+    fn input(&self) -> PortInput {
+        PortInput::Mouse(self.state)
+    }
+
+    fn save_state(&self, w: &mut StateWriter) {
+        w.u16(self.state.dx as u16);
+        w.u16(self.state.dy as u16);
+        w.bool(self.state.left);
+        w.bool(self.state.right);
+        w.u8(self.sensitivity);
+        w.bool(self.latched);
+        w.u32(self.report);
+        w.u8(self.reads);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), Error> {
+        self.state.dx = r.u16()? as i16;
+        self.state.dy = r.u16()? as i16;
+        self.state.left = r.bool()?;
+        self.state.right = r.bool()?;
+        self.sensitivity = r.u8()? % 3;
+        self.latched = r.bool()?;
+        self.report = r.u32()?;
+        self.reads = r.u8()?;
+        Ok(())
+    }
+}
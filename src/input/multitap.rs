@@ -0,0 +1,86 @@
+use std::io::Error;
+
+use input::PortInput;
+use input::device::PortDevice;
+use input::joypad::{Joypad, JoypadState};
+use savestate::{StateReader, StateWriter};
+
+// the 5 player adapter. it holds 4 pads and uses the port's IOBit to pick which
+// pair is connected to the two data lines: pads 1/2 while IOBit is high, 3/4 while it's low.
+// games detect it by reading data line 2 while the latch is high, which a multitap holds at 1
+pub struct Multitap {
+    pads: [Joypad; 4],
+    iobit: bool,
+    latched: bool,
+}
+
+impl Multitap {
+    pub fn new() -> Multitap {
+        Multitap {
+            pads: [Joypad::new(), Joypad::new(), Joypad::new(), Joypad::new()],
+            iobit: true,
+            latched: false,
+        }
+    }
+}
+
+impl PortDevice for Multitap {
+    fn latch(&mut self, high: bool) {
+        self.latched = high;
+        for pad in self.pads.iter_mut() {
+            pad.latch(high);
+        }
+    }
+
+    fn clock(&mut self) -> (u8, u8) {
+        if self.latched {
+            return (0, 1);
+        }
+
+        let (first, second) = if self.iobit { (0, 1) } else { (2, 3) };
+        let d1 = self.pads[first].read_bit();
+        let d2 = self.pads[second].read_bit();
+        (d1, d2)
+    }
+
+    fn set_iobit(&mut self, high: bool) {
+        self.iobit = high;
+    }
+
+    fn set_input(&mut self, input: PortInput) {
+        let states = match input {
+            PortInput::Multitap(states) => states,
+            PortInput::None => [JoypadState::default(); 4],
+            _ => return,
+        };
+
+        for (pad, state) in self.pads.iter_mut().zip(states.iter()) {
+            pad.set_state(*state);
+        }
+    }
+
+    fn input(&self) -> PortInput {
+        let mut states = [JoypadState::default(); 4];
+        for (state, pad) in states.iter_mut().zip(self.pads.iter()) {
+            *state = pad.state();
+        }
+        PortInput::Multitap(states)
+    }
+
+    fn save_state(&self, w: &mut StateWriter) {
+        for pad in self.pads.iter() {
+            pad.save_state(w);
+        }
+        w.bool(self.iobit);
+        w.bool(self.latched);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), Error> {
+        for pad in self.pads.iter_mut() {
+            pad.load_state(r)?;
+        }
+        self.iobit = r.bool()?;
+        self.latched = r.bool()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,139 @@
+use std::io::Error;
+
+use input::PortInput;
+use input::device::PortDevice;
+use savestate::{StateReader, StateWriter};
+
+const SCREEN_WIDTH: i16 = 256;
+const SCREEN_HEIGHT: i16 = 239;
+
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub struct SuperScopeState {
+    pub x: i16, // where the scope is aimed, in dots / lines. off screen values are allowed
+    pub y: i16,
+    pub fire: bool,
+    pub cursor: bool,
+    pub turbo: bool, // the turbo switch, not a button
+    pub pause: bool,
+}
+
+// the Super Scope light gun, used in port 2. its 16 bit report is
+//   F C T P 00 O 0   fire, cursor, turbo, pause, off screen
+//   11111111         id
+// the light sensor pulls IOBit low when the beam passes the aim point, which latches
+// the PPU's H/V counters so the game can read the position from $213C/$213D
+pub struct SuperScope {
+    state: SuperScopeState,
+    prev_fire: bool,
+    latched: bool,
+    report: u16,
+    reads: u8,
+}
+
+impl SuperScope {
+    pub fn new() -> SuperScope {
+        SuperScope {
+            state: SuperScopeState::default(),
+            prev_fire: false,
+            latched: false,
+            report: 0,
+            reads: 16,
+        }
+    }
+
+    fn on_screen(&self) -> bool {
+        self.state.x >= 0 && self.state.x < SCREEN_WIDTH && self.state.y >= 0 && self.state.y < SCREEN_HEIGHT
+    }
+
+    fn build_report(&mut self) {
+        let mut report = 0x00FF;
+
+        // with turbo off, fire only registers in the first report after the trigger is pulled
+        let fire = self.state.fire && (self.state.turbo || !self.prev_fire);
+        self.prev_fire = self.state.fire;
+        if fire {
+            report |= 0x8000;
+        }
+        if self.state.cursor {
+            report |= 0x4000;
+        }
+        if self.state.turbo {
+            report |= 0x2000;
+        }
+        if self.state.pause {
+            report |= 0x1000;
+        }
+        if !self.on_screen() {
+            report |= 0x0200;
+        }
+
+        self.report = report;
+        self.reads = 0;
+    }
+}
+
+impl PortDevice for SuperScope {
+    fn latch(&mut self, high: bool) {
+        if high && !self.latched {
+            self.build_report();
+        }
+        self.latched = high;
+    }
+
+    fn clock(&mut self) -> (u8, u8) {
+        if self.latched || self.reads >= 16 {
+            return (1, 0);
+        }
+
+        let bit = ((self.report >> (15 - self.reads)) & 1) as u8;
+        self.reads += 1;
+        (bit, 0)
+    }
+
+    fn set_input(&mut self, input: PortInput) {
+        match input {
+            PortInput::SuperScope(state) => self.state = state,
+            PortInput::None => self.state = SuperScopeState { x: -1, y: -1, ..SuperScopeState::default() },
+            _ => {}
+        }
+    }
+
+    fn input(&self) -> PortInput {
+        PortInput::SuperScope(self.state)
+    }
+
+    fn save_state(&self, w: &mut StateWriter) {
+        w.u16(self.state.x as u16);
+        w.u16(self.state.y as u16);
+        w.bool(self.state.fire);
+        w.bool(self.state.cursor);
+        w.bool(self.state.turbo);
+        w.bool(self.state.pause);
+        w.bool(self.prev_fire);
+        w.bool(self.latched);
+        w.u16(self.report);
+        w.u8(self.reads);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), Error> {
+        self.state.x = r.u16()? as i16;
+        self.state.y = r.u16()? as i16;
+        self.state.fire = r.bool()?;
+        self.state.cursor = r.bool()?;
+        self.state.turbo = r.bool()?;
+        self.state.pause = r.bool()?;
+        self.prev_fire = r.bool()?;
+        self.latched = r.bool()?;
+        self.report = r.u16()?;
+        self.reads = r.u8()?;
+        Ok(())
+    }
+
+    fn light_position(&self) -> Option<(u16, u16)> {
+        if self.on_screen() {
+            Some((self.state.x as u16, self.state.y as u16))
+        } else {
+            None
+        }
+    }
+}
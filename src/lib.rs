@@ -0,0 +1,34 @@
+// the emulator as a library. the snes-run binary in main.rs is just one consumer of it,
+// debuggers, bots and test harnesses are expected to build on Emulator the same way
+// the lints below argue with how the tree is written: consts spell out 'static, new() is the
+// constructor rather than Default, and cpu::cpu and ppu::ppu are named for what they hold.
+// per channel and per voice loops read better indexed, and % keeps working on older compilers
+#![allow(clippy::redundant_static_lifetimes)]
+#![allow(clippy::new_without_default)]
+#![allow(clippy::module_inception)]
+#![allow(clippy::needless_range_loop)]
+#![allow(clippy::manual_is_multiple_of)]
+
+pub mod apu;
+pub mod bus;
+pub mod cartridge;
+pub mod cdl;
+pub mod cheat;
+pub mod cpu;
+pub mod debugger;
+pub mod dma;
+pub mod emulator;
+pub mod input;
+pub mod movie;
+pub mod patch;
+pub mod ppu;
+pub mod rewind;
+pub mod runner;
+pub mod savestate;
+pub mod scheduler;
+pub mod search;
+pub mod suite;
+pub mod symbols;
+pub mod util;
+
+pub use emulator::Emulator;
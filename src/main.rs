@@ -1,42 +1,51 @@
-mod cpu;
+extern crate my_snes_is_rusty;
 
-use std::fs;
-use std::io::{Read, Error};
+use std::env;
+use std::process;
 
-use cpu::cpu::CPU;
-use cpu::memory::{Mem, SimpleMemory};
-use std::sync::{Arc, RwLock};
+use my_snes_is_rusty::apu::spc_file;
+use my_snes_is_rusty::runner;
+use my_snes_is_rusty::runner::Options;
 
-fn main() -> Result<(), Error> {
-    // todo -> read LinkToThePast
-    // todo -> chunk into groups of 2 chars
-    // todo -> parse the two chars as hex byte
-    // at this point we have Vec<u8> of size ~256k
-    // todo -> chop first 512 elements (header)
-    // construct CPU
-    // todo -> pass the chopped Vec to CPU
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
 
-    // mem needs to be thread-safe / exist outside of the scope of the CPU so the APU and PPU can use it
-    let mut mem: Arc<RwLock<Mem>> = Arc::new(RwLock::new(SimpleMemory::new()));
+    // snes-run <song.spc> <out.wav> [seconds]
+    if args.len() >= 2 && args[0].ends_with(".spc") {
+        let seconds = args.get(2).and_then(|s| s.parse().ok());
+        if let Err(e) = spc_file::render_to_wav(&args[0], &args[1], seconds) {
+            eprintln!("{}", e);
+            process::exit(runner::EXIT_ERROR);
+        }
+        return;
+    }
 
-    // inject mem here
-    let mut cpu = CPU::new(mem.clone());
+    // snes-run --suite <dir>
+    if args.len() == 2 && args[0] == "--suite" {
+        match runner::run_suite(&args[1]) {
+            Ok(true) => process::exit(runner::EXIT_OK),
+            Ok(false) => process::exit(runner::EXIT_ERROR),
+            Err(e) => {
+                eprintln!("{}", e);
+                process::exit(runner::EXIT_ERROR);
+            },
+        }
+    }
 
-    let rom = load_rom("LinkToThePast")?;
-    cpu.run(rom.into_iter().skip(512).collect());
+    let options = match Options::parse(&args) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("{}\n{}", e, runner::USAGE);
+            process::exit(runner::EXIT_USAGE);
+        },
+    };
 
-    Ok(())
-}
-
-type Rom = Vec<u8>;
-
-fn load_rom(rom_name: &str) -> Result<Rom, Error> {
-    let file_path = format!("/Users/matthewrusso/rust/my_snes_is_rusty/data/{}.smc", rom_name);
-
-    let f = fs::File::open(file_path)?;
-    let bytes: Vec<u8> = f.bytes().map(|c| c.unwrap()).collect();
+    if let Err(e) = runner::run(&options) {
+        eprintln!("{}", e);
+        process::exit(runner::EXIT_ERROR);
+    }
 
-    Ok(bytes)
+    process::exit(runner::EXIT_OK);
 }
 
 // old -> reads chars of hexdump
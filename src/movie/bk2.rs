@@ -0,0 +1,126 @@
+use std::io::{Error, ErrorKind};
+
+use input::{DeviceKind, PortInput, PORT_COUNT};
+use input::joypad::*;
+use input::log::{format_buttons, parse_buttons, split_fields};
+use movie::{Movie, MovieStart};
+
+// the input log inside a BizHawk .bk2 (Input Log.txt). one line per frame, the console's
+// reset/power buttons first and then a field per pad:
+//   [Input]
+//   LogKey:#Reset|Power|#P1 Up|P1 Down|...
+//   |..|UDLRsSYBXAlr|UDLRsSYBXAlr|
+//   [/Input]
+// only standard pads are supported and the console buttons are ignored
+const LETTERS: &'static [u8; 12] = b"UDLRsSYBXAlr";
+
+const ORDER: [u16; 12] = [
+    BUTTON_UP, BUTTON_DOWN, BUTTON_LEFT, BUTTON_RIGHT, BUTTON_SELECT, BUTTON_START,
+    BUTTON_Y, BUTTON_B, BUTTON_X, BUTTON_A, BUTTON_L, BUTTON_R,
+];
+
+const NAMES: [&'static str; 12] = ["Up", "Down", "Left", "Right", "Select", "Start", "Y", "B", "X", "A", "L", "R"];
+
+// a movie from power on with a pad in each port
+pub fn import(text: &str, rom_crc32: u32) -> Result<Movie, Error> {
+    let mut movie = Movie::new(rom_crc32, [DeviceKind::Joypad; PORT_COUNT], MovieStart::PowerOn);
+
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if !line.starts_with('|') {
+            continue;
+        }
+
+        let fields: Vec<&str> = split_fields(line).into_iter().skip(1).collect();
+        if fields.len() > PORT_COUNT {
+            return Err(invalid_line(number, "more pads than ports"));
+        }
+
+        let mut frame = [PortInput::None; PORT_COUNT];
+        for (port, field) in fields.iter().enumerate() {
+            let state = parse_buttons(field, LETTERS, &ORDER).ok_or_else(|| invalid_line(number, "bad pad field"))?;
+            frame[port] = PortInput::Joypad(state);
+        }
+        movie.frames.push(frame);
+    }
+
+    Ok(movie)
+}
+
+pub fn export(movie: &Movie) -> Result<String, Error> {
+    check_exportable(movie)?;
+
+    let mut key = String::from("LogKey:#Reset|Power|");
+    for port in 0..PORT_COUNT {
+        let names: Vec<String> = NAMES.iter().map(|name| format!("P{} {}", port + 1, name)).collect();
+        key += &format!("#{}|", names.join("|"));
+    }
+
+    let mut text = format!("[Input]\n{}\n", key);
+    for frame in movie.frames.iter() {
+        text += "|..|";
+        for input in frame.iter() {
+            let state = match *input {
+                PortInput::Joypad(state) => state,
+                _ => JoypadState::default(),
+            };
+            text += &format_buttons(state, LETTERS, &ORDER);
+            text += "|";
+        }
+        text += "\n";
+    }
+    text += "[/Input]\n";
+
+    Ok(text)
+}
+
+// the other emulators' logs only have pads and start from power on
+pub fn check_exportable(movie: &Movie) -> Result<(), Error> {
+    if movie.start != MovieStart::PowerOn {
+        return Err(Error::new(ErrorKind::InvalidInput, "only movies that start from power on can be exported"));
+    }
+    if movie.ports.iter().any(|&kind| kind != DeviceKind::Joypad) {
+        return Err(Error::new(ErrorKind::InvalidInput, "only movies with a pad in each port can be exported"));
+    }
+    Ok(())
+}
+
+fn invalid_line(number: usize, reason: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, format!("bk2 input log line {}: {}", number + 1, reason))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn import_export() {
+        let text = "[Input]\n\
+                    LogKey:#Reset|Power|#P1 Up|...\n\
+                    |..|U....S.B.A.r|............|\n\
+                    |..|..L.s.Y.X.l.|\n\
+                    [/Input]\n";
+        let movie = import(text, 0x1234).unwrap();
+        assert_eq!(movie.rom_crc32, 0x1234);
+        assert_eq!(movie.frames, vec![
+            [PortInput::Joypad(JoypadState::new(BUTTON_UP | BUTTON_START | BUTTON_B | BUTTON_A | BUTTON_R)), PortInput::Joypad(JoypadState::default())],
+            [PortInput::Joypad(JoypadState::new(BUTTON_LEFT | BUTTON_SELECT | BUTTON_Y | BUTTON_X | BUTTON_L)), PortInput::None],
+        ]);
+
+        let exported = export(&movie).unwrap();
+        assert!(exported.starts_with("[Input]\nLogKey:#Reset|Power|#P1 Up|P1 Down|P1 Left|P1 Right|P1 Select|P1 Start|P1 Y|P1 B|P1 X|P1 A|P1 L|P1 R|#P2 Up|"));
+        assert!(exported.ends_with("|..|U....S.B.A.r|............|\n|..|..L.s.Y.X.l.|............|\n[/Input]\n"));
+        assert_eq!(import(&exported, 0x1234).unwrap().frames[0], movie.frames[0]);
+    }
+
+    #[test]
+    fn bad_logs() {
+        assert!(import("|..|D...........|\n", 0).is_err());
+        assert!(import("|..|............|............|............|\n", 0).is_err());
+
+        let from_state = Movie::new(0, [DeviceKind::Joypad; PORT_COUNT], MovieStart::SaveState(vec![0]));
+        assert!(export(&from_state).is_err());
+        let mouse = Movie::new(0, [DeviceKind::Joypad, DeviceKind::Mouse], MovieStart::PowerOn);
+        assert!(export(&mouse).is_err());
+    }
+}
@@ -0,0 +1,103 @@
+use std::io::{Error, ErrorKind};
+
+use input::{DeviceKind, PortInput, PORT_COUNT};
+use input::joypad::*;
+use input::log::{format_buttons, parse_buttons, SHIFT_ORDER};
+use movie::bk2::check_exportable;
+use movie::{Movie, MovieStart};
+
+// the input member of an lsnes .lsmv. a line starting with 'F' begins a frame and any line
+// after it without one is a subframe, which is dropped since movies hold one input per frame.
+// the first field is the console's: the frame flag, the reset button and the two halves of the
+// reset delay. then there's a field per pad:
+//   F. 0 0|BYsSudlrAXLR|............
+// movies hold no resets, so one that presses reset isn't imported and exports never do
+const SYSTEM_FIELD: &'static str = "F. 0 0";
+
+const LETTERS: &'static [u8; 12] = b"BYsSudlrAXLR";
+
+// a movie from power on with a pad in each port
+pub fn import(text: &str, rom_crc32: u32) -> Result<Movie, Error> {
+    let mut movie = Movie::new(rom_crc32, [DeviceKind::Joypad; PORT_COUNT], MovieStart::PowerOn);
+
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if !line.starts_with('F') {
+            continue;
+        }
+
+        let mut fields: Vec<&str> = line.split('|').collect();
+        if fields.remove(0).as_bytes().get(1) == Some(&b'R') {
+            return Err(invalid_line(number, "resets aren't supported"));
+        }
+        if fields.len() > PORT_COUNT {
+            return Err(invalid_line(number, "more pads than ports"));
+        }
+
+        let mut frame = [PortInput::None; PORT_COUNT];
+        for (port, field) in fields.iter().enumerate() {
+            let state = parse_buttons(field, LETTERS, &SHIFT_ORDER).ok_or_else(|| invalid_line(number, "bad pad field"))?;
+            frame[port] = PortInput::Joypad(state);
+        }
+        movie.frames.push(frame);
+    }
+
+    Ok(movie)
+}
+
+pub fn export(movie: &Movie) -> Result<String, Error> {
+    check_exportable(movie)?;
+
+    let mut text = String::new();
+    for frame in movie.frames.iter() {
+        text += SYSTEM_FIELD;
+        for input in frame.iter() {
+            let state = match *input {
+                PortInput::Joypad(state) => state,
+                _ => JoypadState::default(),
+            };
+            text += "|";
+            text += &format_buttons(state, LETTERS, &SHIFT_ORDER);
+        }
+        text += "\n";
+    }
+
+    Ok(text)
+}
+
+fn invalid_line(number: usize, reason: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, format!("lsmv input line {}: {}", number + 1, reason))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn import_export() {
+        let text = "F. 0 0|B..S.d..A..R|............\n\
+                    . 0 0|BYsSudlrAXLR|BYsSudlrAXLR\n\
+                    F|.Y.....r.XL.\n";
+        let movie = import(text, 0x1234).unwrap();
+        assert_eq!(movie.rom_crc32, 0x1234);
+        assert_eq!(movie.frames, vec![
+            [PortInput::Joypad(JoypadState::new(BUTTON_B | BUTTON_START | BUTTON_DOWN | BUTTON_A | BUTTON_R)), PortInput::Joypad(JoypadState::default())],
+            [PortInput::Joypad(JoypadState::new(BUTTON_Y | BUTTON_RIGHT | BUTTON_X | BUTTON_L)), PortInput::None],
+        ]);
+
+        let exported = export(&movie).unwrap();
+        assert_eq!(exported, "F. 0 0|B..S.d..A..R|............\nF. 0 0|.Y.....r.XL.|............\n");
+        assert_eq!(import(&exported, 0x1234).unwrap().frames[0], movie.frames[0]);
+    }
+
+    #[test]
+    fn bad_inputs() {
+        let error = import("F. 0 0|............\nFR 0 0|............\n", 0).unwrap_err();
+        assert_eq!(error.to_string(), "lsmv input line 2: resets aren't supported");
+        assert!(import("F|Y...........\n", 0).is_err());
+        assert!(import("F|............|............|............\n", 0).is_err());
+
+        let from_state = Movie::new(0, [DeviceKind::Joypad; PORT_COUNT], MovieStart::SaveState(vec![0]));
+        assert!(export(&from_state).is_err());
+    }
+}
@@ -0,0 +1,496 @@
+pub mod bk2;
+pub mod lsmv;
+
+use std::fs;
+use std::io::{Error, ErrorKind};
+
+use input::{DeviceKind, InputProvider, PortInput, PORT_COUNT};
+use input::joypad::JoypadState;
+use input::log;
+use input::mouse::MouseState;
+use input::super_scope::SuperScopeState;
+use util::crc32::crc32;
+
+// movie layout
+// ============
+// a text file. the header is one "key value" pair per line:
+//   snes-movie 1             format version, always first
+//   emulator 0.1.0           version of the emulator that recorded it
+//   rom 1a2b3c4d             crc32 of the rom
+//   ports joypad joypad      what was plugged into each port
+//   start power-on           or "start state" with the save state in "state" lines as hex
+//   checkpoint 60 89abcdef   crc32 of WRAM after that many frames
+// then an "input" line, followed by one line per frame with one field per port:
+//   joypad       BYsSUDLRAXlr, the same as an input log
+//   multitap     4 joypad fields separated by ','
+//   mouse        dx,dy,LR
+//   super scope  x,y,FCTP (fire, cursor, turbo, pause)
+// a '.' is a released button and an empty field releases the whole port
+const SIGNATURE: &'static str = "snes-movie";
+
+pub const FORMAT_VERSION: u32 = 1;
+
+// how often a recording stores a hash of WRAM to catch desyncs on playback
+pub const CHECKPOINT_INTERVAL: u64 = 60;
+
+// state lines hold this many bytes each
+const STATE_LINE_BYTES: usize = 64;
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum MovieStart {
+    PowerOn,
+    SaveState(Vec<u8>),
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct Movie {
+    pub emulator_version: String,
+    pub rom_crc32: u32,
+    pub ports: [DeviceKind; PORT_COUNT],
+    pub start: MovieStart,
+    pub checkpoints: Vec<(u64, u32)>,
+    pub frames: Vec<[PortInput; PORT_COUNT]>,
+}
+
+impl Movie {
+    pub fn new(rom_crc32: u32, ports: [DeviceKind; PORT_COUNT], start: MovieStart) -> Movie {
+        Movie {
+            emulator_version: env!("CARGO_PKG_VERSION").to_string(),
+            rom_crc32,
+            ports,
+            start,
+            checkpoints: Vec::new(),
+            frames: Vec::new(),
+        }
+    }
+
+    pub fn load(path: &str) -> Result<Movie, Error> {
+        Self::parse(&fs::read_to_string(path)?)
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), Error> {
+        fs::write(path, self.to_text())
+    }
+
+    // the checkpoint for a frame, if one was taken
+    pub fn checkpoint(&self, frame: u64) -> Option<u32> {
+        self.checkpoints.iter().find(|&&(at, _)| at == frame).map(|&(_, crc)| crc)
+    }
+
+    pub fn parse(text: &str) -> Result<Movie, Error> {
+        let mut lines = text.lines().enumerate()
+            .map(|(number, line)| (number, line.trim()))
+            .filter(|&(_, line)| !line.is_empty());
+
+        match lines.next() {
+            Some((_, line)) if line.starts_with(SIGNATURE) => {
+                let version: u32 = line[SIGNATURE.len()..].trim().parse().unwrap_or(0);
+                if version == 0 || version > FORMAT_VERSION {
+                    return Err(Error::new(ErrorKind::InvalidData, format!("unsupported movie version: {}", version)));
+                }
+            },
+            _ => return Err(Error::new(ErrorKind::InvalidData, "not a movie")),
+        }
+
+        let mut movie = Movie::new(0, [DeviceKind::Joypad; PORT_COUNT], MovieStart::PowerOn);
+        movie.emulator_version = String::new();
+        let mut state = Vec::new();
+        let mut from_state = false;
+
+        for (number, line) in lines.by_ref() {
+            let (key, value) = match line.find(' ') {
+                Some(space) => (&line[..space], line[space + 1..].trim()),
+                None => (line, ""),
+            };
+
+            match key {
+                "input" => break,
+                "emulator" => movie.emulator_version = value.to_string(),
+                "rom" => movie.rom_crc32 = parse_hex(value).ok_or_else(|| invalid_line(number, "bad rom crc32"))?,
+                "ports" => {
+                    let kinds: Vec<Option<DeviceKind>> = value.split_whitespace().map(DeviceKind::from_name).collect();
+                    if kinds.len() != PORT_COUNT || kinds.iter().any(|kind| kind.is_none()) {
+                        return Err(invalid_line(number, "bad port list"));
+                    }
+                    for (port, kind) in kinds.into_iter().enumerate() {
+                        movie.ports[port] = kind.unwrap();
+                    }
+                },
+                "start" => match value {
+                    "power-on" => from_state = false,
+                    "state" => from_state = true,
+                    _ => return Err(invalid_line(number, "unknown start")),
+                },
+                "checkpoint" => {
+                    let mut parts = value.split_whitespace();
+                    let frame = parts.next().and_then(|frame| frame.parse().ok());
+                    let crc = parts.next().and_then(parse_hex);
+                    match (frame, crc) {
+                        (Some(frame), Some(crc)) => movie.checkpoints.push((frame, crc)),
+                        _ => return Err(invalid_line(number, "bad checkpoint")),
+                    }
+                },
+                "state" => {
+                    if value.len() % 2 != 0 || !value.is_ascii() {
+                        return Err(invalid_line(number, "bad state data"));
+                    }
+                    for i in (0..value.len()).step_by(2) {
+                        let byte = u8::from_str_radix(&value[i..i + 2], 16).map_err(|_| invalid_line(number, "bad state data"))?;
+                        state.push(byte);
+                    }
+                },
+                // newer versions can add header lines
+                _ => {},
+            }
+        }
+
+        if from_state {
+            if state.is_empty() {
+                return Err(Error::new(ErrorKind::InvalidData, "movie starts from a save state but doesn't contain one"));
+            }
+            movie.start = MovieStart::SaveState(state);
+        }
+
+        for (number, line) in lines {
+            let fields = log::split_fields(line);
+            if fields.len() > PORT_COUNT {
+                return Err(invalid_line(number, "too many ports"));
+            }
+
+            let mut frame = [PortInput::None; PORT_COUNT];
+            for (port, field) in fields.iter().enumerate() {
+                frame[port] = parse_input(movie.ports[port], field).ok_or_else(|| invalid_line(number, "bad input field"))?;
+            }
+            movie.frames.push(frame);
+        }
+
+        Ok(movie)
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut text = String::new();
+
+        text += &format!("{} {}\n", SIGNATURE, FORMAT_VERSION);
+        text += &format!("emulator {}\n", self.emulator_version);
+        text += &format!("rom {:08x}\n", self.rom_crc32);
+        text += &format!("ports {}\n", self.ports.iter().map(|kind| kind.name()).collect::<Vec<_>>().join(" "));
+
+        match self.start {
+            MovieStart::PowerOn => text += "start power-on\n",
+            MovieStart::SaveState(_) => text += "start state\n",
+        }
+
+        for &(frame, crc) in self.checkpoints.iter() {
+            text += &format!("checkpoint {} {:08x}\n", frame, crc);
+        }
+
+        if let MovieStart::SaveState(ref state) = self.start {
+            for chunk in state.chunks(STATE_LINE_BYTES) {
+                let hex: String = chunk.iter().map(|byte| format!("{:02x}", byte)).collect();
+                text += &format!("state {}\n", hex);
+            }
+        }
+
+        text += "input\n";
+        for frame in self.frames.iter() {
+            for input in frame.iter() {
+                text += "|";
+                text += &format_input(input);
+            }
+            text += "|\n";
+        }
+
+        text
+    }
+}
+
+////////////////////////////////////
+//
+//             SESSIONS
+//
+////////////////////////////////////
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum MovieMode {
+    Recording,
+    Playing,
+}
+
+// a movie being recorded or played back by the emulator
+pub struct MovieSession {
+    movie: Movie,
+    mode: MovieMode,
+    start_frame: u64,
+    desync: Option<u64>,
+
+    // the frontend's provider, put back when the session ends
+    saved_provider: Option<Box<dyn InputProvider>>,
+}
+
+impl MovieSession {
+    pub fn new(movie: Movie, mode: MovieMode, start_frame: u64, saved_provider: Option<Box<dyn InputProvider>>) -> MovieSession {
+        MovieSession {
+            movie,
+            mode,
+            start_frame,
+            desync: None,
+            saved_provider,
+        }
+    }
+
+    pub fn movie(&self) -> &Movie {
+        &self.movie
+    }
+
+    pub fn mode(&self) -> MovieMode {
+        self.mode
+    }
+
+    // frames since the movie started, at the given emulator frame
+    pub fn elapsed(&self, frame: u64) -> u64 {
+        frame.saturating_sub(self.start_frame)
+    }
+
+    // the first checkpoint that didn't match on playback
+    pub fn desync(&self) -> Option<u64> {
+        self.desync
+    }
+
+    // what to set the ports to before running the given frame, while playing
+    pub fn inputs(&self, frame: u64) -> Option<[PortInput; PORT_COUNT]> {
+        match self.mode {
+            MovieMode::Playing => self.movie.frames.get(self.elapsed(frame) as usize).cloned(),
+            MovieMode::Recording => None,
+        }
+    }
+
+    pub fn finished(&self, frame: u64) -> bool {
+        self.mode == MovieMode::Playing && self.elapsed(frame) >= self.movie.frames.len() as u64
+    }
+
+    // called at the end of every frame with what the ports saw and the state of WRAM
+    pub fn end_frame(&mut self, frame: u64, inputs: [PortInput; PORT_COUNT], wram: &[u8]) {
+        let elapsed = self.elapsed(frame);
+
+        match self.mode {
+            MovieMode::Recording => {
+                self.movie.frames.push(inputs);
+                if elapsed % CHECKPOINT_INTERVAL == 0 {
+                    self.movie.checkpoints.push((elapsed, crc32(wram)));
+                }
+            },
+            MovieMode::Playing => {
+                if self.desync.is_none() {
+                    if let Some(expected) = self.movie.checkpoint(elapsed) {
+                        if crc32(wram) != expected {
+                            self.desync = Some(elapsed);
+                        }
+                    }
+                }
+            },
+        }
+    }
+
+    // the emulator went back to an earlier frame, eg by rewinding. a recording picks up from there
+    pub fn rewound_to(&mut self, frame: u64) {
+        let elapsed = self.elapsed(frame);
+
+        if self.mode == MovieMode::Recording {
+            self.movie.frames.truncate(elapsed as usize);
+            self.movie.checkpoints.retain(|&(at, _)| at <= elapsed);
+        } else if self.desync.is_some_and(|desync| desync > elapsed) {
+            self.desync = None;
+        }
+    }
+
+    pub fn finish(self) -> (Movie, Option<Box<dyn InputProvider>>) {
+        (self.movie, self.saved_provider)
+    }
+}
+
+////////////////////////////////////
+//
+//              FIELDS
+//
+////////////////////////////////////
+
+fn format_input(input: &PortInput) -> String {
+    match *input {
+        PortInput::None => String::new(),
+        PortInput::Joypad(state) => log::format_field(state),
+        PortInput::Multitap(ref pads) => pads.iter().map(|&pad| log::format_field(pad)).collect::<Vec<_>>().join(","),
+        PortInput::Mouse(ref mouse) => format!("{},{},{}{}", mouse.dx, mouse.dy, flag(mouse.left, 'L'), flag(mouse.right, 'R')),
+        PortInput::SuperScope(ref scope) => format!("{},{},{}{}{}{}", scope.x, scope.y,
+            flag(scope.fire, 'F'), flag(scope.cursor, 'C'), flag(scope.turbo, 'T'), flag(scope.pause, 'P')),
+    }
+}
+
+fn parse_input(kind: DeviceKind, field: &str) -> Option<PortInput> {
+    if field.is_empty() {
+        return Some(PortInput::None);
+    }
+
+    let parts: Vec<&str> = field.split(',').collect();
+    match kind {
+        DeviceKind::None => None,
+        DeviceKind::Joypad => log::parse_field(field).map(PortInput::Joypad),
+        DeviceKind::Multitap => {
+            if parts.len() != 4 {
+                return None;
+            }
+            let mut pads = [JoypadState::default(); 4];
+            for (pad, part) in pads.iter_mut().zip(parts.iter()) {
+                *pad = log::parse_field(part)?;
+            }
+            Some(PortInput::Multitap(pads))
+        },
+        DeviceKind::Mouse => {
+            if parts.len() != 3 {
+                return None;
+            }
+            let buttons = parse_flags(parts[2], b"LR")?;
+            Some(PortInput::Mouse(MouseState {
+                dx: parts[0].parse().ok()?,
+                dy: parts[1].parse().ok()?,
+                left: buttons[0],
+                right: buttons[1],
+            }))
+        },
+        DeviceKind::SuperScope => {
+            if parts.len() != 3 {
+                return None;
+            }
+            let buttons = parse_flags(parts[2], b"FCTP")?;
+            Some(PortInput::SuperScope(SuperScopeState {
+                x: parts[0].parse().ok()?,
+                y: parts[1].parse().ok()?,
+                fire: buttons[0],
+                cursor: buttons[1],
+                turbo: buttons[2],
+                pause: buttons[3],
+            }))
+        },
+    }
+}
+
+fn flag(set: bool, letter: char) -> char {
+    if set { letter } else { '.' }
+}
+
+fn parse_flags(field: &str, letters: &[u8]) -> Option<Vec<bool>> {
+    if field.len() != letters.len() {
+        return None;
+    }
+
+    field.bytes().zip(letters.iter())
+        .map(|(c, &letter)| match c {
+            _ if c == letter => Some(true),
+            b'.' => Some(false),
+            _ => None,
+        })
+        .collect()
+}
+
+fn parse_hex(value: &str) -> Option<u32> {
+    u32::from_str_radix(value, 16).ok()
+}
+
+fn invalid_line(number: usize, reason: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, format!("movie line {}: {}", number + 1, reason))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use input::joypad::*;
+
+    fn pad(buttons: u16) -> PortInput {
+        PortInput::Joypad(JoypadState::new(buttons))
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut movie = Movie::new(0x1a2b3c4d, [DeviceKind::Multitap, DeviceKind::Mouse], MovieStart::SaveState((0..150).collect()));
+        movie.checkpoints = vec![(0, 0x89abcdef), (60, 0x01234567)];
+        movie.frames.push([
+            PortInput::Multitap([JoypadState::new(BUTTON_B | BUTTON_START), JoypadState::default(), JoypadState::new(BUTTON_R), JoypadState::default()]),
+            PortInput::Mouse(MouseState { dx: -3, dy: 12, left: true, right: false }),
+        ]);
+        movie.frames.push([PortInput::None, PortInput::None]);
+
+        let text = movie.to_text();
+        assert!(text.starts_with("snes-movie 1\n"));
+        assert!(text.contains("rom 1a2b3c4d\nports multitap mouse\nstart state\ncheckpoint 0 89abcdef\ncheckpoint 60 01234567\n"));
+        assert_eq!(text.lines().filter(|line| line.starts_with("state ")).count(), 3);
+        assert!(text.ends_with("input\n|B..S........,............,...........r,............|-3,12,L.|\n|||\n"));
+        assert_eq!(Movie::parse(&text).unwrap(), movie);
+
+        let mut scope = Movie::new(0, [DeviceKind::Joypad, DeviceKind::SuperScope], MovieStart::PowerOn);
+        scope.frames.push([pad(BUTTON_UP | BUTTON_A), PortInput::SuperScope(SuperScopeState { x: 128, y: -1, fire: true, turbo: true, ..Default::default() })]);
+        let text = scope.to_text();
+        assert!(text.ends_with("start power-on\ninput\n|....U...A...|128,-1,F.T.|\n"));
+        assert_eq!(Movie::parse(&text).unwrap(), scope);
+    }
+
+    #[test]
+    fn parse_errors() {
+        let header = "snes-movie 1\nrom 00000000\nports joypad joypad\n";
+
+        assert!(Movie::parse("").is_err());
+        assert!(Movie::parse("snes-movie 2\ninput\n").is_err());
+        assert!(Movie::parse("snes-movie 1\nports joypad\ninput\n").is_err());
+        assert!(Movie::parse("snes-movie 1\nstart state\ninput\n").is_err());
+        assert!(Movie::parse("snes-movie 1\ncheckpoint 60\ninput\n").is_err());
+        assert!(Movie::parse("snes-movie 1\nstate 0\ninput\n").is_err());
+
+        // unknown header lines are for newer versions
+        assert!(Movie::parse(&format!("{}author someone\ninput\n", header)).is_ok());
+
+        let error = Movie::parse(&format!("{}input\n|BY..........|\n|B?..........|\n", header)).unwrap_err();
+        assert_eq!(error.to_string(), "movie line 6: bad input field");
+        assert!(Movie::parse(&format!("{}input\n||||\n", header)).is_err());
+    }
+
+    #[test]
+    fn recording_checkpoints() {
+        let movie = Movie::new(0, [DeviceKind::Joypad; PORT_COUNT], MovieStart::PowerOn);
+        let mut session = MovieSession::new(movie, MovieMode::Recording, 100, None);
+
+        for frame in 100..221 {
+            session.end_frame(frame, [pad(BUTTON_A), PortInput::None], &[frame as u8; 16]);
+        }
+        let checkpoints: Vec<u64> = session.movie().checkpoints.iter().map(|&(at, _)| at).collect();
+        assert_eq!(checkpoints, vec![0, 60, 120]);
+        assert_eq!(session.movie().checkpoint(60), Some(crc32(&[160; 16])));
+
+        // rewinding drops what came after
+        session.rewound_to(150);
+        assert_eq!(session.movie().frames.len(), 50);
+        assert_eq!(session.movie().checkpoint(60), None);
+        assert_eq!(session.movie().checkpoints.len(), 1);
+        assert!(session.inputs(150).is_none());
+    }
+
+    #[test]
+    fn playback_desync() {
+        let mut movie = Movie::new(0, [DeviceKind::Joypad; PORT_COUNT], MovieStart::PowerOn);
+        movie.frames = vec![[pad(BUTTON_A), PortInput::None]; 121];
+        movie.checkpoints = vec![(0, crc32(&[0; 16])), (60, crc32(&[60; 16])), (120, crc32(&[120; 16]))];
+        let mut session = MovieSession::new(movie, MovieMode::Playing, 10, None);
+
+        assert_eq!(session.inputs(10), Some([pad(BUTTON_A), PortInput::None]));
+        for frame in 10..131 {
+            let elapsed = frame as u8 - 10;
+            let wram = if elapsed == 120 { [0xFF; 16] } else { [elapsed; 16] };
+            session.end_frame(frame, [pad(BUTTON_A), PortInput::None], &wram);
+        }
+        assert_eq!(session.desync(), Some(120));
+        assert!(session.finished(131));
+        assert!(session.inputs(131).is_none());
+
+        // a desync after the frame rewound to is forgotten, it may not happen again
+        session.rewound_to(100);
+        assert_eq!(session.desync(), None);
+        assert!(!session.finished(100));
+    }
+}
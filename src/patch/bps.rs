@@ -0,0 +1,96 @@
+use std::io::{Error, ErrorKind};
+
+use patch::{check_footer, check_patch, corrupt, read_number, target_size, truncated};
+
+const SOURCE_READ: u64 = 0;
+const TARGET_READ: u64 = 1;
+const SOURCE_COPY: u64 = 2;
+const TARGET_COPY: u64 = 3;
+
+// "BPS1", source size, target size, metadata size and the metadata, then actions until
+// the 12 byte footer. each action is a number holding the kind in its low 2 bits and the
+// length - 1 above them:
+//   source read   copy from the source at the current output position
+//   target read   copy bytes stored in the patch
+//   source copy   copy from a relative position in the source
+//   target copy   copy from a relative position in the output, which may overlap
+// relative positions are numbers with the sign in bit 0
+pub fn apply(source: &[u8], patch: &[u8]) -> Result<Vec<u8>, Error> {
+    if !patch.starts_with(b"BPS1") || patch.len() < 4 + 12 {
+        return Err(Error::new(ErrorKind::InvalidData, "not a bps patch"));
+    }
+
+    check_patch(patch)?;
+
+    let end = patch.len() - 12;
+    let mut pos = 4;
+
+    let source_size = read_number(patch, &mut pos)? as usize;
+    let target_size = target_size(read_number(patch, &mut pos)?)?;
+    let metadata_size = read_number(patch, &mut pos)? as usize;
+    pos = pos.checked_add(metadata_size).filter(|&pos| pos <= end).ok_or_else(truncated)?;
+
+    if source_size != source.len() {
+        return Err(Error::new(ErrorKind::InvalidData, "patch was made for a different rom"));
+    }
+
+    let mut out = Vec::with_capacity(target_size);
+    let mut source_offset: usize = 0;
+    let mut target_offset: usize = 0;
+
+    while pos < end {
+        let action = read_number(patch, &mut pos)?;
+        let len = (action >> 2) as usize + 1;
+        if out.len() + len > target_size {
+            return Err(corrupt());
+        }
+
+        match action & 3 {
+            SOURCE_READ => {
+                let at = out.len();
+                out.extend_from_slice(source.get(at..at + len).ok_or_else(corrupt)?);
+            },
+            TARGET_READ => {
+                if pos + len > end {
+                    return Err(truncated());
+                }
+                out.extend_from_slice(&patch[pos..pos + len]);
+                pos += len;
+            },
+            SOURCE_COPY => {
+                source_offset = relative(source_offset, read_number(patch, &mut pos)?)?;
+                out.extend_from_slice(source.get(source_offset..source_offset + len).ok_or_else(corrupt)?);
+                source_offset += len;
+            },
+            TARGET_COPY => {
+                target_offset = relative(target_offset, read_number(patch, &mut pos)?)?;
+                if target_offset >= out.len() {
+                    return Err(corrupt());
+                }
+                // byte by byte, a copy that overlaps its own output repeats a pattern
+                for _ in 0..len {
+                    let byte = out[target_offset];
+                    out.push(byte);
+                    target_offset += 1;
+                }
+            },
+            _ => unreachable!(),
+        }
+    }
+
+    if out.len() != target_size {
+        return Err(corrupt());
+    }
+
+    check_footer(source, &out, patch)?;
+    Ok(out)
+}
+
+fn relative(offset: usize, number: u64) -> Result<usize, Error> {
+    let distance = (number >> 1) as usize;
+    if number & 1 != 0 {
+        offset.checked_sub(distance).ok_or_else(corrupt)
+    } else {
+        offset.checked_add(distance).ok_or_else(corrupt)
+    }
+}
@@ -0,0 +1,63 @@
+use std::io::{Error, ErrorKind};
+
+use patch::{corrupt, truncated};
+
+const EOF: usize = 0x454F46; // "EOF"
+
+// "PATCH", then records until "EOF":
+//   u24 offset, u16 length, the bytes
+//   u24 offset, u16 0, u16 count, a byte repeated count times
+// all big endian. a u24 after "EOF" truncates the output to that length.
+// there's no checksum, so an ips patch applies to any rom
+pub fn apply(source: &[u8], patch: &[u8]) -> Result<Vec<u8>, Error> {
+    if !patch.starts_with(b"PATCH") {
+        return Err(Error::new(ErrorKind::InvalidData, "not an ips patch"));
+    }
+
+    let mut out = source.to_vec();
+    let mut pos = 5;
+
+    loop {
+        let offset = read_be(patch, &mut pos, 3)?;
+        if offset == EOF {
+            break;
+        }
+
+        let len = read_be(patch, &mut pos, 2)?;
+        if len > 0 {
+            let data = patch.get(pos..pos + len).ok_or_else(truncated)?;
+            pos += len;
+            write(&mut out, offset, data);
+        } else {
+            let count = read_be(patch, &mut pos, 2)?;
+            let value = *patch.get(pos).ok_or_else(truncated)?;
+            pos += 1;
+            write(&mut out, offset, &vec![value; count]);
+        }
+    }
+
+    match patch.len() - pos {
+        0 => {},
+        3 => {
+            let len = read_be(patch, &mut pos, 3)?;
+            out.truncate(len);
+        },
+        _ => return Err(corrupt()),
+    }
+
+    Ok(out)
+}
+
+// records past the end of the rom grow it
+fn write(out: &mut Vec<u8>, offset: usize, data: &[u8]) {
+    if out.len() < offset + data.len() {
+        out.resize(offset + data.len(), 0);
+    }
+    out[offset..offset + data.len()].copy_from_slice(data);
+}
+
+fn read_be(patch: &[u8], pos: &mut usize, len: usize) -> Result<usize, Error> {
+    let bytes = patch.get(*pos..*pos + len).ok_or_else(truncated)?;
+    *pos += len;
+    Ok(bytes.iter().fold(0, |value, &byte| (value << 8) | byte as usize))
+}
@@ -0,0 +1,540 @@
+pub mod bps;
+pub mod ips;
+pub mod ups;
+
+use std::fs;
+use std::io::{Error, ErrorKind};
+use std::path::Path;
+
+use cartridge::{copier_header_len, Cartridge};
+use util::crc32::crc32;
+
+const MAX_TARGET_SIZE: usize = 0x1000000;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum PatchFormat {
+    IPS,
+    BPS,
+    UPS,
+}
+
+impl PatchFormat {
+    // checksummed formats first, so a patch that can be verified wins
+    const ALL: [PatchFormat; 3] = [PatchFormat::BPS, PatchFormat::UPS, PatchFormat::IPS];
+
+    pub fn detect(patch: &[u8]) -> Option<PatchFormat> {
+        PatchFormat::ALL.iter().find(|format| patch.starts_with(format.signature())).cloned()
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            PatchFormat::IPS => "ips",
+            PatchFormat::BPS => "bps",
+            PatchFormat::UPS => "ups",
+        }
+    }
+
+    fn signature(self) -> &'static [u8] {
+        match self {
+            PatchFormat::IPS => b"PATCH",
+            PatchFormat::BPS => b"BPS1",
+            PatchFormat::UPS => b"UPS1",
+        }
+    }
+}
+
+// where an ips patch's offsets start in a rom file with a copier header. ips has no
+// checksum to tell, and old patches were often made against headered dumps
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum IpsHeader {
+    Detect,     // whichever way leaves the rom's internal checksum right, headerless if it's both or neither
+    Headerless, // offsets start after the header
+    Headered,   // offsets count the header
+}
+
+impl IpsHeader {
+    pub fn from_name(name: &str) -> Option<IpsHeader> {
+        match name {
+            "detect" => Some(IpsHeader::Detect),
+            "headerless" => Some(IpsHeader::Headerless),
+            "headered" => Some(IpsHeader::Headered),
+            _ => None,
+        }
+    }
+}
+
+// applies a patch to a rom file, which may have a copier header. patches are made against
+// the rom without one, so the header is kept aside and put back afterwards. bps and ups
+// patches that only match with the header included are applied to the whole file, and
+// ips patches are checked for the same
+pub fn apply(file: &[u8], patch: &[u8]) -> Result<Vec<u8>, Error> {
+    apply_with(file, patch, IpsHeader::Detect)
+}
+
+pub fn apply_with(file: &[u8], patch: &[u8], ips_header: IpsHeader) -> Result<Vec<u8>, Error> {
+    let format = PatchFormat::detect(patch)
+        .ok_or_else(|| Error::new(ErrorKind::InvalidData, "not an ips, bps or ups patch"))?;
+
+    let header_len = copier_header_len(file);
+    let (header, rom) = file.split_at(header_len);
+
+    let patched = match format {
+        PatchFormat::IPS if header_len == 0 => ips::apply(rom, patch)?,
+        PatchFormat::IPS => match ips_header {
+            IpsHeader::Headerless => ips::apply(rom, patch)?,
+            IpsHeader::Headered => return ips::apply(file, patch),
+            IpsHeader::Detect => {
+                let headerless = ips::apply(rom, patch)?;
+                let headered = ips::apply(file, patch)?;
+                if !checksum_matches(&headerless) && headered.len() > header_len && checksum_matches(&headered[header_len..]) {
+                    return Ok(headered);
+                }
+                headerless
+            },
+        },
+        PatchFormat::BPS | PatchFormat::UPS => {
+            let source_crc32 = read_u32(patch, patch.len().saturating_sub(12))?;
+            if header_len > 0 && crc32(rom) != source_crc32 && crc32(file) == source_crc32 {
+                return apply_checked(format, file, patch);
+            }
+            apply_checked(format, rom, patch)?
+        },
+    };
+
+    let mut out = header.to_vec();
+    out.extend_from_slice(&patched);
+    Ok(out)
+}
+
+fn apply_checked(format: PatchFormat, source: &[u8], patch: &[u8]) -> Result<Vec<u8>, Error> {
+    match format {
+        PatchFormat::BPS => bps::apply(source, patch),
+        PatchFormat::UPS => ups::apply(source, patch),
+        PatchFormat::IPS => ips::apply(source, patch),
+    }
+}
+
+// game.sfc -> game.bps, game.ups or game.ips, whichever exists first
+pub fn find_patch(rom_path: &str) -> Option<String> {
+    PatchFormat::ALL.iter()
+        .map(|format| Path::new(rom_path).with_extension(format.extension()))
+        .find(|path| path.is_file())
+        .map(|path| path.to_string_lossy().into_owned())
+}
+
+// reads a rom and applies the given patches in order. with no patches given, a patch
+// next to the rom with the same name is used if there is one
+pub fn read_patched(rom_path: &str, patches: &[String], ips_header: IpsHeader) -> Result<Vec<u8>, Error> {
+    let mut rom = fs::read(rom_path)?;
+
+    let found;
+    let patches = if patches.is_empty() {
+        found = find_patch(rom_path).into_iter().collect::<Vec<_>>();
+        &found[..]
+    } else {
+        patches
+    };
+
+    for path in patches {
+        let patch = fs::read(path)?;
+        rom = apply_with(&rom, &patch, ips_header).map_err(|e| Error::new(e.kind(), format!("{}: {}", path, e)))?;
+    }
+
+    Ok(rom)
+}
+
+////////////////////////////////////
+//
+//             HELPERS
+//
+////////////////////////////////////
+
+fn checksum_matches(rom: &[u8]) -> bool {
+    Cartridge::new(rom.to_vec()).is_ok_and(|cartridge| cartridge.checksum_matches())
+}
+
+// bps and ups share their footer: source, target and patch crc32s. the patch's own crc32
+// is checked before anything in its header is trusted, the others once it's been applied
+fn check_patch(patch: &[u8]) -> Result<(), Error> {
+    let footer = patch.len() - 12;
+    if crc32(&patch[..footer + 8]) != read_u32(patch, footer + 8)? {
+        return Err(corrupt());
+    }
+    Ok(())
+}
+
+fn check_footer(source: &[u8], target: &[u8], patch: &[u8]) -> Result<(), Error> {
+    let footer = patch.len() - 12;
+
+    if crc32(source) != read_u32(patch, footer)? {
+        return Err(Error::new(ErrorKind::InvalidData, "patch was made for a different rom"));
+    }
+    if crc32(target) != read_u32(patch, footer + 4)? {
+        return Err(Error::new(ErrorKind::InvalidData, "patched rom doesn't match the patch's checksum"));
+    }
+    Ok(())
+}
+
+// a target size from a patch's header, which is allocated up front. nothing bigger than
+// the cpu's whole 24 bit address space is a rom
+fn target_size(size: u64) -> Result<usize, Error> {
+    if size > MAX_TARGET_SIZE as u64 {
+        return Err(Error::new(ErrorKind::InvalidData, "patch makes a rom that's too big"));
+    }
+    Ok(size as usize)
+}
+
+fn read_u32(data: &[u8], pos: usize) -> Result<u32, Error> {
+    if pos + 4 > data.len() {
+        return Err(truncated());
+    }
+    Ok(data[pos..pos + 4].iter().rev().fold(0, |value, &byte| (value << 8) | byte as u32))
+}
+
+// the variable length numbers used by bps and ups. 7 bits at a time, low first, with the
+// top bit marking the last byte and an offset folded in so every value has one encoding
+fn read_number(data: &[u8], pos: &mut usize) -> Result<u64, Error> {
+    let mut value: u64 = 0;
+    let mut shift: u64 = 1;
+
+    loop {
+        let byte = *data.get(*pos).ok_or_else(truncated)?;
+        *pos += 1;
+
+        value = value.checked_add((byte & 0x7F) as u64 * shift).ok_or_else(corrupt)?;
+        if byte & 0x80 != 0 {
+            return Ok(value);
+        }
+        shift = shift.checked_shl(7).filter(|&shift| shift < (1 << 56)).ok_or_else(corrupt)?;
+        value += shift;
+    }
+}
+
+fn truncated() -> Error {
+    Error::new(ErrorKind::UnexpectedEof, "patch is truncated")
+}
+
+fn corrupt() -> Error {
+    Error::new(ErrorKind::InvalidData, "patch is corrupt")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    use cartridge::rom_checksum;
+
+    // bps actions
+    const SOURCE_READ: u64 = 0;
+    const TARGET_READ: u64 = 1;
+    const SOURCE_COPY: u64 = 2;
+    const TARGET_COPY: u64 = 3;
+
+    fn number(out: &mut Vec<u8>, mut value: u64) {
+        loop {
+            let byte = (value & 0x7F) as u8;
+            value >>= 7;
+            if value == 0 {
+                out.push(byte | 0x80);
+                return;
+            }
+            out.push(byte);
+            value -= 1;
+        }
+    }
+
+    fn u32_le(out: &mut Vec<u8>, value: u32) {
+        out.extend_from_slice(&[value as u8, (value >> 8) as u8, (value >> 16) as u8, (value >> 24) as u8]);
+    }
+
+    // the bps/ups footer with the given source and target crc32s, then the patch's own
+    fn footer(patch: &mut Vec<u8>, source_crc32: u32, target_crc32: u32) {
+        u32_le(patch, source_crc32);
+        u32_le(patch, target_crc32);
+        let patch_crc32 = crc32(patch);
+        u32_le(patch, patch_crc32);
+    }
+
+    // records of (offset, bytes), with a single byte repeated as an rle record
+    fn ips(records: &[(usize, &[u8])], rle: &[(usize, u16, u8)]) -> Vec<u8> {
+        let mut patch = b"PATCH".to_vec();
+        for &(offset, data) in records {
+            patch.extend_from_slice(&[(offset >> 16) as u8, (offset >> 8) as u8, offset as u8]);
+            patch.extend_from_slice(&[(data.len() >> 8) as u8, data.len() as u8]);
+            patch.extend_from_slice(data);
+        }
+        for &(offset, count, value) in rle {
+            patch.extend_from_slice(&[(offset >> 16) as u8, (offset >> 8) as u8, offset as u8, 0, 0]);
+            patch.extend_from_slice(&[(count >> 8) as u8, count as u8, value]);
+        }
+        patch.extend_from_slice(b"EOF");
+        patch
+    }
+
+    // 32KB of lorom with a header whose checksum is right
+    fn rom() -> Vec<u8> {
+        let mut rom: Vec<u8> = (0..0x8000).map(|n| (n * 7) as u8).collect();
+        rom[0x7FC0..0x7FD5].copy_from_slice(b"PATCH TEST           ");
+        rom[0x7FD5] = 0x20;
+        rom[0x7FFC] = 0x00;
+        rom[0x7FFD] = 0x80;
+        fix_checksum(&mut rom);
+        rom
+    }
+
+    fn fix_checksum(rom: &mut [u8]) {
+        rom[0x7FDC..0x7FE0].copy_from_slice(&[0xFF, 0xFF, 0x00, 0x00]);
+        let checksum = rom_checksum(rom);
+        rom[0x7FDC..0x7FE0].copy_from_slice(&[!checksum as u8, (!checksum >> 8) as u8, checksum as u8, (checksum >> 8) as u8]);
+    }
+
+    fn headered(rom: &[u8]) -> Vec<u8> {
+        let mut file = vec![0xEE; 512];
+        file.extend_from_slice(rom);
+        file
+    }
+
+    fn bps_patch(source: &[u8], target: &[u8]) -> Vec<u8> {
+        // reads what the two start with from the source and stores the rest of the target
+        let same = source.iter().zip(target).take_while(|&(a, b)| a == b).count();
+        let mut patch = b"BPS1".to_vec();
+        number(&mut patch, source.len() as u64);
+        number(&mut patch, target.len() as u64);
+        number(&mut patch, 0);
+        if same > 0 {
+            number(&mut patch, ((same as u64 - 1) << 2) | SOURCE_READ);
+        }
+        if same < target.len() {
+            number(&mut patch, ((target.len() - same) as u64 - 1) << 2 | TARGET_READ);
+            patch.extend_from_slice(&target[same..]);
+        }
+        footer(&mut patch, crc32(source), crc32(target));
+        patch
+    }
+
+    fn ups_patch(source: &[u8], target: &[u8]) -> Vec<u8> {
+        let mut patch = b"UPS1".to_vec();
+        number(&mut patch, source.len() as u64);
+        number(&mut patch, target.len() as u64);
+        let len = source.len().max(target.len());
+        let byte = |rom: &[u8], at: usize| rom.get(at).cloned().unwrap_or(0);
+
+        let mut last = 0;
+        let mut at = 0;
+        while at < len {
+            if byte(source, at) == byte(target, at) {
+                at += 1;
+                continue;
+            }
+            number(&mut patch, (at - last) as u64);
+            while at < len && byte(source, at) != byte(target, at) {
+                patch.push(byte(source, at) ^ byte(target, at));
+                at += 1;
+            }
+            patch.push(0);
+            at += 1;
+            last = at;
+        }
+        footer(&mut patch, crc32(source), crc32(target));
+        patch
+    }
+
+    fn error(result: Result<Vec<u8>, Error>) -> String {
+        result.unwrap_err().to_string()
+    }
+
+    #[test]
+    fn read_number_undoes_the_encoding() {
+        for &value in [0, 1, 0x7F, 0x80, 0x3FFF, 0x4000, 0x4080, 0xFFFFFF, 1 << 40].iter() {
+            let mut bytes = Vec::new();
+            number(&mut bytes, value);
+            let mut pos = 0;
+            assert_eq!(read_number(&bytes, &mut pos).unwrap(), value);
+            assert_eq!(pos, bytes.len());
+        }
+
+        // every value has one encoding, so 0x80 takes two bytes rather than 00 81
+        assert_eq!(read_number(&[0x00, 0x80], &mut 0).unwrap(), 0x80);
+        assert_eq!(read_number(&[0x7F, 0x7F], &mut 0).unwrap_err().kind(), ErrorKind::UnexpectedEof);
+        assert_eq!(read_number(&[0x7F; 16], &mut 0).unwrap_err().kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn ips_records_copy_repeat_and_grow() {
+        let source = vec![0; 0x20];
+        let patch = ips(&[(0x04, &[0xAA, 0xBB]), (0x22, &[0x09])], &[(0x10, 5, 0x77)]);
+        let out = ips::apply(&source, &patch).unwrap();
+
+        assert_eq!(out.len(), 0x23);
+        assert_eq!(&out[0x03..0x07], &[0x00, 0xAA, 0xBB, 0x00]);
+        assert_eq!(&out[0x0F..0x16], &[0x00, 0x77, 0x77, 0x77, 0x77, 0x77, 0x00]);
+        assert_eq!(&out[0x20..], &[0x00, 0x00, 0x09]);
+    }
+
+    #[test]
+    fn ips_truncation_comes_after_eof() {
+        let mut patch = ips(&[(0x02, &[0x01])], &[]);
+        patch.extend_from_slice(&[0x00, 0x00, 0x08]);
+
+        assert_eq!(ips::apply(&[0xFF; 0x20], &patch).unwrap(), vec![0xFF, 0xFF, 0x01, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF]);
+
+        patch.push(0);
+        assert_eq!(error(ips::apply(&[0; 0x20], &patch)), "patch is corrupt");
+    }
+
+    #[test]
+    fn truncated_ips_patches_are_refused() {
+        let patch = ips(&[(0x02, &[0x01, 0x02, 0x03])], &[(0x10, 4, 0x55)]);
+
+        // cut inside the data, inside the rle record and before "EOF"
+        for &len in [10, 14, 19, patch.len() - 1].iter() {
+            assert_eq!(error(ips::apply(&[0; 0x20], &patch[..len])), "patch is truncated");
+        }
+        assert_eq!(error(ips::apply(&[0; 0x20], b"PATHC")), "not an ips patch");
+    }
+
+    #[test]
+    fn bps_actions_rebuild_the_target() {
+        let source: Vec<u8> = (0..64).collect();
+        let mut target = source[..16].to_vec();
+        target.extend_from_slice(b"hi");
+        target.extend_from_slice(&source[32..40]);
+        let repeat = target[16..22].to_vec();
+        target.extend_from_slice(&repeat);
+
+        let mut patch = b"BPS1".to_vec();
+        number(&mut patch, source.len() as u64);
+        number(&mut patch, target.len() as u64);
+        number(&mut patch, 3);
+        patch.extend_from_slice(b"abc");
+        number(&mut patch, (15 << 2) | SOURCE_READ); // 16
+        number(&mut patch, (1 << 2) | TARGET_READ); // "hi"
+        patch.extend_from_slice(b"hi");
+        number(&mut patch, (7 << 2) | SOURCE_COPY); // 8 from 32
+        number(&mut patch, 32 << 1);
+        number(&mut patch, (5 << 2) | TARGET_COPY); // 6 from 16
+        number(&mut patch, 16 << 1);
+        footer(&mut patch, crc32(&source), crc32(&target));
+
+        assert_eq!(bps::apply(&source, &patch).unwrap(), target);
+    }
+
+    #[test]
+    fn bps_checks_every_crc32() {
+        let source: Vec<u8> = (0..64).collect();
+        let target: Vec<u8> = (0..80).map(|n| n * 3).collect();
+        let patch = bps_patch(&source, &target);
+        assert_eq!(bps::apply(&source, &patch).unwrap(), target);
+
+        let mut other = source.clone();
+        other[0] ^= 1;
+        assert_eq!(error(bps::apply(&other, &patch)), "patch was made for a different rom");
+        assert_eq!(error(bps::apply(&source[1..], &patch)), "patch was made for a different rom");
+
+        let mut wrong_target = patch[..patch.len() - 12].to_vec();
+        footer(&mut wrong_target, crc32(&source), crc32(&source));
+        assert_eq!(error(bps::apply(&source, &wrong_target)), "patched rom doesn't match the patch's checksum");
+
+        let mut damaged = patch.clone();
+        damaged[10] ^= 0x01;
+        assert_eq!(error(bps::apply(&source, &damaged)), "patch is corrupt");
+    }
+
+    #[test]
+    fn ups_checks_every_crc32_and_resizes() {
+        let source: Vec<u8> = (0..64).collect();
+        let mut target = source.clone();
+        target[5] = 0xAA;
+        target.extend_from_slice(&[1, 2, 3]);
+        let patch = ups_patch(&source, &target);
+        assert_eq!(ups::apply(&source, &patch).unwrap(), target);
+
+        let shrink = ups_patch(&source, &source[..40]);
+        assert_eq!(ups::apply(&source, &shrink).unwrap(), &source[..40]);
+
+        let mut other = source.clone();
+        other[63] ^= 1;
+        assert_eq!(error(ups::apply(&other, &patch)), "patch was made for a different rom");
+
+        let mut wrong_target = patch[..patch.len() - 12].to_vec();
+        footer(&mut wrong_target, crc32(&source), 0);
+        assert_eq!(error(ups::apply(&source, &wrong_target)), "patched rom doesn't match the patch's checksum");
+
+        let mut damaged = patch.clone();
+        damaged[4] ^= 0x01;
+        assert_eq!(error(ups::apply(&source, &damaged)), "patch is corrupt");
+    }
+
+    #[test]
+    fn huge_targets_are_refused_before_anything_is_allocated() {
+        let mut patch = b"BPS1".to_vec();
+        number(&mut patch, 4);
+        number(&mut patch, 1 << 40);
+        number(&mut patch, 0);
+        footer(&mut patch, 0, 0);
+        assert_eq!(error(bps::apply(&[0; 4], &patch)), "patch makes a rom that's too big");
+    }
+
+    #[test]
+    fn patches_skip_a_copier_header() {
+        let source = rom();
+        let mut target = source.clone();
+        target[0x100] = 0x42;
+        fix_checksum(&mut target);
+
+        for patch in [ips(&[(0x100, &[0x42]), (0x7FDC, &target[0x7FDC..0x7FE0])], &[]), bps_patch(&source, &target), ups_patch(&source, &target)].iter() {
+            assert_eq!(apply(&source, patch).unwrap(), target);
+            assert_eq!(apply(&headered(&source), patch).unwrap(), headered(&target));
+        }
+
+        // bps and ups made against the headered file are applied to all of it
+        let mut headered_target = headered(&target);
+        headered_target[0] = 0x01;
+        for patch in [bps_patch(&headered(&source), &headered_target), ups_patch(&headered(&source), &headered_target)].iter() {
+            assert_eq!(apply(&headered(&source), patch).unwrap(), headered_target);
+        }
+    }
+
+    #[test]
+    fn ips_patches_for_headered_roms_are_told_apart_by_the_checksum() {
+        let source = rom();
+        let mut target = source.clone();
+        target[0x100] = 0x42;
+        fix_checksum(&mut target);
+        let patch = ips(&[(0x300, &[0x42]), (0x81DC, &target[0x7FDC..0x7FE0])], &[]);
+
+        assert_eq!(apply(&headered(&source), &patch).unwrap(), headered(&target));
+        assert_eq!(apply_with(&headered(&source), &patch, IpsHeader::Headered).unwrap(), headered(&target));
+
+        let headerless = apply_with(&headered(&source), &patch, IpsHeader::Headerless).unwrap();
+        assert_eq!(headerless.len(), 512 + 0x81E0);
+        assert_eq!(headerless[512 + 0x300], 0x42);
+
+        // without a header there's nothing to decide
+        assert_eq!(apply(&source, &patch).unwrap().len(), 0x81E0);
+    }
+
+    #[test]
+    fn patches_stack_in_the_order_given() {
+        let dir = env::temp_dir().join(format!("snes-patch-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = |name: &str| dir.join(name).to_string_lossy().into_owned();
+
+        fs::write(path("game.sfc"), rom()).unwrap();
+        fs::write(path("first.ips"), ips(&[(0x10, &[0x01, 0x01])], &[])).unwrap();
+        fs::write(path("second.ips"), ips(&[(0x11, &[0x02])], &[])).unwrap();
+        fs::write(path("game.ips"), ips(&[(0x10, &[0x03])], &[])).unwrap();
+
+        let both = read_patched(&path("game.sfc"), &[path("first.ips"), path("second.ips")], IpsHeader::Detect).unwrap();
+        assert_eq!(&both[0x10..0x12], &[0x01, 0x02]);
+        let reversed = read_patched(&path("game.sfc"), &[path("second.ips"), path("first.ips")], IpsHeader::Detect).unwrap();
+        assert_eq!(&reversed[0x10..0x12], &[0x01, 0x01]);
+
+        // with none given, the one next to the rom
+        assert_eq!(find_patch(&path("game.sfc")), Some(path("game.ips")));
+        assert_eq!(read_patched(&path("game.sfc"), &[], IpsHeader::Detect).unwrap()[0x10], 0x03);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,55 @@
+use std::io::{Error, ErrorKind};
+
+use patch::{check_footer, check_patch, corrupt, read_number, target_size};
+
+// "UPS1", source size, target size, then records until the 12 byte footer. each record
+// skips a number of bytes and then xors bytes into the output up to and including a 0.
+// bytes past the end of either rom read as 0, so a patch can grow or shrink the rom
+pub fn apply(source: &[u8], patch: &[u8]) -> Result<Vec<u8>, Error> {
+    if !patch.starts_with(b"UPS1") || patch.len() < 4 + 12 {
+        return Err(Error::new(ErrorKind::InvalidData, "not a ups patch"));
+    }
+
+    check_patch(patch)?;
+
+    let end = patch.len() - 12;
+    let mut pos = 4;
+
+    let source_size = read_number(patch, &mut pos)? as usize;
+    let target_size = target_size(read_number(patch, &mut pos)?)?;
+
+    if source_size != source.len() {
+        return Err(Error::new(ErrorKind::InvalidData, "patch was made for a different rom"));
+    }
+
+    let mut out = source.to_vec();
+    out.resize(source_size.max(target_size), 0);
+    let mut at: usize = 0;
+
+    while pos < end {
+        at = at.checked_add(read_number(patch, &mut pos)? as usize).ok_or_else(corrupt)?;
+
+        loop {
+            if pos >= end {
+                return Err(corrupt());
+            }
+            let xor = patch[pos];
+            pos += 1;
+
+            if at < out.len() {
+                out[at] ^= xor;
+            } else if xor != 0 {
+                return Err(corrupt());
+            }
+            at += 1;
+
+            if xor == 0 {
+                break;
+            }
+        }
+    }
+
+    out.truncate(target_size);
+    check_footer(source, &out, patch)?;
+    Ok(out)
+}
@@ -0,0 +1,81 @@
+use std::io::Error;
+
+use savestate::{StateReader, StateWriter};
+
+// the PPU's latched H/V counters. they are latched by reading $2137 (SLHV), by a 1 -> 0
+// transition of $4201 bit 7, or by a light gun pulling the port 2 IOBit low.
+// $213C OPHCT / $213D OPVCT are 9 bit values read low byte first through a flip flop,
+// $213F STAT78 reports whether a latch happened and resets both flip flops
+pub struct Counters {
+    h: u16,
+    v: u16,
+    latched: bool,
+    h_high: bool,
+    v_high: bool,
+}
+
+impl Counters {
+    pub fn new() -> Counters {
+        Counters {
+            h: 0x1FF,
+            v: 0x1FF,
+            latched: false,
+            h_high: false,
+            v_high: false,
+        }
+    }
+
+    pub fn latch(&mut self, h: u16, v: u16) {
+        self.h = h & 0x1FF;
+        self.v = v & 0x1FF;
+        self.latched = true;
+    }
+
+    pub fn latched_position(&self) -> (u16, u16) {
+        (self.h, self.v)
+    }
+
+    // $213C
+    pub fn read_ophct(&mut self) -> u8 {
+        let data = if self.h_high { (self.h >> 8) as u8 } else { self.h as u8 };
+        self.h_high = !self.h_high;
+        data
+    }
+
+    // $213D
+    pub fn read_opvct(&mut self) -> u8 {
+        let data = if self.v_high { (self.v >> 8) as u8 } else { self.v as u8 };
+        self.v_high = !self.v_high;
+        data
+    }
+
+    // $213F. bit 6 is the latch flag, the low bits are the PPU2 version
+    pub fn read_stat78(&mut self) -> u8 {
+        let mut data = 0x03;
+        if self.latched {
+            data |= 0x40;
+        }
+
+        self.latched = false;
+        self.h_high = false;
+        self.v_high = false;
+        data
+    }
+
+    pub fn save_state(&self, w: &mut StateWriter) {
+        w.u16(self.h);
+        w.u16(self.v);
+        w.bool(self.latched);
+        w.bool(self.h_high);
+        w.bool(self.v_high);
+    }
+
+    pub fn load_state(&mut self, r: &mut StateReader) -> Result<(), Error> {
+        self.h = r.u16()?;
+        self.v = r.u16()?;
+        self.latched = r.bool()?;
+        self.h_high = r.bool()?;
+        self.v_high = r.bool()?;
+        Ok(())
+    }
+}
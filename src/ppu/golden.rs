@@ -0,0 +1,156 @@
+use std::io::{Error, ErrorKind};
+
+use ppu::png::{self, Image};
+
+// golden images
+// =============
+// a frame is compared against a .png of how it should look. a tolerance lets through
+// colours that are a little off, from rounding in the colour conversion say, as long as
+// no channel is further off than it. the diff image shows the frame faded to gray with
+// every pixel past the tolerance in red, so it's easy to see where a frame went wrong
+
+const DIFF_COLOUR: u32 = 0xFF0000;
+
+pub struct Comparison {
+    pub width: usize,
+    pub height: usize,
+    pub differing: usize, // pixels with a channel past the tolerance
+    pub largest: u8,      // the most any channel of any pixel was off by
+    pub diff: Vec<u32>,
+}
+
+impl Comparison {
+    pub fn matches(&self) -> bool {
+        self.differing == 0
+    }
+
+    pub fn save_diff(&self, path: &str) -> Result<(), Error> {
+        png::write_png(path, self.width, self.height, &self.diff)
+    }
+}
+
+// frames of a different size never match, so they're an error rather than a comparison
+pub fn compare(pixels: &[u32], width: usize, height: usize, golden: &Image, tolerance: u8) -> Result<Comparison, Error> {
+    if (width, height) != (golden.width, golden.height) {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("frame is {}x{} but the golden image is {}x{}", width, height, golden.width, golden.height),
+        ));
+    }
+
+    let mut comparison = Comparison {
+        width,
+        height,
+        differing: 0,
+        largest: 0,
+        diff: Vec::with_capacity(pixels.len()),
+    };
+    for (&pixel, &expected) in pixels.iter().zip(golden.pixels.iter()) {
+        let off = [16, 8, 0].iter()
+            .map(|&shift| ((pixel >> shift) as u8).abs_diff((expected >> shift) as u8))
+            .max()
+            .unwrap();
+        comparison.largest = comparison.largest.max(off);
+
+        if off > tolerance {
+            comparison.differing += 1;
+            comparison.diff.push(DIFF_COLOUR);
+        } else {
+            comparison.diff.push(faded(pixel));
+        }
+    }
+    Ok(comparison)
+}
+
+// compares against the golden image at golden_path, and writes the diff image to diff_path
+// if anything was past the tolerance
+pub fn compare_png(pixels: &[u32], width: usize, height: usize, golden_path: &str, tolerance: u8, diff_path: &str) -> Result<Comparison, Error> {
+    let comparison = compare(pixels, width, height, &png::read_png(golden_path)?, tolerance)?;
+    if !comparison.matches() {
+        comparison.save_diff(diff_path)?;
+    }
+    Ok(comparison)
+}
+
+// where compare_png writes the diff for a golden image: frame.png -> frame.diff.png
+pub fn diff_path(golden_path: &str) -> String {
+    match golden_path.strip_suffix(".png") {
+        Some(stem) => format!("{}.diff.png", stem),
+        None => format!("{}.diff.png", golden_path),
+    }
+}
+
+// the pixel's brightness at a third, dark enough for red to stand out on
+fn faded(pixel: u32) -> u32 {
+    let (r, g, b) = ((pixel >> 16) & 0xFF, (pixel >> 8) & 0xFF, pixel & 0xFF);
+    let gray = (r * 30 + g * 59 + b * 11) / 300;
+    gray * 0x010101
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs;
+
+    fn golden() -> Image {
+        Image {
+            width: 3,
+            height: 2,
+            pixels: vec![0x000000, 0x808080, 0xFFFFFF, 0x102030, 0x405060, 0x708090],
+        }
+    }
+
+    #[test]
+    fn channels_within_the_tolerance_match() {
+        let frame = [0x030000, 0x807D80, 0xFFFFFC, 0x102030, 0x405060, 0x708090];
+
+        let comparison = compare(&frame, 3, 2, &golden(), 3).unwrap();
+        assert!(comparison.matches());
+        assert_eq!(comparison.largest, 3);
+
+        assert_eq!(compare(&frame, 3, 2, &golden(), 2).unwrap().differing, 3);
+    }
+
+    #[test]
+    fn the_diff_marks_pixels_past_the_tolerance_on_a_faded_frame() {
+        let frame = [0x000000, 0x808080, 0x00FFFF, 0x102030, 0x405060, 0x708090];
+        let comparison = compare(&frame, 3, 2, &golden(), 10).unwrap();
+
+        assert!(!comparison.matches());
+        assert_eq!((comparison.differing, comparison.largest), (1, 0xFF));
+        assert_eq!(comparison.diff[2], DIFF_COLOUR);
+        assert_eq!(comparison.diff[0], 0x000000);
+        assert_eq!(comparison.diff[1], 0x2A2A2A);
+    }
+
+    #[test]
+    fn frames_of_another_size_are_an_error() {
+        let error = compare(&[0; 6], 2, 3, &golden(), 0).err().unwrap();
+        assert_eq!(error.to_string(), "frame is 2x3 but the golden image is 3x2");
+    }
+
+    #[test]
+    fn compare_png_writes_a_diff_only_when_it_has_to() {
+        let dir = env::temp_dir().join(format!("snes-golden-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let golden_path = dir.join("frame.png").to_string_lossy().into_owned();
+        let diff = diff_path(&golden_path);
+        assert_eq!(diff, dir.join("frame.diff.png").to_string_lossy());
+
+        let golden = golden();
+        png::write_png(&golden_path, 3, 2, &golden.pixels).unwrap();
+
+        assert!(compare_png(&golden.pixels, 3, 2, &golden_path, 0, &diff).unwrap().matches());
+        assert!(fs::metadata(&diff).is_err());
+
+        let mut frame = golden.pixels.clone();
+        frame[4] = 0xFF0000;
+        let comparison = compare_png(&frame, 3, 2, &golden_path, 0, &diff).unwrap();
+        assert_eq!(comparison.differing, 1);
+        assert!(png::read_png(&diff).unwrap().pixels == comparison.diff);
+
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(diff_path("frame"), "frame.diff.png");
+    }
+}
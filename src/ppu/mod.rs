@@ -0,0 +1,4 @@
+pub mod counters;
+pub mod golden;
+pub mod png;
+pub mod ppu;
@@ -0,0 +1,374 @@
+use std::fs;
+use std::io::{Error, ErrorKind, Write};
+
+use util::crc32::{crc32, Crc32};
+use util::zlib::{self, adler32};
+
+const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A];
+
+// deflate's stored blocks hold at most 65535 bytes each
+const MAX_STORED_BLOCK: usize = 0xFFFF;
+
+// writes 0x00RRGGBB pixels as an 8 bit RGB .png of any size
+pub fn write_png(path: &str, width: usize, height: usize, pixels: &[u32]) -> Result<(), Error> {
+    let mut f = fs::File::create(path)?;
+    f.write_all(&png_bytes(width, height, pixels))
+}
+
+// the image data is stored uncompressed so no deflate implementation is needed.
+// the files are bigger than they could be but every decoder reads them
+pub fn png_bytes(width: usize, height: usize, pixels: &[u32]) -> Vec<u8> {
+    let mut raw = Vec::with_capacity(height * (1 + width * 3));
+    for row in pixels.chunks(width).take(height) {
+        raw.push(0); // no filter
+        for &pixel in row {
+            raw.push((pixel >> 16) as u8);
+            raw.push((pixel >> 8) as u8);
+            raw.push(pixel as u8);
+        }
+    }
+
+    let mut ihdr = Vec::with_capacity(13);
+    push_u32(&mut ihdr, width as u32);
+    push_u32(&mut ihdr, height as u32);
+    ihdr.extend_from_slice(&[8, 2, 0, 0, 0]); // 8 bits per channel, truecolor, deflate, no filter, no interlace
+
+    let mut bytes = Vec::new();
+    bytes.extend_from_slice(&SIGNATURE);
+    push_chunk(&mut bytes, b"IHDR", &ihdr);
+    push_chunk(&mut bytes, b"IDAT", &zlib_stored(&raw));
+    push_chunk(&mut bytes, b"IEND", &[]);
+    bytes
+}
+
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len() + data.len() / MAX_STORED_BLOCK * 5 + 11);
+    out.push(0x78);
+    out.push(0x01);
+
+    let mut blocks = data.chunks(MAX_STORED_BLOCK).peekable();
+    if blocks.peek().is_none() {
+        out.extend_from_slice(&[0x01, 0x00, 0x00, 0xFF, 0xFF]);
+    }
+    while let Some(block) = blocks.next() {
+        let last = if blocks.peek().is_none() { 1 } else { 0 };
+        let len = block.len() as u16;
+
+        out.push(last);
+        out.extend_from_slice(&[len as u8, (len >> 8) as u8, !len as u8, (!len >> 8) as u8]);
+        out.extend_from_slice(block);
+    }
+
+    push_u32(&mut out, adler32(data));
+    out
+}
+
+fn push_chunk(bytes: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    push_u32(bytes, data.len() as u32);
+
+    let mut crc = Crc32::new();
+    crc.update(kind);
+    crc.update(data);
+
+    bytes.extend_from_slice(kind);
+    bytes.extend_from_slice(data);
+    push_u32(bytes, crc.finish());
+}
+
+// png is big endian throughout
+fn push_u32(bytes: &mut Vec<u8>, value: u32) {
+    bytes.extend_from_slice(&[(value >> 24) as u8, (value >> 16) as u8, (value >> 8) as u8, value as u8]);
+}
+
+////////////////////////////////////
+//
+//             READING
+//
+////////////////////////////////////
+
+// a decoded .png as 0x00RRGGBB pixels, row by row like the framebuffer
+pub struct Image {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<u32>,
+}
+
+pub fn read_png(path: &str) -> Result<Image, Error> {
+    decode_png(&fs::read(path)?)
+}
+
+// reads what png_bytes writes and the usual files other tools write too: every colour type
+// at every bit depth, with any filters and compression. alpha is dropped and interlaced
+// images aren't supported
+pub fn decode_png(bytes: &[u8]) -> Result<Image, Error> {
+    if !bytes.starts_with(&SIGNATURE) {
+        return Err(bad("not a png"));
+    }
+
+    let mut header = None;
+    let mut palette = Vec::new();
+    let mut data = Vec::new();
+    let mut position = SIGNATURE.len();
+    loop {
+        let len = read_u32(bytes, position)? as usize;
+        let kind = bytes.get(position + 4..position + 8).ok_or_else(|| bad("png ends in a chunk header"))?;
+        let body = bytes.get(position + 8..position + 8 + len).ok_or_else(|| bad("png ends in a chunk"))?;
+        if read_u32(bytes, position + 8 + len)? != crc32(&bytes[position + 4..position + 8 + len]) {
+            return Err(bad("png chunk checksum doesn't match"));
+        }
+        position += 12 + len;
+
+        match kind {
+            b"IHDR" => header = Some(Header::parse(body)?),
+            b"PLTE" => palette = body.chunks(3).map(|rgb| rgb.iter().fold(0, |pixel, &c| (pixel << 8) | c as u32)).collect(),
+            b"IDAT" => data.extend_from_slice(body),
+            b"IEND" => break,
+            // ancillary chunks have a lowercase first letter and can be skipped
+            _ if kind[0] & 0x20 != 0 => {},
+            _ => return Err(bad("png has a critical chunk that isn't understood")),
+        }
+    }
+
+    let header = header.ok_or_else(|| bad("png has no IHDR"))?;
+    if header.colour == COLOUR_PALETTE && palette.is_empty() {
+        return Err(bad("paletted png has no PLTE"));
+    }
+
+    let raw = zlib::decompress(&data)?;
+    let rows = unfilter(&header, &raw)?;
+    let pixels = rows.chunks(header.stride()).flat_map(|row| header.row_pixels(row, &palette)).collect();
+
+    Ok(Image {
+        width: header.width,
+        height: header.height,
+        pixels,
+    })
+}
+
+const COLOUR_GRAY: u8 = 0;
+const COLOUR_RGB: u8 = 2;
+const COLOUR_PALETTE: u8 = 3;
+const COLOUR_GRAY_ALPHA: u8 = 4;
+const COLOUR_RGBA: u8 = 6;
+
+struct Header {
+    width: usize,
+    height: usize,
+    depth: u8,
+    colour: u8,
+}
+
+impl Header {
+    fn parse(body: &[u8]) -> Result<Header, Error> {
+        if body.len() != 13 {
+            return Err(bad("png IHDR is the wrong size"));
+        }
+        let header = Header {
+            width: read_u32(body, 0)? as usize,
+            height: read_u32(body, 4)? as usize,
+            depth: body[8],
+            colour: body[9],
+        };
+
+        let depths: &[u8] = match header.colour {
+            COLOUR_GRAY => &[1, 2, 4, 8, 16],
+            COLOUR_PALETTE => &[1, 2, 4, 8],
+            COLOUR_RGB | COLOUR_GRAY_ALPHA | COLOUR_RGBA => &[8, 16],
+            _ => return Err(bad("png has an unknown colour type")),
+        };
+        if !depths.contains(&header.depth) {
+            return Err(bad("png has a bit depth its colour type can't"));
+        }
+        if body[10] != 0 || body[11] != 0 {
+            return Err(bad("png has an unknown compression or filter method"));
+        }
+        if body[12] != 0 {
+            return Err(bad("interlaced pngs aren't supported"));
+        }
+        if header.width == 0 || header.height == 0 {
+            return Err(bad("png is empty"));
+        }
+        Ok(header)
+    }
+
+    fn channels(&self) -> usize {
+        match self.colour {
+            COLOUR_RGB => 3,
+            COLOUR_GRAY_ALPHA => 2,
+            COLOUR_RGBA => 4,
+            _ => 1,
+        }
+    }
+
+    fn bits_per_pixel(&self) -> usize {
+        self.channels() * self.depth as usize
+    }
+
+    // bytes in a row, not counting its filter byte
+    fn stride(&self) -> usize {
+        (self.width * self.bits_per_pixel()).div_ceil(8)
+    }
+
+    fn row_pixels(&self, row: &[u8], palette: &[u32]) -> Vec<u32> {
+        let depth = self.depth as usize;
+        // samples at 16 bits keep their top byte, ones under 8 bits are scaled up
+        let sample = |index: usize| -> u32 {
+            match depth {
+                16 => row[index * 2] as u32,
+                8 => row[index] as u32,
+                _ => {
+                    let bit = index * depth;
+                    let value = (row[bit / 8] >> (8 - depth - bit % 8)) & ((1 << depth) - 1);
+                    value as u32
+                },
+            }
+        };
+        let scale = |value: u32| if depth < 8 { value * 255 / ((1 << depth) - 1) } else { value };
+
+        (0..self.width)
+            .map(|x| {
+                let first = x * self.channels();
+                match self.colour {
+                    COLOUR_PALETTE => palette.get(sample(first) as usize).cloned().unwrap_or(0),
+                    COLOUR_GRAY | COLOUR_GRAY_ALPHA => scale(sample(first)) * 0x010101,
+                    _ => (sample(first) << 16) | (sample(first + 1) << 8) | sample(first + 2),
+                }
+            })
+            .collect()
+    }
+}
+
+// undoes each row's filter, leaving the rows back to back without their filter bytes
+fn unfilter(header: &Header, raw: &[u8]) -> Result<Vec<u8>, Error> {
+    let stride = header.stride();
+    if raw.len() < (stride + 1) * header.height {
+        return Err(bad("png image data is too short"));
+    }
+    // filters work on bytes, comparing each with the one a pixel back
+    let back = header.bits_per_pixel().div_ceil(8);
+
+    let mut rows = vec![0; stride * header.height];
+    for y in 0..header.height {
+        let filter = raw[y * (stride + 1)];
+        let line = &raw[y * (stride + 1) + 1..(y + 1) * (stride + 1)];
+        let (done, rest) = rows.split_at_mut(y * stride);
+        let above = if y == 0 { None } else { Some(&done[(y - 1) * stride..]) };
+        let row = &mut rest[..stride];
+
+        for x in 0..stride {
+            let a = if x >= back { row[x - back] } else { 0 };
+            let b = above.map_or(0, |above| above[x]);
+            let c = if x >= back { above.map_or(0, |above| above[x - back]) } else { 0 };
+            let predicted = match filter {
+                0 => 0,
+                1 => a,
+                2 => b,
+                3 => ((a as u16 + b as u16) / 2) as u8,
+                4 => paeth(a, b, c),
+                _ => return Err(bad("png row has an unknown filter")),
+            };
+            row[x] = line[x].wrapping_add(predicted);
+        }
+    }
+    Ok(rows)
+}
+
+// whichever of left, above and above left is closest to left + above - above left
+fn paeth(a: u8, b: u8, c: u8) -> u8 {
+    let p = a as i16 + b as i16 - c as i16;
+    let (pa, pb, pc) = ((p - a as i16).abs(), (p - b as i16).abs(), (p - c as i16).abs());
+    if pa <= pb && pa <= pc {
+        a
+    } else if pb <= pc {
+        b
+    } else {
+        c
+    }
+}
+
+fn read_u32(bytes: &[u8], position: usize) -> Result<u32, Error> {
+    let b = bytes.get(position..position + 4).ok_or_else(|| bad("png ends early"))?;
+    Ok(u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+}
+
+fn bad(reason: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, reason)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 16x10 8 bit rgba, rows filtered none, sub, up, average and paeth in turn, with a
+    // dynamic huffman block. pixel x, y is x * 16, y * 25, (x + y) * 8 with alpha alternating
+    const RGBA: &'static str = "\
+        89504e470d0a1a0a0000000d49484452000000100000000a0806000000bdbede9c000000bc4944415478daa5\
+        902112826018441730509c216a231888ff9888442391483462b37cc3d28c24c7c811bc811cc12378046fb0fe\
+        7084dff0e6b5373b0b00cc902a4746879d2ae4ac51a88563875244c511274da8f944a3192ddf38eb838e5f5c\
+        15619fca07064f1fe2d807064f1fea04c7ec956ed3c1d38778b396fc1c4fa0ad50668eb9957256b1b2936aab\
+        d95aa3ce5ad2ce1aade364573d8d9ceda6b78dfcd8435f9b18e1ee187ae0e2f89f031727b81ca2d003176fd6\
+        12c28ffc012d38865a561739990000000049454e44ae426082";
+    // 5x2 at 2 bits per pixel from a black, red, green, blue palette, with a fixed huffman block
+    const PALETTE: &'static str = "\
+        89504e470d0a1a0a0000000d4948445200000005000000020203000000ed04fece0000000c504c5445000000\
+        ff000000ff000000ff9bc013dc0000000e4944415478da6390766078d2000003d501c0bc2fd2a80000000049\
+        454e44ae426082";
+
+    fn hex(text: &str) -> Vec<u8> {
+        (0..text.len()).step_by(2).map(|at| u8::from_str_radix(&text[at..at + 2], 16).unwrap()).collect()
+    }
+
+    #[test]
+    fn what_png_bytes_writes_decodes_back() {
+        // big enough to need several stored blocks
+        let (width, height) = (300, 250);
+        let pixels: Vec<u32> = (0..width * height).map(|n| (n as u32).wrapping_mul(0x9E3779B1) & 0xFFFFFF).collect();
+
+        let image = decode_png(&png_bytes(width, height, &pixels)).unwrap();
+        assert_eq!((image.width, image.height), (width, height));
+        assert!(image.pixels == pixels);
+    }
+
+    #[test]
+    fn stored_zlib_streams_round_trip() {
+        for &len in [0, 1, MAX_STORED_BLOCK, MAX_STORED_BLOCK + 1].iter() {
+            let data: Vec<u8> = (0..len).map(|n| (n * 31) as u8).collect();
+            assert!(zlib::decompress(&zlib_stored(&data)).unwrap() == data);
+        }
+    }
+
+    #[test]
+    fn filtered_rgba_from_another_encoder_decodes() {
+        let image = decode_png(&hex(RGBA)).unwrap();
+        assert_eq!((image.width, image.height), (16, 10));
+
+        for y in 0..10 {
+            for x in 0..16 {
+                let expected = ((x * 16) << 16 | (y * 25) << 8 | ((x + y) * 8)) as u32;
+                assert_eq!(image.pixels[y * 16 + x], expected, "pixel {}, {}", x, y);
+            }
+        }
+    }
+
+    #[test]
+    fn paletted_pngs_decode_through_the_palette() {
+        let image = decode_png(&hex(PALETTE)).unwrap();
+        assert_eq!((image.width, image.height), (5, 2));
+        assert_eq!(image.pixels, vec![
+            0x000000, 0xFF0000, 0x00FF00, 0x0000FF, 0xFF0000,
+            0x0000FF, 0x00FF00, 0xFF0000, 0x000000, 0x00FF00,
+        ]);
+    }
+
+    #[test]
+    fn damaged_pngs_are_refused() {
+        let error = |bytes: &[u8]| decode_png(bytes).err().unwrap().to_string();
+        let png = hex(PALETTE);
+
+        let mut checksum = png.clone();
+        checksum[20] ^= 1;
+        assert_eq!(error(&checksum), "png chunk checksum doesn't match");
+        assert_eq!(error(&png[..png.len() - 20]), "png ends in a chunk");
+        assert_eq!(error(&png[1..]), "not a png");
+    }
+}
@@ -1,7 +1,69 @@
+use std::io::Error;
+
+use savestate::{StateReader, StateWriter};
+
+pub const SCREEN_WIDTH: usize = 256;
+pub const SCREEN_HEIGHT: usize = 224;
+
+// a dot is 4 master cycles. the two long dots on each line aren't modelled yet
+const MASTER_CYCLES_PER_DOT: u64 = 4;
+
+// todo -> backgrounds, sprites, color math. until then the screen stays in forced blank
 pub struct PPU {
+    // 0x00RRGGBB per pixel, row by row
+    framebuffer: Vec<u32>,
 
+    // where the beam is. the bus's scheduler starts each line at its master cycle, and the
+    // dot follows from how far the clock has got since
+    line: u16,
+    line_start: u64,
 }
 
 impl PPU {
+    pub fn new() -> PPU {
+        PPU {
+            framebuffer: vec![0; SCREEN_WIDTH * SCREEN_HEIGHT],
+            line: 0,
+            line_start: 0,
+        }
+    }
+
+    pub fn start_line(&mut self, line: u16, at: u64) {
+        self.line = line;
+        self.line_start = at;
+    }
+
+    pub fn line(&self) -> u16 {
+        self.line
+    }
 
-}
\ No newline at end of file
+    pub fn dot(&self, master_cycles: u64) -> u16 {
+        (master_cycles.saturating_sub(self.line_start) / MASTER_CYCLES_PER_DOT) as u16
+    }
+
+    pub fn width(&self) -> usize {
+        SCREEN_WIDTH
+    }
+
+    pub fn height(&self) -> usize {
+        SCREEN_HEIGHT
+    }
+
+    pub fn framebuffer(&self) -> &[u32] {
+        &self.framebuffer
+    }
+
+    // only the beam position. vram, cgram, oam and the register latches aren't emulated yet,
+    // so states don't hold any of them, and the framebuffer is left out since nothing draws
+    // into it
+    pub fn save_state(&self, w: &mut StateWriter) {
+        w.u16(self.line);
+        w.u64(self.line_start);
+    }
+
+    pub fn load_state(&mut self, r: &mut StateReader) -> Result<(), Error> {
+        self.line = r.u16()?;
+        self.line_start = r.u64()?;
+        Ok(())
+    }
+}
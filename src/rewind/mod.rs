@@ -0,0 +1,217 @@
+use std::collections::VecDeque;
+use std::io::{Error, ErrorKind};
+
+pub const DEFAULT_INTERVAL: u64 = 1;
+pub const DEFAULT_BUDGET: usize = 64 << 20;
+
+// deltas compare states this many bytes at a time. smaller blocks find tighter spans,
+// bigger ones mean fewer span headers
+const BLOCK_SIZE: usize = 16;
+
+struct Snapshot {
+    frame: u64,
+    data: Vec<u8>,
+}
+
+// a ring of snapshots taken every few frames, newest last. only the newest is kept whole,
+// every older one is stored as the spans where it differs from the snapshot after it, xored
+// with that snapshot. most of the machine doesn't change from one frame to the next so
+// deltas are tiny next to a full state. when the budget runs out the oldest snapshots go
+pub struct Rewind {
+    interval: u64,
+    budget: usize,
+
+    latest: Option<Snapshot>,
+    deltas: VecDeque<Snapshot>,
+    used: usize,
+}
+
+impl Rewind {
+    // a snapshot every interval frames, in at most budget bytes
+    pub fn new(interval: u64, budget: usize) -> Rewind {
+        Rewind {
+            interval: interval.max(1),
+            budget,
+
+            latest: None,
+            deltas: VecDeque::new(),
+            used: 0,
+        }
+    }
+
+    pub fn interval(&self) -> u64 {
+        self.interval
+    }
+
+    pub fn budget(&self) -> usize {
+        self.budget
+    }
+
+    // bytes currently held, including the newest full snapshot
+    pub fn used(&self) -> usize {
+        self.used
+    }
+
+    // how many snapshots can be stepped back through
+    pub fn len(&self) -> usize {
+        self.deltas.len() + self.latest.is_some() as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.latest.is_none()
+    }
+
+    pub fn clear(&mut self) {
+        self.latest = None;
+        self.deltas.clear();
+        self.used = 0;
+    }
+
+    pub fn wants_snapshot(&self, frame: u64) -> bool {
+        frame % self.interval == 0 && self.latest.as_ref().map(|s| s.frame) != Some(frame)
+    }
+
+    // the state should be uncompressed so unchanged parts line up with the previous one
+    pub fn push(&mut self, frame: u64, state: Vec<u8>) {
+        if let Some(previous) = self.latest.take() {
+            self.used -= previous.data.len();
+
+            let delta = encode_delta(&previous.data, &state);
+            self.used += delta.len();
+            self.deltas.push_back(Snapshot {
+                frame: previous.frame,
+                data: delta,
+            });
+        }
+
+        self.used += state.len();
+        self.latest = Some(Snapshot {
+            frame,
+            data: state,
+        });
+
+        while self.used > self.budget {
+            match self.deltas.pop_front() {
+                Some(oldest) => self.used -= oldest.data.len(),
+                None => break,
+            }
+        }
+    }
+
+    // drops every snapshot from the given frame on and returns the newest one left, which
+    // stays in the ring as the base for the next delta
+    pub fn rewind_to_before(&mut self, frame: u64) -> Result<Option<(u64, Vec<u8>)>, Error> {
+        while let Some(latest) = self.latest.take() {
+            if latest.frame < frame {
+                let found = (latest.frame, latest.data.clone());
+                self.latest = Some(latest);
+                return Ok(Some(found));
+            }
+
+            self.used -= latest.data.len();
+            if let Some(delta) = self.deltas.pop_back() {
+                self.used -= delta.data.len();
+
+                let data = apply_delta(&latest.data, &delta.data)?;
+                self.used += data.len();
+                self.latest = Some(Snapshot {
+                    frame: delta.frame,
+                    data,
+                });
+            }
+        }
+
+        Ok(None)
+    }
+}
+
+////////////////////////////////////
+//
+//              DELTAS
+//
+////////////////////////////////////
+
+// older as spans of older ^ newer where they differ. layout:
+//   u32 length of older
+//   spans until the end: u32 bytes skipped since the last span, u32 length, the xored bytes
+// bytes past the end of the shorter state count as 0
+fn encode_delta(older: &[u8], newer: &[u8]) -> Vec<u8> {
+    let len = older.len().max(newer.len());
+    let mut out = Vec::new();
+    push_u32(&mut out, older.len());
+
+    let mut last_end = 0;
+    let mut i = 0;
+    while i < len {
+        let end = (i + BLOCK_SIZE).min(len);
+        if same(older, newer, i, end) {
+            i = end;
+            continue;
+        }
+
+        let start = i;
+        while i < len {
+            let end = (i + BLOCK_SIZE).min(len);
+            if same(older, newer, i, end) {
+                break;
+            }
+            i = end;
+        }
+
+        push_u32(&mut out, start - last_end);
+        push_u32(&mut out, i - start);
+        out.extend((start..i).map(|n| byte(older, n) ^ byte(newer, n)));
+        last_end = i;
+    }
+
+    out
+}
+
+fn apply_delta(newer: &[u8], delta: &[u8]) -> Result<Vec<u8>, Error> {
+    let older_len = read_u32(delta, 0)?;
+    let mut data = newer.to_vec();
+    data.resize(older_len.max(newer.len()), 0);
+
+    let mut pos = 4;
+    let mut at = 0;
+    while pos < delta.len() {
+        at += read_u32(delta, pos)?;
+        let len = read_u32(delta, pos + 4)?;
+        pos += 8;
+
+        if pos + len > delta.len() || at + len > data.len() {
+            return Err(Error::new(ErrorKind::InvalidData, "corrupt rewind delta"));
+        }
+        for (byte, xor) in data[at..at + len].iter_mut().zip(&delta[pos..pos + len]) {
+            *byte ^= xor;
+        }
+        pos += len;
+        at += len;
+    }
+
+    data.truncate(older_len);
+    Ok(data)
+}
+
+fn same(a: &[u8], b: &[u8], start: usize, end: usize) -> bool {
+    if end <= a.len() && end <= b.len() {
+        a[start..end] == b[start..end]
+    } else {
+        (start..end).all(|n| byte(a, n) == byte(b, n))
+    }
+}
+
+fn byte(data: &[u8], n: usize) -> u8 {
+    data.get(n).cloned().unwrap_or(0)
+}
+
+fn push_u32(out: &mut Vec<u8>, value: usize) {
+    out.extend_from_slice(&[value as u8, (value >> 8) as u8, (value >> 16) as u8, (value >> 24) as u8]);
+}
+
+fn read_u32(data: &[u8], pos: usize) -> Result<usize, Error> {
+    if pos + 4 > data.len() {
+        return Err(Error::new(ErrorKind::InvalidData, "corrupt rewind delta"));
+    }
+    Ok(data[pos..pos + 4].iter().rev().fold(0, |value, &byte| (value << 8) | byte as usize))
+}
@@ -0,0 +1,292 @@
+use std::fs;
+use std::io::{self, BufWriter, Error};
+use std::path::Path;
+
+use apu::spc_file::SAMPLE_RATE;
+use apu::wav;
+use cartridge::{self, Cartridge};
+use cdl::CodeDataLog;
+use cheat::{self, Cheat};
+use debugger::profiler::Profiler;
+use debugger::{self, gdb, Debugger};
+use emulator::Emulator;
+use input::log;
+use movie::Movie;
+use patch::{self, IpsHeader};
+use ppu::golden;
+use savestate;
+use suite::{self, Suite};
+use symbols;
+use util::crc32::crc32;
+
+pub const USAGE: &'static str = "usage: snes-run <rom> [--frames N] [--screenshot out.png] [--audio out.wav] [--spc out.spc] \
+                                 [--input log.txt] [--trace trace.log] [--hash] [--load-slot N] [--save-slot N] \
+                                 [--movie in.snm] [--record out.snm] [--no-sram] [--patch p.bps]... [--ips-header detect|headerless|headered] [--cheat code]... [--debug] [--gdb PORT] [--cdl log.cdl] [--symbols game.sym]... [--profile out.folded] \
+                                 [--golden frame.png] [--tolerance N]";
+
+pub const EXIT_OK: i32 = 0;
+pub const EXIT_ERROR: i32 = 1;
+pub const EXIT_USAGE: i32 = 2;
+
+const DEFAULT_FRAMES: u64 = 60;
+
+// headless, deterministic runs for CI. nothing depends on the wall clock or the host, so the
+// same rom, frame count and input log always give the same framebuffer and audio
+pub struct Options {
+    pub rom: String,
+    pub frames: u64,
+    pub screenshot: Option<String>,
+    pub audio: Option<String>,
+    pub spc: Option<String>, // where to dump the sound state once the frames have run
+    pub input: Option<String>,
+    pub trace: Option<String>,
+    pub hash: bool, // print crc32s of the final framebuffer and all the audio
+    pub load_slot: Option<u8>, // save state slot to start from
+    pub save_slot: Option<u8>, // save state slot to write once the frames have run
+    pub movie: Option<String>,  // movie to play back, checked for desyncs
+    pub record: Option<String>, // where to save a movie of the run
+    pub sram: bool,             // load and save battery backed sram next to the rom
+    pub patches: Vec<String>,   // ips/bps/ups patches applied in order, or one found next to the rom
+    pub ips_header: IpsHeader,  // whether ips offsets count a copier header
+    pub cheats: Vec<String>,    // codes to enable on top of the rom's cheat list
+    pub debug: bool,            // run the debugger on stdin instead of a fixed number of frames
+    pub gdb: Option<u16>,       // wait for gdb on this localhost port and let it drive instead
+    pub cdl: Option<String>,    // code/data log of the rom, added to if the file exists
+    pub symbols: Vec<String>,   // .sym, .dbg or .mlb files, or the ones found next to the rom
+    pub profile: Option<String>, // where to write the cycles spent in each function, as folded stacks
+    pub golden: Option<String>,  // .png the final frame has to match, a diff is written next to it if not
+    pub tolerance: u8,           // how far off a channel of a pixel can be and still match
+}
+
+impl Options {
+    // args doesn't include the program name
+    pub fn parse(args: &[String]) -> Result<Options, String> {
+        let mut options = Options {
+            rom: String::new(),
+            frames: DEFAULT_FRAMES,
+            screenshot: None,
+            audio: None,
+            spc: None,
+            input: None,
+            trace: None,
+            hash: false,
+            load_slot: None,
+            save_slot: None,
+            movie: None,
+            record: None,
+            sram: true,
+            patches: Vec::new(),
+            ips_header: IpsHeader::Detect,
+            cheats: Vec::new(),
+            debug: false,
+            gdb: None,
+            cdl: None,
+            symbols: Vec::new(),
+            profile: None,
+            golden: None,
+            tolerance: 0,
+        };
+
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().cloned().ok_or_else(|| format!("{} needs a value", arg));
+
+            match arg.as_str() {
+                "--frames" => {
+                    let frames = value()?;
+                    options.frames = frames.parse().map_err(|_| format!("bad frame count: {}", frames))?;
+                },
+                "--screenshot" => options.screenshot = Some(value()?),
+                "--audio" => options.audio = Some(value()?),
+                "--spc" => options.spc = Some(value()?),
+                "--input" => options.input = Some(value()?),
+                "--trace" => options.trace = Some(value()?),
+                "--hash" => options.hash = true,
+                "--load-slot" => options.load_slot = Some(parse_slot(&value()?)?),
+                "--save-slot" => options.save_slot = Some(parse_slot(&value()?)?),
+                "--movie" => options.movie = Some(value()?),
+                "--record" => options.record = Some(value()?),
+                "--no-sram" => options.sram = false,
+                "--patch" => options.patches.push(value()?),
+                "--ips-header" => {
+                    let name = value()?;
+                    options.ips_header = IpsHeader::from_name(&name)
+                        .ok_or_else(|| format!("bad ips header: {} (detect, headerless or headered)", name))?;
+                },
+                "--cheat" => options.cheats.push(value()?),
+                "--debug" => options.debug = true,
+                "--cdl" => options.cdl = Some(value()?),
+                "--symbols" => options.symbols.push(value()?),
+                "--profile" => options.profile = Some(value()?),
+                "--golden" => options.golden = Some(value()?),
+                "--tolerance" => {
+                    let tolerance = value()?;
+                    options.tolerance = tolerance.parse().map_err(|_| format!("bad tolerance: {} (0-255)", tolerance))?;
+                },
+                "--gdb" => {
+                    let port = value()?;
+                    options.gdb = Some(port.parse().map_err(|_| format!("bad port: {}", port))?);
+                },
+                _ if arg.starts_with("--") => return Err(format!("unknown option: {}", arg)),
+                _ if options.rom.is_empty() => options.rom = arg.clone(),
+                _ => return Err(format!("unexpected argument: {}", arg)),
+            }
+        }
+
+        if options.rom.is_empty() {
+            return Err("no rom given".to_string());
+        }
+        if options.movie.is_some() && options.record.is_some() {
+            return Err("--movie and --record can't be used together".to_string());
+        }
+        if options.debug && options.gdb.is_some() {
+            return Err("--debug and --gdb can't be used together".to_string());
+        }
+
+        Ok(options)
+    }
+}
+
+fn parse_slot(slot: &str) -> Result<u8, String> {
+    match slot.parse() {
+        Ok(slot) if slot < savestate::SLOT_COUNT => Ok(slot),
+        _ => Err(format!("bad save state slot: {} (0-{})", slot, savestate::SLOT_COUNT - 1)),
+    }
+}
+
+pub fn run(options: &Options) -> Result<(), Error> {
+    let mut emulator = Emulator::new();
+    emulator.load_cartridge(Cartridge::new(patch::read_patched(&options.rom, &options.patches, options.ips_header)?)?);
+    // movies always start from blank sram, so they play back the same on every machine
+    if options.sram && options.movie.is_none() && options.record.is_none() {
+        emulator.set_sram_path(Some(cartridge::sram_path(&options.rom)))?;
+    }
+
+    // the cheat list is left out of movies for the same reason
+    let cheat_path = cheat::cheat_path(&options.rom);
+    if options.movie.is_none() && options.record.is_none() && Path::new(&cheat_path).is_file() {
+        emulator.bus_mut().cheats_mut().load(&cheat_path)?;
+    }
+    for code in options.cheats.iter() {
+        emulator.bus_mut().cheats_mut().add(Cheat::parse(code, "")?);
+    }
+
+    let symbol_files = if options.symbols.is_empty() { symbols::find_symbol_files(&options.rom) } else { options.symbols.clone() };
+    for path in symbol_files.iter() {
+        emulator.bus_mut().add_symbols(symbols::load(path)?);
+    }
+
+    if let Some(slot) = options.load_slot {
+        emulator.load_state(&savestate::read_slot(&options.rom, slot)?)?;
+    }
+
+    if let Some(ref path) = options.input {
+        emulator.input_mut().set_provider(Box::new(log::load_log(path)?));
+    }
+    if let Some(ref path) = options.movie {
+        emulator.play_movie(Movie::load(path)?)?;
+    }
+    if options.record.is_some() {
+        emulator.record_movie(options.load_slot.is_none());
+    }
+    if let Some(ref path) = options.trace {
+        emulator.set_trace(Some(Box::new(BufWriter::new(fs::File::create(path)?))));
+    }
+    if let Some(ref path) = options.cdl {
+        let rom_len = emulator.cartridge().map_or(0, |cartridge| cartridge.rom().len());
+        let log = if Path::new(path).is_file() { CodeDataLog::load(path, rom_len)? } else { CodeDataLog::new(rom_len) };
+        emulator.set_code_data_log(Some(log));
+    }
+    if options.profile.is_some() {
+        emulator.set_profiler(Some(Profiler::new()));
+    }
+
+    let mut audio = Vec::new();
+    if options.debug {
+        let stdin = io::stdin();
+        Debugger::new().repl(&mut emulator, stdin.lock(), &mut io::stdout())?;
+        audio.extend(emulator.audio_samples());
+    } else if let Some(port) = options.gdb {
+        gdb::listen(&mut emulator, port)?;
+        audio.extend(emulator.audio_samples());
+    } else {
+        for _ in 0..options.frames {
+            emulator.run_frame();
+            audio.extend(emulator.audio_samples());
+        }
+    }
+
+    // dropping the trace flushes it
+    emulator.set_trace(None);
+
+    emulator.flush_sram()?;
+
+    if let Some(ref path) = options.cdl {
+        if let Some(log) = emulator.take_code_data_log() {
+            log.save(path)?;
+        }
+    }
+    if let Some(ref path) = options.profile {
+        if let Some(profiler) = emulator.profiler() {
+            profiler.save_folded(path, |bank, address| debugger::function_name(&emulator, bank, address))?;
+        }
+    }
+
+    let desync = emulator.movie().and_then(|session| session.desync());
+    if let Some(ref path) = options.record {
+        if let Some(movie) = emulator.stop_movie() {
+            movie.save(path)?;
+        }
+    }
+
+    if let Some(slot) = options.save_slot {
+        savestate::write_slot(&options.rom, slot, &emulator.save_state())?;
+    }
+
+    if let Some(ref path) = options.screenshot {
+        emulator.save_screenshot(path)?;
+    }
+
+    if let Some(ref path) = options.spc {
+        emulator.save_spc(path)?;
+    }
+
+    if let Some(ref path) = options.audio {
+        wav::write_wav(path, &audio, SAMPLE_RATE)?;
+    }
+
+    if options.hash {
+        let samples: Vec<u8> = audio.iter()
+            .flat_map(|&sample| vec![sample as u8, (sample >> 8) as u8])
+            .collect();
+
+        println!("frames {}", emulator.frame());
+        println!("framebuffer crc32 {:08x}", suite::framebuffer_crc32(&emulator));
+        println!("audio crc32 {:08x}", crc32(&samples));
+    }
+
+    if let Some(frame) = desync {
+        return Err(Error::other(format!("movie desynced at frame {}", frame)));
+    }
+
+    if let Some(ref path) = options.golden {
+        let diff_path = golden::diff_path(path);
+        let comparison = golden::compare_png(emulator.framebuffer(), emulator.width(), emulator.height(), path, options.tolerance, &diff_path)?;
+        if !comparison.matches() {
+            return Err(Error::other(format!(
+                "{} pixels differ from {} by up to {}, see {}",
+                comparison.differing, path, comparison.largest, diff_path
+            )));
+        }
+    }
+
+    Ok(())
+}
+
+// runs the test roms listed in dir's suite.txt and prints how they did. returns false if
+// any failed
+pub fn run_suite(dir: &str) -> Result<bool, Error> {
+    let results = Suite::load(dir)?.run();
+    print!("{}", suite::report(&results));
+    Ok(suite::all_passed(&results))
+}
@@ -0,0 +1,290 @@
+use std::fs;
+use std::io::{Error, ErrorKind};
+use std::path::Path;
+
+use util::rle;
+
+// save state layout
+// =================
+// $00  8 byte signature "SNESSTAT"
+// $08  format version, u32
+// $0C  crc32 of the rom the state was taken from, u32
+// $10  sections until the end of the file:
+//        4 byte tag, u32 length, contents
+//
+// everything is little endian. a reader skips sections it doesn't know and ignores bytes left
+// over at the end of a section it does know. states from any other version aren't read at all,
+// the version goes up whenever a field moves.
+//
+// big blocks like memory are stored as an encoding byte, a u32 length and the data, either
+// run length encoded or as is. uncompressed states keep every field at the same offset from
+// one state to the next, which is what the rewind buffer's deltas rely on
+//
+// the ppu section only has the h/v counters and the beam position. vram, cgram, oam and the
+// ppu's register latches aren't emulated yet, so no state holds them
+//
+// versions
+// 1  blocks are a u32 length and run length encoded data, with no encoding byte
+// 2  blocks start with the encoding byte
+// 3  $420D in the bus and the beam position in the ppu
+// 4  the ppu no longer stores its framebuffer
+const SIGNATURE: &'static [u8; 8] = b"SNESSTAT";
+const HEADER_SIZE: usize = 0x10;
+
+pub const FORMAT_VERSION: u32 = 4;
+
+pub const SLOT_COUNT: u8 = 10;
+
+pub type Tag = [u8; 4];
+
+const STORED: u8 = 0;
+const RLE: u8 = 1;
+
+pub struct StateWriter {
+    bytes: Vec<u8>,
+    compress: bool,
+}
+
+impl StateWriter {
+    pub fn new(rom_crc32: u32) -> StateWriter {
+        Self::with_compression(rom_crc32, true)
+    }
+
+    // bigger, but cheap to write and to diff against another uncompressed state
+    pub fn uncompressed(rom_crc32: u32) -> StateWriter {
+        Self::with_compression(rom_crc32, false)
+    }
+
+    fn with_compression(rom_crc32: u32, compress: bool) -> StateWriter {
+        let mut writer = StateWriter {
+            bytes: Vec::new(),
+            compress,
+        };
+
+        writer.bytes.extend_from_slice(SIGNATURE);
+        writer.u32(FORMAT_VERSION);
+        writer.u32(rom_crc32);
+        writer
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.bytes
+    }
+
+    // everything the closure writes goes into one section
+    pub fn section<F: FnOnce(&mut StateWriter)>(&mut self, tag: &Tag, contents: F) {
+        self.bytes.extend_from_slice(tag);
+        let length_at = self.bytes.len();
+        self.u32(0);
+
+        contents(self);
+
+        let length = (self.bytes.len() - length_at - 4) as u32;
+        self.bytes[length_at..length_at + 4].copy_from_slice(&le_bytes(length as u64, 4));
+    }
+
+    pub fn u8(&mut self, value: u8) {
+        self.bytes.push(value);
+    }
+
+    pub fn bool(&mut self, value: bool) {
+        self.u8(value as u8);
+    }
+
+    pub fn u16(&mut self, value: u16) {
+        self.bytes.extend_from_slice(&le_bytes(value as u64, 2));
+    }
+
+    pub fn u32(&mut self, value: u32) {
+        self.bytes.extend_from_slice(&le_bytes(value as u64, 4));
+    }
+
+    pub fn i32(&mut self, value: i32) {
+        self.u32(value as u32);
+    }
+
+    pub fn u64(&mut self, value: u64) {
+        self.bytes.extend_from_slice(&le_bytes(value, 8));
+    }
+
+    // a fixed size block, the reader has to know the size
+    pub fn bytes(&mut self, bytes: &[u8]) {
+        self.bytes.extend_from_slice(bytes);
+    }
+
+    // a big block that's mostly runs, eg memory. length prefixed
+    pub fn compressed(&mut self, bytes: &[u8]) {
+        if self.compress {
+            let compressed = rle::compress(bytes);
+            self.u8(RLE);
+            self.u32(compressed.len() as u32);
+            self.bytes.extend_from_slice(&compressed);
+        } else {
+            self.u8(STORED);
+            self.u32(bytes.len() as u32);
+            self.bytes.extend_from_slice(bytes);
+        }
+    }
+}
+
+pub struct StateReader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> StateReader<'a> {
+    pub fn new(bytes: &'a [u8]) -> StateReader<'a> {
+        StateReader {
+            bytes,
+            pos: 0,
+        }
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], Error> {
+        if self.pos + len > self.bytes.len() {
+            return Err(Error::new(ErrorKind::UnexpectedEof, "save state section is truncated"));
+        }
+
+        let bytes = &self.bytes[self.pos..self.pos + len];
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    pub fn u8(&mut self) -> Result<u8, Error> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub fn bool(&mut self) -> Result<bool, Error> {
+        Ok(self.u8()? != 0)
+    }
+
+    pub fn u16(&mut self) -> Result<u16, Error> {
+        Ok(from_le_bytes(self.take(2)?) as u16)
+    }
+
+    pub fn u32(&mut self) -> Result<u32, Error> {
+        Ok(from_le_bytes(self.take(4)?) as u32)
+    }
+
+    pub fn i32(&mut self) -> Result<i32, Error> {
+        Ok(self.u32()? as i32)
+    }
+
+    pub fn u64(&mut self) -> Result<u64, Error> {
+        Ok(from_le_bytes(self.take(8)?))
+    }
+
+    pub fn bytes(&mut self, out: &mut [u8]) -> Result<(), Error> {
+        out.copy_from_slice(self.take(out.len())?);
+        Ok(())
+    }
+
+    pub fn compressed(&mut self, out: &mut [u8]) -> Result<(), Error> {
+        let encoding = self.u8()?;
+        let len = self.u32()? as usize;
+
+        match encoding {
+            RLE => rle::decompress(self.take(len)?, out),
+            STORED if len == out.len() => self.bytes(out),
+            STORED => Err(Error::new(ErrorKind::InvalidData, "save state block has the wrong size")),
+            _ => Err(Error::new(ErrorKind::InvalidData, "unknown save state block encoding")),
+        }
+    }
+}
+
+// a parsed save state file
+pub struct SaveState<'a> {
+    pub version: u32,
+    pub rom_crc32: u32,
+    sections: Vec<(Tag, &'a [u8])>,
+}
+
+impl<'a> SaveState<'a> {
+    pub fn parse(bytes: &'a [u8]) -> Result<SaveState<'a>, Error> {
+        if bytes.len() < HEADER_SIZE || &bytes[0..8] != SIGNATURE {
+            return Err(Error::new(ErrorKind::InvalidData, "not a save state"));
+        }
+
+        let version = from_le_bytes(&bytes[0x08..0x0C]) as u32;
+        let rom_crc32 = from_le_bytes(&bytes[0x0C..0x10]) as u32;
+
+        let mut sections = Vec::new();
+        let mut pos = HEADER_SIZE;
+        while pos < bytes.len() {
+            if pos + 8 > bytes.len() {
+                return Err(Error::new(ErrorKind::InvalidData, "save state is truncated"));
+            }
+
+            let mut tag = [0; 4];
+            tag.copy_from_slice(&bytes[pos..pos + 4]);
+            let len = from_le_bytes(&bytes[pos + 4..pos + 8]) as usize;
+            pos += 8;
+
+            if pos + len > bytes.len() {
+                return Err(Error::new(ErrorKind::InvalidData, "save state is truncated"));
+            }
+            sections.push((tag, &bytes[pos..pos + len]));
+            pos += len;
+        }
+
+        Ok(SaveState {
+            version,
+            rom_crc32,
+            sections,
+        })
+    }
+
+    pub fn has_section(&self, tag: &Tag) -> bool {
+        self.sections.iter().any(|&(t, _)| &t == tag)
+    }
+
+    pub fn section(&self, tag: &Tag) -> Result<StateReader<'a>, Error> {
+        self.sections.iter()
+            .find(|&&(t, _)| &t == tag)
+            .map(|&(_, contents)| StateReader::new(contents))
+            .ok_or_else(|| Error::new(ErrorKind::InvalidData,
+                format!("save state has no {} section", String::from_utf8_lossy(tag).trim())))
+    }
+}
+
+////////////////////////////////////
+//
+//              SLOTS
+//
+////////////////////////////////////
+
+// slots live next to the rom: game.sfc -> game.ss0 ... game.ss9
+pub fn slot_path(rom_path: &str, slot: u8) -> String {
+    Path::new(rom_path).with_extension(format!("ss{}", slot)).to_string_lossy().into_owned()
+}
+
+pub fn write_slot(rom_path: &str, slot: u8, state: &[u8]) -> Result<(), Error> {
+    check_slot(slot)?;
+    fs::write(slot_path(rom_path, slot), state)
+}
+
+pub fn read_slot(rom_path: &str, slot: u8) -> Result<Vec<u8>, Error> {
+    check_slot(slot)?;
+    fs::read(slot_path(rom_path, slot))
+}
+
+fn check_slot(slot: u8) -> Result<(), Error> {
+    if slot >= SLOT_COUNT {
+        return Err(Error::new(ErrorKind::InvalidInput, format!("there are only {} save state slots", SLOT_COUNT)));
+    }
+    Ok(())
+}
+
+////////////////////////////////////
+//
+//             HELPERS
+//
+////////////////////////////////////
+
+fn le_bytes(value: u64, len: usize) -> Vec<u8> {
+    (0..len).map(|i| (value >> (i * 8)) as u8).collect()
+}
+
+fn from_le_bytes(bytes: &[u8]) -> u64 {
+    bytes.iter().rev().fold(0, |value, &byte| (value << 8) | byte as u64)
+}
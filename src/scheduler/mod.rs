@@ -0,0 +1,99 @@
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::io::{Error, ErrorKind};
+
+use savestate::{StateReader, StateWriter};
+
+// things that happen at fixed points in the frame rather than in response to an access
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum Event {
+    NewLine,     // dot 0 of every line, the v counter moves on
+    DramRefresh, // the cpu is halted for 40 master cycles while WRAM refreshes
+    HBlankStart,
+    HvIrq,       // the h/v counters matched HTIMER / VTIMER
+}
+
+impl Event {
+    const ALL: [Event; 4] = [Event::NewLine, Event::DramRefresh, Event::HBlankStart, Event::HvIrq];
+
+    fn id(self) -> u8 {
+        Event::ALL.iter().position(|&e| e == self).unwrap() as u8
+    }
+
+    fn from_id(id: u8) -> Option<Event> {
+        Event::ALL.get(id as usize).cloned()
+    }
+}
+
+// a queue of events keyed by master cycle timestamps. events at the same timestamp come out
+// in the order they were scheduled so runs are always reproducible
+pub struct Scheduler {
+    queue: BinaryHeap<Reverse<(u64, u64, Event)>>,
+    sequence: u64,
+}
+
+impl Scheduler {
+    pub fn new() -> Scheduler {
+        Scheduler {
+            queue: BinaryHeap::new(),
+            sequence: 0,
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.queue.clear();
+        self.sequence = 0;
+    }
+
+    pub fn schedule(&mut self, at: u64, event: Event) {
+        self.queue.push(Reverse((at, self.sequence, event)));
+        self.sequence += 1;
+    }
+
+    // drops every pending occurrence of an event, eg when the irq timers are reprogrammed
+    pub fn cancel(&mut self, event: Event) {
+        let pending: Vec<_> = self.queue.drain().filter(|&Reverse((_, _, e))| e != event).collect();
+        self.queue = pending.into_iter().collect();
+    }
+
+    pub fn next_at(&self) -> Option<u64> {
+        self.queue.peek().map(|&Reverse((at, _, _))| at)
+    }
+
+    // takes the next event if it's due at or before the given time
+    pub fn pop_due(&mut self, until: u64) -> Option<(u64, Event)> {
+        match self.next_at() {
+            Some(at) if at <= until => self.queue.pop().map(|Reverse((at, _, event))| (at, event)),
+            _ => None,
+        }
+    }
+
+    // (timestamp, event) in the order they will fire
+    pub fn pending(&self) -> Vec<(u64, Event)> {
+        let mut pending: Vec<_> = self.queue.iter().map(|&Reverse(entry)| entry).collect();
+        pending.sort();
+        pending.into_iter().map(|(at, _, event)| (at, event)).collect()
+    }
+
+    pub fn save_state(&self, w: &mut StateWriter) {
+        let pending = self.pending();
+        w.u32(pending.len() as u32);
+        for (at, event) in pending {
+            w.u64(at);
+            w.u8(event.id());
+        }
+    }
+
+    pub fn load_state(&mut self, r: &mut StateReader) -> Result<(), Error> {
+        self.clear();
+
+        let count = r.u32()?;
+        for _ in 0..count {
+            let at = r.u64()?;
+            let event = Event::from_id(r.u8()?)
+                .ok_or_else(|| Error::new(ErrorKind::InvalidData, "unknown scheduler event"))?;
+            self.schedule(at, event);
+        }
+        Ok(())
+    }
+}
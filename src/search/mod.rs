@@ -0,0 +1,307 @@
+use std::fmt;
+
+use bus::watch::{Space, Watchpoint};
+use bus::Bus;
+use cartridge::Cartridge;
+use cheat::{Cheat, Code, CodeKind};
+
+// memory search
+// =============
+// the usual way to find where a game keeps a value like the player's health: snapshot
+// ram, play until the value changes, then keep only the addresses that changed the same
+// way, and repeat until a handful are left. every filter compares memory as it is now
+// with the snapshot the last one took, then takes a new one
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Region {
+    WRAM,
+    SRAM, // the cartridge's, if it has any
+}
+
+impl Region {
+    pub fn name(self) -> &'static str {
+        match self {
+            Region::WRAM => "wram",
+            Region::SRAM => "sram",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Region> {
+        [Region::WRAM, Region::SRAM].iter().find(|region| region.name() == name).cloned()
+    }
+
+    pub fn memory(self, bus: &Bus) -> &[u8] {
+        match self {
+            Region::WRAM => bus.wram(),
+            Region::SRAM => bus.cartridge().map_or(&[][..], |cartridge| cartridge.sram()),
+        }
+    }
+
+    // the first cpu address an offset shows up at
+    pub fn address(self, offset: usize, cartridge: Option<&Cartridge>) -> Option<(u8, u16)> {
+        match self {
+            Region::WRAM if offset < 0x20000 => Some((0x7E + (offset >> 16) as u8, offset as u16)),
+            Region::WRAM => None,
+            Region::SRAM => cartridge?.sram_address(offset),
+        }
+    }
+}
+
+// how the bytes of a value are read, always least significant first
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Encoding {
+    Binary,
+    BCD, // two decimal digits a byte, how a lot of games keep scores
+}
+
+impl fmt::Display for Encoding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            Encoding::Binary => "binary",
+            Encoding::BCD => "bcd",
+        })
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Operand {
+    Value(u32),
+    Previous, // what the address held in the last snapshot
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Filter {
+    Equal(Operand),
+    NotEqual(Operand),
+    Greater(Operand),
+    Less(Operand),
+    Delta(i64), // went up or down by exactly this much, wrapping around like the counter would
+}
+
+impl Filter {
+    // "changed", "unchanged", "= N", "!= N", "> N", "< N", "+N" or "-N". N is decimal, or hex
+    // with a leading $. without N the comparison is with the last snapshot
+    pub fn parse(text: &str) -> Option<Filter> {
+        let text = text.trim();
+        match text {
+            "changed" => return Some(Filter::NotEqual(Operand::Previous)),
+            "unchanged" => return Some(Filter::Equal(Operand::Previous)),
+            _ => {},
+        }
+
+        let split = text.find(|c: char| !"=!<>".contains(c)).unwrap_or(text.len());
+        let (operator, operand) = (&text[..split], text[split..].trim());
+
+        if operator.is_empty() {
+            let sign = match operand.chars().next()? {
+                '+' => 1,
+                '-' => -1,
+                _ => return None,
+            };
+            return Some(Filter::Delta(sign * parse_value(&operand[1..])? as i64));
+        }
+
+        let operand = if operand.is_empty() { Operand::Previous } else { Operand::Value(parse_value(operand)?) };
+        match operator {
+            "=" | "==" => Some(Filter::Equal(operand)),
+            "!=" => Some(Filter::NotEqual(operand)),
+            ">" => Some(Filter::Greater(operand)),
+            "<" => Some(Filter::Less(operand)),
+            _ => None,
+        }
+    }
+
+    fn matches(self, value: u32, previous: u32, modulus: i64) -> bool {
+        let operand = |operand| match operand {
+            Operand::Value(value) => value,
+            Operand::Previous => previous,
+        };
+        match self {
+            Filter::Equal(other) => value == operand(other),
+            Filter::NotEqual(other) => value != operand(other),
+            Filter::Greater(other) => value > operand(other),
+            Filter::Less(other) => value < operand(other),
+            Filter::Delta(delta) => (previous as i64 + delta).rem_euclid(modulus) == value as i64,
+        }
+    }
+}
+
+// one address still in the running
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Candidate {
+    pub offset: usize,
+    pub value: u32,
+    pub previous: u32,
+}
+
+pub struct Search {
+    region: Region,
+    width: usize, // in bytes, 1 to 3
+    encoding: Encoding,
+    snapshot: Vec<u8>,
+    candidates: Vec<usize>, // offsets into the region, in order
+}
+
+impl Search {
+    // starts with every offset a value of this width fits at as a candidate, apart from
+    // bcd values whose bytes aren't all decimal digits
+    pub fn new(region: Region, width: usize, encoding: Encoding, memory: &[u8]) -> Search {
+        assert!((1..=3).contains(&width), "values are 8, 16 or 24 bit");
+
+        let mut search = Search {
+            region,
+            width,
+            encoding,
+            snapshot: memory.to_vec(),
+            candidates: Vec::new(),
+        };
+        search.candidates = (0..(memory.len() + 1).saturating_sub(width))
+            .filter(|&offset| search.decode(&search.snapshot, offset).is_some())
+            .collect();
+        search
+    }
+
+    pub fn region(&self) -> Region {
+        self.region
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn encoding(&self) -> Encoding {
+        self.encoding
+    }
+
+    pub fn len(&self) -> usize {
+        self.candidates.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.candidates.is_empty()
+    }
+
+    // keeps the candidates the filter matches and snapshots memory for the next one.
+    // returns how many are left
+    pub fn filter(&mut self, filter: Filter, memory: &[u8]) -> usize {
+        let modulus = match self.encoding {
+            Encoding::Binary => 1i64 << (8 * self.width),
+            Encoding::BCD => 100i64.pow(self.width as u32),
+        };
+
+        let mut candidates = Vec::new();
+        for &offset in self.candidates.iter() {
+            let value = self.decode(memory, offset);
+            let previous = self.decode(&self.snapshot, offset);
+            if let (Some(value), Some(previous)) = (value, previous) {
+                if filter.matches(value, previous, modulus) {
+                    candidates.push(offset);
+                }
+            }
+        }
+
+        self.candidates = candidates;
+        self.snapshot = memory.to_vec();
+        self.candidates.len()
+    }
+
+    // the candidates with what they hold now and held at the last snapshot
+    pub fn candidates(&self, memory: &[u8]) -> Vec<Candidate> {
+        self.candidates.iter()
+            .filter_map(|&offset| Some(Candidate {
+                offset,
+                value: self.decode(memory, offset)?,
+                previous: self.decode(&self.snapshot, offset)?,
+            }))
+            .collect()
+    }
+
+    pub fn offsets(&self) -> &[usize] {
+        &self.candidates
+    }
+
+    // a watchpoint on the bytes of the value at an offset, for finding the code that uses it
+    pub fn watchpoint(&self, offset: usize, accesses: u8, cartridge: Option<&Cartridge>) -> Option<Watchpoint> {
+        let (bank, address) = self.region.address(offset, cartridge)?;
+        let start = ((bank as u32) << 16) | address as u32;
+        Some(Watchpoint {
+            space: Space::Bus,
+            start,
+            end: start + self.width as u32 - 1,
+            accesses,
+            value: None,
+        })
+    }
+
+    // a pro action replay cheat that holds the value at an offset at value, without a
+    // description. None if the value doesn't fit or there's no cpu address for the offset
+    pub fn cheat(&self, offset: usize, value: u32, cartridge: Option<&Cartridge>) -> Option<Cheat> {
+        let bytes = self.encode(value)?;
+        let mut codes = Vec::new();
+        for (index, &byte) in bytes.iter().enumerate() {
+            let (bank, address) = self.region.address(offset + index, cartridge)?;
+            codes.push(Code {
+                kind: CodeKind::ProActionReplay,
+                address: ((bank as u32) << 16) | address as u32,
+                value: byte,
+                compare: None,
+            });
+        }
+
+        Some(Cheat {
+            codes,
+            enabled: true,
+            description: String::new(),
+        })
+    }
+
+    fn decode(&self, memory: &[u8], offset: usize) -> Option<u32> {
+        let bytes = memory.get(offset..offset + self.width)?;
+        let mut value = 0;
+        for &byte in bytes.iter().rev() {
+            value = match self.encoding {
+                Encoding::Binary => (value << 8) | byte as u32,
+                Encoding::BCD if byte >> 4 < 10 && byte & 0x0F < 10 => value * 100 + (byte >> 4) as u32 * 10 + (byte & 0x0F) as u32,
+                Encoding::BCD => return None,
+            };
+        }
+        Some(value)
+    }
+
+    fn encode(&self, mut value: u32) -> Option<Vec<u8>> {
+        let mut bytes = Vec::new();
+        for _ in 0..self.width {
+            match self.encoding {
+                Encoding::Binary => {
+                    bytes.push(value as u8);
+                    value >>= 8;
+                },
+                Encoding::BCD => {
+                    let digits = value % 100;
+                    bytes.push((((digits / 10) << 4) | (digits % 10)) as u8);
+                    value /= 100;
+                },
+            }
+        }
+        if value != 0 {
+            return None;
+        }
+        Some(bytes)
+    }
+}
+
+// decimal, or hex with a leading $
+pub fn parse_value(text: &str) -> Option<u32> {
+    match text.strip_prefix('$') {
+        Some(hex) => u32::from_str_radix(hex, 16).ok(),
+        None => text.parse().ok(),
+    }
+}
+
+// 1234 ($04D2). bcd values read as decimal, so they're shown as that alone
+pub fn format_value(value: u32, encoding: Encoding) -> String {
+    match encoding {
+        Encoding::Binary => format!("{} (${:X})", value, value),
+        Encoding::BCD => format!("{}", value),
+    }
+}
@@ -0,0 +1,213 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::{Error, ErrorKind};
+
+// font.txt layout
+// ===============
+// one 8x8 glyph per line: the character's ascii code in hex, then its 8 rows as 16 hex
+// digits, top row first with the leftmost pixel in each row's top bit:
+//   41 183C66667E666600    A
+// anything after the glyph is ignored. that's the layout of a 1bpp font dump, so the font
+// can be taken straight out of the test roms that print with it. blank lines and lines
+// starting with '#' are skipped
+
+const GLYPH_SIZE: usize = 8;
+
+// printable ascii from font8x8_basic, the public domain 8x8 font a lot of homebrew test roms
+// print with. a suite's font.txt adds to it for roms that use their own
+pub const BUILTIN: &'static str = "\
+21 183C3C1818001800    !
+22 6C6C000000000000    \"
+23 6C6CFE6CFE6C6C00    #
+24 307CC0780CF83000    $
+25 00C6CC183066C600    %
+26 386C3876DCCC7600    &
+27 6060C00000000000    '
+28 1830606060301800    (
+29 6030181818306000    )
+2A 00663CFF3C660000    *
+2B 003030FC30300000    +
+2C 0000000000303060    ,
+2D 000000FC00000000    -
+2E 0000000000303000    .
+2F 060C183060C08000    /
+30 7CC6CEDEF6E67C00    0
+31 307030303030FC00    1
+32 78CC0C3860CCFC00    2
+33 78CC0C380CCC7800    3
+34 1C3C6CCCFE0C1E00    4
+35 FCC0F80C0CCC7800    5
+36 3860C0F8CCCC7800    6
+37 FCCC0C1830303000    7
+38 78CCCC78CCCC7800    8
+39 78CCCC7C0C187000    9
+3A 0030300000303000    :
+3B 0030300000303060    ;
+3C 183060C060301800    <
+3D 0000FC0000FC0000    =
+3E 6030180C18306000    >
+3F 78CC0C1830003000    ?
+40 7CC6DEDEDEC07800    @
+41 3078CCCCFCCCCC00    A
+42 FC66667C6666FC00    B
+43 3C66C0C0C0663C00    C
+44 F86C6666666CF800    D
+45 FE6268786862FE00    E
+46 FE6268786860F000    F
+47 3C66C0C0CE663E00    G
+48 CCCCCCFCCCCCCC00    H
+49 7830303030307800    I
+4A 1E0C0C0CCCCC7800    J
+4B E6666C786C66E600    K
+4C F06060606266FE00    L
+4D C6EEFEFED6C6C600    M
+4E C6E6F6DECEC6C600    N
+4F 386CC6C6C66C3800    O
+50 FC66667C6060F000    P
+51 78CCCCCCDC781C00    Q
+52 FC66667C6C66E600    R
+53 78CCE0701CCC7800    S
+54 FCB4303030307800    T
+55 CCCCCCCCCCCCFC00    U
+56 CCCCCCCCCC783000    V
+57 C6C6C6D6FEEEC600    W
+58 C6C66C38386CC600    X
+59 CCCCCC7830307800    Y
+5A FEC68C183266FE00    Z
+5B 7860606060607800    [
+5C C06030180C060200    \\
+5D 7818181818187800    ]
+5E 10386CC600000000    ^
+5F 00000000000000FF    _
+60 3030180000000000    `
+61 0000780C7CCC7600    a
+62 E060607C6666DC00    b
+63 000078CCC0CC7800    c
+64 1C0C0C7CCCCC7600    d
+65 000078CCFCC07800    e
+66 386C60F06060F000    f
+67 000076CCCC7C0CF8    g
+68 E0606C766666E600    h
+69 3000703030307800    i
+6A 0C000C0C0CCCCC78    j
+6B E060666C786CE600    k
+6C 7030303030307800    l
+6D 0000CCFEFED6C600    m
+6E 0000F8CCCCCCCC00    n
+6F 000078CCCCCC7800    o
+70 0000DC66667C60F0    p
+71 000076CCCC7C0C1E    q
+72 0000DC766660F000    r
+73 00007CC0780CF800    s
+74 10307C3030341800    t
+75 0000CCCCCCCC7600    u
+76 0000CCCCCC783000    v
+77 0000C6D6FEFE6C00    w
+78 0000C66C386CC600    x
+79 0000CCCCCC7C0CF8    y
+7A 0000FC983064FC00    z
+7B 1C3030E030301C00    {
+7C 1818180018181800    |
+7D E030301C3030E000    }
+7E 76DC000000000000    ~
+";
+
+// reads text off the screen by matching 8x8 cells against the glyphs of a known font.
+// whatever isn't the screen's most common colour counts as ink
+pub struct Font {
+    glyphs: HashMap<u64, char>,
+}
+
+impl Font {
+    pub fn parse(text: &str) -> Result<Font, Error> {
+        let mut glyphs = HashMap::new();
+
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let bad = || Error::new(ErrorKind::InvalidData, format!("font line {}: {}", number + 1, line));
+            let mut words = line.split_whitespace();
+            let code = words.next().and_then(|code| u8::from_str_radix(code, 16).ok()).ok_or_else(bad)?;
+            let rows = words.next().filter(|rows| rows.len() == 16).and_then(|rows| u64::from_str_radix(rows, 16).ok()).ok_or_else(bad)?;
+            glyphs.insert(rows, code as char);
+        }
+
+        Ok(Font { glyphs })
+    }
+
+    pub fn load(path: &str) -> Result<Font, Error> {
+        Font::parse(&fs::read_to_string(path)?)
+    }
+
+    pub fn builtin() -> Font {
+        Font::parse(BUILTIN).unwrap()
+    }
+
+    // other's glyphs win where both fonts draw a character the same way
+    pub fn extend(&mut self, other: Font) {
+        self.glyphs.extend(other.glyphs);
+    }
+
+    // the screen as lines of text. text can be anywhere on the 8x8 grid, depending on the
+    // scroll registers, so every alignment is tried and the one with the most glyphs kept.
+    // empty cells are spaces and ones that don't match a glyph are '?'
+    pub fn read_screen(&self, pixels: &[u32], width: usize, height: usize) -> String {
+        let ink = ink(pixels);
+
+        let mut best = (0, String::new());
+        for y in 0..GLYPH_SIZE {
+            for x in 0..GLYPH_SIZE {
+                let (found, text) = self.read_grid(&ink, width, height, x, y);
+                if found > best.0 {
+                    best = (found, text);
+                }
+            }
+        }
+        best.1
+    }
+
+    // returns the number of glyphs found and the text
+    fn read_grid(&self, ink: &[bool], width: usize, height: usize, left: usize, top: usize) -> (usize, String) {
+        let mut found = 0;
+        let mut text = String::new();
+
+        for cell_y in (top..height.saturating_sub(GLYPH_SIZE - 1)).step_by(GLYPH_SIZE) {
+            let mut line = String::new();
+            for cell_x in (left..width.saturating_sub(GLYPH_SIZE - 1)).step_by(GLYPH_SIZE) {
+                let mut rows = 0u64;
+                for y in 0..GLYPH_SIZE {
+                    for x in 0..GLYPH_SIZE {
+                        rows = (rows << 1) | ink[(cell_y + y) * width + cell_x + x] as u64;
+                    }
+                }
+
+                line.push(match (rows, self.glyphs.get(&rows)) {
+                    (0, _) => ' ',
+                    (_, Some(&glyph)) => {
+                        found += 1;
+                        glyph
+                    },
+                    (_, None) => '?',
+                });
+            }
+            text += line.trim_end();
+            text.push('\n');
+        }
+
+        (found, text)
+    }
+}
+
+// every pixel that isn't the background, the most common colour
+fn ink(pixels: &[u32]) -> Vec<bool> {
+    let mut counts: HashMap<u32, usize> = HashMap::new();
+    for &pixel in pixels {
+        *counts.entry(pixel).or_insert(0) += 1;
+    }
+    let background = counts.into_iter().max_by_key(|&(colour, count)| (count, colour)).map_or(0, |(colour, _)| colour);
+
+    pixels.iter().map(|&pixel| pixel != background).collect()
+}
@@ -0,0 +1,521 @@
+pub mod font;
+
+use std::fmt;
+use std::fs;
+use std::io::{Error, ErrorKind};
+use std::path::Path;
+
+use cartridge::Cartridge;
+use emulator::Emulator;
+use ppu::golden;
+use util::crc32::crc32;
+
+use self::font::Font;
+
+// suite.txt layout
+// ================
+// a directory of hardware test roms, like blargg's and krom's, with a suite.txt listing
+// them one per line:
+//   cpu  600   cpu/adc.sfc       ram 7E:0000 01
+//   ppu  120   ppu/mode7.sfc     hash 3f2a81c0
+//   apu  1800  spc/dsp_echo.sfc  text Passed
+//   ppu  120   ppu/hires.sfc     png ppu/hires.png 4
+// the subsystem the rom tests (cpu, ppu, apu or dma), the most frames it gets, the rom's
+// path from the directory and how to tell it passed:
+//   ram BB:AAAA XX   the byte at BB:AAAA ends up as XX
+//   hash XXXXXXXX    the crc32 of the last frame, the one snes-run --hash prints, is the
+//                    golden one. a failure prints what it was instead
+//   text WORDS       WORDS appear on screen, read with the built in font plus any glyphs
+//                    in the directory's font.txt
+//   png FILE [N]     the last frame matches the golden image FILE, from the directory,
+//                    with no channel more than N off (0 if left out). a failure writes a
+//                    diff image next to it, FILE.diff.png
+// text is checked every frame, so a rom that passes early stops early. the others are
+// checked once every frame has run. wram starts out zeroed, so ram checked any earlier
+// would pass an expected 00 before the rom had done anything. roms that aren't in the
+// directory are skipped rather than failed, they're not ours to check in.
+// blank lines and lines starting with '#' are skipped
+pub const SUITE_FILE: &'static str = "suite.txt";
+pub const FONT_FILE: &'static str = "font.txt";
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum Subsystem {
+    CPU,
+    PPU,
+    APU,
+    DMA,
+}
+
+impl Subsystem {
+    pub const ALL: [Subsystem; 4] = [Subsystem::CPU, Subsystem::PPU, Subsystem::APU, Subsystem::DMA];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Subsystem::CPU => "cpu",
+            Subsystem::PPU => "ppu",
+            Subsystem::APU => "apu",
+            Subsystem::DMA => "dma",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Subsystem> {
+        Subsystem::ALL.iter().find(|subsystem| subsystem.name() == name).cloned()
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum Check {
+    Ram(u8, u16, u8),
+    Hash(u32),
+    Text(String),
+    Png(String, u8), // golden image relative to the suite's directory, tolerance
+}
+
+impl fmt::Display for Check {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Check::Ram(bank, address, value) => write!(f, "ram {:02X}:{:04X} {:02X}", bank, address, value),
+            Check::Hash(hash) => write!(f, "hash {:08x}", hash),
+            Check::Text(ref text) => write!(f, "text {}", text),
+            Check::Png(ref path, tolerance) => write!(f, "png {} {}", path, tolerance),
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct TestRom {
+    pub subsystem: Subsystem,
+    pub frames: u64,
+    pub rom: String, // relative to the suite's directory
+    pub check: Check,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum Outcome {
+    Passed,
+    Failed(String), // why
+    Skipped(String),
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct TestResult {
+    pub test: TestRom,
+    pub outcome: Outcome,
+    pub frames: u64, // how many ran
+}
+
+pub struct Suite {
+    dir: String,
+    tests: Vec<TestRom>,
+    font: Font,
+}
+
+impl Suite {
+    // reads suite.txt from dir, and font.txt too if it's there
+    pub fn load(dir: &str) -> Result<Suite, Error> {
+        let suite_path = Path::new(dir).join(SUITE_FILE);
+        let text = fs::read_to_string(&suite_path)
+            .map_err(|e| Error::new(e.kind(), format!("{}: {}", suite_path.to_string_lossy(), e)))?;
+
+        let mut suite = Suite::new(dir, parse(&text)?);
+        let font_path = Path::new(dir).join(FONT_FILE);
+        if font_path.is_file() {
+            suite.font.extend(Font::load(&font_path.to_string_lossy())?);
+        }
+        Ok(suite)
+    }
+
+    pub fn new(dir: &str, tests: Vec<TestRom>) -> Suite {
+        Suite {
+            dir: dir.to_string(),
+            tests,
+            font: Font::builtin(),
+        }
+    }
+
+    pub fn tests(&self) -> &[TestRom] {
+        &self.tests
+    }
+
+    pub fn run(&self) -> Vec<TestResult> {
+        self.tests.iter().map(|test| self.run_test(test)).collect()
+    }
+
+    pub fn run_test(&self, test: &TestRom) -> TestResult {
+        let result = |outcome, frames| TestResult {
+            test: test.clone(),
+            outcome,
+            frames,
+        };
+
+        let path = Path::new(&self.dir).join(&test.rom);
+        if !path.is_file() {
+            return result(Outcome::Skipped("rom not found".to_string()), 0);
+        }
+
+        let cartridge = match fs::read(&path).and_then(Cartridge::new) {
+            Ok(cartridge) => cartridge,
+            Err(e) => return result(Outcome::Failed(e.to_string()), 0),
+        };
+
+        let mut emulator = Emulator::new();
+        emulator.load_cartridge(cartridge);
+
+        let start = emulator.frame();
+        let mut outcome = Outcome::Failed("no frames ran".to_string());
+        while emulator.frame() - start < test.frames {
+            emulator.run_frame();
+            if let Check::Text(_) = test.check {
+                outcome = self.check(&emulator, &test.check);
+                if outcome == Outcome::Passed {
+                    break;
+                }
+            }
+        }
+
+        if !matches!(test.check, Check::Text(_)) && emulator.frame() > start {
+            outcome = self.check(&emulator, &test.check);
+        }
+
+        result(outcome, emulator.frame() - start)
+    }
+
+    fn check(&self, emulator: &Emulator, check: &Check) -> Outcome {
+        match *check {
+            Check::Ram(bank, address, expected) => match emulator.bus().peek(bank, address) {
+                value if value == expected => Outcome::Passed,
+                value => Outcome::Failed(format!("{:02X}:{:04X} is {:02X}", bank, address, value)),
+            },
+            _ => self.check_frame(emulator.framebuffer(), emulator.width(), emulator.height(), check),
+        }
+    }
+
+    // the checks that only look at the last frame
+    fn check_frame(&self, pixels: &[u32], width: usize, height: usize, check: &Check) -> Outcome {
+        match *check {
+            Check::Ram(..) => Outcome::Failed("ram isn't part of the frame".to_string()),
+            Check::Hash(expected) => match pixels_crc32(pixels) {
+                hash if hash == expected => Outcome::Passed,
+                hash => Outcome::Failed(format!("framebuffer crc32 is {:08x}", hash)),
+            },
+            Check::Text(ref expected) => {
+                let screen = self.font.read_screen(pixels, width, height);
+                if screen.contains(expected.as_str()) {
+                    return Outcome::Passed;
+                }
+                // the last line with anything on it usually says why
+                let last = screen.lines().rev().find(|line| !line.trim().is_empty()).unwrap_or("").trim();
+                Outcome::Failed(format!("screen ends with \"{}\"", last))
+            },
+            Check::Png(ref path, tolerance) => {
+                let path = Path::new(&self.dir).join(path).to_string_lossy().into_owned();
+                let diff_path = golden::diff_path(&path);
+                match golden::compare_png(pixels, width, height, &path, tolerance, &diff_path) {
+                    Ok(ref comparison) if comparison.matches() => Outcome::Passed,
+                    Ok(comparison) => Outcome::Failed(format!("{} pixels differ by up to {}, see {}", comparison.differing, comparison.largest, diff_path)),
+                    Err(e) => Outcome::Failed(e.to_string()),
+                }
+            },
+        }
+    }
+}
+
+pub fn parse(text: &str) -> Result<Vec<TestRom>, Error> {
+    let mut tests = Vec::new();
+
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let bad = |reason: &str| Error::new(ErrorKind::InvalidData, format!("{} line {}: {}", SUITE_FILE, number + 1, reason));
+        let words: Vec<&str> = line.split_whitespace().collect();
+        if words.len() < 5 {
+            return Err(bad("expected subsystem, frames, rom and check"));
+        }
+
+        let subsystem = Subsystem::from_name(words[0]).ok_or_else(|| bad("subsystem isn't cpu, ppu, apu or dma"))?;
+        let frames = words[1].parse().map_err(|_| bad("bad frame count"))?;
+        let check = match (words[3], &words[4..]) {
+            ("ram", &[address, value]) => {
+                let at = address.find(':').ok_or_else(|| bad("ram address isn't BB:AAAA"))?;
+                let bank = u8::from_str_radix(&address[..at], 16).map_err(|_| bad("bad ram address"))?;
+                let address = u16::from_str_radix(&address[at + 1..], 16).map_err(|_| bad("bad ram address"))?;
+                let value = u8::from_str_radix(value, 16).map_err(|_| bad("bad ram value"))?;
+                Check::Ram(bank, address, value)
+            },
+            ("hash", &[hash]) => Check::Hash(u32::from_str_radix(hash, 16).map_err(|_| bad("bad hash"))?),
+            ("text", text) => Check::Text(text.join(" ")),
+            ("png", &[path]) => Check::Png(path.to_string(), 0),
+            ("png", &[path, tolerance]) => Check::Png(path.to_string(), tolerance.parse().map_err(|_| bad("bad png tolerance"))?),
+            _ => return Err(bad("check isn't ram BB:AAAA XX, hash XXXXXXXX, text WORDS or png FILE [N]")),
+        };
+
+        tests.push(TestRom {
+            subsystem,
+            frames,
+            rom: words[2].to_string(),
+            check,
+        });
+    }
+
+    Ok(tests)
+}
+
+// the crc32 of the framebuffer as rgb bytes, the hash snes-run --hash prints
+pub fn framebuffer_crc32(emulator: &Emulator) -> u32 {
+    pixels_crc32(emulator.framebuffer())
+}
+
+pub fn pixels_crc32(pixels: &[u32]) -> u32 {
+    let frame: Vec<u8> = pixels.iter()
+        .flat_map(|&pixel| vec![(pixel >> 16) as u8, (pixel >> 8) as u8, pixel as u8])
+        .collect();
+    crc32(&frame)
+}
+
+// a line per test and then a count per subsystem:
+//   PASS  cpu  cpu/adc.sfc     41 frames
+//   FAIL  ppu  ppu/mode7.sfc   120 frames  framebuffer crc32 is 0badf00d
+//   skip  apu  spc/echo.sfc    rom not found
+//
+//   subsystem  passed  failed  skipped
+//   cpu             1       0        0
+pub fn report(results: &[TestResult]) -> String {
+    let width = results.iter().map(|result| result.test.rom.len()).max().unwrap_or(0);
+    let mut text = String::new();
+
+    for result in results.iter() {
+        let (status, detail) = match result.outcome {
+            Outcome::Passed => ("PASS", format!("{} frames", result.frames)),
+            Outcome::Failed(ref why) => ("FAIL", format!("{} frames  {}", result.frames, why)),
+            Outcome::Skipped(ref why) => ("skip", why.clone()),
+        };
+        text += &format!("{}  {}  {:width$}  {}\n", status, result.test.subsystem.name(), result.test.rom, detail, width = width);
+    }
+
+    text += &format!("\n{:9}  {:>6}  {:>6}  {:>7}\n", "subsystem", "passed", "failed", "skipped");
+    for &subsystem in Subsystem::ALL.iter() {
+        let count = |outcome: fn(&Outcome) -> bool| results.iter()
+            .filter(|result| result.test.subsystem == subsystem && outcome(&result.outcome))
+            .count();
+        let passed = count(|outcome| *outcome == Outcome::Passed);
+        let failed = count(|outcome| matches!(*outcome, Outcome::Failed(_)));
+        let skipped = count(|outcome| matches!(*outcome, Outcome::Skipped(_)));
+        text += &format!("{:9}  {:>6}  {:>6}  {:>7}\n", subsystem.name(), passed, failed, skipped);
+    }
+
+    text
+}
+
+pub fn all_passed(results: &[TestResult]) -> bool {
+    !results.iter().any(|result| matches!(result.outcome, Outcome::Failed(_)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    use cpu::memory::Mem;
+    use ppu::png;
+
+    const WIDTH: usize = 64;
+    const HEIGHT: usize = 32;
+    const INK: u32 = 0xFFFFFF;
+    const PAPER: u32 = 0x000040;
+
+    fn test_rom(subsystem: Subsystem, rom: &str, check: Check) -> TestRom {
+        TestRom {
+            subsystem,
+            frames: 60,
+            rom: rom.to_string(),
+            check,
+        }
+    }
+
+    fn result(subsystem: Subsystem, rom: &str, outcome: Outcome, frames: u64) -> TestResult {
+        TestResult {
+            test: test_rom(subsystem, rom, Check::Hash(0)),
+            outcome,
+            frames,
+        }
+    }
+
+    // the built in glyph for c, as font.txt rows
+    fn glyph(c: char) -> u64 {
+        let line = font::BUILTIN.lines().find(|line| u8::from_str_radix(&line[..2], 16).ok() == Some(c as u8)).unwrap();
+        u64::from_str_radix(&line[3..19], 16).unwrap()
+    }
+
+    // a synthetic frame with text drawn at left, top in the built in font
+    fn screen(lines: &[&str], left: usize, top: usize) -> Vec<u32> {
+        let mut pixels = vec![PAPER; WIDTH * HEIGHT];
+        for (row, line) in lines.iter().enumerate() {
+            for (column, c) in line.chars().enumerate() {
+                let rows = if c == ' ' { 0 } else { glyph(c) };
+                for y in 0..8 {
+                    for x in 0..8 {
+                        if rows >> (63 - (y * 8 + x)) & 1 != 0 {
+                            pixels[(top + row * 8 + y) * WIDTH + left + column * 8 + x] = INK;
+                        }
+                    }
+                }
+            }
+        }
+        pixels
+    }
+
+    fn temp_dir(name: &str) -> String {
+        let dir = env::temp_dir().join(format!("snes-suite-{}-{}", name, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        dir.to_string_lossy().into_owned()
+    }
+
+    #[test]
+    fn parse_reads_every_kind_of_check() {
+        let tests = parse("\
+# a comment, and a blank line
+
+cpu  600   cpu/adc.sfc       ram 7E:0000 01
+ppu  120   ppu/mode7.sfc     hash 3f2a81c0
+apu  1800  spc/dsp_echo.sfc  text Passed all tests
+ppu  120   ppu/hires.sfc     png ppu/hires.png 4
+dma  60    dma/hdma.sfc      png dma/hdma.png
+").unwrap();
+
+        assert_eq!(tests, vec![
+            TestRom { subsystem: Subsystem::CPU, frames: 600, rom: "cpu/adc.sfc".to_string(), check: Check::Ram(0x7E, 0x0000, 0x01) },
+            TestRom { subsystem: Subsystem::PPU, frames: 120, rom: "ppu/mode7.sfc".to_string(), check: Check::Hash(0x3f2a81c0) },
+            TestRom { subsystem: Subsystem::APU, frames: 1800, rom: "spc/dsp_echo.sfc".to_string(), check: Check::Text("Passed all tests".to_string()) },
+            TestRom { subsystem: Subsystem::PPU, frames: 120, rom: "ppu/hires.sfc".to_string(), check: Check::Png("ppu/hires.png".to_string(), 4) },
+            TestRom { subsystem: Subsystem::DMA, frames: 60, rom: "dma/hdma.sfc".to_string(), check: Check::Png("dma/hdma.png".to_string(), 0) },
+        ]);
+    }
+
+    #[test]
+    fn parse_says_which_line_is_wrong() {
+        let error = |text: &str| parse(text).unwrap_err().to_string();
+
+        assert_eq!(error("\ncpu 600 adc.sfc ram\n"), "suite.txt line 2: expected subsystem, frames, rom and check");
+        assert_eq!(error("gpu 600 adc.sfc ram 7E:0000 01"), "suite.txt line 1: subsystem isn't cpu, ppu, apu or dma");
+        assert_eq!(error("cpu many adc.sfc ram 7E:0000 01"), "suite.txt line 1: bad frame count");
+        assert_eq!(error("cpu 600 adc.sfc ram 7E0000 01"), "suite.txt line 1: ram address isn't BB:AAAA");
+        assert_eq!(error("cpu 600 adc.sfc ram 7E:0000 100"), "suite.txt line 1: bad ram value");
+        assert_eq!(error("cpu 600 adc.sfc hash nothex"), "suite.txt line 1: bad hash");
+        assert_eq!(error("cpu 600 adc.sfc png a.png lots"), "suite.txt line 1: bad png tolerance");
+        assert!(error("cpu 600 adc.sfc crc 1234").starts_with("suite.txt line 1: check isn't"));
+    }
+
+    #[test]
+    fn report_lists_every_rom_and_counts_each_subsystem() {
+        let results = vec![
+            result(Subsystem::CPU, "cpu/adc.sfc", Outcome::Passed, 41),
+            result(Subsystem::PPU, "ppu/mode7.sfc", Outcome::Failed("framebuffer crc32 is 0badf00d".to_string()), 120),
+            result(Subsystem::APU, "spc/echo.sfc", Outcome::Skipped("rom not found".to_string()), 0),
+        ];
+
+        assert_eq!(report(&results), "\
+PASS  cpu  cpu/adc.sfc    41 frames
+FAIL  ppu  ppu/mode7.sfc  120 frames  framebuffer crc32 is 0badf00d
+skip  apu  spc/echo.sfc   rom not found
+
+subsystem  passed  failed  skipped
+cpu             1       0        0
+ppu             0       1        0
+apu             0       0        1
+dma             0       0        0
+");
+    }
+
+    #[test]
+    fn skipped_roms_dont_fail_the_suite() {
+        let passed = result(Subsystem::CPU, "a.sfc", Outcome::Passed, 1);
+        let skipped = result(Subsystem::CPU, "b.sfc", Outcome::Skipped("rom not found".to_string()), 0);
+        let failed = result(Subsystem::CPU, "c.sfc", Outcome::Failed("no".to_string()), 1);
+
+        assert!(all_passed(&[]));
+        assert!(all_passed(&[passed.clone(), skipped.clone()]));
+        assert!(!all_passed(&[passed, skipped, failed]));
+    }
+
+    #[test]
+    fn ram_checks_read_the_bus() {
+        let suite = Suite::new(".", Vec::new());
+        let mut emulator = Emulator::new();
+        emulator.bus_mut().store(0x7E, 0x0010, 0x45);
+
+        assert_eq!(suite.check(&emulator, &Check::Ram(0x7E, 0x0010, 0x45)), Outcome::Passed);
+        assert_eq!(suite.check(&emulator, &Check::Ram(0x7E, 0x0010, 0x00)), Outcome::Failed("7E:0010 is 45".to_string()));
+    }
+
+    #[test]
+    fn hash_checks_take_the_crc32_of_the_rgb_bytes() {
+        let suite = Suite::new(".", Vec::new());
+        let pixels = [0x123456, 0xABCDEF];
+        let hash = crc32(&[0x12, 0x34, 0x56, 0xAB, 0xCD, 0xEF]);
+
+        assert_eq!(pixels_crc32(&pixels), hash);
+        assert_eq!(suite.check_frame(&pixels, 2, 1, &Check::Hash(hash)), Outcome::Passed);
+        assert_eq!(suite.check_frame(&pixels, 2, 1, &Check::Hash(0)), Outcome::Failed(format!("framebuffer crc32 is {:08x}", hash)));
+    }
+
+    #[test]
+    fn text_checks_read_the_screen_wherever_the_text_sits() {
+        let suite = Suite::new(".", Vec::new());
+        let pixels = screen(&["spc700", "Passed!"], 3, 5);
+
+        assert_eq!(suite.check_frame(&pixels, WIDTH, HEIGHT, &Check::Text("Passed".to_string())), Outcome::Passed);
+        assert_eq!(
+            suite.check_frame(&pixels, WIDTH, HEIGHT, &Check::Text("Failed".to_string())),
+            Outcome::Failed("screen ends with \"Passed!\"".to_string())
+        );
+    }
+
+    #[test]
+    fn every_builtin_glyph_reads_back_as_itself() {
+        let font = Font::builtin();
+        let printable: String = (0x21u8..0x7F).map(|c| c as char).collect();
+
+        for chunk in printable.as_bytes().chunks(WIDTH / 8) {
+            let line = String::from_utf8(chunk.to_vec()).unwrap();
+            let pixels = screen(&[&line], 0, 0);
+            assert_eq!(font.read_screen(&pixels, WIDTH, HEIGHT).lines().next(), Some(line.as_str()));
+        }
+    }
+
+    #[test]
+    fn png_checks_allow_the_tolerance_and_write_a_diff_past_it() {
+        let dir = temp_dir("png");
+        let suite = Suite::new(&dir, Vec::new());
+        let golden = screen(&["PASS"], 0, 0);
+        png::write_png(&format!("{}/golden.png", dir), WIDTH, HEIGHT, &golden).unwrap();
+
+        let mut close = golden.clone();
+        close[WIDTH * HEIGHT - 1] += 0x020202;
+        assert_eq!(suite.check_frame(&close, WIDTH, HEIGHT, &Check::Png("golden.png".to_string(), 2)), Outcome::Passed);
+
+        let diff_path = golden::diff_path(&Path::new(&dir).join("golden.png").to_string_lossy());
+        assert_eq!(
+            suite.check_frame(&close, WIDTH, HEIGHT, &Check::Png("golden.png".to_string(), 1)),
+            Outcome::Failed(format!("1 pixels differ by up to 2, see {}", diff_path))
+        );
+        assert!(Path::new(&diff_path).is_file());
+
+        match suite.check_frame(&close, WIDTH, HEIGHT, &Check::Png("missing.png".to_string(), 0)) {
+            Outcome::Failed(_) => {},
+            outcome => panic!("{:?}", outcome),
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn missing_roms_are_skipped() {
+        let suite = Suite::new(".", vec![test_rom(Subsystem::CPU, "no/such.sfc", Check::Ram(0x7E, 0, 1))]);
+        let results = suite.run();
+
+        assert_eq!(results[0].outcome, Outcome::Skipped("rom not found".to_string()));
+        assert_eq!(results[0].frames, 0);
+    }
+}
@@ -0,0 +1,93 @@
+use std::collections::HashMap;
+use std::io::Error;
+
+use symbols::{bad_line, Location, Symbol};
+
+// ld65 debug info (--dbgfile). one record per line, a type and then key=value pairs:
+//   seg  id=0,name="CODE",start=0x008000,size=0x0120,addrsize=absolute,type=ro,...
+//   sym  id=4,name="main",addrsize=absolute,scope=0,def=12,val=0x8000,seg=0,type=lab
+// only labels are kept. values that don't say their bank take it from their segment
+pub fn parse(text: &str) -> Result<Vec<Symbol>, Error> {
+    let mut segment_banks = HashMap::new();
+    let mut labels = Vec::new();
+
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim();
+        let (kind, fields) = match line.find(char::is_whitespace) {
+            Some(at) => (&line[..at], fields(&line[at..])),
+            None => continue,
+        };
+
+        match kind {
+            "seg" => {
+                let id = fields.get("id").and_then(|id| number_value(id));
+                let start = fields.get("start").and_then(|start| number_value(start));
+                match (id, start) {
+                    (Some(id), Some(start)) => { segment_banks.insert(id, start >> 16); },
+                    _ => return Err(bad_line(number, line)),
+                }
+            },
+            "sym" if fields.get("type").map(|kind| kind.as_str()) == Some("lab") => {
+                let name = fields.get("name").ok_or_else(|| bad_line(number, line))?;
+                let value = fields.get("val").and_then(|value| number_value(value)).ok_or_else(|| bad_line(number, line))?;
+                let segment = fields.get("seg").and_then(|segment| number_value(segment));
+                labels.push((name.clone(), value, segment));
+            },
+            _ => {},
+        }
+    }
+
+    // segments are listed before symbols, but nothing promises that
+    Ok(labels.into_iter()
+        .map(|(name, value, segment)| {
+            let bank = match segment.and_then(|segment| segment_banks.get(&segment)) {
+                Some(&bank) if value <= 0xFFFF => bank,
+                _ => value >> 16,
+            };
+            Symbol {
+                location: Location::Bus(((bank & 0xFF) << 16) | (value & 0xFFFF)),
+                label: Some(name),
+                comment: None,
+            }
+        })
+        .collect())
+}
+
+// key=value pairs separated by commas. quoted values have their quotes taken off
+fn fields(text: &str) -> HashMap<String, String> {
+    let mut fields = HashMap::new();
+    let mut rest = text.trim();
+
+    while !rest.is_empty() {
+        let at = match rest.find('=') {
+            Some(at) => at,
+            None => break,
+        };
+        let key = rest[..at].trim().to_string();
+        rest = &rest[at + 1..];
+
+        let value = if let Some(quoted) = rest.strip_prefix('"') {
+            let end = quoted.find('"').unwrap_or(quoted.len());
+            let value = &quoted[..end];
+            rest = quoted.get(end + 1..).unwrap_or("");
+            value
+        } else {
+            let end = rest.find(',').unwrap_or(rest.len());
+            let value = &rest[..end];
+            rest = &rest[end..];
+            value
+        };
+        fields.insert(key, value.to_string());
+        rest = rest.trim_start_matches(',');
+    }
+
+    fields
+}
+
+// 0x8000 or 32768
+fn number_value(text: &str) -> Option<u32> {
+    match text.strip_prefix("0x") {
+        Some(hex) => u32::from_str_radix(hex, 16).ok(),
+        None => text.parse().ok(),
+    }
+}
@@ -0,0 +1,52 @@
+use std::io::Error;
+
+use symbols::{bad_line, bus_location, Location, Symbol};
+
+// Mesen label files. one label per line, addresses are offsets into the memory named first:
+//   SnesPrgRom:1A00:update_sprites:runs once a frame
+//   SnesWorkRam:0010-0011:player_x
+//   SnesRegister:2100:INIDISP
+// the label or the comment can be empty. Mesen-S's older PRG, WORK, SAVE and REG names
+// are read too, and memory types that don't apply to the main cpu are skipped
+pub fn parse(text: &str) -> Result<Vec<Symbol>, Error> {
+    let mut symbols = Vec::new();
+
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim_end_matches('\r');
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let fields: Vec<&str> = line.splitn(4, ':').collect();
+        if fields.len() < 3 {
+            return Err(bad_line(number, line));
+        }
+
+        // a range labels its first byte
+        let start = fields[1].split('-').next().unwrap_or("");
+        let offset = usize::from_str_radix(start, 16).map_err(|_| bad_line(number, line))?;
+
+        let location = match fields[0] {
+            "SnesPrgRom" | "PRG" => Location::Rom(offset),
+            "SnesWorkRam" | "WORK" => Location::WRAM(offset),
+            "SnesSaveRam" | "SAVE" => Location::SRAM(offset),
+            "SnesRegister" | "REG" => bus_location(0, offset as u16),
+            _ => continue,
+        };
+
+        // mesen keeps multi line comments on one line with \n
+        let label = Some(fields[2]).filter(|label| !label.is_empty()).map(|label| label.to_string());
+        let comment = fields.get(3).filter(|comment| !comment.is_empty()).map(|comment| comment.replace("\\n", "\n"));
+        if label.is_none() && comment.is_none() {
+            continue;
+        }
+
+        symbols.push(Symbol {
+            location,
+            label,
+            comment,
+        });
+    }
+
+    Ok(symbols)
+}
@@ -0,0 +1,218 @@
+pub mod ca65;
+pub mod mlb;
+pub mod wla;
+
+use std::collections::HashMap;
+use std::fs;
+use std::io::{Error, ErrorKind};
+use std::path::Path;
+
+use cartridge::Cartridge;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum SymbolFormat {
+    WLA,  // .sym, from wla-dx and most other 65816 assemblers
+    CA65, // .dbg, ld65's debug info
+    MLB,  // .mlb, Mesen's label files
+}
+
+impl SymbolFormat {
+    const ALL: [SymbolFormat; 3] = [SymbolFormat::WLA, SymbolFormat::CA65, SymbolFormat::MLB];
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            SymbolFormat::WLA => "sym",
+            SymbolFormat::CA65 => "dbg",
+            SymbolFormat::MLB => "mlb",
+        }
+    }
+
+    pub fn from_path(path: &str) -> Option<SymbolFormat> {
+        let extension = Path::new(path).extension()?.to_str()?.to_ascii_lowercase();
+        SymbolFormat::ALL.iter().find(|format| format.extension() == extension).cloned()
+    }
+}
+
+// where a symbol points. files give either cpu addresses or offsets into one kind of memory
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum Location {
+    Bus(u32), // 24 bit, bank in the top 8 bits
+    Rom(usize),
+    WRAM(usize),
+    SRAM(usize),
+}
+
+// a label, a comment or both, for one address
+#[derive(Clone, PartialEq, Debug)]
+pub struct Symbol {
+    pub location: Location,
+    pub label: Option<String>,
+    pub comment: Option<String>,
+}
+
+// every symbol loaded for the cartridge, looked up by address or by name. the same byte
+// can be reached through many cpu addresses, so lookups go by what the address lands on
+pub struct Symbols {
+    symbols: Vec<Symbol>,
+    labels: HashMap<Location, usize>,
+    comments: HashMap<Location, usize>,
+    names: HashMap<String, Vec<usize>>,
+}
+
+impl Symbols {
+    pub fn new() -> Symbols {
+        Symbols {
+            symbols: Vec::new(),
+            labels: HashMap::new(),
+            comments: HashMap::new(),
+            names: HashMap::new(),
+        }
+    }
+
+    pub fn symbols(&self) -> &[Symbol] {
+        &self.symbols
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.symbols.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        *self = Symbols::new();
+    }
+
+    // the first label or comment loaded for an address is the one that's shown
+    pub fn add(&mut self, symbols: Vec<Symbol>, cartridge: Option<&Cartridge>) {
+        for symbol in symbols {
+            let index = self.symbols.len();
+            let location = canonical(symbol.location, cartridge);
+
+            if let Some(ref label) = symbol.label {
+                self.labels.entry(location).or_insert(index);
+                self.names.entry(label.clone()).or_default().push(index);
+            }
+            if symbol.comment.is_some() {
+                self.comments.entry(location).or_insert(index);
+            }
+            self.symbols.push(symbol);
+        }
+    }
+
+    pub fn label(&self, bank: u8, address: u16, cartridge: Option<&Cartridge>) -> Option<&str> {
+        let index = *self.labels.get(&canonical(bus_location(bank, address), cartridge))?;
+        self.symbols[index].label.as_deref()
+    }
+
+    pub fn comment(&self, bank: u8, address: u16, cartridge: Option<&Cartridge>) -> Option<&str> {
+        let index = *self.comments.get(&canonical(bus_location(bank, address), cartridge))?;
+        self.symbols[index].comment.as_deref()
+    }
+
+    // the cpu address of a label, as "name" or bank qualified as "BB:name" for names that
+    // are used in more than one bank
+    pub fn address_of(&self, name: &str, cartridge: Option<&Cartridge>) -> Option<(u8, u16)> {
+        let (bank, name) = match name.find(':') {
+            Some(at) => (Some(u8::from_str_radix(&name[..at], 16).ok()?), &name[at + 1..]),
+            None => (None, name),
+        };
+
+        self.names.get(name)?.iter()
+            .filter_map(|&index| cpu_address(self.symbols[index].location, cartridge))
+            .find(|&(found, _)| bank.is_none_or(|bank| bank == found))
+    }
+}
+
+////////////////////////////////////
+//
+//             LOADING
+//
+////////////////////////////////////
+
+pub fn parse(text: &str, format: SymbolFormat) -> Result<Vec<Symbol>, Error> {
+    match format {
+        SymbolFormat::WLA => wla::parse(text),
+        SymbolFormat::CA65 => ca65::parse(text),
+        SymbolFormat::MLB => mlb::parse(text),
+    }
+}
+
+// the format comes from the extension
+pub fn load(path: &str) -> Result<Vec<Symbol>, Error> {
+    let format = SymbolFormat::from_path(path)
+        .ok_or_else(|| Error::new(ErrorKind::InvalidInput, format!("{} isn't a .sym, .dbg or .mlb file", path)))?;
+    parse(&fs::read_to_string(path)?, format)
+}
+
+// game.sym, game.dbg and game.mlb next to game.sfc, whichever exist
+pub fn find_symbol_files(rom_path: &str) -> Vec<String> {
+    SymbolFormat::ALL.iter()
+        .map(|format| Path::new(rom_path).with_extension(format.extension()))
+        .filter(|path| path.is_file())
+        .map(|path| path.to_string_lossy().into_owned())
+        .collect()
+}
+
+////////////////////////////////////
+//
+//             HELPERS
+//
+////////////////////////////////////
+
+pub fn bus_location(bank: u8, address: u16) -> Location {
+    Location::Bus(((bank as u32) << 16) | address as u32)
+}
+
+// what a location lands on, so that mirrors of the same byte find the same symbol
+pub fn canonical(location: Location, cartridge: Option<&Cartridge>) -> Location {
+    let (bank, address) = match location {
+        Location::Bus(address) => ((address >> 16) as u8, address as u16),
+        other => return other,
+    };
+    let system_bank = bank & 0x40 == 0; // $00-$3F and $80-$BF
+
+    if bank & 0xFE == 0x7E {
+        return Location::WRAM(((bank as usize & 1) << 16) | address as usize);
+    }
+    if system_bank && address < 0x2000 {
+        return Location::WRAM(address as usize);
+    }
+    if let Some(cartridge) = cartridge {
+        if let Some(offset) = cartridge.sram_offset(bank, address) {
+            return Location::SRAM(offset);
+        }
+        if let Some(offset) = cartridge.rom_offset(bank, address) {
+            return Location::Rom(offset);
+        }
+    }
+    // the io registers are the same in every system bank
+    if system_bank && address < 0x8000 {
+        return bus_location(0, address);
+    }
+    location
+}
+
+// where the cpu sees a location. cpu addresses from the file are kept as they were written
+pub fn cpu_address(location: Location, cartridge: Option<&Cartridge>) -> Option<(u8, u16)> {
+    match location {
+        Location::Bus(address) => Some(((address >> 16) as u8, address as u16)),
+        Location::Rom(offset) => cartridge?.rom_address(offset),
+        Location::SRAM(offset) => cartridge?.sram_address(offset),
+        Location::WRAM(offset) if offset < 0x20000 => Some((0x7E + (offset >> 16) as u8, offset as u16)),
+        Location::WRAM(_) => None,
+    }
+}
+
+fn bad_line(number: usize, line: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, format!("bad symbol on line {}: {}", number + 1, line))
+}
+
+// "BB:AAAA", the way wla-dx and most others write an address
+fn parse_bank_address(text: &str) -> Option<u32> {
+    let at = text.find(':')?;
+    let bank = u32::from_str_radix(&text[..at], 16).ok()?;
+    let address = u32::from_str_radix(&text[at + 1..], 16).ok()?;
+    if bank > 0xFF || address > 0xFFFF {
+        return None;
+    }
+    Some((bank << 16) | address)
+}
@@ -0,0 +1,49 @@
+use std::io::Error;
+
+use symbols::{bad_line, parse_bank_address, Location, Symbol};
+
+// wla-dx symbol files, also written by no$sns and read by most snes debuggers:
+//   ; comment
+//   [labels]
+//   00:8000 main
+//   7E:0010 player_x
+//   [comments]
+//   00:8000 reset lands here
+// lines before any section are labels, other sections ([definitions], [source files]...)
+// are skipped
+pub fn parse(text: &str) -> Result<Vec<Symbol>, Error> {
+    let mut symbols = Vec::new();
+    let mut section = "labels".to_string();
+
+    for (number, line) in text.lines().enumerate() {
+        let line = line.split(';').next().unwrap_or("").trim();
+        if line.is_empty() {
+            continue;
+        }
+        if line.starts_with('[') && line.ends_with(']') {
+            section = line[1..line.len() - 1].to_ascii_lowercase();
+            continue;
+        }
+        if section != "labels" && section != "comments" {
+            continue;
+        }
+
+        let (address, rest) = match line.find(char::is_whitespace) {
+            Some(at) => (&line[..at], line[at..].trim()),
+            None => return Err(bad_line(number, line)),
+        };
+        let address = parse_bank_address(address).ok_or_else(|| bad_line(number, line))?;
+        if rest.is_empty() {
+            return Err(bad_line(number, line));
+        }
+
+        let (label, comment) = if section == "labels" { (Some(rest.to_string()), None) } else { (None, Some(rest.to_string())) };
+        symbols.push(Symbol {
+            location: Location::Bus(address),
+            label,
+            comment,
+        });
+    }
+
+    Ok(symbols)
+}
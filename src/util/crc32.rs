@@ -0,0 +1,60 @@
+// the standard reflected CRC-32 (polynomial 0xEDB88320) used by zip, png and the patch formats
+pub struct Crc32 {
+    table: [u32; 256],
+    crc: u32,
+}
+
+impl Crc32 {
+    pub fn new() -> Crc32 {
+        let mut table = [0; 256];
+        for (n, entry) in table.iter_mut().enumerate() {
+            let mut c = n as u32;
+            for _ in 0..8 {
+                c = if c & 1 != 0 { 0xEDB88320 ^ (c >> 1) } else { c >> 1 };
+            }
+            *entry = c;
+        }
+
+        Crc32 {
+            table,
+            crc: 0xFFFFFFFF,
+        }
+    }
+
+    pub fn update(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.crc = self.table[((self.crc ^ byte as u32) & 0xFF) as usize] ^ (self.crc >> 8);
+        }
+    }
+
+    pub fn finish(&self) -> u32 {
+        self.crc ^ 0xFFFFFFFF
+    }
+}
+
+pub fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = Crc32::new();
+    crc.update(bytes);
+    crc.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn crc32_matches_the_standard_check_value() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xCBF43926);
+        assert_eq!(crc32(b"The quick brown fox jumps over the lazy dog"), 0x414FA339);
+    }
+
+    #[test]
+    fn updates_add_up_to_the_whole() {
+        let mut crc = Crc32::new();
+        crc.update(b"1234");
+        crc.update(b"");
+        crc.update(b"56789");
+        assert_eq!(crc.finish(), 0xCBF43926);
+    }
+}
@@ -0,0 +1,3 @@
+pub mod crc32;
+pub mod rle;
+pub mod zlib;
@@ -0,0 +1,82 @@
+use std::io::{Error, ErrorKind};
+
+// PackBits style run length encoding, for memory images that are mostly runs of the same byte.
+// a control byte below $80 is followed by that many + 1 literal bytes, $80 and above by
+// a single byte that is repeated control - $7D times (3 to 130)
+const MAX_LITERAL: usize = 128;
+const MIN_RUN: usize = 3;
+const MAX_RUN: usize = 130;
+
+pub fn compress(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+    let mut literal_start = 0;
+    let mut i = 0;
+
+    while i < data.len() {
+        let mut run = 1;
+        while i + run < data.len() && run < MAX_RUN && data[i + run] == data[i] {
+            run += 1;
+        }
+
+        if run >= MIN_RUN {
+            push_literals(&mut out, &data[literal_start..i]);
+            out.push((run + 0x7D) as u8);
+            out.push(data[i]);
+            i += run;
+            literal_start = i;
+        } else {
+            i += 1;
+        }
+    }
+
+    push_literals(&mut out, &data[literal_start..]);
+    out
+}
+
+// decompresses into out, which has to be exactly the size of the original data
+pub fn decompress(data: &[u8], out: &mut [u8]) -> Result<(), Error> {
+    let mut i = 0;
+    let mut o = 0;
+
+    while i < data.len() {
+        let control = data[i] as usize;
+        i += 1;
+
+        if control < 0x80 {
+            let len = control + 1;
+            if i + len > data.len() || o + len > out.len() {
+                return Err(corrupt());
+            }
+            out[o..o + len].copy_from_slice(&data[i..i + len]);
+            i += len;
+            o += len;
+        } else {
+            let len = control - 0x7D;
+            if i >= data.len() || o + len > out.len() {
+                return Err(corrupt());
+            }
+            for byte in out[o..o + len].iter_mut() {
+                *byte = data[i];
+            }
+            i += 1;
+            o += len;
+        }
+    }
+
+    if o != out.len() {
+        return Err(corrupt());
+    }
+
+    Ok(())
+}
+
+fn push_literals(out: &mut Vec<u8>, literals: &[u8]) {
+    for chunk in literals.chunks(MAX_LITERAL) {
+        out.push((chunk.len() - 1) as u8);
+        out.extend_from_slice(chunk);
+    }
+}
+
+fn corrupt() -> Error {
+    Error::new(ErrorKind::InvalidData, "corrupt run length encoded data")
+}
@@ -0,0 +1,320 @@
+use std::io::{Error, ErrorKind};
+
+// zlib streams (rfc 1950) around deflate data (rfc 1951), enough to read what other tools
+// write: stored, fixed and dynamic huffman blocks. nothing here compresses, writers store
+
+const MAX_BITS: usize = 15;
+
+// extra bits and bases for the length codes 257-285 and the 30 distance codes
+const LENGTH_BASE: [u16; 29] = [3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258];
+const LENGTH_EXTRA: [u8; 29] = [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0];
+const DISTANCE_BASE: [u16; 30] = [1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577];
+const DISTANCE_EXTRA: [u8; 30] = [0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13];
+
+// the order code length code lengths come in, in a dynamic block's header
+const CODE_LENGTH_ORDER: [usize; 19] = [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15];
+
+pub fn adler32(data: &[u8]) -> u32 {
+    let mut a: u32 = 1;
+    let mut b: u32 = 0;
+    for &byte in data {
+        a = (a + byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    (b << 16) | a
+}
+
+// the 2 byte header, deflate data and the adler32 of what it inflates to
+pub fn decompress(data: &[u8]) -> Result<Vec<u8>, Error> {
+    if data.len() < 6 {
+        return Err(bad("zlib stream is too short"));
+    }
+    let (cmf, flg) = (data[0], data[1]);
+    if cmf & 0x0F != 8 || !((cmf as u16) << 8 | flg as u16).is_multiple_of(31) {
+        return Err(bad("not a zlib stream"));
+    }
+    if flg & 0x20 != 0 {
+        return Err(bad("zlib streams with a preset dictionary aren't supported"));
+    }
+
+    let (out, used) = inflate(&data[2..])?;
+    let end = 2 + used;
+    let check = data.get(end..end + 4).ok_or_else(|| bad("zlib stream is missing its checksum"))?;
+    if u32::from_be_bytes([check[0], check[1], check[2], check[3]]) != adler32(&out) {
+        return Err(bad("zlib checksum doesn't match"));
+    }
+    Ok(out)
+}
+
+// raw deflate data. returns what it inflates to and how many bytes of data it took up
+pub fn inflate(data: &[u8]) -> Result<(Vec<u8>, usize), Error> {
+    let mut bits = Bits { data, position: 0, buffer: 0, count: 0 };
+    let mut out = Vec::new();
+
+    loop {
+        let last = bits.read(1)? == 1;
+        match bits.read(2)? {
+            0 => {
+                bits.align();
+                let len = bits.read(16)? as u16;
+                let inverse = bits.read(16)? as u16;
+                if len != !inverse {
+                    return Err(bad("stored block length is corrupt"));
+                }
+                let start = bits.position;
+                let block = data.get(start..start + len as usize).ok_or_else(|| bad("stored block runs past the end"))?;
+                out.extend_from_slice(block);
+                bits.position += len as usize;
+            },
+            1 => {
+                let (lengths, distances) = fixed_codes();
+                inflate_block(&mut bits, &mut out, &lengths, &distances)?;
+            },
+            2 => {
+                let (lengths, distances) = dynamic_codes(&mut bits)?;
+                inflate_block(&mut bits, &mut out, &lengths, &distances)?;
+            },
+            _ => return Err(bad("bad deflate block type")),
+        }
+
+        if last {
+            return Ok((out, bits.position));
+        }
+    }
+}
+
+fn bad(reason: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, reason)
+}
+
+// deflate packs its bits starting from each byte's lowest
+struct Bits<'a> {
+    data: &'a [u8],
+    position: usize, // of the next byte to load
+    buffer: u32,
+    count: u32,
+}
+
+impl<'a> Bits<'a> {
+    fn read(&mut self, count: u32) -> Result<u32, Error> {
+        while self.count < count {
+            let byte = *self.data.get(self.position).ok_or_else(|| bad("deflate data ends early"))?;
+            self.buffer |= (byte as u32) << self.count;
+            self.position += 1;
+            self.count += 8;
+        }
+        let value = self.buffer & ((1u64 << count) - 1) as u32;
+        self.buffer >>= count;
+        self.count -= count;
+        Ok(value)
+    }
+
+    // stored blocks start on a byte boundary
+    fn align(&mut self) {
+        self.buffer = 0;
+        self.count = 0;
+    }
+}
+
+// a canonical huffman code, as the number of codes of each length and the symbols in
+// code order
+struct Huffman {
+    counts: [u16; MAX_BITS + 1],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Result<Huffman, Error> {
+        let mut counts = [0u16; MAX_BITS + 1];
+        for &len in lengths {
+            counts[len as usize] += 1;
+        }
+        counts[0] = 0;
+
+        // more codes of a length than there's room for can't be decoded. fewer is allowed,
+        // a block with a single distance code has one
+        let mut left: i32 = 1;
+        for &count in counts[1..].iter() {
+            left = (left << 1) - count as i32;
+            if left < 0 {
+                return Err(bad("huffman code is oversubscribed"));
+            }
+        }
+
+        let mut offsets = [0u16; MAX_BITS + 2];
+        for len in 1..=MAX_BITS {
+            offsets[len + 1] = offsets[len] + counts[len];
+        }
+        let mut symbols = vec![0; offsets[MAX_BITS + 1] as usize];
+        for (symbol, &len) in lengths.iter().enumerate() {
+            if len != 0 {
+                symbols[offsets[len as usize] as usize] = symbol as u16;
+                offsets[len as usize] += 1;
+            }
+        }
+
+        Ok(Huffman { counts, symbols })
+    }
+
+    fn decode(&self, bits: &mut Bits) -> Result<u16, Error> {
+        let mut code: i32 = 0;
+        let mut first: i32 = 0;
+        let mut index: i32 = 0;
+        for len in 1..=MAX_BITS {
+            code |= bits.read(1)? as i32;
+            let count = self.counts[len] as i32;
+            if code - first < count {
+                return Ok(self.symbols[(index + code - first) as usize]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err(bad("bad huffman code"))
+    }
+}
+
+fn fixed_codes() -> (Huffman, Huffman) {
+    let mut lengths = [0u8; 288];
+    for (symbol, len) in lengths.iter_mut().enumerate() {
+        *len = match symbol {
+            0..=143 => 8,
+            144..=255 => 9,
+            256..=279 => 7,
+            _ => 8,
+        };
+    }
+    // these are complete codes, so building them can't fail
+    (Huffman::new(&lengths).unwrap(), Huffman::new(&[5; 30]).unwrap())
+}
+
+fn dynamic_codes(bits: &mut Bits) -> Result<(Huffman, Huffman), Error> {
+    let literal_count = bits.read(5)? as usize + 257;
+    let distance_count = bits.read(5)? as usize + 1;
+    let code_length_count = bits.read(4)? as usize + 4;
+    if literal_count > 286 || distance_count > 30 {
+        return Err(bad("dynamic block has too many codes"));
+    }
+
+    let mut code_lengths = [0u8; 19];
+    for &symbol in CODE_LENGTH_ORDER[..code_length_count].iter() {
+        code_lengths[symbol] = bits.read(3)? as u8;
+    }
+    let code_length_code = Huffman::new(&code_lengths)?;
+
+    // the literal/length and distance code lengths run on from one into the other
+    let mut lengths = Vec::with_capacity(literal_count + distance_count);
+    while lengths.len() < literal_count + distance_count {
+        let (len, repeat) = match code_length_code.decode(bits)? {
+            symbol @ 0..=15 => (symbol as u8, 1),
+            16 => (*lengths.last().ok_or_else(|| bad("code length repeat with nothing before it"))?, 3 + bits.read(2)?),
+            17 => (0, 3 + bits.read(3)?),
+            18 => (0, 11 + bits.read(7)?),
+            _ => return Err(bad("bad code length symbol")),
+        };
+        for _ in 0..repeat {
+            lengths.push(len);
+        }
+    }
+    if lengths.len() != literal_count + distance_count {
+        return Err(bad("code lengths run past the codes"));
+    }
+    if lengths[256] == 0 {
+        return Err(bad("dynamic block has no end of block code"));
+    }
+
+    Ok((Huffman::new(&lengths[..literal_count])?, Huffman::new(&lengths[literal_count..])?))
+}
+
+fn inflate_block(bits: &mut Bits, out: &mut Vec<u8>, lengths: &Huffman, distances: &Huffman) -> Result<(), Error> {
+    loop {
+        let symbol = lengths.decode(bits)? as usize;
+        match symbol {
+            0..=255 => out.push(symbol as u8),
+            256 => return Ok(()),
+            257..=285 => {
+                let index = symbol - 257;
+                let len = LENGTH_BASE[index] as usize + bits.read(LENGTH_EXTRA[index] as u32)? as usize;
+
+                let index = distances.decode(bits)? as usize;
+                if index >= DISTANCE_BASE.len() {
+                    return Err(bad("bad distance code"));
+                }
+                let distance = DISTANCE_BASE[index] as usize + bits.read(DISTANCE_EXTRA[index] as u32)? as usize;
+                if distance > out.len() {
+                    return Err(bad("distance reaches back before the start"));
+                }
+
+                // the copy can overlap what it's writing, so it goes a byte at a time
+                let start = out.len() - distance;
+                for n in 0..len {
+                    let byte = out[start + n];
+                    out.push(byte);
+                }
+            },
+            _ => return Err(bad("bad literal/length code")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // "hello hello hello hello", compressed with a fixed huffman block
+    const FIXED: &'static str = "78dacb48cdc9c957c8402701680308b1";
+    // dynamic_text(), compressed with a dynamic huffman block
+    const DYNAMIC: &'static str = "\
+        78daedd13d1282301445e1de55dc05588822fb09f9c148c803346a5c3d83a5f52b6f7d664ef3a5983d4e90\
+        000357b399a2459fc48e473c6f1e4b897644bfca3b23c807f732cd0fc8cbafbf9cccb7c2c97048fba6d1d9\
+        9c7536179d4dabb3b9ea6c3a9d0dc1094e7082139ce0042738c1ff371b76d0e5a9";
+
+    fn hex(text: &str) -> Vec<u8> {
+        (0..text.len()).step_by(2).map(|at| u8::from_str_radix(&text[at..at + 2], 16).unwrap()).collect()
+    }
+
+    fn dynamic_text() -> Vec<u8> {
+        (0..40).flat_map(|n| format!("line {} of a dynamic block, the quick brown fox jumps over the lazy dog\n", n % 7).into_bytes()).collect()
+    }
+
+    #[test]
+    fn adler32_matches_known_values() {
+        assert_eq!(adler32(b""), 1);
+        assert_eq!(adler32(b"Wikipedia"), 0x11E60398);
+        // big enough for both sums to wrap
+        assert_eq!(adler32(&[0xFF; 6000]), 0xA49759EA);
+    }
+
+    #[test]
+    fn fixed_huffman_blocks_inflate() {
+        let stream = hex(FIXED);
+        assert_eq!(stream[2] >> 1 & 3, 1);
+        assert_eq!(decompress(&stream).unwrap(), b"hello hello hello hello");
+    }
+
+    #[test]
+    fn dynamic_huffman_blocks_inflate() {
+        let stream = hex(DYNAMIC);
+        assert_eq!(stream[2] >> 1 & 3, 2);
+        assert_eq!(decompress(&stream).unwrap(), dynamic_text());
+    }
+
+    #[test]
+    fn damaged_streams_are_refused() {
+        let error = |stream: &[u8]| decompress(stream).unwrap_err().to_string();
+        let stream = hex(FIXED);
+
+        let mut checksum = stream.clone();
+        *checksum.last_mut().unwrap() ^= 1;
+        assert_eq!(error(&checksum), "zlib checksum doesn't match");
+
+        let mut header = stream.clone();
+        header[1] ^= 1;
+        assert_eq!(error(&header), "not a zlib stream");
+
+        assert_eq!(error(&stream[..stream.len() - 2]), "zlib stream is missing its checksum");
+        assert_eq!(error(&stream[..4]), "zlib stream is too short");
+        assert_eq!(error(&[0x78, 0x01, 0x07, 0x00, 0x00, 0x00, 0x00]), "bad deflate block type");
+        assert_eq!(error(&[0x78, 0x01, 0x01, 0x05, 0x00, 0xFF, 0xFF, 0x00, 0x00, 0x00, 0x01]), "stored block length is corrupt");
+    }
+}
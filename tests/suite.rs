@@ -0,0 +1,17 @@
+extern crate my_snes_is_rusty;
+
+use std::env;
+
+use my_snes_is_rusty::suite::{self, Suite};
+
+// runs the test roms listed in tests/roms/suite.txt, or in $SNES_TEST_ROMS, the same as
+// snes-run --suite. roms that aren't there are skipped, but the list itself has to be
+#[test]
+fn test_roms() {
+    let dir = env::var("SNES_TEST_ROMS").unwrap_or_else(|_| "tests/roms".to_string());
+    let suite = Suite::load(&dir).unwrap_or_else(|e| panic!("can't load the suite in {}: {}", dir, e));
+
+    let results = suite.run();
+    print!("{}", suite::report(&results));
+    assert!(suite::all_passed(&results), "some test roms failed, see the report above");
+}